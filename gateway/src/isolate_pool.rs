@@ -0,0 +1,360 @@
+use crate::{GatewayError, Result};
+use std::ffi::c_void;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    mpsc, Arc, Mutex,
+};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+use tracing::error;
+
+type Job = Box<dyn FnOnce(&mut v8::Isolate) + Send>;
+
+/// Maximum stack size (in bytes) V8 uses for JavaScript, a function that recurses past
+/// it throws a RangeError
+pub(crate) const V8_STACK_SIZE: usize = 1024 * 1024;
+
+/// Stack size of the isolate threads, well above the V8 limit so the native frames of
+/// host functions and V8 itself can't overflow the thread's stack
+const ISOLATE_THREAD_STACK_SIZE: usize = 8 * V8_STACK_SIZE;
+
+/// Stored in an isolate's slot when the isolate has a memory limit
+#[derive(Clone, Default)]
+pub(crate) struct MemoryLimit {
+    /// Whether the running job is terminated when it reaches the limit, set by the job
+    pub(crate) enforced: Arc<AtomicBool>,
+    /// Set when the running job reaches the limit
+    pub(crate) exceeded: Arc<AtomicBool>,
+}
+
+/// Passed to the near heap limit callback of an isolate
+struct NearHeapLimit {
+    handle: v8::IsolateHandle,
+    limit: MemoryLimit,
+}
+
+/// Called by V8 when the isolate's heap is close to the memory limit. Terminates the
+/// running job if the limit is enforced, and raises the limit so V8 can unwind (or
+/// continue) the job instead of aborting the process. The limit is restored once the
+/// job has finished.
+extern "C" fn near_heap_limit(
+    data: *mut c_void,
+    current_heap_limit: usize,
+    _initial_heap_limit: usize,
+) -> usize {
+    // SAFETY: data points to the NearHeapLimit owned by the isolate's worker thread,
+    // which is only dropped after the isolate
+    let near_heap_limit = unsafe { &*(data as *const NearHeapLimit) };
+    near_heap_limit.limit.exceeded.store(true, Ordering::SeqCst);
+    if near_heap_limit.limit.enforced.load(Ordering::SeqCst) {
+        near_heap_limit.handle.terminate_execution();
+    }
+    current_heap_limit * 2
+}
+
+/// A fixed set of V8 isolates, each pinned to a dedicated thread as isolates are not `Send`.
+/// Jobs check out an idle isolate, so each isolate runs one job at a time while jobs on
+/// different isolates run in parallel.
+pub(crate) struct IsolatePool {
+    state: Arc<PoolState>,
+}
+
+struct PoolState {
+    /// Job senders of the workers, a worker whose thread stopped is replaced
+    workers: Mutex<Vec<mpsc::Sender<Job>>>,
+    /// Indexes of the workers that are not running a job
+    idle: Mutex<Vec<usize>>,
+    /// One permit per idle worker, closed once every worker has stopped
+    permits: Arc<Semaphore>,
+    /// Number of workers that have not stopped
+    live: AtomicUsize,
+    /// Heap limit of each isolate, in bytes
+    memory_limit: usize,
+}
+
+/// A worker checked out of the pool. The worker is returned to the pool once its job
+/// has finished, even if the caller stopped waiting for the result. A worker whose job
+/// didn't finish (e.g. its thread stopped) is replaced by a new worker, or discarded if
+/// a new worker can't be spawned.
+struct Checkout {
+    state: Arc<PoolState>,
+    worker: usize,
+    permit: Option<OwnedSemaphorePermit>,
+    finished: bool,
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        if self.finished {
+            self.state
+                .idle
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .push(self.worker);
+            return;
+        }
+
+        match spawn_worker(self.worker, self.state.memory_limit) {
+            Ok(worker) => {
+                self.state
+                    .workers
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())[self.worker] = worker;
+                self.state
+                    .idle
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .push(self.worker);
+            }
+            Err(err) => {
+                error!(
+                    worker = self.worker,
+                    ?err,
+                    "Failed to replace stopped isolate worker"
+                );
+
+                // Keep the worker's permit, so no other job waits for the worker
+                if let Some(permit) = self.permit.take() {
+                    permit.forget();
+                }
+                if self.state.live.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    self.state.permits.close();
+                }
+            }
+        }
+    }
+}
+
+/// Spawn a worker thread with its own isolate, returning the sender for its jobs. The
+/// thread exits once the sender is dropped.
+fn spawn_worker(i: usize, memory_limit: usize) -> std::io::Result<mpsc::Sender<Job>> {
+    let (tx, rx) = mpsc::channel::<Job>();
+    std::thread::Builder::new()
+        .name(format!("gateway-isolate-{i}"))
+        .stack_size(ISOLATE_THREAD_STACK_SIZE)
+        .spawn(move || {
+            let mut params = v8::CreateParams::default();
+            if memory_limit > 0 {
+                params = params.heap_limits(0, memory_limit);
+            }
+            let mut isolate = v8::Isolate::new(params);
+
+            let mut near_heap_limit_data = (memory_limit > 0).then(|| {
+                let limit = MemoryLimit::default();
+                isolate.set_slot(limit.clone());
+                Box::new(NearHeapLimit {
+                    handle: isolate.thread_safe_handle(),
+                    limit,
+                })
+            });
+            if let Some(data) = &mut near_heap_limit_data {
+                isolate.add_near_heap_limit_callback(
+                    near_heap_limit,
+                    &mut **data as *mut NearHeapLimit as *mut c_void,
+                );
+            }
+
+            // Exits once the pool is dropped
+            while let Ok(job) = rx.recv() {
+                job(&mut isolate);
+                // A timed out job may leave the isolate terminating, which would
+                // stop the next job from running
+                isolate.cancel_terminate_execution();
+
+                // Restore the memory limit, if it was raised during the job
+                if let Some(data) = &mut near_heap_limit_data {
+                    if data.limit.exceeded.swap(false, Ordering::SeqCst) {
+                        isolate.remove_near_heap_limit_callback(near_heap_limit, memory_limit);
+                        isolate.add_near_heap_limit_callback(
+                            near_heap_limit,
+                            &mut **data as *mut NearHeapLimit as *mut c_void,
+                        );
+                    }
+                }
+            }
+
+            // The callback data must outlive the isolate
+            drop(isolate);
+            drop(near_heap_limit_data);
+        })?;
+
+    Ok(tx)
+}
+
+impl IsolatePool {
+    /// Create a pool of `size` isolates, each limited to a heap of `memory_limit` bytes
+    /// (0 uses the V8 default)
+    pub(crate) fn new(size: usize, memory_limit: usize) -> Self {
+        let size = size.max(1);
+
+        let workers = (0..size)
+            .map(|i| spawn_worker(i, memory_limit))
+            .collect::<std::io::Result<Vec<_>>>();
+
+        #[allow(clippy::expect_used)] // the node can't run functions without any isolates
+        let workers = workers.expect("failed to spawn gateway isolate thread");
+
+        Self {
+            state: Arc::new(PoolState {
+                workers: Mutex::new(workers),
+                idle: Mutex::new((0..size).collect()),
+                permits: Arc::new(Semaphore::new(size)),
+                live: AtomicUsize::new(size),
+                memory_limit,
+            }),
+        }
+    }
+
+    /// Run `f` on an idle isolate, waiting for one to become idle if they are all busy.
+    /// If the returned future is dropped, `f` still runs and the isolate is returned to
+    /// the pool once it finishes.
+    pub(crate) async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut v8::Isolate) -> T + Send + 'static,
+    ) -> Result<T> {
+        let permit = Arc::clone(&self.state.permits)
+            .acquire_owned()
+            .await
+            .map_err(|_| GatewayError::IsolateWorkerStopped)?;
+        let worker = self
+            .state
+            .idle
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pop()
+            .ok_or(GatewayError::IsolateWorkerStopped)?;
+        let sender = self
+            .state
+            .workers
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())[worker]
+            .clone();
+        let mut checkout = Checkout {
+            state: Arc::clone(&self.state),
+            worker,
+            permit: Some(permit),
+            finished: false,
+        };
+
+        let (tx, rx) = oneshot::channel();
+        sender
+            .send(Box::new(move |isolate| {
+                let result = f(isolate);
+                checkout.finished = true;
+                drop(checkout);
+                tx.send(result).ok();
+            }))
+            .map_err(|_| GatewayError::IsolateWorkerStopped)?;
+
+        rx.await.map_err(|_| GatewayError::IsolateWorkerStopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_runs_serially_per_isolate_and_parallel_across_pool() {
+        crate::initialize(1, Default::default());
+        let pool = Arc::new(IsolatePool::new(2, 0));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let start = Instant::now();
+        let handles = (0..6)
+            .map(|_| {
+                let pool = Arc::clone(&pool);
+                let running = Arc::clone(&running);
+                let max_running = Arc::clone(&max_running);
+                tokio::spawn(async move {
+                    pool.run(move |_| {
+                        let count = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_running.fetch_max(count, Ordering::SeqCst);
+                        let started = Instant::now();
+                        std::thread::sleep(Duration::from_millis(50));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        let thread = std::thread::current().name().map(String::from);
+                        (thread, started, Instant::now())
+                    })
+                    .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = vec![];
+        for handle in handles {
+            results.push(handle.await.unwrap().unwrap());
+        }
+
+        // Both isolates were used at the same time, so 6 jobs take ~3 job durations
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() < Duration::from_millis(6 * 50));
+
+        // Jobs on the same isolate never overlap
+        let mut by_thread = HashMap::<_, Vec<_>>::new();
+        for (thread, started, finished) in results {
+            by_thread
+                .entry(thread.unwrap())
+                .or_default()
+                .push((started, finished));
+        }
+        assert_eq!(by_thread.len(), 2);
+        for runs in by_thread.values_mut() {
+            runs.sort();
+            for pair in runs.windows(2) {
+                assert!(pair[0].1 <= pair[1].0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_run_returns_isolate() {
+        crate::initialize(1, Default::default());
+        let pool = IsolatePool::new(1, 0);
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(10),
+            pool.run(|_| std::thread::sleep(Duration::from_millis(50))),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        // The isolate is returned once the cancelled job finishes
+        assert_eq!(pool.run(|_| 1).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stopped_workers_are_replaced() {
+        crate::initialize(1, Default::default());
+        let pool = IsolatePool::new(1, 0);
+
+        // Each panic stops the worker's thread, and a new worker takes its place
+        for _ in 0..3 {
+            assert!(matches!(
+                pool.run(|_| panic!("worker stopped")).await,
+                Err(GatewayError::IsolateWorkerStopped)
+            ));
+            assert_eq!(pool.run(|_| 1).await.unwrap(), 1);
+        }
+
+        // The new worker has a usable isolate
+        let result = pool
+            .run(|isolate| {
+                let scope = &mut v8::HandleScope::new(isolate);
+                let context = v8::Context::new(scope);
+                let scope = &mut v8::ContextScope::new(scope, context);
+                let code = v8::String::new(scope, "1 + 2").unwrap();
+                let script = v8::Script::compile(scope, code, None).unwrap();
+                script.run(scope).unwrap().integer_value(scope)
+            })
+            .await
+            .unwrap();
+        assert_eq!(result, Some(3));
+    }
+}
@@ -1,13 +1,25 @@
 #![warn(clippy::unwrap_used, clippy::expect_used)]
 
-use indexer::auth_user::AuthUser;
+mod isolate_pool;
+mod parse_cache;
+
+use indexer::{auth_user::AuthUser, references::RecordKey};
+use isolate_pool::{IsolatePool, MemoryLimit, V8_STACK_SIZE};
+use parse_cache::{ParseCache, DEFAULT_PARSE_CACHE_SIZE};
 use schema::{self, publickey::PublicKey};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Once};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Once},
+    time::Duration,
+};
 use tracing::debug;
 
 pub type Result<T> = std::result::Result<T, GatewayError>;
 
+/// Message of the RangeError thrown by V8 when a function exceeds the stack size
+const STACK_OVERFLOW_MESSAGE: &str = "Maximum call stack size exceeded";
+
 #[derive(Debug, thiserror::Error)]
 pub enum GatewayError {
     #[error("gateway user error")]
@@ -31,6 +43,9 @@ pub enum GatewayError {
     #[error("invalid output args")]
     InvalidOutputArgs,
 
+    #[error("gateway isolate worker stopped")]
+    IsolateWorkerStopped,
+
     #[error("serde_json error")]
     SerdeJsonError(#[from] serde_json::Error),
 }
@@ -62,6 +77,15 @@ pub enum GatewayUserError {
     #[error("function timed out")]
     FunctionTimedOut,
 
+    #[error("function call limit of {limit} exceeded")]
+    CallLimitExceeded { limit: u32 },
+
+    #[error("function memory limit exceeded")]
+    MemoryLimitExceeded,
+
+    #[error("function exceeded the maximum call stack size")]
+    StackOverflow,
+
     #[error("you do not have permission to call this function")]
     UnauthorizedCall,
 
@@ -69,12 +93,23 @@ pub enum GatewayUserError {
     JavaScriptException { message: String },
 
     #[error("collection function error: {message}")]
-    CollectionFunctionError { message: String },
+    CollectionFunctionError {
+        /// Optional code passed to `error(code, message)`
+        code: Option<String>,
+        message: String,
+    },
 
     #[error("constructor must assign id")]
     ConstructorMustAssignId,
 }
 
+/// Error thrown by a collection function using `error()`
+#[derive(Debug, Deserialize)]
+struct CollectionFunctionErrorData {
+    code: Option<String>,
+    message: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FunctionOutput {
     pub args: Vec<serde_json::Value>,
@@ -83,25 +118,71 @@ pub struct FunctionOutput {
     pub self_destruct: bool,
 }
 
+/// Records a function can read with `$$__getRecord(collectionId, id)`, keyed by
+/// collection id and record id. Functions run synchronously, so any records they may
+/// read must be loaded (and checked for read access) before the call.
+pub type ReadableRecords = HashMap<RecordKey, serde_json::Value>;
+
+/// Whether the collection code can read records with `$$__getRecord`, records only need
+/// to be loaded for calls to code that does
+pub fn reads_records(collection_code: &str) -> bool {
+    collection_code.contains("$$__getRecord")
+}
+
+/// Limits applied to every function call
+#[derive(Debug, Clone, Copy)]
+pub struct CallOptions {
+    /// Maximum number of collection methods a call can invoke, including the called
+    /// method, to prevent infinite recursion
+    pub call_limit: u32,
+    /// Time after which the call is terminated, `None` if calls are never terminated
+    pub timeout: Option<Duration>,
+    /// Maximum heap size (in bytes) of each isolate, 0 uses the V8 default. The heap size
+    /// is set when the gateway is initialized, so a call can only choose whether it's
+    /// enforced (see `Gateway::call_with_options`).
+    pub memory_limit: usize,
+}
+
+impl Default for CallOptions {
+    fn default() -> Self {
+        Self {
+            call_limit: 100,
+            timeout: Some(Duration::from_secs(5)),
+            memory_limit: 0,
+        }
+    }
+}
+
 pub struct Gateway {
-    // This is so the consumer of this library can't create a Gateway without calling initialize
-    _x: (),
+    // Private, so the consumer of this library can't create a Gateway without calling initialize
+    pool: IsolatePool,
+    options: CallOptions,
+    parse_cache: Arc<ParseCache>,
 }
 
 static INIT: Once = Once::new();
 
-pub fn initialize() -> Gateway {
+/// Initialize V8 and create a gateway that runs functions on `pool_size` isolates
+pub fn initialize(pool_size: usize, options: CallOptions) -> Gateway {
     INIT.call_once(|| {
+        // Flags must be set before V8 is initialized. rusty_v8's CreateParams doesn't
+        // expose the stack limit, so it's set for all isolates.
+        v8::V8::set_flags_from_string(&format!("--stack-size={}", V8_STACK_SIZE / 1024));
+
         let platform = v8::new_default_platform(0, false).make_shared();
         v8::V8::initialize_platform(platform);
         v8::V8::initialize();
     });
 
-    Gateway { _x: () }
+    Gateway {
+        pool: IsolatePool::new(pool_size, options.memory_limit),
+        options,
+        parse_cache: Arc::new(ParseCache::new(DEFAULT_PARSE_CACHE_SIZE)),
+    }
 }
 
 impl Gateway {
-    #[tracing::instrument(skip(self))]
+    /// Call a function with the options the gateway was initialized with
     pub async fn call<'a>(
         &self,
         collection_id: &str,
@@ -109,10 +190,65 @@ impl Gateway {
         method: &str,
         instance: &serde_json::Value,
         args: &[serde_json::Value],
+        records: &ReadableRecords,
         auth: Option<&AuthUser>,
     ) -> Result<FunctionOutput> {
-        // Run the function
-        let output = self.run(collection_id, js_code, method, instance, args, auth)?;
+        self.call_with_options(
+            collection_id,
+            js_code,
+            method,
+            instance,
+            args,
+            records,
+            auth,
+            &self.options,
+        )
+        .await
+    }
+
+    /// Call a function with different limits to the gateway's options. A `memory_limit`
+    /// of 0 disables the gateway's memory limit for the call, any other value enforces it.
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip(self))]
+    pub async fn call_with_options<'a>(
+        &self,
+        collection_id: &str,
+        js_code: &str,
+        method: &str,
+        instance: &serde_json::Value,
+        args: &[serde_json::Value],
+        records: &ReadableRecords,
+        auth: Option<&AuthUser>,
+        options: &CallOptions,
+    ) -> Result<FunctionOutput> {
+        // Run the function on an isolate from the pool, each call gets a fresh context
+        let output = {
+            let collection_id = collection_id.to_string();
+            let js_code = js_code.to_string();
+            let method = method.to_string();
+            let instance = instance.clone();
+            let args = args.to_vec();
+            let records = records.clone();
+            let auth = auth.cloned();
+            let options = *options;
+            let parse_cache = Arc::clone(&self.parse_cache);
+            self.pool
+                .run(move |isolate| {
+                    Self::run(
+                        isolate,
+                        &options,
+                        parse_cache,
+                        &collection_id,
+                        &js_code,
+                        &method,
+                        &instance,
+                        &args,
+                        &records,
+                        auth.as_ref(),
+                    )
+                })
+                .await??
+        };
 
         // Log the function call
         debug!(
@@ -142,30 +278,50 @@ impl Gateway {
     }
 
     fn run(
-        &self,
+        isolate: &mut v8::Isolate,
+        options: &CallOptions,
+        parse_cache: Arc<ParseCache>,
         collection_id: &str,
         collection_code: &str,
         method: &str,
         instance: &serde_json::Value,
         args: &[serde_json::Value],
+        records: &ReadableRecords,
         auth: Option<&AuthUser>,
     ) -> Result<FunctionOutput> {
-        let mut isolate = v8::Isolate::new(Default::default());
         let terminate_handle = isolate.thread_safe_handle();
+        let memory_limit = isolate.get_slot::<MemoryLimit>().cloned();
+        if let Some(memory_limit) = &memory_limit {
+            memory_limit.enforced.store(
+                options.memory_limit > 0,
+                std::sync::atomic::Ordering::SeqCst,
+            );
+        }
 
-        // If the script takes more than 5 seconds to run, terminate it.
+        // If the script takes longer than the timeout to run, terminate it.
         let terminated = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
         let (finished_tx, finished_rx) = std::sync::mpsc::channel::<()>();
         let terminated_clone = terminated.clone();
+        let timeout = options.timeout;
         let script_termination = std::thread::spawn(move || {
-            let timeout = std::time::Duration::from_secs(5);
-            if finished_rx.recv_timeout(timeout).is_err() {
+            let timed_out = match timeout {
+                Some(timeout) => finished_rx.recv_timeout(timeout).is_err(),
+                None => {
+                    let _ = finished_rx.recv();
+                    false
+                }
+            };
+            if timed_out {
                 terminated_clone.store(true, std::sync::atomic::Ordering::SeqCst);
                 terminate_handle.terminate_execution();
             }
         });
 
-        let mut scope = v8::HandleScope::new(&mut isolate);
+        // Host function callbacks can't capture state, so the parse cache is passed to
+        // them in the isolate's slot
+        isolate.set_slot(parse_cache);
+
+        let mut scope = v8::HandleScope::new(isolate);
 
         let global = v8::ObjectTemplate::new(&mut scope);
 
@@ -202,23 +358,23 @@ impl Gateway {
                             parts.join("/")
                         };
 
-                        let mut program = None;
-                        let (_, stable_ast) = match polylang::parse(&code, &namespace, &mut program)
-                        {
-                            Ok(x) => x,
-                            Err(e) => {
-                                #[allow(clippy::unwrap_used)] // we can't recover from this
-                                let error_msg = v8::String::new(scope, &e.message).unwrap();
-                                let exception = v8::Exception::error(scope, error_msg);
-                                scope.throw_exception(exception);
-                                return;
-                            }
+                        let parse = || {
+                            let mut program = None;
+                            let (_, stable_ast) = polylang::parse(&code, &namespace, &mut program)
+                                .map_err(|e| e.message.to_string())?;
+                            serde_json::to_string(&stable_ast).map_err(|e| format!("{e:?}"))
+                        };
+
+                        let parse_cache = scope.get_slot::<Arc<ParseCache>>().cloned();
+                        let json = match parse_cache {
+                            Some(parse_cache) => parse_cache.get_or_parse(&code, &namespace, parse),
+                            None => parse().map(Into::into),
                         };
-                        let json = match serde_json::to_string(&stable_ast) {
+                        let json = match json {
                             Ok(json) => json,
-                            Err(e) => {
+                            Err(message) => {
                                 #[allow(clippy::unwrap_used)] // we can't recover from this
-                                let error_msg = v8::String::new(scope, &format!("{e:?}")).unwrap();
+                                let error_msg = v8::String::new(scope, &message).unwrap();
                                 let exception = v8::Exception::error(scope, error_msg);
                                 scope.throw_exception(exception);
                                 return;
@@ -318,6 +474,23 @@ impl Gateway {
                 .into(),
         );
 
+        // JSON object keys must be strings, so records are keyed by the JSON encoded
+        // [collectionId, id] pair
+        let records = records
+            .iter()
+            .map(|((collection_id, id), record)| {
+                Ok::<_, GatewayError>((serde_json::to_string(&[collection_id, id])?, record))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        global.set(
+            v8::String::new(&mut scope, "recordsJSON")
+                .ok_or(GatewayError::FailedToCreateV8String)?
+                .into(),
+            v8::String::new(&mut scope, &serde_json::to_string(&records)?)
+                .ok_or(GatewayError::FailedToCreateV8String)?
+                .into(),
+        );
+
         let context = v8::Context::new_from_template(&mut scope, global);
         let mut scope = v8::ContextScope::new(&mut scope, context);
 
@@ -329,8 +502,8 @@ impl Gateway {
                     if (typeof obj[key] === "function") {
                         const originalFn = obj[key];
                         obj[key] = function replaced(...args) {
-                            if (calls >= 100) {
-                                throw new Error("call limit exceeded");
+                            if (calls >= $CALL_LIMIT) {
+                                throw new Error("$$__CALL_LIMIT_EXCEEDED");
                             }
 
                             calls++;
@@ -384,13 +557,31 @@ impl Gateway {
                 return obj;
             }
 
+            // Returns a copy of a readable record (or null if the record can't be read),
+            // so changes to the returned record are not saved
+            const $$__records = JSON.parse(recordsJSON);
+            function $$__getRecord(collectionId, id) {
+                const record = $$__records[JSON.stringify([collectionId, id])];
+                if (record === undefined) return null;
+                return internPublicKeys(JSON.parse(JSON.stringify(record)));
+            }
+
             const $$__instance = JSON.parse(instanceJSON);
             $FUNCTION_CODE
             limitMethods($$__instance);
             internPublicKeys($$__instance);
-            function error(str) {{
-                    throw new Error("$$__USER_ERROR:" + str);
-            }}
+            // Can be called as error(message) or error(code, message)
+            function error(code, message) {
+                if (message === undefined) {
+                    message = code;
+                    code = null;
+                }
+
+                throw new Error("$$__USER_ERROR:" + JSON.stringify({
+                    code: code === null ? null : String(code),
+                    message: String(message),
+                }));
+            }
             ctx = JSON.parse(authJSON);
             internPublicKeys(ctx);
             $auth = ctx;
@@ -414,7 +605,8 @@ impl Gateway {
                 instance,
                 selfdestruct: $$__selfdestruct,
             });
-        "#.replace("$FUNCTION_CODE", collection_code)
+        "#.replace("$CALL_LIMIT", &options.call_limit.to_string())
+            .replace("$FUNCTION_CODE", collection_code)
             .replace("$FUNCTION_NAME", method)
             .replace("$FUNCTION_ARGS", &args.iter().enumerate().map(|(i, _)| format!("args[{i}]")).collect::<Vec<_>>().join(", "));
 
@@ -434,6 +626,13 @@ impl Gateway {
             return Err(GatewayUserError::FunctionTimedOut.into());
         }
 
+        if memory_limit.is_some_and(|limit| {
+            limit.enforced.load(std::sync::atomic::Ordering::SeqCst)
+                && limit.exceeded.load(std::sync::atomic::Ordering::SeqCst)
+        }) {
+            return Err(GatewayUserError::MemoryLimitExceeded.into());
+        }
+
         match (result, try_catch.exception()) {
             (_, Some(exception)) => {
                 let msg = (|| {
@@ -455,15 +654,44 @@ impl Gateway {
                     .ok_or(GatewayError::FailedToCreateV8String)?
                     .to_rust_string_lossy(&mut try_catch);
 
-                let s = exception_string.replace("$$__USER_ERROR:", "");
-                if exception_string == s {
-                    Err(GatewayUserError::JavaScriptException {
-                        message: exception_string,
+                if exception_string == "$$__CALL_LIMIT_EXCEEDED" {
+                    return Err(GatewayUserError::CallLimitExceeded {
+                        limit: options.call_limit,
+                    }
+                    .into());
+                }
+
+                if exception_string == STACK_OVERFLOW_MESSAGE && exception.is_native_error() {
+                    // Check the error's name too, so other errors with the same message
+                    // (e.g. thrown by the function) aren't reported as a stack overflow
+                    let is_range_error = (|| {
+                        let name_str = v8::String::new(&mut try_catch, "name")?;
+                        let name = exception
+                            .to_object(&mut try_catch)?
+                            .get(&mut try_catch, name_str.into())?;
+                        Some(name.to_rust_string_lossy(&mut try_catch) == "RangeError")
+                    })()
+                    .unwrap_or(false);
+
+                    if is_range_error {
+                        return Err(GatewayUserError::StackOverflow.into());
                     }
-                    .into())
-                } else {
-                    Err(GatewayUserError::CollectionFunctionError { message: s }.into())
                 }
+
+                let Some(data) = exception_string.strip_prefix("$$__USER_ERROR:") else {
+                    return Err(GatewayUserError::JavaScriptException {
+                        message: exception_string,
+                    }
+                    .into());
+                };
+
+                let CollectionFunctionErrorData { code, message } = serde_json::from_str(data)
+                    .unwrap_or_else(|_| CollectionFunctionErrorData {
+                        code: None,
+                        message: data.to_string(),
+                    });
+
+                Err(GatewayUserError::CollectionFunctionError { code, message }.into())
             }
             (Some(result), _) => {
                 let result = result.to_rust_string_lossy(&mut try_catch);
@@ -505,7 +733,7 @@ mod tests {
         "#;
         let js_code = get_code("User", user_col_code);
 
-        let gateway = initialize();
+        let gateway = initialize(1, CallOptions::default());
         let output = gateway
             .call(
                 "ns/User",
@@ -513,6 +741,7 @@ mod tests {
                 "constructor",
                 &json!({}),
                 &[json!("new name")],
+                &ReadableRecords::new(),
                 None,
             )
             .await
@@ -538,7 +767,7 @@ mod tests {
         "#;
         let js_code = get_code("User", user_col_code);
 
-        let gateway = initialize();
+        let gateway = initialize(1, CallOptions::default());
         let output = gateway
             .call(
                 "ns/User",
@@ -549,6 +778,7 @@ mod tests {
                     "name": "old name",
                 }),
                 &[json!("new name")],
+                &ReadableRecords::new(),
                 None,
             )
             .await
@@ -579,7 +809,7 @@ mod tests {
         "#;
         let js_code = get_code("Account", user_col_code);
 
-        let gateway = initialize();
+        let gateway = initialize(1, CallOptions::default());
         let output = gateway
             .call(
                 "ns/Account",
@@ -587,6 +817,7 @@ mod tests {
                 "constructor",
                 &json!({}),
                 &[json!("1"), json!("new name")],
+                &ReadableRecords::new(),
                 None,
             )
             .await
@@ -600,6 +831,316 @@ mod tests {
         assert!(!output.self_destruct, "selfdestruct() was called");
     }
 
+    #[tokio::test]
+    async fn test_get_record() {
+        let js_code = r#"
+            const instance = $$__instance;
+            instance.copyManagerName = function () {
+                const manager = $$__getRecord("ns/User", this.manager.id);
+                this.managerName = manager.name;
+                // Changes to the returned record are not saved
+                manager.name = "changed";
+                this.missing = $$__getRecord("ns/User", "missing");
+            };
+        "#;
+
+        assert!(reads_records(js_code));
+        assert!(!reads_records("const instance = $$__instance;"));
+
+        let records = ReadableRecords::from([(
+            ("ns/User".to_string(), "2".to_string()),
+            json!({ "id": "2", "name": "Manager" }),
+        )]);
+
+        let gateway = initialize(1, CallOptions::default());
+        let output = gateway
+            .call(
+                "ns/User",
+                js_code,
+                "copyManagerName",
+                &json!({ "id": "1", "manager": { "id": "2" } }),
+                &[],
+                &records,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            output.instance,
+            json!({
+                "id": "1",
+                "manager": { "id": "2" },
+                "managerName": "Manager",
+                "missing": null,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_limit() {
+        let js_code = r#"
+            const instance = $$__instance;
+            instance.recurse = function (n) {
+                if (n > 1) this.recurse(n - 1);
+            };
+        "#;
+        let call = |gateway: Gateway| async move {
+            gateway
+                .call(
+                    "ns/User",
+                    js_code,
+                    "recurse",
+                    &json!({ "id": "1" }),
+                    &[json!(10)],
+                    &ReadableRecords::new(),
+                    None,
+                )
+                .await
+        };
+
+        let low_limit = CallOptions {
+            call_limit: 5,
+            ..Default::default()
+        };
+        let err = call(initialize(1, low_limit)).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GatewayError::UserError(GatewayUserError::CallLimitExceeded { limit: 5 })
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        let high_limit = CallOptions {
+            call_limit: 20,
+            ..Default::default()
+        };
+        assert!(call(initialize(1, high_limit)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let js_code = r#"
+            const instance = $$__instance;
+            instance.wait = function (ms) {
+                const end = Date.now() + ms;
+                while (Date.now() < end) {}
+            };
+        "#;
+        let call = |gateway: Gateway| async move {
+            gateway
+                .call(
+                    "ns/User",
+                    js_code,
+                    "wait",
+                    &json!({ "id": "1" }),
+                    &[json!(200)],
+                    &ReadableRecords::new(),
+                    None,
+                )
+                .await
+        };
+
+        let short_timeout = CallOptions {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let err = call(initialize(1, short_timeout)).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GatewayError::UserError(GatewayUserError::FunctionTimedOut)
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        let no_timeout = CallOptions {
+            timeout: None,
+            ..Default::default()
+        };
+        assert!(call(initialize(1, no_timeout)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit() {
+        let js_code = r#"
+            const instance = $$__instance;
+            instance.allocate = function () {
+                const items = [];
+                while (true) {
+                    items.push("x".repeat(1024) + items.length);
+                }
+            };
+            instance.allocateMany = function (n) {
+                const items = [];
+                for (let i = 0; i < n; i++) {
+                    items.push("x".repeat(1024) + i);
+                }
+                this.count = items.length;
+            };
+            instance.setName = function (name) {
+                this.name = name;
+            };
+        "#;
+
+        let gateway = initialize(
+            1,
+            CallOptions {
+                memory_limit: 32 * 1024 * 1024,
+                timeout: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+        );
+        let call = |method: &'static str, args: Vec<serde_json::Value>| {
+            let gateway = &gateway;
+            async move {
+                gateway
+                    .call(
+                        "ns/User",
+                        js_code,
+                        method,
+                        &json!({ "id": "1" }),
+                        &args,
+                        &ReadableRecords::new(),
+                        None,
+                    )
+                    .await
+            }
+        };
+
+        let err = call("allocate", vec![]).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GatewayError::UserError(GatewayUserError::MemoryLimitExceeded)
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        // The isolate can run other calls once the limit is restored
+        let output = call("setName", vec![json!("John")]).await.unwrap();
+        assert_eq!(output.instance, json!({ "id": "1", "name": "John" }));
+
+        // Calls that don't enforce the limit can use more memory
+        let output = gateway
+            .call_with_options(
+                "ns/User",
+                js_code,
+                "allocateMany",
+                &json!({ "id": "1" }),
+                &[json!(64 * 1024)],
+                &ReadableRecords::new(),
+                None,
+                &CallOptions {
+                    memory_limit: 0,
+                    timeout: Some(Duration::from_secs(30)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.instance, json!({ "id": "1", "count": 64 * 1024 }));
+
+        // The limit is enforced again for the next call
+        let err = call("allocate", vec![]).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GatewayError::UserError(GatewayUserError::MemoryLimitExceeded)
+            ),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stack_overflow() {
+        // Helper functions aren't methods of the instance, so they aren't call limited
+        let js_code = r#"
+            const instance = $$__instance;
+            function recurse(n) {
+                return recurse(n + 1) + 1;
+            }
+            instance.overflow = function () {
+                recurse(0);
+            };
+            instance.fakeOverflow = function () {
+                throw new Error("Maximum call stack size exceeded");
+            };
+        "#;
+
+        let gateway = initialize(1, CallOptions::default());
+        let call = |method: &'static str| {
+            let gateway = &gateway;
+            async move {
+                gateway
+                    .call(
+                        "ns/User",
+                        js_code,
+                        method,
+                        &json!({ "id": "1" }),
+                        &[],
+                        &ReadableRecords::new(),
+                        None,
+                    )
+                    .await
+            }
+        };
+
+        let err = call("overflow").await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GatewayError::UserError(GatewayUserError::StackOverflow)
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        // Only V8's RangeError is a stack overflow
+        let err = call("fakeOverflow").await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GatewayError::UserError(GatewayUserError::JavaScriptException { .. })
+            ),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_is_cached() {
+        let js_code = schema::COLLECTION_SCHEMA.generate_js();
+        let code = r#"
+            @public
+            collection User {
+                id: string;
+            }
+        "#;
+
+        let gateway = initialize(1, CallOptions::default());
+        let mut asts = vec![];
+        for id in ["ns/A", "ns/B"] {
+            let output = gateway
+                .call(
+                    "Collection",
+                    &js_code,
+                    "constructor",
+                    &json!({}),
+                    &[json!(id), json!(code)],
+                    &ReadableRecords::new(),
+                    None,
+                )
+                .await
+                .unwrap();
+            asts.push(output.instance["ast"].clone());
+        }
+
+        // Both collections have the same code and namespace, so the code is parsed once
+        assert_eq!(gateway.parse_cache.parses(), 1);
+        assert!(asts[0].is_string());
+        assert_eq!(asts[0], asts[1]);
+    }
+
     #[tokio::test]
     async fn test_self_destruct() {
         let user_col_code = r#"
@@ -614,9 +1155,17 @@ mod tests {
         "#;
         let js_code = get_code("User", user_col_code);
 
-        let gateway = initialize();
+        let gateway = initialize(1, CallOptions::default());
         let output = gateway
-            .call("ns/User", &js_code, "del", &json!({}), &[], None)
+            .call(
+                "ns/User",
+                &js_code,
+                "del",
+                &json!({}),
+                &[],
+                &ReadableRecords::new(),
+                None,
+            )
             .await
             .unwrap();
 
@@ -624,4 +1173,72 @@ mod tests {
         assert_eq!(output.args, Vec::<serde_json::Value>::new());
         assert!(output.self_destruct, "selfdestruct() was not called");
     }
+
+    #[tokio::test]
+    async fn test_collection_function_error() {
+        let user_col_code = r#"
+            @public
+            collection User {
+                id: string;
+
+                fail () {
+                    error("something went wrong");
+                }
+
+                failWithCode () {
+                    error("not-found", "no such thing");
+                }
+            }
+        "#;
+        let js_code = get_code("User", user_col_code);
+
+        let gateway = initialize(1, CallOptions::default());
+        let err = gateway
+            .call(
+                "ns/User",
+                &js_code,
+                "fail",
+                &json!({}),
+                &[],
+                &ReadableRecords::new(),
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(
+                &err,
+                GatewayError::UserError(GatewayUserError::CollectionFunctionError {
+                    code: None,
+                    message,
+                }) if message == "something went wrong"
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        let err = gateway
+            .call(
+                "ns/User",
+                &js_code,
+                "failWithCode",
+                &json!({}),
+                &[],
+                &ReadableRecords::new(),
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(
+                &err,
+                GatewayError::UserError(GatewayUserError::CollectionFunctionError {
+                    code: Some(code),
+                    message,
+                }) if code == "not-found" && message == "no such thing"
+            ),
+            "unexpected error: {err:?}"
+        );
+    }
 }
@@ -0,0 +1,103 @@
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+/// Default number of parsed collection ASTs to cache
+pub(crate) const DEFAULT_PARSE_CACHE_SIZE: usize = 1000;
+
+type ParseKey = (String, String);
+
+/// Bounded least-recently-used cache of the stable AST JSON produced by the `parse` host
+/// function, keyed by the collection code and namespace. Shared by all isolates in the pool.
+pub(crate) struct ParseCache {
+    state: Mutex<ParseCacheState>,
+}
+
+struct ParseCacheState {
+    /// Cached ASTs, or None if the cache is disabled with a capacity of 0
+    entries: Option<LruCache<ParseKey, Arc<str>>>,
+    /// Number of times the code was parsed, because it was not cached
+    #[cfg(test)]
+    parses: usize,
+}
+
+impl ParseCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(ParseCacheState {
+                entries: NonZeroUsize::new(capacity).map(LruCache::new),
+                #[cfg(test)]
+                parses: 0,
+            }),
+        }
+    }
+
+    /// Get the cached AST JSON for the code, or parse it with `parse`. Parse errors are
+    /// not cached.
+    pub(crate) fn get_or_parse(
+        &self,
+        code: &str,
+        namespace: &str,
+        parse: impl FnOnce() -> Result<String, String>,
+    ) -> Result<Arc<str>, String> {
+        let key = (code.to_string(), namespace.to_string());
+
+        {
+            let mut state = self.lock();
+            if let Some(ast) = state.entries.as_mut().and_then(|entries| entries.get(&key)) {
+                return Ok(Arc::clone(ast));
+            }
+
+            #[cfg(test)]
+            {
+                state.parses += 1;
+            }
+        }
+
+        // Parse without holding the lock, so other isolates aren't blocked
+        let ast: Arc<str> = parse()?.into();
+
+        if let Some(entries) = self.lock().entries.as_mut() {
+            entries.put(key, Arc::clone(&ast));
+        }
+
+        Ok(ast)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn parses(&self) -> usize {
+        self.lock().parses
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ParseCacheState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = ParseCache::new(2);
+        let parse = |code: &str| cache.get_or_parse(code, "ns", || Ok(format!("ast:{code}")));
+
+        assert_eq!(&*parse("a").unwrap(), "ast:a");
+        parse("b").unwrap();
+        // Touch "a", so "b" is evicted
+        parse("a").unwrap();
+        parse("c").unwrap();
+        assert_eq!(cache.parses(), 3);
+
+        parse("a").unwrap();
+        assert_eq!(cache.parses(), 3);
+        parse("b").unwrap();
+        assert_eq!(cache.parses(), 4);
+
+        // Errors are not cached
+        assert!(cache.get_or_parse("d", "ns", || Err("bad".into())).is_err());
+        assert!(cache.get_or_parse("d", "ns", || Err("bad".into())).is_err());
+        assert_eq!(cache.parses(), 6);
+    }
+}
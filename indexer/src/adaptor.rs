@@ -1,7 +1,9 @@
-use crate::{where_query::WhereQuery, IndexerChange};
+use crate::{
+    cursor::Cursor, diff::RecordDiff, references::RecordKey, where_query::WhereQuery, IndexerChange,
+};
 use schema::{self, record::RecordRoot, Schema};
 use serde::{Deserialize, Serialize};
-use std::{pin::Pin, time::SystemTime};
+use std::{collections::HashMap, pin::Pin, time::SystemTime};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -15,11 +17,26 @@ pub enum Error {
     #[error("store error: {0}")]
     Store(#[from] Box<dyn std::error::Error + Send + Sync>),
 
+    /// A store error that may succeed if the operation is retried (e.g. the store
+    /// was busy or the operation timed out)
+    #[error("transient store error: {0}")]
+    TransientStore(Box<dyn std::error::Error + Send + Sync>),
+
     #[error("schema error: {0}")]
     Schema(#[from] schema::Error),
 
     #[error("Collection collection record not found for collection {id:?}")]
     CollectionCollectionRecordNotFound { id: String },
+
+    #[error("record history is not available at height {height}")]
+    HistoryUnavailable { height: usize },
+}
+
+impl Error {
+    /// Whether the operation may succeed if it is retried
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::TransientStore(_))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,14 +45,187 @@ pub struct SnapshotValue {
     pub value: Box<[u8]>,
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CollectionStats {
+    pub record_count: u64,
+    pub last_updated: Option<SystemTime>,
+}
+
+/// A change recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub height: usize,
+    pub collection_id: String,
+    pub record_id: String,
+    pub op: AuditOp,
+    pub timestamp: SystemTime,
+    /// Fields changed compared to the previous value of the record
+    pub diff: RecordDiff,
+    /// Value used to check who can read the change, i.e. the record after it was set, or
+    /// before it was deleted. `None` for entries stored before the value was recorded.
+    pub record: Option<RecordRoot>,
+}
+
+/// Encoded audit entries start with this marker and the version of the encoding.
+/// Entries written before the encoding was versioned start with their height instead,
+/// which is never `u64::MAX`.
+const AUDIT_ENTRY_MARKER: u64 = u64::MAX;
+const AUDIT_ENTRY_VERSION: u8 = 2;
+
+/// An audit entry as it was encoded in version 1, without the record
+#[derive(Deserialize)]
+struct AuditEntryV1 {
+    height: usize,
+    collection_id: String,
+    record_id: String,
+    op: AuditOp,
+    timestamp: SystemTime,
+    diff: RecordDiff,
+}
+
+impl From<AuditEntryV1> for AuditEntry {
+    fn from(entry: AuditEntryV1) -> Self {
+        Self {
+            height: entry.height,
+            collection_id: entry.collection_id,
+            record_id: entry.record_id,
+            op: entry.op,
+            timestamp: entry.timestamp,
+            diff: entry.diff,
+            record: None,
+        }
+    }
+}
+
+/// An audit entry as it was encoded before it was versioned, without a diff
+#[derive(Deserialize)]
+struct AuditEntryV0 {
+    height: usize,
+    collection_id: String,
+    record_id: String,
+    op: AuditOp,
+    timestamp: SystemTime,
+}
+
+impl From<AuditEntryV0> for AuditEntry {
+    fn from(entry: AuditEntryV0) -> Self {
+        Self {
+            height: entry.height,
+            collection_id: entry.collection_id,
+            record_id: entry.record_id,
+            op: entry.op,
+            timestamp: entry.timestamp,
+            diff: RecordDiff::default(),
+            record: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOp {
+    Set,
+    Delete,
+}
+
+impl AuditEntry {
+    /// Encode the entry for storage, see `decode`
+    pub fn encode(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(&(AUDIT_ENTRY_MARKER, AUDIT_ENTRY_VERSION, self))
+    }
+
+    /// Decode an entry encoded with `encode`, or by an earlier version of it. Entries
+    /// stored before the encoding was versioned have an empty diff, and entries stored
+    /// before version 2 have no record.
+    pub fn decode(bytes: &[u8]) -> bincode::Result<Self> {
+        match bincode::deserialize::<(u64, u8)>(bytes)? {
+            (AUDIT_ENTRY_MARKER, AUDIT_ENTRY_VERSION) => {
+                let (_, _, entry): (u64, u8, AuditEntry) = bincode::deserialize(bytes)?;
+                Ok(entry)
+            }
+            (AUDIT_ENTRY_MARKER, 1) => {
+                let (_, _, entry): (u64, u8, AuditEntryV1) = bincode::deserialize(bytes)?;
+                Ok(entry.into())
+            }
+            (AUDIT_ENTRY_MARKER, version) => Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "unsupported audit entry version {version}"
+            )))),
+            _ => Ok(bincode::deserialize::<AuditEntryV0>(bytes)?.into()),
+        }
+    }
+
+    /// Create an entry for the change, `previous` is the value of the record before
+    /// the change was applied
+    pub fn from_change(
+        height: usize,
+        change: &IndexerChange,
+        previous: Option<&RecordRoot>,
+        timestamp: SystemTime,
+    ) -> Self {
+        let (collection_id, record_id, op, diff, record) = match change {
+            IndexerChange::Set {
+                collection_id,
+                record_id,
+                record,
+            } => (
+                collection_id,
+                record_id,
+                AuditOp::Set,
+                RecordDiff::between(previous, Some(record)),
+                Some(record),
+            ),
+            IndexerChange::Delete {
+                collection_id,
+                record_id,
+            } => (
+                collection_id,
+                record_id,
+                AuditOp::Delete,
+                RecordDiff::between(previous, None),
+                previous,
+            ),
+        };
+
+        Self {
+            height,
+            collection_id: collection_id.clone(),
+            record_id: record_id.clone(),
+            op,
+            timestamp,
+            diff,
+            record: record.cloned(),
+        }
+    }
+}
+
 /// The Store trait
 #[async_trait::async_trait]
 pub trait IndexerAdaptor: Send + Sync {
     // TODO: add a height in here, so we can track where we are up to
     async fn commit(&self, height: usize, changes: Vec<IndexerChange>) -> Result<()>;
 
+    /// Discard writes that have not been committed yet, e.g. the system keys written for
+    /// a height whose commit failed, so they aren't committed with the next height.
+    /// Adaptors that write immediately have nothing to discard.
+    async fn discard_uncommitted(&self) -> Result<()> {
+        Ok(())
+    }
+
     async fn get(&self, collection_id: &str, record_id: &str) -> Result<Option<RecordRoot>>;
 
+    /// Get the value of a record as of a committed block height, or `None` if the record
+    /// did not exist at that height. Returns `Error::HistoryUnavailable` if the height is
+    /// outside of the history retained by the adaptor.
+    async fn get_at_height(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+        height: usize,
+    ) -> Result<Option<RecordRoot>>;
+
+    /// List records matching the query, in the order of the index for the query. If a
+    /// `cursor` is given, the list starts from the record after the cursor in the listed
+    /// order (i.e. before the cursor when listed in `reverse`).
     async fn list(
         &self,
         collection_id: &str,
@@ -43,10 +233,24 @@ pub trait IndexerAdaptor: Send + Sync {
         where_query: WhereQuery<'_>,
         order_by: &[IndexField],
         reverse: bool,
+        cursor: Option<&Cursor<'_>>,
     ) -> Result<Pin<Box<dyn futures::Stream<Item = RecordRoot> + '_ + Send>>>;
 
     async fn get_schema(&self, collection_id: &str) -> Result<Option<Schema>>;
 
+    /// Get the schemas of multiple collections, keyed by collection id. Collections that
+    /// do not exist are omitted. Adaptors that can load schemas in a single round trip
+    /// should override this, the default loads each schema individually.
+    async fn get_schema_many(&self, collection_ids: &[&str]) -> Result<HashMap<String, Schema>> {
+        let mut schemas = HashMap::new();
+        for collection_id in collection_ids {
+            if let Some(schema) = self.get_schema(collection_id).await? {
+                schemas.insert(collection_id.to_string(), schema);
+            }
+        }
+        Ok(schemas)
+    }
+
     async fn last_record_update(
         &self,
         collection_id: &str,
@@ -55,10 +259,30 @@ pub trait IndexerAdaptor: Send + Sync {
 
     async fn last_collection_update(&self, collection_id: &str) -> Result<Option<SystemTime>>;
 
+    async fn collection_stats(&self, collection_id: &str) -> Result<CollectionStats>;
+
     async fn set_system_key(&self, key: &str, data: &RecordRoot) -> Result<()>;
 
     async fn get_system_key(&self, key: &str) -> Result<Option<RecordRoot>>;
 
+    async fn delete_system_key(&self, key: &str) -> Result<()>;
+
+    /// Record that the `source` record references the `target` record. Each reference
+    /// is stored separately, so records with many referrers don't need to be rewritten
+    /// for every change.
+    async fn set_reference(&self, target: &RecordKey, source: &RecordKey) -> Result<()>;
+
+    async fn delete_reference(&self, target: &RecordKey, source: &RecordKey) -> Result<()>;
+
+    /// Records that reference the `target` record, as of the last commit
+    async fn list_referrers(&self, target: &RecordKey) -> Result<Vec<RecordKey>>;
+
+    /// Set a key that is local to this node, e.g. for counters that differ between nodes.
+    /// Local keys are written immediately, and are not included in snapshots.
+    async fn set_local_key(&self, key: &str, data: &RecordRoot) -> Result<()>;
+
+    async fn get_local_key(&self, key: &str) -> Result<Option<RecordRoot>>;
+
     async fn snapshot(
         &self,
         chunk_size: usize,
@@ -66,5 +290,107 @@ pub trait IndexerAdaptor: Send + Sync {
 
     async fn restore(&self, chunk: Vec<SnapshotValue>) -> Result<()>;
 
+    /// Find a system key in snapshot data, so a snapshot can be checked before it is
+    /// restored
+    fn snapshot_system_key(
+        &self,
+        values: &[SnapshotValue],
+        key: &str,
+    ) -> Result<Option<RecordRoot>>;
+
     async fn reset(&self) -> Result<()>;
+
+    /// Reclaim space used by deleted or overwritten data
+    async fn compact(&self) -> Result<()>;
+
+    /// Re-create every index entry of a collection from its records and current schema,
+    /// removing any stale entries
+    async fn rebuild_indexes(&self, collection_id: &str) -> Result<()>;
+
+    /// List audit log entries committed at or after `from_height`, in commit order.
+    /// Returns no entries if the adaptor was not created with the audit log enabled.
+    async fn audit_log(
+        &self,
+        collection_id: Option<&str>,
+        from_height: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_entry_roundtrip() {
+        let mut record = RecordRoot::new();
+        record.insert(
+            "name".to_string(),
+            schema::record::RecordValue::String("John".to_string()),
+        );
+        let change = IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: "id1".to_string(),
+            record,
+        };
+        let entry = AuditEntry::from_change(1, &change, None, SystemTime::UNIX_EPOCH);
+
+        let encoded = entry.encode().unwrap();
+        assert_eq!(AuditEntry::decode(&encoded).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_audit_entry_decodes_unversioned_entry() {
+        let timestamp = SystemTime::UNIX_EPOCH;
+        let encoded = bincode::serialize(&(
+            2usize,
+            "ns/Person".to_string(),
+            "id1".to_string(),
+            AuditOp::Delete,
+            timestamp,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            AuditEntry::decode(&encoded).unwrap(),
+            AuditEntry {
+                height: 2,
+                collection_id: "ns/Person".to_string(),
+                record_id: "id1".to_string(),
+                op: AuditOp::Delete,
+                timestamp,
+                diff: RecordDiff::default(),
+                record: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_audit_entry_decodes_version_1_entry() {
+        let timestamp = SystemTime::UNIX_EPOCH;
+        let encoded = bincode::serialize(&(
+            AUDIT_ENTRY_MARKER,
+            1u8,
+            2usize,
+            "ns/Person".to_string(),
+            "id1".to_string(),
+            AuditOp::Delete,
+            timestamp,
+            RecordDiff::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            AuditEntry::decode(&encoded).unwrap(),
+            AuditEntry {
+                height: 2,
+                collection_id: "ns/Person".to_string(),
+                record_id: "id1".to_string(),
+                op: AuditOp::Delete,
+                timestamp,
+                diff: RecordDiff::default(),
+                record: None,
+            }
+        );
+    }
 }
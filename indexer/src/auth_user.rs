@@ -4,14 +4,36 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthUser {
     public_key: PublicKey,
+    /// Other keys presented with the request (e.g. a root key alongside a session key),
+    /// any of which can authorise the request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    additional_public_keys: Vec<PublicKey>,
 }
 
 impl AuthUser {
     pub fn new(public_key: PublicKey) -> Self {
-        Self { public_key }
+        Self {
+            public_key,
+            additional_public_keys: vec![],
+        }
+    }
+
+    pub fn with_additional_public_keys(
+        public_key: PublicKey,
+        additional_public_keys: Vec<PublicKey>,
+    ) -> Self {
+        Self {
+            public_key,
+            additional_public_keys,
+        }
     }
 
     pub fn public_key(&self) -> &PublicKey {
         &self.public_key
     }
+
+    /// All keys presented by the user, starting with the primary key
+    pub fn public_keys(&self) -> impl Iterator<Item = &PublicKey> {
+        std::iter::once(&self.public_key).chain(self.additional_public_keys.iter())
+    }
 }
@@ -2,11 +2,12 @@ use super::where_query::{WhereNode, WhereQuery};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use schema::{
     field_path::FieldPath,
+    index::{IndexDirection, IndexField},
     index_value::{IndexValue, IndexValueError},
     record::RecordRoot,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{cmp::Ordering, collections::HashMap};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -60,12 +61,6 @@ pub struct WrappedCursor<'a> {
     pub values: HashMap<FieldPath, IndexValue<'a>>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum CursorDirection {
-    Before,
-    After,
-}
-
 impl<'a> WrappedCursor<'a> {
     pub fn as_base64(&self) -> Result<String> {
         // serialize the cursor to bytes
@@ -74,16 +69,29 @@ impl<'a> WrappedCursor<'a> {
         Ok(STANDARD.encode(buf))
     }
 
-    pub fn from_record(record: &RecordRoot, query: &WhereQuery) -> Result<Self> {
+    /// Create a cursor for the record, `index_fields` are the fields of the index used
+    /// to list the record, so the cursor can be used to find the record's position
+    /// in the list, even if other records have the same sort values
+    pub fn from_record(
+        record: &RecordRoot,
+        query: &WhereQuery,
+        index_fields: &[IndexField],
+    ) -> Result<Self> {
         let mut values = HashMap::new();
 
-        for (key, node) in query.0.iter() {
-            if let WhereNode::Inequality(_) = node {
-                if let Some(value) = record.get(&key.to_string()) {
-                    values.insert(key.clone(), value.clone().try_into()?);
-                } else {
-                    values.insert(key.clone(), IndexValue::Null);
-                }
+        let inequality_paths = query
+            .0
+            .iter()
+            .filter(|(_, node)| matches!(node, WhereNode::Inequality(_)))
+            .map(|(path, _)| path);
+        let index_paths = index_fields
+            .iter()
+            .map(|field| &field.path)
+            .filter(|path| **path != FieldPath::id());
+
+        for path in inequality_paths.chain(index_paths) {
+            if !values.contains_key(path) {
+                values.insert(path.clone(), index_value(record, path));
             }
         }
 
@@ -96,6 +104,46 @@ impl<'a> WrappedCursor<'a> {
             values,
         })
     }
+
+    /// Compares the position of the record with the position of the cursor, in the
+    /// order of the given index fields. The record id is used as the final tie-break,
+    /// so only the cursor record itself is equal to the cursor.
+    pub fn cmp_record(&self, record: &RecordRoot, index_fields: &[IndexField]) -> Ordering {
+        let id = FieldPath::id();
+        for field in index_fields.iter().filter(|field| field.path != id) {
+            let cursor_value = self.values.get(&field.path).unwrap_or(&IndexValue::Null);
+            let ordering = cmp_index_values(&index_value(record, &field.path), cursor_value);
+            let ordering = match field.direction {
+                IndexDirection::Ascending => ordering,
+                IndexDirection::Descending => ordering.reverse(),
+            };
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        let ordering = cmp_index_values(&index_value(record, &id), &self.record_id);
+        match index_fields.iter().find(|field| field.path == id) {
+            Some(IndexField {
+                direction: IndexDirection::Descending,
+                ..
+            }) => ordering.reverse(),
+            _ => ordering,
+        }
+    }
+}
+
+/// Value of the field used to index the record, missing fields are indexed as null
+pub(crate) fn index_value(record: &RecordRoot, path: &FieldPath) -> IndexValue<'static> {
+    record
+        .get_path(path)
+        .and_then(|value| value.clone().try_into().ok())
+        .unwrap_or(IndexValue::Null)
+}
+
+pub(crate) fn cmp_index_values(a: &IndexValue, b: &IndexValue) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
 }
 
 #[cfg(test)]
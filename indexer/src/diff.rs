@@ -0,0 +1,138 @@
+use crate::schema_version::SCHEMA_VERSION_FIELD;
+use schema::{
+    field_path::FieldPath,
+    record::{RecordRoot, RecordValue},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Paths of the fields that differ between two versions of a record. Nested maps are
+/// compared field by field, any other value (including arrays) is compared as a whole.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordDiff {
+    pub added: Vec<FieldPath>,
+    pub removed: Vec<FieldPath>,
+    pub changed: Vec<FieldPath>,
+}
+
+impl RecordDiff {
+    /// Diff between the previous and new value of a record, `None` if the record
+    /// does not exist (i.e. before it is created or after it is deleted)
+    pub fn between(previous: Option<&RecordRoot>, new: Option<&RecordRoot>) -> Self {
+        let empty = HashMap::new();
+        let mut diff = RecordDiff::default();
+
+        diff_maps(
+            &FieldPath::new(vec![]),
+            previous.map_or(&empty, |r| &r.0),
+            new.map_or(&empty, |r| &r.0),
+            &mut diff,
+        );
+
+        // Records are hash maps, so sort for a stable order. The schema version is not
+        // part of the record's data, so it's never reported as a change.
+        for paths in [&mut diff.added, &mut diff.removed, &mut diff.changed] {
+            paths.retain(|path| path.0 != [SCHEMA_VERSION_FIELD]);
+            paths.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn diff_maps(
+    path: &FieldPath,
+    previous: &HashMap<String, RecordValue>,
+    new: &HashMap<String, RecordValue>,
+    diff: &mut RecordDiff,
+) {
+    for (key, previous_value) in previous {
+        let path = path.append(key.clone());
+        match new.get(key) {
+            Some(new_value) => diff_values(path, previous_value, new_value, diff),
+            None => diff.removed.push(path),
+        }
+    }
+
+    for key in new.keys().filter(|key| !previous.contains_key(*key)) {
+        diff.added.push(path.append(key.clone()));
+    }
+}
+
+fn diff_values(path: FieldPath, previous: &RecordValue, new: &RecordValue, diff: &mut RecordDiff) {
+    match (previous, new) {
+        (RecordValue::Map(previous), RecordValue::Map(new)) => {
+            diff_maps(&path, previous, new, diff)
+        }
+        (previous, new) if previous != new => diff.changed.push(path),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(name: &str, balance: f64, city: &str) -> RecordRoot {
+        let mut address = HashMap::new();
+        address.insert("city".to_string(), RecordValue::String(city.to_string()));
+
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String("1".to_string()));
+        record.insert("name".to_string(), RecordValue::String(name.to_string()));
+        record.insert("balance".to_string(), RecordValue::Number(balance));
+        record.insert("address".to_string(), RecordValue::Map(address));
+        record
+    }
+
+    #[test]
+    fn test_diff_lists_only_changed_field() {
+        let previous = account("John", 10.0, "London");
+
+        let diff = RecordDiff::between(Some(&previous), Some(&account("John", 20.0, "London")));
+        assert_eq!(
+            diff,
+            RecordDiff {
+                changed: vec![FieldPath::from("balance")],
+                ..Default::default()
+            }
+        );
+
+        // Nested fields are diffed by path
+        let diff = RecordDiff::between(Some(&previous), Some(&account("John", 10.0, "Paris")));
+        assert_eq!(diff.changed, vec![FieldPath::from("address.city")]);
+
+        assert!(RecordDiff::between(Some(&previous), Some(&previous)).is_empty());
+    }
+
+    #[test]
+    fn test_diff_added_and_removed_fields() {
+        let previous = account("John", 10.0, "London");
+        let mut new = previous.clone();
+        new.remove("name");
+        new.insert("age".to_string(), RecordValue::Number(30.0));
+
+        let diff = RecordDiff::between(Some(&previous), Some(&new));
+        assert_eq!(
+            diff,
+            RecordDiff {
+                added: vec![FieldPath::from("age")],
+                removed: vec![FieldPath::from("name")],
+                changed: vec![],
+            }
+        );
+
+        // Creating or deleting a record adds or removes all of its fields
+        let created = RecordDiff::between(None, Some(&previous));
+        assert_eq!(
+            created.added,
+            ["address", "balance", "id", "name"].map(FieldPath::from)
+        );
+        let deleted = RecordDiff::between(Some(&previous), None);
+        assert_eq!(deleted.removed, created.added);
+    }
+}
@@ -0,0 +1,116 @@
+use parking_lot::Mutex;
+use schema::{
+    index::{Index, IndexField},
+    record::{RecordRoot, RecordValue},
+};
+use std::collections::HashMap;
+
+/// Number of list queries that used an index of a collection, so operators can find
+/// (and drop) indexes that are never used
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexStats {
+    pub fields: Vec<IndexField>,
+    pub hits: u64,
+}
+
+/// Local key where the index hit counters of a collection are stored
+pub(crate) fn index_stats_key(collection_id: &str) -> String {
+    format!("index_stats/{collection_id}")
+}
+
+/// Identifies an index within its collection, e.g. `name ASC, id ASC`
+pub(crate) fn index_key(index: &Index) -> String {
+    index
+        .fields
+        .iter()
+        .map(|field| field.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Counts the index hits of each collection since the counters were last stored,
+/// keyed by collection id and then by index key
+#[derive(Default)]
+pub(crate) struct IndexHitMeter {
+    pending: Mutex<HashMap<String, HashMap<String, u64>>>,
+}
+
+impl IndexHitMeter {
+    pub(crate) fn record_hit(&self, collection_id: &str, index: &Index) {
+        *self
+            .pending
+            .lock()
+            .entry(collection_id.to_string())
+            .or_default()
+            .entry(index_key(index))
+            .or_default() += 1;
+    }
+
+    /// Hits of a collection's indexes recorded since the counters were last stored
+    pub(crate) fn pending(&self, collection_id: &str) -> HashMap<String, u64> {
+        self.pending
+            .lock()
+            .get(collection_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Take the hits recorded since the counters were last stored, so they can be added
+    /// to the stored counters
+    pub(crate) fn take(&self) -> HashMap<String, HashMap<String, u64>> {
+        std::mem::take(&mut *self.pending.lock())
+    }
+
+    /// Put back hits that were taken but could not be stored
+    pub(crate) fn restore(&self, hits: HashMap<String, HashMap<String, u64>>) {
+        let mut pending = self.pending.lock();
+        for (collection_id, hits) in hits {
+            add_hits(pending.entry(collection_id).or_default(), hits);
+        }
+    }
+}
+
+pub(crate) fn add_hits(totals: &mut HashMap<String, u64>, hits: HashMap<String, u64>) {
+    for (index_key, hits) in hits {
+        *totals.entry(index_key).or_default() += hits;
+    }
+}
+
+pub(crate) fn hits_from_record(
+    record: Option<RecordRoot>,
+) -> Result<HashMap<String, u64>, bincode::Error> {
+    match record.and_then(|mut r| r.remove("hits")) {
+        Some(RecordValue::Bytes(b)) => bincode::deserialize(&b),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+pub(crate) fn hits_to_record(hits: &HashMap<String, u64>) -> Result<RecordRoot, bincode::Error> {
+    let mut record = RecordRoot::new();
+    record.insert(
+        "hits".to_string(),
+        RecordValue::Bytes(bincode::serialize(hits)?),
+    );
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_and_restore() {
+        let name = Index::new(vec![IndexField::new_asc("name".into())]);
+        let meter = IndexHitMeter::default();
+        meter.record_hit("ns/A", &name);
+        meter.record_hit("ns/A", &name);
+
+        let taken = meter.take();
+        assert!(meter.pending("ns/A").is_empty());
+        assert_eq!(taken["ns/A"]["name ASC, id ASC"], 2);
+
+        meter.record_hit("ns/A", &name);
+        meter.restore(taken);
+        assert_eq!(meter.pending("ns/A")[&index_key(&name)], 3);
+    }
+}
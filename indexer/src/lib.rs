@@ -2,24 +2,42 @@
 
 // TODO: we should export schema from here, so that indexer builders
 // are using the correct schema
-use crate::adaptor::{IndexerAdaptor, SnapshotValue};
+use crate::adaptor::{AuditEntry, CollectionStats, IndexerAdaptor, SnapshotValue};
+use crate::auth_user::AuthUser;
+use crate::index_stats::{IndexHitMeter, IndexStats};
 use crate::list_query::ListQuery;
+use crate::record_cache::RecordCache;
+use crate::references::RecordKey;
+use crate::schema_version::SCHEMA_VERSION_FIELD;
+use crate::usage::{Usage, UsageMeter};
 use crate::where_query::WhereQuery;
-use futures::stream::{FuturesUnordered, StreamExt};
+use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
 use schema::{
     directive::DirectiveKind,
     field_path::FieldPath,
+    index::{Index, IndexField},
     publickey::PublicKey,
     record::{ForeignRecordReference, RecordReference, RecordRoot, Reference},
     Schema, COLLECTION_RECORD, COLLECTION_SCHEMA,
 };
-use std::{borrow::Cow, pin::Pin, time::SystemTime};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    pin::Pin,
+    time::{Duration, SystemTime},
+};
+use tracing::warn;
 
 pub mod adaptor;
 pub mod auth_user;
 pub mod cursor;
+pub mod diff;
+pub mod index_stats;
 pub mod list_query;
 pub mod memory;
+pub mod record_cache;
+pub mod references;
+pub mod schema_version;
+pub mod usage;
 pub mod where_query;
 
 // pub use indexer::{Error, Indexer, IndexerChange, Result, UserError};
@@ -36,6 +54,21 @@ pub enum Error {
 
     #[error("where query error: {0}")]
     WhereQuery(#[from] where_query::WhereQueryError),
+
+    #[error("bincode error: {0}")]
+    Bincode(#[from] bincode::Error),
+}
+
+impl Error {
+    /// Whether the operation may succeed if it is retried
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Adaptor(err) => err.is_retryable(),
+            Error::User(_) => false,
+            Error::WhereQuery(_) => false,
+            Error::Bincode(_) => false,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -53,11 +86,45 @@ pub enum UserError {
     NoIndexFoundMatchingTheQuery,
 }
 
+/// Describes how a list query would be executed
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    /// Fields of the index used to list the records
+    pub index_fields: Vec<IndexField>,
+    /// Whether the index is scanned in reverse
+    pub reverse: bool,
+}
+
+/// Default maximum number of records held in the indexer's record cache
+pub const DEFAULT_RECORD_CACHE_SIZE: usize = 10_000;
+
+/// Default maximum number of records returned by a single list query
+pub const DEFAULT_MAX_LIST_LIMIT: usize = 1000;
+
+/// Maximum number of times a commit is retried after a transient adaptor error
+const COMMIT_MAX_RETRIES: u32 = 3;
+
+/// Delay before the first commit retry, doubled for each subsequent retry
+const COMMIT_RETRY_INITIAL_DELAY: Duration = Duration::from_millis(50);
+
 pub struct Indexer<A: IndexerAdaptor> {
     adaptor: A,
+    record_cache: RecordCache,
+    max_list_limit: usize,
+    /// Usage is only counted if metering is enabled
+    usage: Option<UsageMeter>,
+    index_hits: IndexHitMeter,
 }
 
+/// Changes committed after a height, see `Indexer::changes`
 #[derive(Debug, Clone)]
+pub struct Changes {
+    pub changes: Vec<AuditEntry>,
+    /// Height the changes are complete up to
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum IndexerChange {
     Set {
         collection_id: String,
@@ -72,336 +139,2639 @@ pub enum IndexerChange {
 
 impl<A: IndexerAdaptor> Indexer<A> {
     pub fn new(adaptor: A) -> Self {
-        Self { adaptor }
+        Self::with_record_cache_size(adaptor, DEFAULT_RECORD_CACHE_SIZE)
     }
 
-    pub async fn snapshot(
-        &self,
-        chunk_size: usize,
-    ) -> Pin<Box<dyn futures::Stream<Item = Result<Vec<SnapshotValue>>> + '_ + Send>> {
-        self.adaptor
-            .snapshot(chunk_size)
-            .await
-            .map(|s| s.map_err(Error::from))
-            .boxed()
+    /// Create an indexer that caches up to `record_cache_size` records read using `get`,
+    /// a size of 0 disables the cache
+    pub fn with_record_cache_size(adaptor: A, record_cache_size: usize) -> Self {
+        Self {
+            adaptor,
+            record_cache: RecordCache::new(record_cache_size),
+            max_list_limit: DEFAULT_MAX_LIST_LIMIT,
+            usage: None,
+            index_hits: IndexHitMeter::default(),
+        }
     }
 
-    pub async fn restore(&self, chunk: Vec<SnapshotValue>) -> Result<()> {
-        Ok(self.adaptor.restore(chunk).await?)
+    /// Limit the number of records returned by a single list query, queries without a
+    /// limit (or with a larger limit) are truncated, and should be paged using a cursor
+    pub fn with_max_list_limit(mut self, max_list_limit: usize) -> Self {
+        self.max_list_limit = max_list_limit.max(1);
+        self
     }
 
-    pub async fn reset(&self) -> Result<()> {
-        Ok(self.adaptor.reset().await?)
+    pub fn max_list_limit(&self) -> usize {
+        self.max_list_limit
     }
 
-    pub async fn commit(&self, height: usize, changes: Vec<IndexerChange>) -> Result<()> {
-        Ok(self.adaptor.commit(height, changes).await?)
+    /// Count the reads and writes of each collection, the counters are stored in local
+    /// keys after each commit so they persist across restarts
+    pub fn with_usage_metering(mut self, enabled: bool) -> Self {
+        self.usage = enabled.then(UsageMeter::default);
+        self
     }
 
-    pub async fn get(
-        &self,
-        collection_id: &str,
-        record_id: &str,
-        public_key: Option<&PublicKey>,
-    ) -> Result<Option<RecordRoot>> {
-        // Automatically respond with Collection collection record
-        if collection_id == "Collection" && record_id == "Collection" {
-            return Ok(Some(COLLECTION_RECORD.clone()));
-        }
-
-        let record = match self.adaptor.get(collection_id, record_id).await? {
-            Some(record) => record,
-            None => return Ok(None),
+    /// Usage of each collection that matches `filter`, including usage that has not
+    /// been stored yet. Returns an empty map if metering is disabled.
+    pub async fn usage(&self, filter: impl Fn(&str) -> bool) -> Result<HashMap<String, Usage>> {
+        let Some(meter) = &self.usage else {
+            return Ok(HashMap::new());
         };
 
-        let schema = self.get_schema_required(collection_id).await?;
+        let mut usage =
+            usage::usage_from_record(self.adaptor.get_local_key(usage::USAGE_LOCAL_KEY).await?)?;
+        usage::add_usage(&mut usage, meter.pending());
+        usage.retain(|collection_id, _| filter(collection_id));
 
-        if !self
-            .verify_read(collection_id, &schema, &record, public_key)
-            .await
-        {
-            return Err(UserError::UnauthorizedRead)?;
+        Ok(usage)
+    }
+
+    /// Count the writes of the changes, so they can be added to the meter once the
+    /// changes are committed
+    fn count_writes(&self, changes: &[IndexerChange]) -> Option<HashMap<String, Usage>> {
+        self.usage.as_ref()?;
+
+        let writes = UsageMeter::default();
+        for change in changes {
+            match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record,
+                    ..
+                } => writes.record_write(collection_id, Some(record)),
+                IndexerChange::Delete { collection_id, .. } => {
+                    writes.record_write(collection_id, None)
+                }
+            }
         }
 
-        Ok(Some(record))
+        Some(writes.take())
     }
 
-    pub async fn get_without_auth_check(
-        &self,
-        collection_id: &str,
-        record_id: &str,
-    ) -> Result<Option<RecordRoot>> {
-        let record = match self.adaptor.get(collection_id, record_id).await? {
-            Some(record) => record,
-            None => return Ok(None),
+    /// Add the usage counted since the last commit to the stored counters. Metering is
+    /// best-effort, so errors are logged rather than failing the commit.
+    async fn store_usage(&self) {
+        let Some(meter) = &self.usage else {
+            return;
         };
 
-        Ok(Some(record))
+        let pending = meter.take();
+        let result = async {
+            let mut usage = usage::usage_from_record(
+                self.adaptor.get_local_key(usage::USAGE_LOCAL_KEY).await?,
+            )?;
+            usage::add_usage(&mut usage, pending.clone());
+            self.adaptor
+                .set_local_key(usage::USAGE_LOCAL_KEY, &usage::usage_to_record(&usage)?)
+                .await?;
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            warn!(?err, "Failed to store usage counters");
+            meter.restore(pending);
+        }
     }
 
-    pub async fn list<'a>(
-        &'a self,
-        collection_id: &'a str,
-        query: ListQuery<'a>,
-        public_key: Option<&'a PublicKey>,
-    ) -> Result<Pin<Box<dyn futures::Stream<Item = RecordRoot> + '_ + Send>>> {
+    /// Number of list queries that used each of the collection's indexes, including hits
+    /// that have not been stored yet
+    pub async fn index_stats(&self, collection_id: &str) -> Result<Vec<IndexStats>> {
         let schema = self.get_schema_required(collection_id).await?;
 
-        // Check we have a matching index
-        if !schema
+        let mut hits = index_stats::hits_from_record(
+            self.adaptor
+                .get_local_key(&index_stats::index_stats_key(collection_id))
+                .await?,
+        )?;
+        index_stats::add_hits(&mut hits, self.index_hits.pending(collection_id));
+
+        Ok(schema
             .indexes
             .iter()
-            .any(|index| query.where_query.matches(index, query.order_by))
+            .map(|index| IndexStats {
+                fields: index.fields.clone(),
+                hits: hits
+                    .get(&index_stats::index_key(index))
+                    .copied()
+                    .unwrap_or(0),
+            })
+            .collect())
+    }
+
+    /// Add the index hits counted since the last commit to the stored counters. Like
+    /// usage, the counters are best-effort, so errors are logged rather than failing the
+    /// commit.
+    async fn store_index_hits(&self) {
+        let pending = self.index_hits.take();
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut failed = HashMap::new();
+        for (collection_id, collection_hits) in pending {
+            let key = index_stats::index_stats_key(&collection_id);
+            let result = async {
+                let mut hits =
+                    index_stats::hits_from_record(self.adaptor.get_local_key(&key).await?)?;
+                index_stats::add_hits(&mut hits, collection_hits.clone());
+                self.adaptor
+                    .set_local_key(&key, &index_stats::hits_to_record(&hits)?)
+                    .await?;
+                Ok::<_, Error>(())
+            }
+            .await;
+
+            if let Err(err) = result {
+                warn!(?err, %collection_id, "Failed to store index hit counters");
+                failed.insert(collection_id, collection_hits);
+            }
+        }
+
+        self.index_hits.restore(failed);
+    }
+
+    /// Records that reference the given record, as `(collection_id, record_id)`
+    pub async fn referencing_records(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+    ) -> Result<Vec<RecordKey>> {
+        let target = (collection_id.to_string(), record_id.to_string());
+        Ok(self.adaptor.list_referrers(&target).await?)
+    }
+
+    /// Update the index of the records referenced by each changed record, and the reverse
+    /// index of the records that reference each record. Stored before the changes are
+    /// committed, so the adaptor writes the indexes in the same batch as the changes.
+    async fn store_references(&self, changes: &[IndexerChange]) -> Result<()> {
+        if self
+            .adaptor
+            .get_system_key(references::REFERENCES_BACKFILLED)
+            .await?
+            .is_none()
         {
-            return Err(UserError::NoIndexFoundMatchingTheQuery)?;
-        };
+            self.backfill_references().await?;
+        }
 
-        // if !self
-        //     .verify_list(collection_id, &schema, &query.where_query, public_key)
-        //     .await
-        // {
-        //     return Err(UserError::UnauthorizedRead)?;
-        // };
+        // References of each changed record, after the changes are applied
+        let mut references = HashMap::<RecordKey, BTreeSet<RecordKey>>::new();
 
-        let ListQuery {
-            limit,
-            where_query,
-            order_by,
-            cursor_after,
-            cursor_before,
-        } = query;
+        for change in changes {
+            let (collection_id, record_id, record) = match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    record,
+                } => (collection_id, record_id, Some(record)),
+                IndexerChange::Delete {
+                    collection_id,
+                    record_id,
+                } => (collection_id, record_id, None),
+            };
 
-        let mut where_query = where_query.clone();
+            let referrer = (collection_id.clone(), record_id.clone());
+            let previous = match references.remove(&referrer) {
+                Some(previous) => previous,
+                None => references::keys_from_record(
+                    self.adaptor
+                        .get_system_key(&references::references_key(collection_id, record_id))
+                        .await?,
+                )?,
+            };
+            let current = record
+                .map(|record| references::find_references(collection_id, record))
+                .unwrap_or_default();
 
-        // Apply the cursor to the where_query
-        let reverse = match (cursor_before, cursor_after) {
-            (Some(cursor_before), None) => {
-                where_query.apply_cursor(cursor_before, cursor::CursorDirection::Before, order_by);
-                true
+            for target in previous.symmetric_difference(&current) {
+                if current.contains(target) {
+                    self.adaptor.set_reference(target, &referrer).await?;
+                } else {
+                    self.adaptor.delete_reference(target, &referrer).await?;
+                }
             }
-            (None, Some(cursor_after)) => {
-                where_query.apply_cursor(cursor_after, cursor::CursorDirection::After, order_by);
-                false
+
+            // Records without references are only stored if they had references before
+            if !previous.is_empty() || !current.is_empty() {
+                references.insert(referrer, current);
             }
-            (Some(_), Some(_)) => {
-                return Err(UserError::InvalidCursorBeforeAndAfterSpecified)?;
+        }
+
+        for ((collection_id, record_id), keys) in references {
+            self.store_reference_keys(
+                &references::references_key(&collection_id, &record_id),
+                &keys,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Index the references of every stored record. Records stored before the reference
+    /// index was added (or while it used the legacy keys) are not indexed, so the index
+    /// is rebuilt from the records once, and the legacy keys are removed.
+    async fn backfill_references(&self) -> Result<()> {
+        let mut collections = self
+            .adaptor
+            .list("Collection", None, WhereQuery::default(), &[], false, None)
+            .await?;
+
+        let mut collection_ids = vec![];
+        while let Some(collection) = collections.next().await {
+            if let Ok(collection_id) = collection.id() {
+                collection_ids.push(collection_id.to_string());
             }
-            (None, None) => false,
-        };
+        }
+        drop(collections);
 
-        where_query.cast(&schema)?;
+        for collection_id in collection_ids {
+            let mut records = self
+                .adaptor
+                .list(
+                    &collection_id,
+                    None,
+                    WhereQuery::default(),
+                    &[],
+                    false,
+                    None,
+                )
+                .await?;
 
-        let schema = std::sync::Arc::new(schema);
+            while let Some(record) = records.next().await {
+                let Ok(record_id) = record.id() else {
+                    continue;
+                };
+                let referrer = (collection_id.clone(), record_id.to_string());
+                let refs = references::find_references(&collection_id, &record);
 
-        Ok(Box::pin(
+                for target in &refs {
+                    self.adaptor.set_reference(target, &referrer).await?;
+                    self.adaptor
+                        .delete_system_key(&references::legacy_referrers_key(&target.0, &target.1))
+                        .await?;
+                }
+
+                self.adaptor
+                    .delete_system_key(&references::legacy_references_key(
+                        &collection_id,
+                        record_id,
+                    ))
+                    .await?;
+                self.store_reference_keys(
+                    &references::references_key(&collection_id, record_id),
+                    &refs,
+                )
+                .await?;
+            }
+        }
+
+        self.adaptor
+            .set_system_key(references::REFERENCES_BACKFILLED, &RecordRoot::new())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keys are removed once they are empty, so deleted records don't leave any keys behind
+    async fn store_reference_keys(&self, key: &str, keys: &BTreeSet<RecordKey>) -> Result<()> {
+        if keys.is_empty() {
+            self.adaptor.delete_system_key(key).await?;
+        } else {
             self.adaptor
-                .list(collection_id, limit, where_query, order_by, reverse)
-                .await?
-                .filter(move |r| {
-                    let r = r.clone();
-                    let schema = schema.clone();
-                    async move {
-                        self.verify_read(
-                            collection_id,
-                            &std::sync::Arc::clone(&schema),
-                            &r,
-                            public_key,
-                        )
-                        .await
-                    }
-                }),
-        ))
+                .set_system_key(key, &references::keys_to_record(keys)?)
+                .await?;
+        }
+
+        Ok(())
     }
 
-    pub async fn last_record_update(
+    pub async fn snapshot(
         &self,
-        collection_id: &str,
-        record_id: &str,
-    ) -> Result<Option<SystemTime>> {
-        Ok(self
-            .adaptor
-            .last_record_update(collection_id, record_id)
-            .await?)
+        chunk_size: usize,
+    ) -> Pin<Box<dyn futures::Stream<Item = Result<Vec<SnapshotValue>>> + '_ + Send>> {
+        self.adaptor
+            .snapshot(chunk_size)
+            .await
+            .map(|s| s.map_err(Error::from))
+            .boxed()
     }
 
-    pub async fn last_collection_update(&self, collection_id: &str) -> Result<Option<SystemTime>> {
-        Ok(self.adaptor.last_collection_update(collection_id).await?)
+    pub async fn restore(&self, chunk: Vec<SnapshotValue>) -> Result<()> {
+        let result = self.adaptor.restore(chunk).await;
+        self.record_cache.clear();
+        Ok(result?)
     }
 
-    pub async fn set_system_key(&self, key: &str, data: &RecordRoot) -> Result<()> {
-        Ok(self.adaptor.set_system_key(key, data).await?)
+    /// Find a system key in snapshot data, without restoring it
+    pub fn snapshot_system_key(
+        &self,
+        values: &[SnapshotValue],
+        key: &str,
+    ) -> Result<Option<RecordRoot>> {
+        Ok(self.adaptor.snapshot_system_key(values, key)?)
     }
 
-    pub async fn get_system_key(&self, key: &str) -> Result<Option<RecordRoot>> {
-        Ok(self.adaptor.get_system_key(key).await?)
+    pub async fn reset(&self) -> Result<()> {
+        let result = self.adaptor.reset().await;
+        self.record_cache.clear();
+        Ok(result?)
     }
 
-    /// Verify a list query is valid
-    pub async fn verify_list<'a>(
-        &self,
-        collection_id: &str,
-        schema: &Schema,
-        where_query: &WhereQuery<'a>,
-        public_key: Option<&PublicKey>,
-    ) -> bool {
-        // Convert where query to a record, so we can verify it
-        let record = where_query.to_record_root(schema);
+    pub async fn compact(&self) -> Result<()> {
+        Ok(self.adaptor.compact().await?)
+    }
 
-        self.verify_read(collection_id, schema, &record, public_key)
-            .await
+    pub async fn rebuild_indexes(&self, collection_id: &str) -> Result<()> {
+        Ok(self.adaptor.rebuild_indexes(collection_id).await?)
     }
 
-    /// Verify public_key is allowed to call method on record, we don't check record parameters
-    /// here, as they will automatically be checked when they are fetched using the normal read
-    /// rules.
-    pub async fn verify_call(
+    /// Stream every record of a collection, without checking read permissions. Used to
+    /// export a single collection, unlike `snapshot` which includes the whole store.
+    pub fn export<'a>(
+        &'a self,
+        collection_id: &'a str,
+    ) -> Pin<Box<dyn futures::Stream<Item = Result<RecordRoot>> + 'a + Send>> {
+        futures::stream::once(async move {
+            let schema = self.get_schema_required(collection_id).await?;
+            let records = self
+                .adaptor
+                .list(collection_id, None, WhereQuery::default(), &[], false, None)
+                .await?;
+
+            Ok::<_, Error>(records.map(move |record| Ok(upcast(collection_id, &schema, record))))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    pub async fn audit_log(
         &self,
-        collection_id: &str,
-        method: &str,
-        schema: &Schema,
-        record: &RecordRoot,
-        public_key: Option<&PublicKey>,
-    ) -> bool {
-        // Always allow call if schema allows any, and there are no @call directives
-        // on the method be called
-        if schema.call_all && schema.method_auth(method).next().is_none() {
-            return true;
-        }
+        collection_id: Option<&str>,
+        from_height: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .adaptor
+            .audit_log(collection_id, from_height, limit)
+            .await?)
+    }
 
-        // If no public key and not call all, deny call
-        let Some(public_key) = public_key else {
-            return false;
+    /// Changes committed after the `since` height that the user can read, in height order,
+    /// read from the audit log (so only changes committed while the audit log was enabled
+    /// are included). Pages only end at the end of a height, so `Changes::height` can be
+    /// used as `since` to get the next page.
+    ///
+    /// A change is readable if the collection is public, or if the user can read the
+    /// record as recorded with the change (the record after it was set, or before it
+    /// was deleted). Entries stored before the record was recorded are checked against
+    /// the current value of the record instead.
+    pub async fn changes(
+        &self,
+        since: usize,
+        limit: usize,
+        auth: Option<&AuthUser>,
+    ) -> Result<Changes> {
+        let limit = limit.max(1);
+        let mut page_limit = limit;
+        let (entries, height) = loop {
+            let mut entries = self.adaptor.audit_log(None, since + 1, page_limit).await?;
+
+            let Some(last_height) = entries.last().map(|entry| entry.height) else {
+                break (entries, since);
+            };
+
+            if entries.len() < page_limit {
+                break (entries, last_height);
+            }
+
+            // The last height may have more changes than fit in the page, so leave it
+            // for the next page. If the page only has a single height, get all of it.
+            if entries.iter().any(|entry| entry.height != last_height) {
+                entries.retain(|entry| entry.height != last_height);
+                break (entries, last_height - 1);
+            }
+
+            page_limit *= 2;
         };
 
-        // Check for matching public keys in record
-        if schema.authorise_method_with_public_key(method, record, public_key) {
-            return true;
-        }
+        let mut schemas = HashMap::<String, Option<Schema>>::new();
+        let mut changes = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if !schemas.contains_key(&entry.collection_id) {
+                let schema = self.get_schema_required(&entry.collection_id).await.ok();
+                schemas.insert(entry.collection_id.clone(), schema);
+            }
 
-        // Otherwise, get method references
-        let refs = schema.find_method_references(method, record);
+            // The collection has been deleted
+            let Some(Some(schema)) = schemas.get(&entry.collection_id) else {
+                continue;
+            };
 
-        self.verify_references(collection_id, schema, public_key, refs)
-            .await
-    }
+            let readable = schema.read_all
+                || match &entry.record {
+                    Some(record) => {
+                        self.verify_read(&entry.collection_id, schema, record, auth)
+                            .await
+                    }
+                    None => match self
+                        .adaptor
+                        .get(&entry.collection_id, &entry.record_id)
+                        .await?
+                    {
+                        Some(record) => {
+                            self.verify_read(&entry.collection_id, schema, &record, auth)
+                                .await
+                        }
+                        None => false,
+                    },
+                };
 
-    /// Verify user can read a give record
-    pub async fn verify_read(
-        &self,
-        collection_id: &str,
-        schema: &Schema,
-        record: &RecordRoot,
-        public_key: Option<&PublicKey>,
-    ) -> bool {
-        // Always allow read if schema allows any
-        if schema.read_all {
-            return true;
+            if readable {
+                changes.push(entry);
+            }
         }
 
-        // If no public key and not read all, deny read
-        let public_key = match public_key {
-            Some(pk) => pk,
-            None => return false,
+        Ok(Changes { changes, height })
+    }
+
+    /// Commit the changes at the height. If a record is changed more than once, only its
+    /// last change is applied (see `last_change_per_record`), so replaying the same changes
+    /// always results in the same state.
+    pub async fn commit(&self, height: usize, changes: Vec<IndexerChange>) -> Result<()> {
+        let writes = match self.apply_commit(height, changes).await {
+            Ok(writes) => writes,
+            Err(err) => {
+                // Discard everything written for the height, including system keys written
+                // before the commit (e.g. the manifest), so a later commit doesn't persist them
+                if let Err(discard_err) = self.adaptor.discard_uncommitted().await {
+                    warn!(height, ?discard_err, "Failed to discard uncommitted writes");
+                }
+                return Err(err);
+            }
         };
 
-        // Otherwise get read permissions
-        self.verify_directives(
-            collection_id,
-            &[DirectiveKind::Delegate, DirectiveKind::Read],
-            schema,
-            record,
-            public_key,
-        )
-        .await
+        // Counters are local to this node, so they are stored after the changes rather
+        // than in the same (replicated) batch
+        if let (Some(meter), Some(writes)) = (&self.usage, writes) {
+            meter.restore(writes);
+        }
+        self.store_usage().await;
+        self.store_index_hits().await;
+
+        Ok(())
     }
 
-    #[async_recursion::async_recursion]
-    pub async fn verify_directives(
+    /// Apply the changes and commit them, returning the writes to add to the usage meter
+    async fn apply_commit(
         &self,
-        collection_id: &str,
-        directives: &[DirectiveKind],
-        schema: &Schema,
-        record: &RecordRoot,
-        public_key: &PublicKey,
-    ) -> bool {
-        // Check for matching public keys in record
-        if schema.authorise_directives_with_public_key(directives, record, public_key) {
-            return true;
-        }
+        height: usize,
+        changes: Vec<IndexerChange>,
+    ) -> Result<Option<HashMap<String, Usage>>> {
+        let changes = last_change_per_record(changes);
+        let mut changes = self.expand_collection_deletes(changes).await?;
 
-        // Otherwise, get references
-        let refs = schema.find_directive_references(directives, record);
+        let writes = self.count_writes(&changes);
+        self.store_references(&changes).await?;
+        self.stamp_schema_versions(&mut changes).await;
 
-        // Create a future for each reference (recursive lookup)
-        self.verify_references(collection_id, schema, public_key, refs)
-            .await
+        let keys = changes
+            .iter()
+            .map(|change| match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    ..
+                }
+                | IndexerChange::Delete {
+                    collection_id,
+                    record_id,
+                } => (collection_id.clone(), record_id.clone()),
+            })
+            .collect::<Vec<_>>();
+
+        let result = self.commit_with_retry(height, changes).await;
+
+        // Invalidate even if the commit failed, as some changes may have been written
+        self.record_cache.invalidate(
+            keys.iter()
+                .map(|(collection_id, record_id)| (collection_id.as_str(), record_id.as_str())),
+        );
+        result?;
+
+        Ok(writes)
     }
 
-    pub async fn verify_references<'a>(
+    /// Deleting a Collection record deletes the collection, so add a delete for each of its
+    /// stored records. Expects the changes from `last_change_per_record`, where changes to
+    /// the collection's records before the delete have been dropped, and later changes
+    /// belong to a re-created collection. The records are deleted before the Collection
+    /// record, while the collection's schema is still available to the adaptor.
+    async fn expand_collection_deletes(
         &self,
-        collection_id: &str,
-        schema: &Schema,
-        public_key: &PublicKey,
-        refs: impl Iterator<Item = (FieldPath, Vec<Reference<'a>>)>,
-    ) -> bool {
-        // Create a future for each reference (recursive lookup)
-        let mut futures = FuturesUnordered::new();
-        let refs = refs.flat_map(|(_, refs)| refs);
-        for reference in refs {
-            let (collection_id, schema, record_id) = match reference {
-                Reference::Record(RecordReference { id }) => {
-                    (collection_id, Cow::Borrowed(schema), id.as_str())
+        changes: Vec<IndexerChange>,
+    ) -> Result<Vec<IndexerChange>> {
+        let mut expanded = Vec::with_capacity(changes.len());
+
+        for change in changes {
+            if let IndexerChange::Delete {
+                collection_id,
+                record_id: deleted_collection_id,
+            } = &change
+            {
+                if collection_id == "Collection" {
+                    let mut records = self
+                        .adaptor
+                        .list(
+                            deleted_collection_id,
+                            None,
+                            WhereQuery::default(),
+                            &[],
+                            false,
+                            None,
+                        )
+                        .await?;
+
+                    while let Some(record) = records.next().await {
+                        let Ok(record_id) = record.id() else {
+                            continue;
+                        };
+                        expanded.push(IndexerChange::Delete {
+                            collection_id: deleted_collection_id.clone(),
+                            record_id: record_id.to_string(),
+                        });
+                    }
                 }
-                Reference::ForeignRecord(ForeignRecordReference {
-                    ref collection_id,
-                    ref id,
-                }) => match self.adaptor.get_schema(collection_id).await {
-                    Ok(Some(schema)) => (collection_id.as_str(), Cow::Owned(schema), id.as_str()),
-                    _ => continue,
-                },
-            };
+            }
 
-            // Get record, ignore if record deleted / missing
-            let record = match self.adaptor.get(collection_id, record_id).await {
-                Ok(Some(record)) => record,
-                _ => continue,
+            expanded.push(change);
+        }
+
+        Ok(expanded)
+    }
+
+    /// Stamp each record with the schema version of its collection, so reads can tell
+    /// whether the record needs to be upcast. Collections created or updated in the same
+    /// commit use the schema from the changes. Records that can't be stamped are stored
+    /// as they are, and are upcast on every read.
+    async fn stamp_schema_versions(&self, changes: &mut [IndexerChange]) {
+        let mut schemas = HashMap::<String, Option<Schema>>::new();
+
+        for change in changes.iter_mut() {
+            let IndexerChange::Set {
+                collection_id,
+                record_id,
+                record,
+            } = change
+            else {
+                continue;
             };
 
-            futures.push(async move {
-                self.verify_directives(
-                    collection_id,
-                    // When we recurse, we only look for delegates
-                    &[DirectiveKind::Delegate],
-                    &schema,
-                    &record,
-                    public_key,
-                )
-                .await
-            });
+            if collection_id == "Collection" {
+                schemas.insert(record_id.clone(), Schema::from_record(record).ok());
+                continue;
+            }
+
+            if !schemas.contains_key(collection_id.as_str()) {
+                let schema = self.adaptor.get_schema(collection_id).await.ok().flatten();
+                schemas.insert(collection_id.clone(), schema);
+            }
+
+            if let Some(Some(schema)) = schemas.get(collection_id.as_str()) {
+                schema_version::stamp(schema, record);
+            }
         }
+    }
 
-        // Check the reults of the futures, find the first one that returns true
-        while let Some(result) = futures.next().await {
-            if result {
-                return true;
+    /// Commit the changes to the adaptor, retrying with backoff if the adaptor fails
+    /// with a transient error
+    async fn commit_with_retry(
+        &self,
+        height: usize,
+        changes: Vec<IndexerChange>,
+    ) -> adaptor::Result<()> {
+        let mut delay = COMMIT_RETRY_INITIAL_DELAY;
+        let mut retries = 0;
+
+        loop {
+            match self.adaptor.commit(height, changes.clone()).await {
+                Err(err) if err.is_retryable() && retries < COMMIT_MAX_RETRIES => {
+                    retries += 1;
+                    warn!(
+                        height,
+                        retries,
+                        ?err,
+                        "Transient error during commit, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => return result,
             }
         }
+    }
 
-        false
+    pub async fn get(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+        auth: Option<&AuthUser>,
+    ) -> Result<Option<RecordRoot>> {
+        // Automatically respond with Collection collection record
+        if collection_id == "Collection" && record_id == "Collection" {
+            return Ok(Some(COLLECTION_RECORD.clone()));
+        }
+
+        // Check the collection exists first, so a missing collection is not reported
+        // as a missing record
+        let schema = self.get_schema_required(collection_id).await?;
+
+        self.get_with_schema(collection_id, &schema, record_id, auth)
+            .await
     }
 
-    /// Use this instead of self.adaptor.get_schema to error when schema is missing, i.e.
-    /// collection does not exist
-    pub async fn get_schema_required(&self, collection_id: &str) -> Result<Schema> {
+    /// Get multiple records, checking read permissions for each record. Results are
+    /// returned in the same order as `ids`, and each collection's schema is only loaded once.
+    pub async fn get_many(
+        &self,
+        ids: &[(String, String)],
+        auth: Option<&AuthUser>,
+    ) -> Vec<Result<Option<RecordRoot>>> {
+        let mut schemas = HashMap::<&str, Schema>::new();
+        let mut results = Vec::with_capacity(ids.len());
+
+        for (collection_id, record_id) in ids {
+            if collection_id == "Collection" && record_id == "Collection" {
+                results.push(Ok(Some(COLLECTION_RECORD.clone())));
+                continue;
+            }
+
+            let schema = match schemas.remove(collection_id.as_str()) {
+                Some(schema) => schema,
+                None => match self.get_schema_required(collection_id).await {
+                    Ok(schema) => schema,
+                    Err(err) => {
+                        results.push(Err(err));
+                        continue;
+                    }
+                },
+            };
+
+            results.push(
+                self.get_with_schema(collection_id, &schema, record_id, auth)
+                    .await,
+            );
+            schemas.insert(collection_id, schema);
+        }
+
+        results
+    }
+
+    async fn get_with_schema(
+        &self,
+        collection_id: &str,
+        schema: &Schema,
+        record_id: &str,
+        auth: Option<&AuthUser>,
+    ) -> Result<Option<RecordRoot>> {
+        let record = match self.get_record(collection_id, record_id).await? {
+            Some(record) => upcast(collection_id, schema, record),
+            None => return Ok(None),
+        };
+
+        if !self.verify_read(collection_id, schema, &record, auth).await {
+            return Err(UserError::UnauthorizedRead)?;
+        }
+
+        if let Some(meter) = &self.usage {
+            meter.record_read(collection_id, &record);
+        }
+
+        Ok(Some(record))
+    }
+
+    pub async fn get_without_auth_check(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+    ) -> Result<Option<RecordRoot>> {
+        match self.get_record(collection_id, record_id).await? {
+            Some(record) => Ok(Some(
+                self.upcast_with_stored_schema(collection_id, record)
+                    .await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the value of a record as of a committed block height, without checking read
+    /// permissions. Returns `None` if the record did not exist at that height.
+    pub async fn get_at_height(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+        height: usize,
+    ) -> Result<Option<RecordRoot>> {
+        match self
+            .adaptor
+            .get_at_height(collection_id, record_id, height)
+            .await?
+        {
+            Some(record) => Ok(Some(
+                self.upcast_with_stored_schema(collection_id, record)
+                    .await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Upcast a record when the schema hasn't been loaded yet. Records of a collection
+    /// that no longer exists are returned as they were stored.
+    async fn upcast_with_stored_schema(
+        &self,
+        collection_id: &str,
+        mut record: RecordRoot,
+    ) -> Result<RecordRoot> {
         if collection_id == "Collection" {
-            return Ok(COLLECTION_SCHEMA.clone());
+            return Ok(record);
         }
 
         match self.adaptor.get_schema(collection_id).await? {
-            Some(schema) => Ok(schema),
-            None => Err(UserError::CollectionNotFound {
-                id: collection_id.to_string(),
-            })?,
+            Some(schema) => Ok(schema_version::upcast(&schema, record)),
+            None => {
+                record.remove(SCHEMA_VERSION_FIELD);
+                Ok(record)
+            }
+        }
+    }
+
+    /// Get a record from the cache, or from the adaptor if it's not cached
+    async fn get_record(&self, collection_id: &str, record_id: &str) -> Result<Option<RecordRoot>> {
+        if let Some(record) = self.record_cache.get(collection_id, record_id) {
+            return Ok(Some(record));
+        }
+
+        let generation = self.record_cache.generation();
+        let record = self.adaptor.get(collection_id, record_id).await?;
+        if let Some(record) = &record {
+            self.record_cache
+                .insert(generation, collection_id, record_id, record.clone());
+        }
+
+        Ok(record)
+    }
+
+    pub async fn list<'a>(
+        &'a self,
+        collection_id: &'a str,
+        query: ListQuery<'a>,
+        auth: Option<&'a AuthUser>,
+    ) -> Result<Pin<Box<dyn futures::Stream<Item = RecordRoot> + '_ + Send>>> {
+        let schema = self.get_schema_required(collection_id).await?;
+
+        // Check we have a matching index
+        let index = find_index(&schema, &query.where_query, query.order_by)?;
+        self.index_hits.record_hit(collection_id, index);
+
+        // if !self
+        //     .verify_list(collection_id, &schema, &query.where_query, auth)
+        //     .await
+        // {
+        //     return Err(UserError::UnauthorizedRead)?;
+        // };
+
+        let ListQuery {
+            limit,
+            where_query,
+            order_by,
+            cursor_after,
+            cursor_before,
+        } = query;
+
+        let mut where_query = where_query.clone();
+
+        // Never scan more than max_list_limit records, the caller can use the last record
+        // as the cursor for the next page
+        let limit = limit.map_or(self.max_list_limit, |limit| limit.min(self.max_list_limit));
+
+        // Records are listed from the cursor, in reverse for records before the cursor
+        let (reverse, cursor) = match (cursor_before, cursor_after) {
+            (Some(cursor_before), None) => (true, Some(cursor_before)),
+            (None, Some(cursor_after)) => (false, Some(cursor_after)),
+            (Some(_), Some(_)) => {
+                return Err(UserError::InvalidCursorBeforeAndAfterSpecified)?;
+            }
+            (None, None) => (false, None),
+        };
+
+        where_query.cast(&schema)?;
+
+        let records = self
+            .adaptor
+            .list(
+                collection_id,
+                Some(limit),
+                where_query,
+                order_by,
+                reverse,
+                cursor.as_ref(),
+            )
+            .await?;
+
+        let schema = std::sync::Arc::new(schema);
+        let upcast_schema = std::sync::Arc::clone(&schema);
+
+        Ok(Box::pin(
+            records
+                .map(move |r| upcast(collection_id, &upcast_schema, r))
+                .filter(move |r| {
+                    let r = r.clone();
+                    let schema = schema.clone();
+                    async move {
+                        self.verify_read(collection_id, &std::sync::Arc::clone(&schema), &r, auth)
+                            .await
+                    }
+                })
+                .inspect(move |r| {
+                    if let Some(meter) = &self.usage {
+                        meter.record_read(collection_id, r);
+                    }
+                }),
+        ))
+    }
+
+    /// Returns the index that would be used for a list query, without executing it
+    pub async fn explain(&self, collection_id: &str, query: &ListQuery<'_>) -> Result<QueryPlan> {
+        let schema = self.get_schema_required(collection_id).await?;
+        let index = find_index(&schema, &query.where_query, query.order_by)?;
+
+        let cursor_reverse = match (&query.cursor_before, &query.cursor_after) {
+            (Some(_), Some(_)) => return Err(UserError::InvalidCursorBeforeAndAfterSpecified)?,
+            (Some(_), None) => true,
+            _ => false,
+        };
+
+        Ok(QueryPlan {
+            index_fields: index.fields.clone(),
+            // Matches the scan direction used by the adaptor
+            reverse: index.should_list_in_reverse(query.order_by) != cursor_reverse,
+        })
+    }
+
+    pub async fn last_record_update(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+    ) -> Result<Option<SystemTime>> {
+        Ok(self
+            .adaptor
+            .last_record_update(collection_id, record_id)
+            .await?)
+    }
+
+    pub async fn last_collection_update(&self, collection_id: &str) -> Result<Option<SystemTime>> {
+        Ok(self.adaptor.last_collection_update(collection_id).await?)
+    }
+
+    pub async fn collection_stats(&self, collection_id: &str) -> Result<CollectionStats> {
+        Ok(self.adaptor.collection_stats(collection_id).await?)
+    }
+
+    pub async fn set_system_key(&self, key: &str, data: &RecordRoot) -> Result<()> {
+        Ok(self.adaptor.set_system_key(key, data).await?)
+    }
+
+    pub async fn get_system_key(&self, key: &str) -> Result<Option<RecordRoot>> {
+        Ok(self.adaptor.get_system_key(key).await?)
+    }
+
+    /// Verify a list query is valid
+    pub async fn verify_list<'a>(
+        &self,
+        collection_id: &str,
+        schema: &Schema,
+        where_query: &WhereQuery<'a>,
+        auth: Option<&AuthUser>,
+    ) -> bool {
+        // Convert where query to a record, so we can verify it
+        let record = where_query.to_record_root(schema);
+
+        self.verify_read(collection_id, schema, &record, auth).await
+    }
+
+    /// Verify user is allowed to call method on record, we don't check record parameters
+    /// here, as they will automatically be checked when they are fetched using the normal read
+    /// rules. The call is allowed if any of the user's public keys authorise it.
+    pub async fn verify_call(
+        &self,
+        collection_id: &str,
+        method: &str,
+        schema: &Schema,
+        record: &RecordRoot,
+        auth: Option<&AuthUser>,
+    ) -> bool {
+        // Always allow call if schema allows any, and there are no @call directives
+        // on the method be called
+        if schema.call_all && schema.method_auth(method).next().is_none() {
+            return true;
         }
+
+        // If no public key and not call all, deny call
+        let Some(auth) = auth else {
+            return false;
+        };
+
+        for public_key in auth.public_keys() {
+            // Check for matching public keys in record
+            if schema.authorise_method_with_public_key(method, record, public_key) {
+                return true;
+            }
+
+            // Otherwise, get method references
+            let refs = schema.find_method_references(method, record);
+
+            if self
+                .verify_references(collection_id, schema, public_key, refs)
+                .await
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Verify user can read a give record, if any of the user's public keys authorise it
+    pub async fn verify_read(
+        &self,
+        collection_id: &str,
+        schema: &Schema,
+        record: &RecordRoot,
+        auth: Option<&AuthUser>,
+    ) -> bool {
+        // Always allow read if schema allows any
+        if schema.read_all {
+            return true;
+        }
+
+        // If no public key and not read all, deny read
+        let auth = match auth {
+            Some(auth) => auth,
+            None => return false,
+        };
+
+        // Otherwise get read permissions
+        for public_key in auth.public_keys() {
+            if self
+                .verify_directives(
+                    collection_id,
+                    &[DirectiveKind::Delegate, DirectiveKind::Read],
+                    schema,
+                    record,
+                    public_key,
+                )
+                .await
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Verify user can modify a given record, if any of the user's public keys authorise it
+    pub async fn verify_write(
+        &self,
+        collection_id: &str,
+        schema: &Schema,
+        record: &RecordRoot,
+        auth: Option<&AuthUser>,
+    ) -> bool {
+        // Always allow write if schema has no write restrictions
+        if schema.write_all {
+            return true;
+        }
+
+        // If no public key and not write all, deny write
+        let auth = match auth {
+            Some(auth) => auth,
+            None => return false,
+        };
+
+        // Otherwise get write permissions
+        for public_key in auth.public_keys() {
+            if self
+                .verify_directives(
+                    collection_id,
+                    &[DirectiveKind::Delegate, DirectiveKind::Write],
+                    schema,
+                    record,
+                    public_key,
+                )
+                .await
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    #[async_recursion::async_recursion]
+    pub async fn verify_directives(
+        &self,
+        collection_id: &str,
+        directives: &[DirectiveKind],
+        schema: &Schema,
+        record: &RecordRoot,
+        public_key: &PublicKey,
+    ) -> bool {
+        // Check for matching public keys in record
+        if schema.authorise_directives_with_public_key(directives, record, public_key) {
+            return true;
+        }
+
+        // Otherwise, get references
+        let refs = schema.find_directive_references(directives, record);
+
+        // Create a future for each reference (recursive lookup)
+        self.verify_references(collection_id, schema, public_key, refs)
+            .await
+    }
+
+    pub async fn verify_references<'a>(
+        &self,
+        collection_id: &str,
+        schema: &Schema,
+        public_key: &PublicKey,
+        refs: impl Iterator<Item = (FieldPath, Vec<Reference<'a>>)>,
+    ) -> bool {
+        let refs = refs.flat_map(|(_, refs)| refs).collect::<Vec<_>>();
+
+        // Load the schemas of all foreign collections at once
+        let mut foreign_collection_ids = refs
+            .iter()
+            .filter_map(|reference| match reference {
+                Reference::Record(_) => None,
+                Reference::ForeignRecord(ForeignRecordReference { collection_id, .. }) => {
+                    Some(collection_id.as_str())
+                }
+            })
+            .collect::<Vec<_>>();
+        foreign_collection_ids.sort_unstable();
+        foreign_collection_ids.dedup();
+        let foreign_schemas = if foreign_collection_ids.is_empty() {
+            HashMap::new()
+        } else {
+            self.adaptor
+                .get_schema_many(&foreign_collection_ids)
+                .await
+                .unwrap_or_default()
+        };
+
+        // Create a future for each reference (recursive lookup)
+        let mut futures = FuturesUnordered::new();
+        for reference in refs {
+            let (collection_id, schema, record_id) = match reference {
+                Reference::Record(RecordReference { id }) => (collection_id, schema, id.as_str()),
+                Reference::ForeignRecord(ForeignRecordReference {
+                    ref collection_id,
+                    ref id,
+                }) => match foreign_schemas.get(collection_id) {
+                    Some(schema) => (collection_id.as_str(), schema, id.as_str()),
+                    None => continue,
+                },
+            };
+
+            // Get record, ignore if record deleted / missing
+            let record = match self.adaptor.get(collection_id, record_id).await {
+                Ok(Some(record)) => record,
+                _ => continue,
+            };
+
+            futures.push(async move {
+                self.verify_directives(
+                    collection_id,
+                    // When we recurse, we only look for delegates
+                    &[DirectiveKind::Delegate],
+                    schema,
+                    &record,
+                    public_key,
+                )
+                .await
+            });
+        }
+
+        // Check the reults of the futures, find the first one that returns true
+        while let Some(result) = futures.next().await {
+            if result {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Use this instead of self.adaptor.get_schema to error when schema is missing, i.e.
+    /// collection does not exist
+    pub async fn get_schema_required(&self, collection_id: &str) -> Result<Schema> {
+        if collection_id == "Collection" {
+            return Ok(COLLECTION_SCHEMA.clone());
+        }
+
+        match self.adaptor.get_schema(collection_id).await? {
+            Some(schema) => Ok(schema),
+            None => Err(UserError::CollectionNotFound {
+                id: collection_id.to_string(),
+            })?,
+        }
+    }
+}
+
+/// Keep only the last change of each record, applied at the position of the record's
+/// first change. Keeping the first position means a collection that is created and then
+/// updated in the same commit is still created before any of its records are set.
+///
+/// Deleting a collection is applied in order: earlier changes to the collection and its
+/// records are dropped, as they're deleted with it, and later changes are kept after the
+/// delete, so a collection re-created in the same commit starts without the old records.
+fn last_change_per_record(changes: Vec<IndexerChange>) -> Vec<IndexerChange> {
+    let mut positions = HashMap::<RecordKey, usize>::with_capacity(changes.len());
+    let mut deduped = Vec::<Option<IndexerChange>>::with_capacity(changes.len());
+    // Collections deleted earlier in the commit
+    let mut deleted = HashSet::<String>::new();
+
+    for change in changes {
+        let key = match &change {
+            IndexerChange::Set {
+                collection_id,
+                record_id,
+                ..
+            }
+            | IndexerChange::Delete {
+                collection_id,
+                record_id,
+            } => (collection_id.clone(), record_id.clone()),
+        };
+
+        if let IndexerChange::Delete {
+            collection_id,
+            record_id: deleted_collection_id,
+        } = &change
+        {
+            if collection_id == "Collection" {
+                positions.retain(|(collection_id, record_id), &mut i| {
+                    let dropped = collection_id == deleted_collection_id
+                        || (collection_id == "Collection" && record_id == deleted_collection_id);
+                    if dropped {
+                        deduped[i] = None;
+                    }
+                    !dropped
+                });
+
+                // The stored records were already deleted by the earlier delete, and the
+                // changes since then have been dropped
+                if deleted.insert(deleted_collection_id.clone()) {
+                    deduped.push(Some(change));
+                }
+                continue;
+            }
+        }
+
+        match positions.get(&key) {
+            Some(&i) => deduped[i] = Some(change),
+            None => {
+                positions.insert(key, deduped.len());
+                deduped.push(Some(change));
+            }
+        }
+    }
+
+    deduped.into_iter().flatten().collect()
+}
+
+/// Convert a stored record to the current shape of its collection's schema. The
+/// Collection schema never changes, so its records are not versioned.
+fn upcast(collection_id: &str, schema: &Schema, record: RecordRoot) -> RecordRoot {
+    if collection_id == "Collection" {
+        return record;
+    }
+
+    schema_version::upcast(schema, record)
+}
+
+/// Find the first (most specific) index that can be used for the query
+fn find_index<'s>(
+    schema: &'s Schema,
+    where_query: &WhereQuery,
+    order_by: &[IndexField],
+) -> Result<&'s Index> {
+    where_query.validate_against(schema)?;
+
+    schema
+        .indexes
+        .iter()
+        .find(|index| where_query.matches(index, order_by))
+        .ok_or_else(|| UserError::NoIndexFoundMatchingTheQuery.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryStore;
+    use schema::record::RecordValue;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Memory store that counts the number of calls to `get`, `get_schema`,
+    /// `get_schema_many` and `commit`, and fails commits with the queued `commit_errors`
+    #[derive(Default)]
+    struct CountingStore {
+        store: MemoryStore,
+        gets: AtomicUsize,
+        schema_gets: AtomicUsize,
+        schema_many_gets: AtomicUsize,
+        commits: AtomicUsize,
+        commit_errors: parking_lot::Mutex<Vec<adaptor::Error>>,
+    }
+
+    #[async_trait::async_trait]
+    impl IndexerAdaptor for CountingStore {
+        async fn commit(&self, height: usize, changes: Vec<IndexerChange>) -> adaptor::Result<()> {
+            self.commits.fetch_add(1, Ordering::SeqCst);
+            if let Some(err) = self.commit_errors.lock().pop() {
+                return Err(err);
+            }
+            self.store.commit(height, changes).await
+        }
+
+        async fn discard_uncommitted(&self) -> adaptor::Result<()> {
+            self.store.discard_uncommitted().await
+        }
+
+        async fn get(
+            &self,
+            collection_id: &str,
+            record_id: &str,
+        ) -> adaptor::Result<Option<RecordRoot>> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.store.get(collection_id, record_id).await
+        }
+
+        async fn list(
+            &self,
+            collection_id: &str,
+            limit: Option<usize>,
+            where_query: WhereQuery<'_>,
+            order_by: &[IndexField],
+            reverse: bool,
+            cursor: Option<&cursor::Cursor<'_>>,
+        ) -> adaptor::Result<Pin<Box<dyn futures::Stream<Item = RecordRoot> + '_ + Send>>> {
+            self.store
+                .list(collection_id, limit, where_query, order_by, reverse, cursor)
+                .await
+        }
+
+        async fn get_schema(&self, collection_id: &str) -> adaptor::Result<Option<Schema>> {
+            self.schema_gets.fetch_add(1, Ordering::SeqCst);
+            self.store.get_schema(collection_id).await
+        }
+
+        async fn get_schema_many(
+            &self,
+            collection_ids: &[&str],
+        ) -> adaptor::Result<HashMap<String, Schema>> {
+            self.schema_many_gets.fetch_add(1, Ordering::SeqCst);
+            self.store.get_schema_many(collection_ids).await
+        }
+
+        async fn last_record_update(
+            &self,
+            collection_id: &str,
+            record_id: &str,
+        ) -> adaptor::Result<Option<SystemTime>> {
+            self.store
+                .last_record_update(collection_id, record_id)
+                .await
+        }
+
+        async fn last_collection_update(
+            &self,
+            collection_id: &str,
+        ) -> adaptor::Result<Option<SystemTime>> {
+            self.store.last_collection_update(collection_id).await
+        }
+
+        async fn collection_stats(&self, collection_id: &str) -> adaptor::Result<CollectionStats> {
+            self.store.collection_stats(collection_id).await
+        }
+
+        async fn set_system_key(&self, key: &str, data: &RecordRoot) -> adaptor::Result<()> {
+            self.store.set_system_key(key, data).await
+        }
+
+        async fn get_system_key(&self, key: &str) -> adaptor::Result<Option<RecordRoot>> {
+            self.store.get_system_key(key).await
+        }
+
+        async fn delete_system_key(&self, key: &str) -> adaptor::Result<()> {
+            self.store.delete_system_key(key).await
+        }
+
+        async fn set_reference(
+            &self,
+            target: &RecordKey,
+            source: &RecordKey,
+        ) -> adaptor::Result<()> {
+            self.store.set_reference(target, source).await
+        }
+
+        async fn delete_reference(
+            &self,
+            target: &RecordKey,
+            source: &RecordKey,
+        ) -> adaptor::Result<()> {
+            self.store.delete_reference(target, source).await
+        }
+
+        async fn list_referrers(&self, target: &RecordKey) -> adaptor::Result<Vec<RecordKey>> {
+            self.store.list_referrers(target).await
+        }
+
+        async fn set_local_key(&self, key: &str, data: &RecordRoot) -> adaptor::Result<()> {
+            self.store.set_local_key(key, data).await
+        }
+
+        async fn get_local_key(&self, key: &str) -> adaptor::Result<Option<RecordRoot>> {
+            self.store.get_local_key(key).await
+        }
+
+        async fn snapshot(
+            &self,
+            chunk_size: usize,
+        ) -> Pin<Box<dyn futures::Stream<Item = adaptor::Result<Vec<SnapshotValue>>> + '_ + Send>>
+        {
+            self.store.snapshot(chunk_size).await
+        }
+
+        async fn restore(&self, chunk: Vec<SnapshotValue>) -> adaptor::Result<()> {
+            self.store.restore(chunk).await
+        }
+
+        fn snapshot_system_key(
+            &self,
+            values: &[SnapshotValue],
+            key: &str,
+        ) -> adaptor::Result<Option<RecordRoot>> {
+            self.store.snapshot_system_key(values, key)
+        }
+
+        async fn reset(&self) -> adaptor::Result<()> {
+            self.store.reset().await
+        }
+
+        async fn compact(&self) -> adaptor::Result<()> {
+            self.store.compact().await
+        }
+
+        async fn rebuild_indexes(&self, collection_id: &str) -> adaptor::Result<()> {
+            self.store.rebuild_indexes(collection_id).await
+        }
+
+        async fn get_at_height(
+            &self,
+            collection_id: &str,
+            record_id: &str,
+            height: usize,
+        ) -> adaptor::Result<Option<RecordRoot>> {
+            self.store
+                .get_at_height(collection_id, record_id, height)
+                .await
+        }
+
+        async fn audit_log(
+            &self,
+            collection_id: Option<&str>,
+            from_height: usize,
+            limit: usize,
+        ) -> adaptor::Result<Vec<adaptor::AuditEntry>> {
+            self.store
+                .audit_log(collection_id, from_height, limit)
+                .await
+        }
+    }
+
+    fn person(id: &str, name: &str) -> RecordRoot {
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String(id.to_string()));
+        record.insert("name".to_string(), RecordValue::String(name.to_string()));
+        record.insert("age".to_string(), RecordValue::Number(30.0));
+        record
+    }
+
+    async fn create_indexer() -> Indexer<MemoryStore> {
+        create_indexer_with(MemoryStore::default()).await
+    }
+
+    async fn create_indexer_with<A: IndexerAdaptor>(adaptor: A) -> Indexer<A> {
+        let code = r#"
+            @public
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+
+                @index(name, age);
+            }
+        "#;
+
+        let indexer = Indexer::new(adaptor);
+        indexer
+            .commit(0, vec![set_person_collection(code)])
+            .await
+            .unwrap();
+
+        indexer
+    }
+
+    /// Create or update the ns/Person collection
+    fn set_person_collection(code: &str) -> IndexerChange {
+        let mut program = None;
+        let (_, ast) = polylang::parse(code, "ns", &mut program).unwrap();
+
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String("ns/Person".into()));
+        record.insert(
+            "ast".to_string(),
+            RecordValue::String(serde_json::to_string(&ast).unwrap()),
+        );
+
+        IndexerChange::Set {
+            collection_id: "Collection".to_string(),
+            record_id: "ns/Person".to_string(),
+            record,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain_sorted_query() {
+        let indexer = create_indexer().await;
+        let order_by = [IndexField::new_desc("age".into())];
+
+        let plan = indexer
+            .explain(
+                "ns/Person",
+                &ListQuery {
+                    limit: None,
+                    where_query: serde_json::from_str(r#"{"name":"John"}"#).unwrap(),
+                    order_by: &order_by,
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            plan,
+            QueryPlan {
+                index_fields: vec![
+                    IndexField::new_asc("name".into()),
+                    IndexField::new_asc("age".into()),
+                    IndexField::new_asc(FieldPath::id()),
+                ],
+                // The index is ascending, so a descending sort scans in reverse
+                reverse: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explain_no_matching_index() {
+        let indexer = create_indexer().await;
+        let order_by = [IndexField::new_asc("name".into())];
+
+        let err = indexer
+            .explain(
+                "ns/Person",
+                &ListQuery {
+                    limit: None,
+                    where_query: serde_json::from_str(r#"{"age":10}"#).unwrap(),
+                    order_by: &order_by,
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+            )
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(err, Error::User(UserError::NoIndexFoundMatchingTheQuery)),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_where_query_unknown_field() {
+        let indexer = create_indexer().await;
+        let order_by: [IndexField; 0] = [];
+        let list = |where_query: &'static str| {
+            indexer.list(
+                "ns/Person",
+                ListQuery {
+                    limit: None,
+                    where_query: serde_json::from_str(where_query).unwrap(),
+                    order_by: &order_by,
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+                None,
+            )
+        };
+
+        let err = list(r#"{"nickname":"Johnny"}"#).await.err().unwrap();
+        assert!(
+            matches!(
+                &err,
+                Error::WhereQuery(where_query::WhereQueryError::UserError(
+                    where_query::WhereQueryUserError::UnknownField { field }
+                )) if field == "nickname"
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        assert!(list(r#"{"name":"John"}"#).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_list_where_query_type_mismatch() {
+        let indexer = create_indexer().await;
+        let order_by: [IndexField; 0] = [];
+        let list = |where_query: &'static str| {
+            indexer.list(
+                "ns/Person",
+                ListQuery {
+                    limit: None,
+                    where_query: serde_json::from_str(where_query).unwrap(),
+                    order_by: &order_by,
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+                None,
+            )
+        };
+
+        let err = list(r#"{"name":"John","age":"thirty"}"#)
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(
+                &err,
+                Error::WhereQuery(where_query::WhereQueryError::UserError(
+                    where_query::WhereQueryUserError::FieldTypeMismatch { field, got, .. }
+                )) if field == "age" && got == "thirty"
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        // A value that can be cast to the field type is accepted
+        assert!(list(r#"{"name":"John","age":"30"}"#).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_commit_applies_last_change_of_each_record() {
+        let indexer = create_indexer().await;
+        let set = |id: &str, name: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+            record: person(id, name),
+        };
+        let delete = |id: &str| IndexerChange::Delete {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+        };
+
+        indexer.commit(1, vec![set("1", "John")]).await.unwrap();
+
+        // Set then delete, the record is deleted
+        indexer
+            .commit(2, vec![set("1", "Jane"), set("2", "Bob"), delete("1")])
+            .await
+            .unwrap();
+        assert_eq!(
+            indexer
+                .get_without_auth_check("ns/Person", "1")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            indexer
+                .get_without_auth_check("ns/Person", "2")
+                .await
+                .unwrap(),
+            Some(person("2", "Bob"))
+        );
+
+        // Delete then set, the record is set
+        indexer
+            .commit(3, vec![delete("1"), set("1", "John"), set("1", "Jane")])
+            .await
+            .unwrap();
+        assert_eq!(
+            indexer
+                .get_without_auth_check("ns/Person", "1")
+                .await
+                .unwrap(),
+            Some(person("1", "Jane"))
+        );
+
+        assert_eq!(
+            last_change_per_record(vec![set("1", "John"), set("2", "Bob"), delete("1")]),
+            vec![delete("1"), set("2", "Bob")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_at_height() {
+        let indexer = create_indexer().await;
+        let set = |name: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: "1".to_string(),
+            record: person("1", name),
+        };
+
+        indexer.commit(2, vec![set("John")]).await.unwrap();
+        indexer.commit(4, vec![set("Jane")]).await.unwrap();
+        indexer
+            .commit(
+                6,
+                vec![IndexerChange::Delete {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        let indexer = &indexer;
+        let get_at_height = move |height| indexer.get_at_height("ns/Person", "1", height);
+        assert_eq!(get_at_height(1).await.unwrap(), None);
+        assert_eq!(get_at_height(2).await.unwrap(), Some(person("1", "John")));
+        assert_eq!(get_at_height(3).await.unwrap(), Some(person("1", "John")));
+        assert_eq!(get_at_height(4).await.unwrap(), Some(person("1", "Jane")));
+        assert_eq!(get_at_height(6).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_uses_record_cache() {
+        let indexer = create_indexer_with(CountingStore::default()).await;
+        indexer
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "id1".to_string(),
+                    record: person("id1", "John"),
+                }],
+            )
+            .await
+            .unwrap();
+
+        let first = indexer.get("ns/Person", "id1", None).await.unwrap();
+        let second = indexer.get("ns/Person", "id1", None).await.unwrap();
+
+        assert_eq!(first, Some(person("id1", "John")));
+        assert_eq!(second, first);
+        assert_eq!(indexer.adaptor.gets.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_counted_and_stored() {
+        let indexer = create_indexer().await.with_usage_metering(true);
+        let set = |id: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+            record: person(id, "John"),
+        };
+
+        indexer
+            .commit(1, vec![set("id1"), set("id2")])
+            .await
+            .unwrap();
+        indexer.get("ns/Person", "id1", None).await.unwrap();
+        let listed = indexer
+            .list(
+                "ns/Person",
+                ListQuery {
+                    limit: None,
+                    where_query: WhereQuery::default(),
+                    order_by: &[],
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+                None,
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(listed.len(), 2);
+
+        let record_size = bincode::serialized_size(&person("id1", "John")).unwrap();
+        let usage = indexer.usage(|id| id == "ns/Person").await.unwrap();
+        assert_eq!(
+            usage["ns/Person"],
+            Usage {
+                reads: 3,
+                writes: 2,
+                read_bytes: 3 * record_size,
+                write_bytes: 2 * record_size,
+            }
+        );
+
+        // Reads are stored with the next commit, so they survive a restart
+        indexer
+            .commit(
+                2,
+                vec![IndexerChange::Delete {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "id2".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        let Indexer { adaptor, .. } = indexer;
+        // Usage differs between nodes, so it must not be in the replicated data
+        assert!(adaptor
+            .get_system_key(usage::USAGE_LOCAL_KEY)
+            .await
+            .unwrap()
+            .is_none());
+        let indexer = Indexer::new(adaptor).with_usage_metering(true);
+        let usage = indexer.usage(|_| true).await.unwrap();
+        assert_eq!(
+            usage["ns/Person"],
+            Usage {
+                reads: 3,
+                writes: 3,
+                read_bytes: 3 * record_size,
+                write_bytes: 2 * record_size,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_many_returns_results_in_order() {
+        let indexer = create_indexer_with(CountingStore::default()).await;
+        let set = |id: &str, name: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+            record: person(id, name),
+        };
+        indexer
+            .commit(1, vec![set("id1", "John"), set("id2", "Jane")])
+            .await
+            .unwrap();
+
+        let schema_gets = indexer.adaptor.schema_gets.load(Ordering::SeqCst);
+        let ids = [
+            ("ns/Person", "id2"),
+            ("ns/Person", "id3"),
+            ("ns/Missing", "id1"),
+            ("ns/Person", "id1"),
+        ]
+        .map(|(collection_id, record_id)| (collection_id.to_string(), record_id.to_string()));
+        let results = indexer.get_many(&ids, None).await;
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), &Some(person("id2", "Jane")));
+        assert_eq!(results[1].as_ref().unwrap(), &None);
+        assert!(matches!(
+            results[2],
+            Err(Error::User(UserError::CollectionNotFound { .. }))
+        ));
+        assert_eq!(results[3].as_ref().unwrap(), &Some(person("id1", "John")));
+
+        // The schema is only loaded once for each collection
+        assert_eq!(
+            indexer.adaptor.schema_gets.load(Ordering::SeqCst) - schema_gets,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_referencing_records() {
+        let indexer = create_indexer().await;
+        let key = |id: &str| ("ns/Person".to_string(), id.to_string());
+        let set = |id: &str, reference: Option<RecordValue>| {
+            let mut record = person(id, "John");
+            if let Some(reference) = reference {
+                record.insert("manager".to_string(), reference);
+            }
+            IndexerChange::Set {
+                collection_id: "ns/Person".to_string(),
+                record_id: id.to_string(),
+                record,
+            }
+        };
+        let reference = RecordValue::RecordReference(RecordReference { id: "1".into() });
+        let foreign_reference = RecordValue::ForeignRecordReference(ForeignRecordReference {
+            collection_id: "ns/Person".into(),
+            id: "1".into(),
+        });
+
+        indexer
+            .commit(
+                1,
+                vec![
+                    set("1", None),
+                    set("2", Some(reference.clone())),
+                    set("3", Some(foreign_reference)),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            indexer.referencing_records("ns/Person", "1").await.unwrap(),
+            vec![key("2"), key("3")]
+        );
+        assert!(indexer
+            .referencing_records("ns/Person", "2")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Removing the reference, or deleting the referencing record, updates the index
+        indexer
+            .commit(
+                2,
+                vec![
+                    set("2", None),
+                    IndexerChange::Delete {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "3".to_string(),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+        assert!(indexer
+            .referencing_records("ns/Person", "1")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Changes in the same commit are applied in order
+        indexer
+            .commit(3, vec![set("2", None), set("2", Some(reference))])
+            .await
+            .unwrap();
+        assert_eq!(
+            indexer.referencing_records("ns/Person", "1").await.unwrap(),
+            vec![key("2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_references_are_backfilled() {
+        let indexer = create_indexer().await;
+        let mut record = person("2", "John");
+        record.insert(
+            "manager".to_string(),
+            RecordValue::RecordReference(RecordReference { id: "1".into() }),
+        );
+
+        // Records stored before the reference index was added, with a legacy key
+        indexer
+            .adaptor
+            .delete_system_key(references::REFERENCES_BACKFILLED)
+            .await
+            .unwrap();
+        let legacy_key = references::legacy_referrers_key("ns/Person", "1");
+        indexer
+            .adaptor
+            .set_system_key(&legacy_key, &RecordRoot::new())
+            .await
+            .unwrap();
+        indexer
+            .adaptor
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "2".to_string(),
+                    record,
+                }],
+            )
+            .await
+            .unwrap();
+        assert!(indexer
+            .referencing_records("ns/Person", "1")
+            .await
+            .unwrap()
+            .is_empty());
+
+        indexer.commit(2, vec![]).await.unwrap();
+        assert_eq!(
+            indexer.referencing_records("ns/Person", "1").await.unwrap(),
+            vec![("ns/Person".to_string(), "2".to_string())]
+        );
+        assert!(indexer
+            .adaptor
+            .get_system_key(&legacy_key)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_collection_deletes_records_changed_in_the_same_block() {
+        let indexer = create_indexer().await;
+        let set = |id: &str, reference: Option<&str>| {
+            let mut record = person(id, "John");
+            if let Some(reference) = reference {
+                record.insert(
+                    "manager".to_string(),
+                    RecordValue::RecordReference(RecordReference {
+                        id: reference.into(),
+                    }),
+                );
+            }
+            IndexerChange::Set {
+                collection_id: "ns/Person".to_string(),
+                record_id: id.to_string(),
+                record,
+            }
+        };
+
+        indexer
+            .commit(1, vec![set("1", None), set("2", Some("1"))])
+            .await
+            .unwrap();
+
+        indexer
+            .commit(
+                2,
+                vec![
+                    set("3", Some("1")),
+                    set("4", None),
+                    IndexerChange::Delete {
+                        collection_id: "Collection".to_string(),
+                        record_id: "ns/Person".to_string(),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert!(indexer
+            .adaptor
+            .get_schema("ns/Person")
+            .await
+            .unwrap()
+            .is_none());
+        for id in ["1", "2", "3", "4"] {
+            assert!(indexer
+                .adaptor
+                .get("ns/Person", id)
+                .await
+                .unwrap()
+                .is_none());
+        }
+
+        // No reference keys are left behind
+        for key in [
+            references::references_key("ns/Person", "2"),
+            references::references_key("ns/Person", "3"),
+        ] {
+            assert!(indexer
+                .adaptor
+                .get_system_key(&key)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        assert!(indexer
+            .referencing_records("ns/Person", "1")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_collection_then_recreate_in_the_same_block() {
+        let indexer = create_indexer().await;
+        let set = |id: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+            record: person(id, "John"),
+        };
+
+        indexer.commit(1, vec![set("1"), set("2")]).await.unwrap();
+
+        indexer
+            .commit(
+                2,
+                vec![
+                    set("3"),
+                    IndexerChange::Delete {
+                        collection_id: "Collection".to_string(),
+                        record_id: "ns/Person".to_string(),
+                    },
+                    set_person_collection(
+                        r#"
+                        @public
+                        collection Person {
+                            id: string;
+                            name: string;
+                            age: number;
+                        }
+                    "#,
+                    ),
+                    set("4"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert!(indexer
+            .adaptor
+            .get_schema("ns/Person")
+            .await
+            .unwrap()
+            .is_some());
+
+        // The records of the deleted collection are gone, including records changed
+        // before the delete, and the records of the re-created collection are kept
+        for id in ["1", "2", "3"] {
+            assert!(indexer
+                .adaptor
+                .get("ns/Person", id)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        assert!(indexer
+            .adaptor
+            .get("ns/Person", "4")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_get_schema_many_matches_get_schema() {
+        let indexer = create_indexer().await;
+
+        let schemas = indexer
+            .adaptor
+            .get_schema_many(&["ns/Person", "ns/Missing"])
+            .await
+            .unwrap();
+
+        // Missing collections are omitted
+        assert_eq!(schemas.len(), 1);
+        assert_eq!(
+            schemas.get("ns/Person"),
+            indexer
+                .adaptor
+                .get_schema("ns/Person")
+                .await
+                .unwrap()
+                .as_ref()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_references_batches_schema_loads() {
+        let indexer = create_indexer_with(CountingStore::default()).await;
+        let schema = indexer.get_schema_required("ns/Person").await.unwrap();
+        let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let public_key = PublicKey::from_secp256k1_key(&public_key).unwrap();
+
+        let foreign_reference = |collection_id: &str, id: &str| ForeignRecordReference {
+            collection_id: collection_id.into(),
+            id: id.into(),
+        };
+        let refs = [
+            foreign_reference("ns/Person", "1"),
+            foreign_reference("ns/Person", "2"),
+            foreign_reference("ns/Missing", "1"),
+        ];
+        let schema_gets = indexer.adaptor.schema_gets.load(Ordering::SeqCst);
+
+        assert!(
+            !indexer
+                .verify_references(
+                    "ns/Person",
+                    &schema,
+                    &public_key,
+                    std::iter::once((
+                        FieldPath::from("manager"),
+                        refs.iter().map(Reference::ForeignRecord).collect(),
+                    )),
+                )
+                .await
+        );
+
+        // Both collections are loaded in one call, and references to the missing
+        // collection are skipped without loading the record
+        assert_eq!(indexer.adaptor.schema_many_gets.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            indexer.adaptor.schema_gets.load(Ordering::SeqCst),
+            schema_gets
+        );
+        assert_eq!(indexer.adaptor.gets.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_commit_invalidates_record_cache() {
+        let indexer = create_indexer_with(CountingStore::default()).await;
+        let set = |name: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: "id1".to_string(),
+            record: person("id1", name),
+        };
+
+        indexer.commit(1, vec![set("John")]).await.unwrap();
+        assert_eq!(
+            indexer.get("ns/Person", "id1", None).await.unwrap(),
+            Some(person("id1", "John"))
+        );
+
+        indexer.commit(2, vec![set("Jane")]).await.unwrap();
+        assert_eq!(
+            indexer.get("ns/Person", "id1", None).await.unwrap(),
+            Some(person("id1", "Jane"))
+        );
+        assert_eq!(indexer.adaptor.gets.load(Ordering::SeqCst), 2);
+
+        indexer
+            .commit(
+                3,
+                vec![IndexerChange::Delete {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "id1".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+        assert_eq!(indexer.get("ns/Person", "id1", None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_index_stats_count_used_indexes() {
+        let indexer = create_indexer().await;
+        let query = || ListQuery {
+            limit: None,
+            where_query: serde_json::from_str(r#"{"name":"John"}"#).unwrap(),
+            order_by: &[],
+            cursor_before: None,
+            cursor_after: None,
+        };
+        let used = indexer
+            .explain("ns/Person", &query())
+            .await
+            .unwrap()
+            .index_fields;
+
+        for _ in 0..2 {
+            indexer
+                .list("ns/Person", query(), None)
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+        }
+
+        let assert_only_used_index_hit = |stats: Vec<IndexStats>| {
+            assert!(stats.len() > 1);
+            for stats in stats {
+                let expected = if stats.fields == used { 2 } else { 0 };
+                assert_eq!(stats.hits, expected, "index {:?}", stats.fields);
+            }
+        };
+        assert_only_used_index_hit(indexer.index_stats("ns/Person").await.unwrap());
+
+        // Counters are stored with the next commit
+        indexer.commit(1, vec![]).await.unwrap();
+        assert!(indexer.index_hits.pending("ns/Person").is_empty());
+        assert_only_used_index_hit(indexer.index_stats("ns/Person").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_list_starts_with() {
+        let indexer = create_indexer().await;
+        let names = ["use", "user", "user/a", "user/b", "user0", "users"];
+        indexer
+            .commit(
+                1,
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: format!("id{i}"),
+                        record: person(&format!("id{i}"), name),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let records = indexer
+            .list(
+                "ns/Person",
+                ListQuery {
+                    limit: None,
+                    where_query: serde_json::from_str(r#"{"name":{"$startsWith":"user/"}}"#)
+                        .unwrap(),
+                    order_by: &[],
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+                None,
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut names = records
+            .iter()
+            .map(|record| match record.get("name") {
+                Some(RecordValue::String(name)) => name.as_str(),
+                _ => panic!("record has no name"),
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["user/a", "user/b"]);
+    }
+
+    #[tokio::test]
+    async fn test_records_are_upcast_to_the_current_schema() {
+        let indexer = create_indexer().await;
+        indexer
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                    record: person("1", "John"),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // v2 removes age, and adds a required and an optional field
+        indexer
+            .commit(
+                2,
+                vec![set_person_collection(
+                    r#"
+                    @public
+                    collection Person {
+                        id: string;
+                        name: string;
+                        verified: boolean;
+                        email?: string;
+
+                        @index(name);
+                    }
+                "#,
+                )],
+            )
+            .await
+            .unwrap();
+
+        let mut expected = person("1", "John");
+        expected.remove("age");
+        expected.insert("verified".to_string(), RecordValue::Boolean(false));
+
+        assert_eq!(
+            indexer.get("ns/Person", "1", None).await.unwrap(),
+            Some(expected.clone())
+        );
+        assert_eq!(
+            indexer
+                .get_without_auth_check("ns/Person", "1")
+                .await
+                .unwrap(),
+            Some(expected.clone())
+        );
+
+        let records = indexer
+            .list(
+                "ns/Person",
+                ListQuery {
+                    limit: None,
+                    where_query: WhereQuery::default(),
+                    order_by: &[],
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+                None,
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(records, vec![expected.clone()]);
+
+        // The stored record keeps the v1 shape
+        let stored = indexer
+            .adaptor
+            .get("ns/Person", "1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.get("age"), Some(&RecordValue::Number(30.0)));
+        assert_eq!(stored.get("verified"), None);
+        assert!(stored.get(SCHEMA_VERSION_FIELD).is_some());
+
+        // Queries match the stored record, so the default of the new field is only
+        // matched once the record is written with v2
+        let list_verified = || async {
+            indexer
+                .list(
+                    "ns/Person",
+                    ListQuery {
+                        limit: None,
+                        where_query: serde_json::from_str(r#"{"verified":false}"#).unwrap(),
+                        order_by: &[],
+                        cursor_before: None,
+                        cursor_after: None,
+                    },
+                    None,
+                )
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+        };
+        assert!(list_verified().await.is_empty());
+
+        indexer
+            .commit(
+                3,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                    record: expected.clone(),
+                }],
+            )
+            .await
+            .unwrap();
+        assert_eq!(list_verified().await, vec![expected]);
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import_collection() {
+        let indexer = create_indexer().await;
+        let records = (0..5)
+            .map(|i| person(&format!("id{i}"), &format!("Name {i}")))
+            .collect::<Vec<_>>();
+        indexer
+            .commit(
+                1,
+                records
+                    .iter()
+                    .map(|record| IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: record.id().unwrap().to_string(),
+                        record: record.clone(),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let exported = indexer
+            .export("ns/Person")
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let imported = create_indexer().await;
+        imported
+            .commit(
+                1,
+                exported
+                    .into_iter()
+                    .map(|record| IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: record.id().unwrap().to_string(),
+                        record,
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let mut reexported = imported
+            .export("ns/Person")
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        reexported.sort_by(|a, b| a.id().unwrap().cmp(b.id().unwrap()));
+        assert_eq!(reexported, records);
+
+        assert!(matches!(
+            indexer.export("ns/Missing").try_collect::<Vec<_>>().await,
+            Err(Error::User(UserError::CollectionNotFound { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_paginates_records_with_equal_sort_values() {
+        let indexer = create_indexer().await;
+        indexer
+            .commit(
+                1,
+                ["id3", "id1", "id5", "id2", "id4"]
+                    .iter()
+                    .map(|id| IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: id.to_string(),
+                        record: person(id, "John"),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        // All records have the same age, so the id is used as the tie-break
+        let order_by = [IndexField::new_desc("age".into())];
+        let mut ids = vec![];
+        let mut cursor_after = None;
+
+        loop {
+            let query = ListQuery {
+                limit: Some(2),
+                where_query: WhereQuery::default(),
+                order_by: &order_by,
+                cursor_before: None,
+                cursor_after: cursor_after.clone(),
+            };
+            let index_fields = indexer
+                .explain("ns/Person", &query)
+                .await
+                .unwrap()
+                .index_fields;
+
+            let records = indexer
+                .list("ns/Person", query, None)
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+
+            let Some(last) = records.last() else {
+                break;
+            };
+
+            cursor_after = Some(cursor::Cursor(
+                cursor::WrappedCursor::from_record(last, &WhereQuery::default(), &index_fields)
+                    .unwrap(),
+            ));
+            ids.extend(records.iter().map(|r| r.id().unwrap().to_string()));
+        }
+
+        assert_eq!(ids, vec!["id5", "id4", "id3", "id2", "id1"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_limit_is_clamped_to_max_list_limit() {
+        let indexer = create_indexer().await.with_max_list_limit(2);
+        indexer
+            .commit(
+                1,
+                ["id1", "id2", "id3", "id4", "id5"]
+                    .iter()
+                    .map(|id| IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: id.to_string(),
+                        record: person(id, "John"),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        async fn list(
+            indexer: &Indexer<MemoryStore>,
+            limit: Option<usize>,
+            cursor_after: Option<cursor::Cursor<'static>>,
+        ) -> Vec<String> {
+            indexer
+                .list(
+                    "ns/Person",
+                    ListQuery {
+                        limit,
+                        where_query: WhereQuery::default(),
+                        order_by: &[],
+                        cursor_before: None,
+                        cursor_after,
+                    },
+                    None,
+                )
+                .await
+                .unwrap()
+                .map(|r| r.id().unwrap().to_string())
+                .collect()
+                .await
+        }
+
+        // Queries without a limit, or with a limit above the max, are truncated
+        assert_eq!(list(&indexer, None, None).await, vec!["id1", "id2"]);
+        assert_eq!(list(&indexer, Some(10), None).await, vec!["id1", "id2"]);
+        assert_eq!(list(&indexer, Some(1), None).await, vec!["id1"]);
+
+        // The last record can be used as the cursor for the next page
+        let cursor = |id: &str| {
+            Some(cursor::Cursor(
+                cursor::WrappedCursor::from_record(
+                    &person(id, "John"),
+                    &WhereQuery::default(),
+                    &[IndexField::new_asc(FieldPath::id())],
+                )
+                .unwrap(),
+            ))
+        };
+        assert_eq!(
+            list(&indexer, None, cursor("id2")).await,
+            vec!["id3", "id4"]
+        );
+        assert_eq!(list(&indexer, None, cursor("id4")).await, vec!["id5"]);
+    }
+
+    fn store_error(message: &str) -> Box<dyn std::error::Error + Send + Sync> {
+        message.into()
+    }
+
+    #[tokio::test]
+    async fn test_commit_retries_transient_error() {
+        let indexer = create_indexer_with(CountingStore::default()).await;
+        let commits = indexer.adaptor.commits.load(Ordering::SeqCst);
+
+        *indexer.adaptor.commit_errors.lock() = vec![
+            adaptor::Error::TransientStore(store_error("deadlock detected")),
+            adaptor::Error::TransientStore(store_error("deadlock detected")),
+        ];
+
+        indexer
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                    record: person("1", "John"),
+                }],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(indexer.adaptor.commits.load(Ordering::SeqCst), commits + 3);
+        assert_eq!(
+            indexer
+                .get_without_auth_check("ns/Person", "1")
+                .await
+                .unwrap(),
+            Some(person("1", "John"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_does_not_retry_permanent_error() {
+        let indexer = create_indexer_with(CountingStore::default()).await;
+        let commits = indexer.adaptor.commits.load(Ordering::SeqCst);
+
+        *indexer.adaptor.commit_errors.lock() =
+            vec![adaptor::Error::Store(store_error("constraint violation"))];
+
+        let err = indexer
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                    record: person("1", "John"),
+                }],
+            )
+            .await
+            .unwrap_err();
+
+        assert!(!err.is_retryable());
+        assert_eq!(indexer.adaptor.commits.load(Ordering::SeqCst), commits + 1);
+        assert_eq!(
+            indexer
+                .get_without_auth_check("ns/Person", "1")
+                .await
+                .unwrap(),
+            None
+        );
     }
 }
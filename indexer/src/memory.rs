@@ -1,4 +1,6 @@
-use crate::adaptor::{Error, Result, SnapshotValue};
+use crate::adaptor::{AuditEntry, CollectionStats, Error, Result, SnapshotValue};
+use crate::cursor::{self, Cursor};
+use crate::references::RecordKey;
 use crate::where_query::{WhereInequality, WhereNode, WhereQuery};
 use crate::IndexerAdaptor;
 use crate::IndexerChange;
@@ -9,7 +11,13 @@ use schema::{
     record::{RecordRoot, RecordValue},
     Schema,
 };
-use std::{collections::HashMap, pin::Pin, sync::Arc, time::SystemTime};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    pin::Pin,
+    sync::Arc,
+    time::SystemTime,
+};
 use tokio::sync::Mutex;
 
 #[derive(Debug, thiserror::Error)]
@@ -18,6 +26,8 @@ pub enum MemoryStoreError {
     Get,
     #[error("error during `list`")]
     List,
+    #[error("the memory store does not support snapshots")]
+    SnapshotsNotSupported,
 }
 
 #[derive(Clone)]
@@ -28,6 +38,12 @@ pub struct MemoryStore {
 struct MemoryStoreState {
     data: HashMap<String, Collection>,
     system_data: HashMap<String, RecordRoot>,
+    /// References as `(target, source)`, see [IndexerAdaptor::set_reference]
+    references: BTreeSet<(RecordKey, RecordKey)>,
+    /// Node-local keys, see [IndexerAdaptor::set_local_key]
+    local_data: HashMap<String, RecordRoot>,
+    /// Every committed value of each record by height, `None` if the record was deleted
+    history: HashMap<(String, String), BTreeMap<usize, Option<RecordRoot>>>,
 }
 
 struct Collection {
@@ -46,6 +62,9 @@ impl MemoryStore {
             state: Arc::new(Mutex::new(MemoryStoreState {
                 data: HashMap::new(),
                 system_data: HashMap::new(),
+                references: BTreeSet::new(),
+                local_data: HashMap::new(),
+                history: HashMap::new(),
             })),
         }
     }
@@ -109,12 +128,7 @@ impl MemoryStore {
     }
 
     pub async fn destroy(&self) -> Result<()> {
-        let mut state = self.state.lock().await;
-
-        state.data.clear();
-        state.system_data.clear();
-
-        Ok(())
+        self.reset().await
     }
 }
 
@@ -155,6 +169,11 @@ fn record_matches(where_query: &WhereQuery<'_>, record: &RecordRoot) -> Result<b
                             (IndexValue::Boolean(wbool), IndexValue::Boolean(rec_bool)) => {
                                 rec_bool & !wbool
                             }
+                            (wval @ IndexValue::Bytes(_), rec_val @ IndexValue::Bytes(_))
+                            | (
+                                wval @ IndexValue::PublicKey(_),
+                                rec_val @ IndexValue::PublicKey(_),
+                            ) => rec_val > wval,
                             _ => true,
                         }));
                     }
@@ -174,6 +193,11 @@ fn record_matches(where_query: &WhereQuery<'_>, record: &RecordRoot) -> Result<b
                             (IndexValue::Boolean(wbool), IndexValue::Boolean(rec_bool)) => {
                                 rec_bool >= wbool
                             }
+                            (wval @ IndexValue::Bytes(_), rec_val @ IndexValue::Bytes(_))
+                            | (
+                                wval @ IndexValue::PublicKey(_),
+                                rec_val @ IndexValue::PublicKey(_),
+                            ) => rec_val >= wval,
                             _ => true,
                         }));
                     }
@@ -193,6 +217,11 @@ fn record_matches(where_query: &WhereQuery<'_>, record: &RecordRoot) -> Result<b
                             (IndexValue::Boolean(wbool), IndexValue::Boolean(rec_bool)) => {
                                 !rec_bool & wbool
                             }
+                            (wval @ IndexValue::Bytes(_), rec_val @ IndexValue::Bytes(_))
+                            | (
+                                wval @ IndexValue::PublicKey(_),
+                                rec_val @ IndexValue::PublicKey(_),
+                            ) => rec_val < wval,
                             _ => true,
                         }));
                     }
@@ -212,10 +241,25 @@ fn record_matches(where_query: &WhereQuery<'_>, record: &RecordRoot) -> Result<b
                             (IndexValue::Boolean(wbool), IndexValue::Boolean(rec_bool)) => {
                                 rec_bool <= wbool
                             }
+                            (wval @ IndexValue::Bytes(_), rec_val @ IndexValue::Bytes(_))
+                            | (
+                                wval @ IndexValue::PublicKey(_),
+                                rec_val @ IndexValue::PublicKey(_),
+                            ) => rec_val <= wval,
                             _ => true,
                         }));
                     }
                 }
+                WhereNode::Contains(ref contains) => {
+                    rec_field_matches.push(Ok(match rec_val {
+                        RecordValue::Array(values) => values.iter().any(|value| {
+                            IndexValue::try_from(value.clone())
+                                .map(|value| value == contains.contains.0)
+                                .unwrap_or(false)
+                        }),
+                        _ => false,
+                    }));
+                }
             }
         }
     }
@@ -225,17 +269,59 @@ fn record_matches(where_query: &WhereQuery<'_>, record: &RecordRoot) -> Result<b
         .all(|res| res.as_ref().map(|&b| b).unwrap_or(false)))
 }
 
+/// Sorts records by each field in `order_by` in turn, so that later fields
+/// only break ties left by earlier ones. Values are compared as they're indexed, so
+/// records are in the same order as in the index, with missing fields sorted as null.
+pub(crate) fn sort_records(records: &mut [RecordRoot], order_by: &[IndexField]) {
+    records.sort_by(|a, b| {
+        for IndexField { path, direction } in order_by {
+            let ordering = cursor::cmp_index_values(
+                &cursor::index_value(a, path),
+                &cursor::index_value(b, path),
+            );
+
+            let ordering = match direction {
+                IndexDirection::Ascending => ordering,
+                IndexDirection::Descending => ordering.reverse(),
+            };
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        Ordering::Equal
+    });
+}
+
+/// Appends the id to the sort order, in the direction of the last sort field
+fn with_id_tie_break(order_by: &[IndexField]) -> Vec<IndexField> {
+    let id = FieldPath::id();
+    let mut fields = order_by.to_vec();
+    if !fields.iter().any(|field| field.path == id) {
+        fields.push(IndexField::new(
+            id,
+            order_by
+                .last()
+                .map(|field| field.direction)
+                .unwrap_or(IndexDirection::Ascending),
+        ));
+    }
+    fields
+}
+
 #[async_trait::async_trait]
 impl IndexerAdaptor for MemoryStore {
-    async fn commit(&self, _height: usize, changes: Vec<IndexerChange>) -> Result<()> {
+    async fn commit(&self, height: usize, changes: Vec<IndexerChange>) -> Result<()> {
         for change in changes {
-            match change {
+            let (collection_id, record_id, value) = match change {
                 IndexerChange::Set {
                     collection_id,
                     record_id,
                     record,
                 } => {
                     self.set(&collection_id, &record_id, &record).await?;
+                    (collection_id, record_id, Some(record))
                 }
 
                 IndexerChange::Delete {
@@ -243,8 +329,25 @@ impl IndexerAdaptor for MemoryStore {
                     record_id,
                 } => {
                     self.delete(&collection_id, &record_id).await?;
+                    (collection_id, record_id, None)
                 }
+            };
+
+            let mut state = self.state.lock().await;
+
+            // The history of a deleted collection's records is removed with the records.
+            // Changes after the delete belong to a re-created collection.
+            if collection_id == "Collection" && value.is_none() {
+                state
+                    .history
+                    .retain(|(deleted_collection_id, _), _| deleted_collection_id != &record_id);
             }
+
+            state
+                .history
+                .entry((collection_id, record_id))
+                .or_default()
+                .insert(height, value);
         }
 
         Ok(())
@@ -272,7 +375,21 @@ impl IndexerAdaptor for MemoryStore {
         Ok(None)
     }
 
-    #[allow(unused_variables)]
+    async fn get_at_height(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+        height: usize,
+    ) -> Result<Option<RecordRoot>> {
+        let state = self.state.lock().await;
+
+        Ok(state
+            .history
+            .get(&(collection_id.to_string(), record_id.to_string()))
+            .and_then(|versions| versions.range(..=height).next_back())
+            .and_then(|(_, value)| value.clone()))
+    }
+
     async fn list(
         &self,
         collection_id: &str,
@@ -280,7 +397,11 @@ impl IndexerAdaptor for MemoryStore {
         where_query: WhereQuery<'_>,
         order_by: &[IndexField],
         reverse: bool,
+        cursor: Option<&Cursor<'_>>,
     ) -> Result<Pin<Box<dyn futures::Stream<Item = RecordRoot> + '_ + Send>>> {
+        // Collections without a schema (or matching index) are sorted by `order_by` only
+        let schema = self.get_schema(collection_id).await.ok().flatten();
+
         let state = self.state.lock().await;
 
         let collection = match state.data.get(collection_id) {
@@ -309,66 +430,45 @@ impl IndexerAdaptor for MemoryStore {
             })
             .collect();
 
-        // sort the matching records based on order_by
-        for IndexField { path, direction } in order_by {
-            records.sort_by(|a, b| {
-                let joined_path = path.0.join("."); // vector of fields
-                if let Some(rec_a) = a.get(&joined_path) {
-                    if let Some(rec_b) = b.get(&joined_path) {
-                        match (rec_a, rec_b) {
-                            (RecordValue::Number(na), RecordValue::Number(nb)) => match direction {
-                                IndexDirection::Ascending => {
-                                    na.partial_cmp(nb).unwrap_or(std::cmp::Ordering::Greater)
-                                }
-                                IndexDirection::Descending => {
-                                    nb.partial_cmp(na).unwrap_or(std::cmp::Ordering::Greater)
-                                }
-                            },
-                            (RecordValue::String(sa), RecordValue::String(sb)) => match direction {
-                                IndexDirection::Ascending => sa.cmp(sb),
-                                IndexDirection::Descending => sb.cmp(sa),
-                            },
-                            (RecordValue::Boolean(ba), RecordValue::Boolean(bb)) => match direction
-                            {
-                                IndexDirection::Ascending => ba.cmp(bb),
-                                IndexDirection::Descending => bb.cmp(ba),
-                            },
-
-                            (RecordValue::PublicKey(pka), RecordValue::PublicKey(pkb)) => {
-                                match direction {
-                                    IndexDirection::Ascending => {
-                                        pka.partial_cmp(pkb).unwrap_or(std::cmp::Ordering::Greater)
-                                    }
-                                    IndexDirection::Descending => {
-                                        pkb.partial_cmp(pka).unwrap_or(std::cmp::Ordering::Greater)
-                                    }
-                                }
-                            }
+        // Sort in the same order as the index would be scanned, with the id as the
+        // final tie-break, so records with the same sort values have a stable order
+        let index = schema.as_ref().and_then(|schema| {
+            schema
+                .indexes
+                .iter()
+                .find(|index| where_query.matches(index, order_by))
+        });
+        let (index_fields, reverse) = match index {
+            Some(index) => (
+                index.fields.clone(),
+                index.should_list_in_reverse(order_by) != reverse,
+            ),
+            None => (with_id_tie_break(order_by), reverse),
+        };
+        sort_records(&mut records, &index_fields);
 
-                            (
-                                RecordValue::ForeignRecordReference(fra),
-                                RecordValue::ForeignRecordReference(frb),
-                            ) => match direction {
-                                IndexDirection::Ascending => {
-                                    fra.partial_cmp(frb).unwrap_or(std::cmp::Ordering::Greater)
-                                }
-                                IndexDirection::Descending => {
-                                    frb.partial_cmp(fra).unwrap_or(std::cmp::Ordering::Greater)
-                                }
-                            },
-                            _ => std::cmp::Ordering::Equal,
-                        }
-                    } else {
-                        std::cmp::Ordering::Equal // todo - PublicKey and ForeignRecordReference
-                    }
-                } else {
-                    std::cmp::Ordering::Equal
-                }
-            });
+        if reverse {
+            records.reverse();
         }
 
+        // Start after the cursor, in the order the records are listed
+        let start = match cursor {
+            Some(cursor) => records
+                .iter()
+                .position(|record| match cursor.0.cmp_record(record, &index_fields) {
+                    Ordering::Greater => !reverse,
+                    Ordering::Less => reverse,
+                    Ordering::Equal => false,
+                })
+                .unwrap_or(records.len()),
+            None => 0,
+        };
+
         Ok(Box::pin(futures::stream::iter(
-            records.into_iter().take(limit.unwrap_or(usize::MAX)),
+            records
+                .into_iter()
+                .skip(start)
+                .take(limit.unwrap_or(usize::MAX)),
         )))
     }
 
@@ -405,6 +505,18 @@ impl IndexerAdaptor for MemoryStore {
         Ok(None)
     }
 
+    async fn collection_stats(&self, collection_id: &str) -> Result<CollectionStats> {
+        let state = self.state.lock().await;
+
+        Ok(match state.data.get(collection_id) {
+            Some(collection) => CollectionStats {
+                record_count: collection.data.len() as u64,
+                last_updated: Some(collection.last_updated),
+            },
+            None => CollectionStats::default(),
+        })
+    }
+
     async fn set_system_key(&self, key: &str, data: &RecordRoot) -> Result<()> {
         let mut state = self.state.lock().await;
 
@@ -419,6 +531,55 @@ impl IndexerAdaptor for MemoryStore {
         Ok(state.system_data.get(key).cloned())
     }
 
+    async fn delete_system_key(&self, key: &str) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.system_data.remove(key);
+
+        Ok(())
+    }
+
+    async fn set_reference(&self, target: &RecordKey, source: &RecordKey) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.references.insert((target.clone(), source.clone()));
+
+        Ok(())
+    }
+
+    async fn delete_reference(&self, target: &RecordKey, source: &RecordKey) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.references.remove(&(target.clone(), source.clone()));
+
+        Ok(())
+    }
+
+    async fn list_referrers(&self, target: &RecordKey) -> Result<Vec<RecordKey>> {
+        let state = self.state.lock().await;
+
+        Ok(state
+            .references
+            .range((target.clone(), RecordKey::default())..)
+            .take_while(|(t, _)| t == target)
+            .map(|(_, source)| source.clone())
+            .collect())
+    }
+
+    async fn set_local_key(&self, key: &str, data: &RecordRoot) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.local_data.insert(key.to_string(), data.clone());
+
+        Ok(())
+    }
+
+    async fn get_local_key(&self, key: &str) -> Result<Option<RecordRoot>> {
+        let state = self.state.lock().await;
+
+        Ok(state.local_data.get(key).cloned())
+    }
+
     async fn snapshot(
         &self,
         _: usize,
@@ -430,14 +591,42 @@ impl IndexerAdaptor for MemoryStore {
         todo!()
     }
 
+    fn snapshot_system_key(&self, _: &[SnapshotValue], _: &str) -> Result<Option<RecordRoot>> {
+        Err(Error::Store(Box::new(
+            MemoryStoreError::SnapshotsNotSupported,
+        )))
+    }
+
+    /// Remove all collections, records, history, references and system keys, under a single lock so
+    /// no reads see a partially reset store
     async fn reset(&self) -> Result<()> {
-        todo!()
+        let mut state = self.state.lock().await;
+
+        state.data.clear();
+        state.system_data.clear();
+        state.references.clear();
+        state.history.clear();
+
+        Ok(())
+    }
+
+    async fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Indexes are not stored, records are filtered and sorted when listed
+    async fn rebuild_indexes(&self, _: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn audit_log(&self, _: Option<&str>, _: usize, _: usize) -> Result<Vec<AuditEntry>> {
+        Ok(vec![])
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::where_query::{WhereInequality, WhereValue};
+    use crate::where_query::{WhereContains, WhereInequality, WhereValue};
 
     use super::*;
     use futures::StreamExt;
@@ -522,7 +711,7 @@ mod tests {
         store.commit(9, changes).await.unwrap();
 
         let records = store
-            .list(collection_id, None, WhereQuery::default(), &[], false)
+            .list(collection_id, None, WhereQuery::default(), &[], false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -592,7 +781,7 @@ mod tests {
         );
 
         let records = store
-            .list(collection_id, None, where_query, &[], false)
+            .list(collection_id, None, where_query, &[], false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -677,7 +866,7 @@ mod tests {
         }];
 
         let records = store
-            .list(collection_id, None, where_query, &order_by, false)
+            .list(collection_id, None, where_query, &order_by, false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -706,7 +895,7 @@ mod tests {
         }];
 
         let records = store
-            .list(collection_id, None, where_query, &order_by, false)
+            .list(collection_id, None, where_query, &order_by, false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -801,7 +990,7 @@ mod tests {
         ];
 
         let mut records = store
-            .list(collection_id, None, where_query, &order_by, false)
+            .list(collection_id, None, where_query, &order_by, false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -864,7 +1053,7 @@ mod tests {
         );
 
         let records = store
-            .list(collection_id, None, where_query, &[], false)
+            .list(collection_id, None, where_query, &[], false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -880,29 +1069,23 @@ mod tests {
 
         let collection_id = "test_collection";
 
-        let record1_data = create_record_root(
-            &["id", "info.name"],
-            &[
-                RecordValue::String("id1".into()),
-                RecordValue::String("Bob".into()),
-            ],
-        );
-
-        let record2_data = create_record_root(
-            &["id", "info.name"],
-            &[
-                RecordValue::String("id2".into()),
-                RecordValue::String("Dave".into()),
-            ],
-        );
+        let record_with_name = |id: &str, name: &str| {
+            create_record_root(
+                &["id", "info"],
+                &[
+                    RecordValue::String(id.into()),
+                    RecordValue::Map(
+                        [("name".to_string(), RecordValue::String(name.into()))].into(),
+                    ),
+                ],
+            )
+        };
 
-        let record3_data = create_record_root(
-            &["id", "info.name"],
-            &[
-                RecordValue::String("id3".into()),
-                RecordValue::String("Wanda".into()),
-            ],
-        );
+        let record1_data = record_with_name("id1", "Bob");
+        let record2_data = record_with_name("id2", "Dave");
+        let record3_data = record_with_name("id3", "Wanda");
+        // Missing fields are sorted as null, before any other value
+        let record4_data = create_record_root(&["id"], &[RecordValue::String("id4".into())]);
 
         let changes = vec![
             IndexerChange::Set {
@@ -920,6 +1103,11 @@ mod tests {
                 record_id: "record3".to_string(),
                 record: record3_data.clone(),
             },
+            IndexerChange::Set {
+                collection_id: collection_id.into(),
+                record_id: "record4".to_string(),
+                record: record4_data.clone(),
+            },
         ];
 
         store.commit(0, changes).await.unwrap();
@@ -930,16 +1118,28 @@ mod tests {
         }];
 
         let records = store
-            .list(collection_id, None, WhereQuery::default(), &order_by, false)
+            .list(
+                collection_id,
+                None,
+                WhereQuery::default(),
+                &order_by,
+                false,
+                None,
+            )
             .await
             .unwrap()
             .collect::<Vec<_>>()
             .await;
 
-        assert!(records.len() == 3);
-        assert_eq!(records[0], record1_data);
-        assert_eq!(records[1], record2_data);
-        assert_eq!(records[2], record3_data);
+        assert_eq!(
+            records,
+            vec![
+                record4_data.clone(),
+                record1_data.clone(),
+                record2_data.clone(),
+                record3_data.clone()
+            ]
+        );
 
         let order_by = vec![IndexField {
             path: vec!["info".to_string(), "name".to_string()].into(),
@@ -947,16 +1147,23 @@ mod tests {
         }];
 
         let records = store
-            .list(collection_id, None, WhereQuery::default(), &order_by, false)
+            .list(
+                collection_id,
+                None,
+                WhereQuery::default(),
+                &order_by,
+                false,
+                None,
+            )
             .await
             .unwrap()
             .collect::<Vec<_>>()
             .await;
 
-        assert!(records.len() == 3);
-        assert_eq!(records[0], record3_data);
-        assert_eq!(records[1], record2_data);
-        assert_eq!(records[2], record1_data);
+        assert_eq!(
+            records,
+            vec![record3_data, record2_data, record1_data, record4_data]
+        );
     }
 
     #[tokio::test]
@@ -1013,7 +1220,7 @@ mod tests {
         );
 
         let records = store
-            .list(collection_id, None, where_query, &[], false)
+            .list(collection_id, None, where_query, &[], false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -1037,7 +1244,7 @@ mod tests {
         );
 
         let records = store
-            .list(collection_id, None, where_query, &[], false)
+            .list(collection_id, None, where_query, &[], false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -1047,6 +1254,85 @@ mod tests {
         assert_eq!(records[0], record2_data);
     }
 
+    #[tokio::test]
+    async fn test_where_array_contains() {
+        let store = MemoryStore::default();
+
+        let collection_id = "test_collection";
+
+        let record1_data = create_record_root(
+            &["id", "tags"],
+            &[
+                RecordValue::String("id1".into()),
+                RecordValue::Array(vec![
+                    RecordValue::String("rust".into()),
+                    RecordValue::String("db".into()),
+                ]),
+            ],
+        );
+
+        let record2_data = create_record_root(
+            &["id", "tags"],
+            &[
+                RecordValue::String("id2".into()),
+                RecordValue::Array(vec![RecordValue::String("js".into())]),
+            ],
+        );
+
+        let changes = vec![
+            IndexerChange::Set {
+                collection_id: collection_id.into(),
+                record_id: "record1".to_string(),
+                record: record1_data.clone(),
+            },
+            IndexerChange::Set {
+                collection_id: collection_id.into(),
+                record_id: "record2".to_string(),
+                record: record2_data.clone(),
+            },
+        ];
+
+        store.commit(0, changes).await.unwrap();
+
+        let where_query = WhereQuery(
+            [(
+                FieldPath(["tags".to_string()].into()),
+                WhereNode::Contains(WhereContains {
+                    contains: WhereValue(IndexValue::String(Cow::Owned("db".into()))),
+                }),
+            )]
+            .into(),
+        );
+
+        let records = store
+            .list(collection_id, None, where_query, &[], false, None)
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(records, vec![record1_data]);
+
+        let where_query = WhereQuery(
+            [(
+                FieldPath(["tags".to_string()].into()),
+                WhereNode::Contains(WhereContains {
+                    contains: WhereValue(IndexValue::String(Cow::Owned("go".into()))),
+                }),
+            )]
+            .into(),
+        );
+
+        let records = store
+            .list(collection_id, None, where_query, &[], false, None)
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert!(records.is_empty());
+    }
+
     #[tokio::test]
     async fn test_sort_boolean_field() {
         let store = MemoryStore::default();
@@ -1092,7 +1378,14 @@ mod tests {
         }];
 
         let records = store
-            .list(collection_id, None, WhereQuery::default(), &order_by, false)
+            .list(
+                collection_id,
+                None,
+                WhereQuery::default(),
+                &order_by,
+                false,
+                None,
+            )
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -1108,7 +1401,14 @@ mod tests {
         }];
 
         let records = store
-            .list(collection_id, None, WhereQuery::default(), &order_by, false)
+            .list(
+                collection_id,
+                None,
+                WhereQuery::default(),
+                &order_by,
+                false,
+                None,
+            )
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -1211,7 +1511,7 @@ mod tests {
         );
 
         let records = store
-            .list(account_collection, None, where_query, &[], false)
+            .list(account_collection, None, where_query, &[], false, None)
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -1324,6 +1624,7 @@ mod tests {
                 where_query.clone(),
                 &order_by,
                 false,
+                None,
             )
             .await
             .unwrap()
@@ -1340,7 +1641,14 @@ mod tests {
         }];
 
         let records = store
-            .list(account_collection, None, where_query, &order_by, false)
+            .list(
+                account_collection,
+                None,
+                where_query,
+                &order_by,
+                false,
+                None,
+            )
             .await
             .unwrap()
             .collect::<Vec<_>>()
@@ -1459,4 +1767,231 @@ mod tests {
         assert!(retrieved_data.is_none());
         assert!(system_data.is_none());
     }
+
+    #[tokio::test]
+    async fn test_memory_store_reset() {
+        let store = MemoryStore::new();
+
+        let mut program = None;
+        let (_, ast) = polylang::parse(
+            "collection Person { id: string; name: string; }",
+            "ns",
+            &mut program,
+        )
+        .unwrap();
+        let collection_record = create_record_root(
+            &["id", "ast"],
+            &[
+                RecordValue::String("ns/Person".into()),
+                RecordValue::String(serde_json::to_string(&ast).unwrap()),
+            ],
+        );
+        let person = create_record_root(
+            &["id", "name"],
+            &[
+                RecordValue::String("id1".into()),
+                RecordValue::String("Bob".into()),
+            ],
+        );
+
+        store
+            .commit(
+                0,
+                vec![
+                    IndexerChange::Set {
+                        collection_id: "Collection".into(),
+                        record_id: "ns/Person".into(),
+                        record: collection_record,
+                    },
+                    IndexerChange::Set {
+                        collection_id: "ns/Person".into(),
+                        record_id: "id1".into(),
+                        record: person,
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+        store
+            .set_system_key("some_system_key", &RecordRoot::new())
+            .await
+            .unwrap();
+        assert!(store.get_schema("ns/Person").await.unwrap().is_some());
+
+        store.reset().await.unwrap();
+
+        assert!(store.get_schema("ns/Person").await.unwrap().is_none());
+        assert!(store.get("ns/Person", "id1").await.unwrap().is_none());
+        assert!(store
+            .get_at_height("ns/Person", "id1", 0)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .get_system_key("some_system_key")
+            .await
+            .unwrap()
+            .is_none());
+        for collection_id in ["Collection", "ns/Person"] {
+            let records = store
+                .list(collection_id, None, WhereQuery::default(), &[], false, None)
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+            assert!(records.is_empty());
+        }
+        assert_eq!(
+            store.collection_stats("ns/Person").await.unwrap(),
+            CollectionStats::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sort_multiple_fields_with_reverse() {
+        let store = MemoryStore::default();
+
+        let collection_id = "test_collection";
+
+        let records = [("id1", 1.0, "x"), ("id2", 2.0, "y"), ("id3", 1.0, "z")]
+            .iter()
+            .map(|(id, a, b)| {
+                create_record_root(
+                    &["id", "a", "b"],
+                    &[
+                        RecordValue::String(id.to_string()),
+                        RecordValue::Number(*a),
+                        RecordValue::String(b.to_string()),
+                    ],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let changes = records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| IndexerChange::Set {
+                collection_id: collection_id.into(),
+                record_id: format!("record{}", i + 1),
+                record: record.clone(),
+            })
+            .collect();
+
+        store.commit(0, changes).await.unwrap();
+
+        let order_by = vec![
+            IndexField {
+                path: vec!["a".to_string()].into(),
+                direction: IndexDirection::Ascending,
+            },
+            IndexField {
+                path: vec!["b".to_string()].into(),
+                direction: IndexDirection::Descending,
+            },
+        ];
+
+        let listed = store
+            .list(
+                collection_id,
+                None,
+                WhereQuery::default(),
+                &order_by,
+                false,
+                None,
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            listed,
+            vec![records[2].clone(), records[0].clone(), records[1].clone()]
+        );
+
+        let listed = store
+            .list(
+                collection_id,
+                Some(2),
+                WhereQuery::default(),
+                &order_by,
+                true,
+                None,
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(listed, vec![records[1].clone(), records[0].clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_collection_stats_tracks_inserts_and_deletes() {
+        let store = MemoryStore::default();
+
+        let collection_id = "test_collection";
+
+        assert_eq!(
+            store.collection_stats(collection_id).await.unwrap(),
+            CollectionStats::default()
+        );
+
+        let changes = ["record1", "record2", "record3"]
+            .iter()
+            .map(|record_id| IndexerChange::Set {
+                collection_id: collection_id.into(),
+                record_id: record_id.to_string(),
+                record: create_record_root(&["id"], &[RecordValue::String(record_id.to_string())]),
+            })
+            .collect();
+
+        store.commit(0, changes).await.unwrap();
+
+        let stats = store.collection_stats(collection_id).await.unwrap();
+        assert_eq!(stats.record_count, 3);
+        assert!(stats.last_updated.is_some());
+
+        // Updating an existing record does not change the count
+        store
+            .commit(
+                0,
+                vec![IndexerChange::Set {
+                    collection_id: collection_id.into(),
+                    record_id: "record1".to_string(),
+                    record: create_record_root(&["id"], &[RecordValue::String("record1".into())]),
+                }],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store
+                .collection_stats(collection_id)
+                .await
+                .unwrap()
+                .record_count,
+            3
+        );
+
+        store
+            .commit(
+                0,
+                vec![IndexerChange::Delete {
+                    collection_id: collection_id.into(),
+                    record_id: "record2".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store
+                .collection_stats(collection_id)
+                .await
+                .unwrap()
+                .record_count,
+            2
+        );
+    }
 }
@@ -0,0 +1,121 @@
+use lru::LruCache;
+use parking_lot::Mutex;
+use schema::record::RecordRoot;
+use std::num::NonZeroUsize;
+
+type RecordKey = (String, String);
+
+/// Bounded least-recently-used cache of records, keyed by collection id and record id.
+/// Only the record is cached, read authorization must still be checked on every get.
+pub struct RecordCache {
+    state: Mutex<RecordCacheState>,
+}
+
+struct RecordCacheState {
+    /// Cached records, or None if the cache is disabled with a capacity of 0
+    entries: Option<LruCache<RecordKey, RecordRoot>>,
+    /// Incremented on every invalidation, so a record read from the adaptor before a
+    /// concurrent commit is not inserted into the cache after the commit
+    generation: u64,
+}
+
+impl RecordCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(RecordCacheState {
+                entries: NonZeroUsize::new(capacity).map(LruCache::new),
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Current generation of the cache, pass this to `insert` after reading the
+    /// record from the adaptor
+    pub fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    pub fn get(&self, collection_id: &str, record_id: &str) -> Option<RecordRoot> {
+        let mut state = self.state.lock();
+        let key = (collection_id.to_string(), record_id.to_string());
+        state.entries.as_mut()?.get(&key).cloned()
+    }
+
+    /// Insert a record read from the adaptor, the record is ignored if the cache
+    /// has been invalidated since `generation`
+    pub fn insert(
+        &self,
+        generation: u64,
+        collection_id: &str,
+        record_id: &str,
+        record: RecordRoot,
+    ) {
+        let mut state = self.state.lock();
+        if state.generation != generation {
+            return;
+        }
+
+        if let Some(entries) = state.entries.as_mut() {
+            entries.put((collection_id.to_string(), record_id.to_string()), record);
+        }
+    }
+
+    /// Remove the cached records for the given keys, e.g. when they are committed
+    pub fn invalidate<'a>(&self, keys: impl IntoIterator<Item = (&'a str, &'a str)>) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        if let Some(entries) = state.entries.as_mut() {
+            for (collection_id, record_id) in keys {
+                entries.pop(&(collection_id.to_string(), record_id.to_string()));
+            }
+        }
+    }
+
+    /// Remove all cached records, e.g. when the store is reset or restored
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        if let Some(entries) = state.entries.as_mut() {
+            entries.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::record::RecordValue;
+
+    fn record(id: &str) -> RecordRoot {
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String(id.to_string()));
+        record
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = RecordCache::new(2);
+        let generation = cache.generation();
+
+        cache.insert(generation, "ns/A", "1", record("1"));
+        cache.insert(generation, "ns/A", "2", record("2"));
+        // Use 1, so 2 is the least recently used
+        assert!(cache.get("ns/A", "1").is_some());
+        cache.insert(generation, "ns/A", "3", record("3"));
+
+        assert!(cache.get("ns/A", "1").is_some());
+        assert!(cache.get("ns/A", "2").is_none());
+        assert!(cache.get("ns/A", "3").is_some());
+    }
+
+    #[test]
+    fn test_insert_after_invalidate_is_ignored() {
+        let cache = RecordCache::new(10);
+        let generation = cache.generation();
+
+        cache.invalidate([("ns/A", "1")]);
+        cache.insert(generation, "ns/A", "1", record("1"));
+
+        assert!(cache.get("ns/A", "1").is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use schema::record::{RecordRoot, RecordValue};
+use std::collections::BTreeSet;
+
+/// A record, identified by its collection id and record id
+pub type RecordKey = (String, String);
+
+/// System record that is set once the references of the records stored before the
+/// reference index was added have been backfilled
+pub(crate) const REFERENCES_BACKFILLED: &str = "referenceIndex/backfilled";
+
+/// System key storing the records referenced by a record. Collection ids contain `/`, so
+/// the collection id is prefixed with its length to keep keys of different records apart.
+pub(crate) fn references_key(collection_id: &str, record_id: &str) -> String {
+    format!(
+        "references/{}:{collection_id}/{record_id}",
+        collection_id.len()
+    )
+}
+
+/// System key that stored the records referenced by a record, before the collection id
+/// was length prefixed. Only used to remove the key once it has been backfilled.
+pub(crate) fn legacy_references_key(collection_id: &str, record_id: &str) -> String {
+    format!("references/{collection_id}/{record_id}")
+}
+
+/// System key that stored all of the records referencing a record, before each reference
+/// was stored under its own key. Only used to remove the key once it has been backfilled.
+pub(crate) fn legacy_referrers_key(collection_id: &str, record_id: &str) -> String {
+    format!("referrers/{collection_id}/{record_id}")
+}
+
+/// Records referenced by any field of the record, including nested fields
+pub fn find_references(collection_id: &str, record: &RecordRoot) -> BTreeSet<RecordKey> {
+    let mut refs = BTreeSet::new();
+    for (_, value) in record.iter() {
+        find_value_references(collection_id, value, &mut refs);
+    }
+    refs
+}
+
+/// Records referenced by the value, including nested values. `collection_id` is the
+/// collection of the record containing the value, used for same-collection references.
+pub fn find_value_references(
+    collection_id: &str,
+    value: &RecordValue,
+    refs: &mut BTreeSet<RecordKey>,
+) {
+    match value {
+        RecordValue::RecordReference(r) => {
+            refs.insert((collection_id.to_string(), r.id.clone()));
+        }
+        RecordValue::ForeignRecordReference(r) => {
+            refs.insert((r.collection_id.clone(), r.id.clone()));
+        }
+        RecordValue::Map(map) => {
+            for value in map.values() {
+                find_value_references(collection_id, value, refs);
+            }
+        }
+        RecordValue::Array(values) => {
+            for value in values {
+                find_value_references(collection_id, value, refs);
+            }
+        }
+        RecordValue::Number(_)
+        | RecordValue::Boolean(_)
+        | RecordValue::Null
+        | RecordValue::String(_)
+        | RecordValue::PublicKey(_)
+        | RecordValue::Bytes(_) => {}
+    }
+}
+
+pub(crate) fn keys_from_record(
+    record: Option<RecordRoot>,
+) -> Result<BTreeSet<RecordKey>, bincode::Error> {
+    match record.and_then(|mut r| r.remove("keys")) {
+        Some(RecordValue::Bytes(b)) => bincode::deserialize(&b),
+        _ => Ok(BTreeSet::new()),
+    }
+}
+
+pub(crate) fn keys_to_record(keys: &BTreeSet<RecordKey>) -> Result<RecordRoot, bincode::Error> {
+    let mut record = RecordRoot::new();
+    record.insert(
+        "keys".to_string(),
+        RecordValue::Bytes(bincode::serialize(keys)?),
+    );
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::record::{ForeignRecordReference, RecordReference};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_references_key_is_unambiguous() {
+        assert_ne!(references_key("ns/a", "b/c"), references_key("ns/a/b", "c"));
+    }
+
+    #[test]
+    fn test_find_nested_references() {
+        let mut nested = HashMap::new();
+        nested.insert(
+            "manager".to_string(),
+            RecordValue::RecordReference(RecordReference { id: "2".into() }),
+        );
+
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String("1".into()));
+        record.insert("team".to_string(), RecordValue::Map(nested));
+        record.insert(
+            "accounts".to_string(),
+            RecordValue::Array(vec![RecordValue::ForeignRecordReference(
+                ForeignRecordReference {
+                    collection_id: "ns/Account".into(),
+                    id: "a".into(),
+                },
+            )]),
+        );
+
+        assert_eq!(
+            find_references("ns/Person", &record),
+            BTreeSet::from([
+                ("ns/Account".to_string(), "a".to_string()),
+                ("ns/Person".to_string(), "2".to_string()),
+            ])
+        );
+    }
+}
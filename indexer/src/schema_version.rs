@@ -0,0 +1,106 @@
+use schema::{
+    property::PropertyList,
+    record::{RecordRoot, RecordValue},
+    types::Type,
+    Schema,
+};
+use std::collections::HashMap;
+
+/// Field of a stored record that holds the version of the schema the record was written
+/// with. Field names can't start with `$`, so it never clashes with a collection field.
+pub const SCHEMA_VERSION_FIELD: &str = "$schemaVersion";
+
+/// Version of the record shape of a schema, see `Schema::record_version`
+pub fn schema_version(schema: &Schema) -> &str {
+    schema.record_version()
+}
+
+/// Set the schema version of a record that is about to be stored
+pub(crate) fn stamp(schema: &Schema, record: &mut RecordRoot) {
+    record.insert(
+        SCHEMA_VERSION_FIELD.to_string(),
+        RecordValue::String(schema_version(schema).to_string()),
+    );
+}
+
+/// Convert a stored record to the current shape of the schema. Records written with an
+/// older schema get the default value for new required fields, and lose fields that were
+/// removed (new optional fields are left unset). The stored record is not rewritten, so
+/// where queries and indexes still see the stored values, e.g. a query for the default
+/// of a new field does not match the record until the record is next written.
+pub fn upcast(schema: &Schema, mut record: RecordRoot) -> RecordRoot {
+    let version = record.remove(SCHEMA_VERSION_FIELD);
+    if matches!(&version, Some(RecordValue::String(v)) if *v == schema_version(schema)) {
+        return record;
+    }
+
+    RecordRoot(upcast_map(&schema.properties, record.0))
+}
+
+fn upcast_map(
+    properties: &PropertyList,
+    mut values: HashMap<String, RecordValue>,
+) -> HashMap<String, RecordValue> {
+    let mut upcasted = HashMap::with_capacity(values.len());
+
+    for prop in properties.iter() {
+        let value = match (values.remove(prop.name()), &prop.type_) {
+            (Some(RecordValue::Map(map)), Type::Object(object)) => {
+                RecordValue::Map(upcast_map(&object.fields, map))
+            }
+            (Some(value), _) => value,
+            (None, _) if prop.required => RecordValue::default_from_type(&prop.type_),
+            (None, _) => continue,
+        };
+
+        upcasted.insert(prop.name().to_string(), value);
+    }
+
+    upcasted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(code: &str) -> Schema {
+        let mut program = None;
+        let (_, ast) = polylang::parse(code, "ns", &mut program).unwrap();
+        Schema::from_json_str("Person", &serde_json::to_string(&ast).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_version_only_depends_on_fields() {
+        let v1 = schema("collection Person { id: string; name: string; }");
+        let v1_with_index = schema("collection Person { id: string; name: string; @index(name); }");
+        let v2 = schema("collection Person { id: string; name?: string; }");
+
+        assert_eq!(schema_version(&v1), schema_version(&v1_with_index));
+        assert_ne!(schema_version(&v1), schema_version(&v2));
+    }
+
+    #[test]
+    fn test_upcast_nested_object() {
+        let schema =
+            schema("collection Person { id: string; info: { name: string; verified: boolean; }; }");
+
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String("1".to_string()));
+        record.insert(
+            "info".to_string(),
+            RecordValue::Map(HashMap::from([
+                ("name".to_string(), RecordValue::String("John".to_string())),
+                ("nickname".to_string(), RecordValue::String("J".to_string())),
+            ])),
+        );
+
+        let record = upcast(&schema, record);
+        assert_eq!(
+            record.get("info"),
+            Some(&RecordValue::Map(HashMap::from([
+                ("name".to_string(), RecordValue::String("John".to_string())),
+                ("verified".to_string(), RecordValue::Boolean(false)),
+            ])))
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use parking_lot::Mutex;
+use schema::record::{RecordRoot, RecordValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Local key where the usage counters of all collections are stored, usage differs
+/// between nodes so it is not replicated
+pub(crate) const USAGE_LOCAL_KEY: &str = "usage";
+
+/// Number of record reads and writes for a collection, and the size (in bytes) of the
+/// records read or written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub reads: u64,
+    pub writes: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+    }
+}
+
+/// Counts the usage of each collection since the counters were last stored
+#[derive(Default)]
+pub(crate) struct UsageMeter {
+    pending: Mutex<HashMap<String, Usage>>,
+}
+
+impl UsageMeter {
+    pub(crate) fn record_read(&self, collection_id: &str, record: &RecordRoot) {
+        let mut pending = self.pending.lock();
+        let usage = pending.entry(collection_id.to_string()).or_default();
+        usage.reads += 1;
+        usage.read_bytes += record_size(record);
+    }
+
+    /// Record a write of the record, or a delete if `record` is None
+    pub(crate) fn record_write(&self, collection_id: &str, record: Option<&RecordRoot>) {
+        let mut pending = self.pending.lock();
+        let usage = pending.entry(collection_id.to_string()).or_default();
+        usage.writes += 1;
+        usage.write_bytes += record.map(record_size).unwrap_or(0);
+    }
+
+    /// Usage recorded since the counters were last stored
+    pub(crate) fn pending(&self) -> HashMap<String, Usage> {
+        self.pending.lock().clone()
+    }
+
+    /// Take the usage recorded since the counters were last stored, so it can be added
+    /// to the stored counters
+    pub(crate) fn take(&self) -> HashMap<String, Usage> {
+        std::mem::take(&mut *self.pending.lock())
+    }
+
+    /// Put back usage that was taken but could not be stored
+    pub(crate) fn restore(&self, usage: HashMap<String, Usage>) {
+        add_usage(&mut self.pending.lock(), usage);
+    }
+}
+
+pub(crate) fn add_usage(totals: &mut HashMap<String, Usage>, usage: HashMap<String, Usage>) {
+    for (collection_id, usage) in usage {
+        totals.entry(collection_id).or_default().add(&usage);
+    }
+}
+
+pub(crate) fn usage_from_record(
+    record: Option<RecordRoot>,
+) -> Result<HashMap<String, Usage>, bincode::Error> {
+    match record.and_then(|mut r| r.remove("usage")) {
+        Some(RecordValue::Bytes(b)) => bincode::deserialize(&b),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+pub(crate) fn usage_to_record(
+    usage: &HashMap<String, Usage>,
+) -> Result<RecordRoot, bincode::Error> {
+    let mut record = RecordRoot::new();
+    record.insert(
+        "usage".to_string(),
+        RecordValue::Bytes(bincode::serialize(usage)?),
+    );
+    Ok(record)
+}
+
+fn record_size(record: &RecordRoot) -> u64 {
+    bincode::serialized_size(record).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str) -> RecordRoot {
+        let mut record = RecordRoot::new();
+        record.insert("name".to_string(), RecordValue::String(name.to_string()));
+        record
+    }
+
+    #[test]
+    fn test_take_and_restore() {
+        let meter = UsageMeter::default();
+        meter.record_read("ns/A", &record("a"));
+        meter.record_write("ns/A", Some(&record("a")));
+        meter.record_write("ns/B", None);
+
+        let taken = meter.take();
+        assert!(meter.pending().is_empty());
+        assert_eq!(taken["ns/A"].reads, 1);
+        assert_eq!(taken["ns/A"].writes, 1);
+        assert_eq!(taken["ns/A"].read_bytes, taken["ns/A"].write_bytes);
+        assert_eq!(
+            taken["ns/B"],
+            Usage {
+                writes: 1,
+                ..Default::default()
+            }
+        );
+
+        meter.record_read("ns/A", &record("a"));
+        meter.restore(taken);
+        assert_eq!(meter.pending()["ns/A"].reads, 2);
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let mut usage = HashMap::new();
+        usage.insert(
+            "ns/A".to_string(),
+            Usage {
+                reads: 1,
+                writes: 2,
+                read_bytes: 3,
+                write_bytes: 4,
+            },
+        );
+
+        let record = usage_to_record(&usage).unwrap();
+        assert_eq!(usage_from_record(Some(record)).unwrap(), usage);
+        assert!(usage_from_record(None).unwrap().is_empty());
+    }
+}
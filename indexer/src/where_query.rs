@@ -1,4 +1,3 @@
-use super::cursor::{Cursor, CursorDirection};
 use schema::{
     field_path::FieldPath,
     index::{EitherIndexField, Index, IndexDirection, IndexField},
@@ -8,7 +7,7 @@ use schema::{
     Schema,
 };
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, collections::HashMap};
+use std::{borrow::Cow, cmp::Ordering, collections::HashMap};
 
 pub type Result<T> = std::result::Result<T, WhereQueryError>;
 
@@ -32,18 +31,24 @@ pub enum WhereQueryUserError {
     #[error("you cannot filter/sort by field {0}")]
     CannotFilterOrSortByField(String),
 
+    #[error("unknown field {field} in where query, the field is not in the collection schema")]
+    UnknownField { field: String },
+
     #[error("unexpected query field: {}", .field.as_deref().unwrap_or("unknown"))]
     InvalidWhereQueryField { field: Option<String> },
 
-    #[error("where query value at field \"{}\" does not match the schema type, expected type: {expected_type}, got value: {value}", .field.as_deref().unwrap_or("unknown"))]
-    InvalidWhereQueryValue {
-        value: serde_json::Value,
-        expected_type: String,
-        field: Option<String>,
+    #[error("where query value at field {field:?} does not match the schema type, expected type: {expected}, got value: {got}")]
+    FieldTypeMismatch {
+        field: String,
+        expected: String,
+        got: serde_json::Value,
     },
 
     #[error("can only sort by inequality if it's the same direction")]
     InequalitySortDirectionMismatch,
+
+    #[error("$contains can only be used on array fields, got field {0}")]
+    ContainsRequiresArrayField(String),
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -116,138 +121,13 @@ impl<'a> WhereQuery<'a> {
         true
     }
 
-    // Applies a cursor to the query, updating the query to only return records after the cursor.
-    //
-    // # Example
-    //
-    // ## Cursor (ASC / After)
-    //
-    // Given the original query:
-    // ```sql
-    // WHERE
-    //     name == calum && group > 0 and group <= 3 && age > 10
-    // ORDER BY name, group, age ASC
-    // ```
-    //
-    // After applying the cursor, it would look like:
-    // ```sql
-    // WHERE
-    //     name == calum && group >= 2 and group <= 3 && age >= 30
-    // ORDER BY name, group, age ASC
-    // ```
-    //
-    // The record list (before applying the cursor) would look like this:
-    // ```
-    // calum, 1, 20, 4  <- lower bound
-    // calum, 2, 20, 2
-    // calum, 2, 30, 1  <- this is the cursor
-    // calum, 2, 40, 7
-    // calum, 3, 10, 3  <- upper bound
-    // ---
-    // john, 1, 20, 5
-    // ```
-    //
-    // ## Cursor (DESC / After)
-    //
-    // Given the original query:
-    // ```sql
-    // WHERE
-    //     name == calum && group > 0 and group <= 3 && age > 10
-    // ORDER BY name, group, age DESC
-    // ```
-    //
-    // After applying the cursor, it would look like:
-    // ```sql
-    // WHERE
-    //     name == calum && group >= 2 and group <= 3 && age > 10 && age <= 30
-    // ORDER BY name, group, age DESC
-    // ```
-    //
-    // The record list (DESC) (before applying the cursor) would look like this:
-    // ```
-    //
-    // calum, 2, 40, 7  <- lower bound
-    // calum, 2, 30, 1  <- this is the cursor
-    // calum, 1, 20, 4
-    // calum, 2, 20, 2
-    // calum, 3, 10, 3  <- upper bound
-    // ---
-    // john, 1, 20, 5
-    // ```
-    // ## Filter Conditions
-    // * If equality filter, leave as is
-    // * If range filter (>, >=, <, <=):
-    //     * If ASC + (>, >=), update to >= `<cursor_record_value>`
-    //     * If DESC + (<, <=), update to <= `<cursor_record_value>`
-    //
-    // `index selection` - Determined by `where_query` + `order_by`
-    //
-    // `direction` - Determined by `order_by`
-    //
-    // `lower bound` - Determined by `cursor`
-    //
-    // `upper bound` - Determined by `where_query`
-    pub fn apply_cursor(
-        &mut self,
-        cursor: Cursor,
-        cursor_dir: CursorDirection,
-        order_by: &[IndexField],
-    ) {
-        // let values = cursor.values.with_static();
-        for (key, value) in &mut self.0 {
-            // We only care about inequality filters
-            if let WhereNode::Inequality(node) = value {
-                // Determine which direction we want to continue in (which determines
-                // the inequality filter to update)
-                let forward = is_inequality_forwards(key, order_by, &cursor_dir);
-
-                // TODO: Only add fields in the cursor, or should we add these as Null?
-                if let Some(cursor_field_value) = cursor.0.values.get(key) {
-                    if forward && (node.gt.is_some() || node.gte.is_some()) {
-                        // Only update if the cursor has the value for the field
-                        node.gte = Some(WhereValue(cursor_field_value.clone().with_static()));
-                        node.gt = None;
-                    }
-
-                    if !forward && (node.lt.is_some() || node.lte.is_some()) {
-                        // Only update if the cursor has the value for the field
-                        node.lte = Some(WhereValue(cursor_field_value.clone().with_static()));
-                        node.lt = None;
-                    }
-                }
-            }
-        }
-
-        // If id field not present, we should add it to the query so we don't end up
-        // sending the last record in the previous query back to the user
-        let id = FieldPath::id();
-        if let std::collections::hash_map::Entry::Vacant(e) = self.0.entry(id.clone()) {
-            let forward = is_inequality_forwards(&id, order_by, &cursor_dir);
-            let where_value = Some(WhereValue(cursor.0.record_id.with_static()));
-
-            e.insert(match forward {
-                true => WhereNode::Inequality(Box::new(WhereInequality {
-                    gt: where_value,
-                    gte: None,
-                    lt: None,
-                    lte: None,
-                })),
-                false => WhereNode::Inequality(Box::new(WhereInequality {
-                    gt: None,
-                    gte: None,
-                    lt: where_value,
-                    lte: None,
-                })),
-            });
-        }
-    }
-
     fn index_requirements(&self, sorts: &[IndexField]) -> Result<Vec<EitherIndexField>> {
         let mut requirements = vec![];
 
         for (field, node) in &self.0 {
             match node {
-                WhereNode::Equality(_) => {
+                // Contains is an equality match against an element of an array index
+                WhereNode::Equality(_) | WhereNode::Contains(_) => {
                     let path: Vec<String> = field.0.iter().map(|x| x.to_string()).collect();
 
                     requirements.push(EitherIndexField {
@@ -269,7 +149,7 @@ impl<'a> WhereQuery<'a> {
 
         for (field, node) in &self.0 {
             match node {
-                WhereNode::Equality(_) => {}
+                WhereNode::Equality(_) | WhereNode::Contains(_) => {}
                 WhereNode::Inequality(ineq) => {
                     let direction = if ineq.lt.is_some() || ineq.lte.is_some() {
                         IndexDirection::Descending
@@ -377,18 +257,40 @@ impl<'a> WhereQuery<'a> {
         })
     }
 
+    /// Check every field in the query is in the schema, so queries on unknown fields
+    /// are rejected before an index is looked up
+    pub fn validate_against(&self, schema: &Schema) -> Result<()> {
+        for path in self.0.keys() {
+            if schema.properties.get_path_type(path).is_none() {
+                return Err(WhereQueryUserError::UnknownField {
+                    field: path.to_string(),
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
     // TODO: needs optimizing, consider using RecordValue insead of IndexValue
     pub fn cast(&mut self, schema: &Schema) -> Result<()> {
         for (path, node) in &mut self.0 {
-            let prop = schema.properties.get_path(path).ok_or(
+            let type_ = schema.properties.get_path_type(path).ok_or(
                 WhereQueryUserError::InvalidWhereQueryField {
                     field: Some(path.to_string()),
                 },
             )?;
 
             match node {
-                WhereNode::Equality(val) => val.cast(&prop.type_, path)?,
-                WhereNode::Inequality(ineq) => ineq.cast(&prop.type_, path)?,
+                WhereNode::Equality(val) => val.cast(type_, path)?,
+                WhereNode::Inequality(ineq) => ineq.cast(type_, path)?,
+                WhereNode::Contains(contains) => match type_ {
+                    Type::Array(a) => contains.contains.cast(&a.value, path)?,
+                    _ => {
+                        return Err(WhereQueryUserError::ContainsRequiresArrayField(
+                            path.to_string(),
+                        ))?
+                    }
+                },
             }
         }
 
@@ -404,9 +306,9 @@ impl<'a> WhereQuery<'a> {
             .filter_map(|(k, values)| match values {
                 WhereNode::Equality(WhereValue(v)) => {
                     let rv: RecordValue = RecordValue::from(v.clone());
-                    let prop = schema.properties.get_path(k)?;
+                    let type_ = schema.properties.get_path_type(k)?;
                     // TODO: we should return the error
-                    let v = rv.cast(&prop.type_, k).ok()?;
+                    let v = rv.cast(type_, k).ok()?;
                     Some((k, v))
                 }
                 _ => None,
@@ -419,38 +321,20 @@ impl<'a> WhereQuery<'a> {
     }
 }
 
-/// Determines if the inequality projection should be forwards (gt/gte) or backwards (lt/lte)
-fn is_inequality_forwards(
-    key: &FieldPath,
-    order_by: &[IndexField],
-    cursor_dir: &CursorDirection,
-) -> bool {
-    // Find the sort order direction for a key
-    let order_for_key = order_by
-        .iter()
-        .find(|field| &field.path == key)
-        .map(|field| field.direction)
-        .unwrap_or(IndexDirection::Ascending);
-
-    should_invert(&order_for_key, cursor_dir)
-}
-
-fn should_invert(order_for_key: &IndexDirection, cursor_dir: &CursorDirection) -> bool {
-    // Determine which direction we want to continue in (which determines
-    // the inequality filter to update)
-    match (order_for_key, cursor_dir) {
-        (IndexDirection::Ascending, CursorDirection::After) => true,
-        (IndexDirection::Ascending, CursorDirection::Before) => false,
-        (IndexDirection::Descending, CursorDirection::After) => false,
-        (IndexDirection::Descending, CursorDirection::Before) => true,
-    }
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum WhereNode<'a> {
     Equality(WhereValue<'a>),
     Inequality(Box<WhereInequality<'a>>),
+    Contains(WhereContains<'a>),
+}
+
+/// Matches records where the array field contains the value, e.g. `{"tags": {"$contains": "x"}}`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WhereContains<'a> {
+    #[serde(rename = "$contains")]
+    pub contains: WhereValue<'a>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -459,7 +343,14 @@ pub struct WhereValue<'a>(pub IndexValue<'a>);
 impl<'a> WhereValue<'a> {
     fn cast(&mut self, type_: &Type, path: &FieldPath) -> Result<()> {
         let rv: RecordValue = RecordValue::from(self.0.clone());
-        let v = rv.cast(type_, path)?;
+        let v =
+            rv.clone()
+                .cast(type_, path)
+                .map_err(|_| WhereQueryUserError::FieldTypeMismatch {
+                    field: path.to_string(),
+                    expected: type_.to_string(),
+                    got: rv.into(),
+                })?;
         // We've just converted from a RecordValue to a IndexValue
         #[allow(clippy::unwrap_used)]
         let index_value: IndexValue = v.try_into().unwrap();
@@ -468,6 +359,9 @@ impl<'a> WhereValue<'a> {
     }
 }
 
+/// Range filter on a field. A `$startsWith` filter on a string field is converted to a
+/// range, e.g. `{"$startsWith": "user/"}` is `{"$gte": "user/", "$lt": "user0"}`, so it can
+/// be answered by scanning the field's index.
 #[derive(Debug, Serialize, Default, Clone)]
 pub struct WhereInequality<'a> {
     #[serde(rename = "$gt")]
@@ -543,6 +437,28 @@ impl<'de, 'a> Deserialize<'de> for WhereInequality<'a> {
             );
         }
 
+        if let Some(value) = map.remove("$startsWith") {
+            let serde_json::Value::String(prefix) = value else {
+                return Err(serde::de::Error::custom(
+                    "invalid $startsWith: expected a string",
+                ));
+            };
+
+            if inequality.gt.is_some()
+                || inequality.gte.is_some()
+                || inequality.lt.is_some()
+                || inequality.lte.is_some()
+            {
+                return Err(serde::de::Error::custom(
+                    "$startsWith cannot be combined with $gt, $gte, $lt or $lte",
+                ));
+            }
+
+            inequality.lt = prefix_upper_bound(&prefix)
+                .map(|upper| WhereValue(IndexValue::String(Cow::Owned(upper))));
+            inequality.gte = Some(WhereValue(IndexValue::String(Cow::Owned(prefix))));
+        }
+
         if !map.is_empty() {
             return Err(serde::de::Error::custom("too many fields in inequality"));
         }
@@ -551,6 +467,23 @@ impl<'de, 'a> Deserialize<'de> for WhereInequality<'a> {
     }
 }
 
+/// The smallest string that is greater than every string starting with `prefix`, or `None`
+/// if there is no such string (i.e. every string starts with an empty prefix)
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut chars = prefix.chars().collect::<Vec<_>>();
+
+    // Increment the last char, dropping chars that can't be incremented
+    while let Some(c) = chars.pop() {
+        // Skips the surrogate range, which are not valid chars
+        if let Some(next) = (c as u32 + 1..=char::MAX as u32).find_map(char::from_u32) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -574,6 +507,30 @@ mod test {
         let _: WhereQuery = serde_json::from_str(query_str).unwrap();
     }
 
+    #[test]
+    fn test_contains_serialization() {
+        let query: WhereQuery<'_> = WhereQuery(
+            [(
+                "tags".into(),
+                WhereNode::Contains(WhereContains {
+                    contains: WhereValue(IndexValue::String("rust".into())),
+                }),
+            )]
+            .into(),
+        );
+        let query_str = r#"{"tags":{"$contains":"rust"}}"#;
+
+        assert_eq!(query_str, serde_json::to_string(&query).unwrap());
+
+        let query: WhereQuery = serde_json::from_str(query_str).unwrap();
+        assert!(matches!(
+            query.0.get(&FieldPath::from("tags")),
+            Some(WhereNode::Contains(WhereContains {
+                contains: WhereValue(IndexValue::String(s)),
+            })) if s == "rust"
+        ));
+    }
+
     #[test]
     fn test_inequality_serialization() {
         let query: WhereQuery<'_> = WhereQuery(
@@ -599,4 +556,27 @@ mod test {
 
         let _: WhereQuery = serde_json::from_str(query_str).unwrap();
     }
+
+    #[test]
+    fn test_starts_with_is_a_prefix_range() {
+        let query: WhereQuery = serde_json::from_str(r#"{"id":{"$startsWith":"user/"}}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&query).unwrap(),
+            r#"{"id":{"$gte":"user/","$lt":"user0"}}"#
+        );
+
+        assert!(serde_json::from_str::<WhereQuery>(r#"{"id":{"$startsWith":1}}"#).is_err());
+        assert!(
+            serde_json::from_str::<WhereQuery>(r#"{"id":{"$startsWith":"a","$gt":"b"}}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound("abc").as_deref(), Some("abd"));
+        assert_eq!(prefix_upper_bound("a\u{10FFFF}").as_deref(), Some("b"));
+        assert_eq!(prefix_upper_bound("\u{D7FF}").as_deref(), Some("\u{E000}"));
+        assert_eq!(prefix_upper_bound("\u{10FFFF}"), None);
+        assert_eq!(prefix_upper_bound(""), None);
+    }
 }
@@ -8,7 +8,9 @@ use crate::{
 use async_recursion::async_recursion;
 use futures::{StreamExt, TryStreamExt};
 use indexer::{
-    adaptor::{self, IndexerAdaptor, SnapshotValue},
+    adaptor::{self, AuditEntry, CollectionStats, IndexerAdaptor, SnapshotValue},
+    cursor::Cursor,
+    references::RecordKey,
     where_query::WhereQuery,
     IndexerChange,
 };
@@ -17,27 +19,66 @@ use schema::COLLECTION_SCHEMA;
 use schema::{
     field_path::FieldPath,
     index::{IndexDirection, IndexField},
+    index_value::IndexValue,
     record::{json_to_record, record_to_json, RecordRoot, RecordValue},
     Schema,
 };
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
     path::Path,
     pin::Pin,
+    sync::Arc,
     time::{Duration, SystemTime},
 };
 use tracing::{self, error, warn};
 
+/// Values of the cursor record for each field of the index, in index order
+fn cursor_index_values(
+    cursor: &Cursor<'_>,
+    index_fields: &[IndexField],
+) -> Vec<Cow<'static, IndexValue<'static>>> {
+    let id = FieldPath::id();
+    index_fields
+        .iter()
+        .map(|field| {
+            let value = if field.path == id {
+                &cursor.0.record_id
+            } else {
+                cursor
+                    .0
+                    .values
+                    .get(&field.path)
+                    .unwrap_or(&IndexValue::Null)
+            };
+            Cow::Owned(value.clone().with_static())
+        })
+        .collect()
+}
+
 pub struct CollectionMetadata {
     pub last_record_updated_at: SystemTime,
+    pub record_count: u64,
 }
 
 pub struct RecordMetadata {
     pub updated_at: SystemTime,
 }
 
+pub struct HistoryMetadata {
+    /// Height of the first commit after record history was enabled
+    pub start_height: usize,
+    /// Height of the last commit
+    pub height: usize,
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// System record that is set once the audit entries have been backfilled under their
+/// collection
+const AUDIT_COLLECTION_KEYS: &str = "audit/collectionKeys";
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("collection not found")]
@@ -49,6 +90,9 @@ pub enum Error {
     #[error("metadata is missing updatedAt")]
     MetadataMissingUpdatedAt,
 
+    #[error("history metadata is missing {field}")]
+    HistoryMetadataMissingField { field: &'static str },
+
     #[error("no index found matching the query")]
     NoIndexFoundMatchingTheQuery,
 
@@ -78,26 +122,488 @@ pub enum Error {
 
     #[error("schema error")]
     Record(#[from] schema::record::RecordError),
+
+    #[error("bincode error")]
+    Bincode(#[from] bincode::Error),
+}
+
+impl Error {
+    /// Whether the operation may succeed if it is retried
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::StoreError(err) => err.is_retryable(),
+            Error::IndexerAdaptor(err) => err.is_retryable(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct RocksDBAdaptor {
     store: Store,
+    audit_log: bool,
+    history_retention: Option<usize>,
+    /// Held while changes are written and committed to the store, as the store's pending
+    /// writes are shared, so concurrent commits are applied one after another
+    commit_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl RocksDBAdaptor {
     pub fn new(config: impl AsRef<Path>) -> Self {
-        Self {
-            store: Store::open(config).unwrap(),
-        }
+        Self::open(config, false).unwrap()
+    }
+
+    /// Open the adaptor's store, see `repair_on_corruption` in `Store::open`
+    pub fn open(path: impl AsRef<Path>, repair_on_corruption: bool) -> Result<Self> {
+        Ok(Self {
+            store: Store::open(path, repair_on_corruption)?,
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        })
+    }
+
+    /// Record every committed change in an append-only audit log
+    pub fn with_audit_log(mut self, enabled: bool) -> Self {
+        self.audit_log = enabled;
+        self
+    }
+
+    /// Keep the previous values of records, so they can be read as of any of the last
+    /// `blocks` committed heights. History is disabled if `None`.
+    pub fn with_history_retention(mut self, blocks: Option<usize>) -> Self {
+        self.history_retention = blocks;
+        self
     }
 
     pub fn snapshot(&self, chunk_size: usize) -> snapshot::SnapshotIterator {
         self.store.snapshot(chunk_size)
     }
 
-    pub fn restore(&self, data: snapshot::SnapshotChunk) -> Result<()> {
-        Ok(self.store.restore(data)?)
+    pub async fn restore(&self, data: snapshot::SnapshotChunk) -> Result<()> {
+        Ok(self.store.restore(data).await?)
+    }
+
+    /// Create an audit log entry for each change. Must be called before the changes are
+    /// applied, so each change can be diffed against the previous value of its record.
+    async fn audit_entries(
+        &self,
+        height: usize,
+        changes: &[IndexerChange],
+    ) -> Result<Vec<AuditEntry>> {
+        let timestamp = SystemTime::now();
+        // Value of records changed earlier in the same commit
+        let mut latest = HashMap::<(&str, &str), Option<&RecordRoot>>::new();
+        let mut entries = Vec::with_capacity(changes.len());
+
+        for change in changes {
+            let (collection_id, record_id, record) = match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    record,
+                } => (collection_id, record_id, Some(record)),
+                IndexerChange::Delete {
+                    collection_id,
+                    record_id,
+                } => (collection_id, record_id, None),
+            };
+
+            let key = (collection_id.as_str(), record_id.as_str());
+            let entry = match latest.get(&key) {
+                Some(previous) => AuditEntry::from_change(height, change, *previous, timestamp),
+                None => {
+                    let previous = self._get(collection_id, record_id).await?;
+                    AuditEntry::from_change(height, change, previous.as_ref(), timestamp)
+                }
+            };
+
+            entries.push(entry);
+            latest.insert(key, record);
+        }
+
+        Ok(entries)
+    }
+
+    /// Add the audit log entries to the pending batch, so they are written in the same
+    /// transaction as the changes. Each entry is also written under its collection, so
+    /// the log can be read for one collection without scanning every entry. The log is
+    /// append-only, the entries of deleted collections are kept.
+    async fn append_audit_log(&self, height: usize, entries: &[AuditEntry]) -> Result<()> {
+        self.backfill_collection_audit_keys().await?;
+        let first_seq = self.next_audit_seq(height)?;
+
+        for (i, entry) in entries.iter().enumerate() {
+            let seq = first_seq + i as u32;
+            let value = store::Value::AuditValue(entry);
+            let key = keys::Key::new_audit(height as u64, seq);
+            self.store.set(&key, &value).await?;
+
+            let key =
+                keys::Key::new_collection_audit(entry.collection_id.clone(), height as u64, seq)?;
+            self.store.set(&key, &value).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The seq of the next audit entry at a height. Changes can be committed more than
+    /// once at the same height (e.g. by an import), so entries are appended after any
+    /// existing entries rather than overwriting them.
+    fn next_audit_seq(&self, height: usize) -> Result<u32> {
+        let lower = keys::Key::new_audit(height as u64, 0);
+        let upper = keys::Key::new_audit(height as u64, u32::MAX);
+        let Some(entry) = self.store.list(&lower, &upper, true)?.next() else {
+            return Ok(0);
+        };
+
+        match keys::Key::deserialize(&entry?.0)? {
+            keys::Key::Audit { seq, .. } => Ok(seq + 1),
+            _ => Ok(0),
+        }
+    }
+
+    /// Write the collection keys of audit entries appended before entries were written
+    /// under their collection, once per store
+    async fn backfill_collection_audit_keys(&self) -> Result<()> {
+        if self
+            ._get_system_record(AUDIT_COLLECTION_KEYS)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let lower = keys::Key::new_audit(0, 0);
+        let upper = keys::Key::new_audit(u64::MAX, u32::MAX);
+        for entry in self.store.list(&lower, &upper, false)? {
+            let (key, value) = entry?;
+            let keys::Key::Audit { height, seq } = keys::Key::deserialize(&key)? else {
+                continue;
+            };
+            let entry = AuditEntry::decode(&value)?;
+            let key = keys::Key::new_collection_audit(entry.collection_id.clone(), height, seq)?;
+            self.store
+                .set(&key, &store::Value::AuditValue(&entry))
+                .await?;
+        }
+
+        self._set_system_record(AUDIT_COLLECTION_KEYS, &RecordRoot::new())
+            .await
+    }
+
+    fn _list_referrers(&self, target: &RecordKey) -> Result<Vec<RecordKey>> {
+        let lower = keys::Key::new_referrer(target.0.clone(), target.1.clone(), None)?;
+        let upper = lower.clone().wildcard();
+
+        let mut referrers = vec![];
+        for entry in self.store.list(&lower, &upper, false)? {
+            let (key, _) = entry?;
+            if let keys::Key::Referrer {
+                source: Some((collection_id, record_id)),
+                ..
+            } = keys::Key::deserialize(&key)?
+            {
+                referrers.push((collection_id.into_owned(), record_id.into_owned()));
+            }
+        }
+
+        Ok(referrers)
+    }
+
+    fn _audit_log(
+        &self,
+        collection_id: Option<&str>,
+        from_height: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>> {
+        let (lower, upper) = match collection_id {
+            Some(collection_id) => (
+                keys::Key::new_collection_audit(collection_id.to_string(), from_height as u64, 0)?,
+                keys::Key::new_collection_audit(collection_id.to_string(), u64::MAX, u32::MAX)?,
+            ),
+            None => (
+                keys::Key::new_audit(from_height as u64, 0),
+                keys::Key::new_audit(u64::MAX, u32::MAX),
+            ),
+        };
+
+        let mut entries = vec![];
+        for entry in self.store.list(&lower, &upper, false)?.take(limit) {
+            let (_, value) = entry?;
+            entries.push(AuditEntry::decode(&value)?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Add a version of each changed record to the pending batch, and remove versions
+    /// that are no longer needed to read any height in the retention window, or that
+    /// belong to a deleted collection. Must be called before the changes are applied, as
+    /// records that existed before history was enabled have their previous value recorded
+    /// as a version too.
+    async fn append_history(
+        &self,
+        height: usize,
+        changes: &[IndexerChange],
+        retention: usize,
+        deleted_collections: &HashMap<String, usize>,
+    ) -> Result<()> {
+        let start_height = match self.get_history_metadata().await? {
+            Some(metadata) => metadata.start_height,
+            None => height,
+        };
+        let cutoff = height.saturating_sub(retention);
+
+        self.prune_history(cutoff).await?;
+
+        // Changes can be committed more than once at the same height, so expiry keys are
+        // appended after those of earlier commits rather than overwriting them
+        let mut expiry_seq = self.next_version_expiry_seq(height)?;
+
+        for (i, change) in changes.iter().enumerate() {
+            let (collection_id, record_id, record) = match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    record,
+                } => (collection_id, record_id, Some(record)),
+                IndexerChange::Delete {
+                    collection_id,
+                    record_id,
+                } => (collection_id, record_id, None),
+            };
+
+            let versions = match deleted_collections.get(collection_id) {
+                // The history of a deleted collection's records is removed with the records
+                Some(&deleted_at) if i < deleted_at => {
+                    for (_, key) in self.version_keys(collection_id, record_id)? {
+                        self.store.delete(&keys::Key::deserialize(&key)?).await?;
+                    }
+                    continue;
+                }
+                // The record belongs to a collection re-created after the delete, its
+                // history starts at this change
+                Some(_) => {
+                    let key = keys::Key::new_version(
+                        collection_id.to_string(),
+                        record_id.to_string(),
+                        height as u64,
+                    )?;
+                    self.store
+                        .set(&key, &store::Value::VersionValue(record))
+                        .await?;
+                    continue;
+                }
+                None => self.version_keys(collection_id, record_id)?,
+            };
+
+            // Versions written before their expiry was recorded are removed when the
+            // record changes, keeping the latest version at or before the cutoff
+            let mut expired = versions
+                .iter()
+                .filter(|(version, _)| *version <= cutoff as u64)
+                .collect::<Vec<_>>();
+            expired.pop();
+            for (_, key) in expired {
+                self.store.delete(&keys::Key::deserialize(key)?).await?;
+            }
+
+            let superseded = match versions.last() {
+                Some((_, key)) => Some(key.to_vec()),
+                // The record has not changed since history was enabled, so its current
+                // value is the value for every height before this change
+                None => match self._get(collection_id, record_id).await? {
+                    Some(previous) => {
+                        let key = keys::Key::new_version(
+                            collection_id.to_string(),
+                            record_id.to_string(),
+                            start_height.saturating_sub(1) as u64,
+                        )?;
+                        self.store
+                            .set(&key, &store::Value::VersionValue(Some(&previous)))
+                            .await?;
+                        Some(key.serialize()?)
+                    }
+                    None => None,
+                },
+            };
+
+            // The latest version is superseded by this change, so it's only needed until
+            // this height leaves the retention window
+            if let Some(superseded) = superseded {
+                self.store
+                    .set(
+                        &keys::Key::new_version_expiry(height as u64, expiry_seq),
+                        &store::Value::VersionExpiryValue(&superseded),
+                    )
+                    .await?;
+                expiry_seq += 1;
+            }
+
+            let key = keys::Key::new_version(
+                collection_id.to_string(),
+                record_id.to_string(),
+                height as u64,
+            )?;
+            self.store
+                .set(&key, &store::Value::VersionValue(record))
+                .await?;
+
+            // Once the delete leaves the retention window, reads of the record at any
+            // height in the window fall back to the deleted record without its tombstone
+            if record.is_none() {
+                self.store
+                    .set(
+                        &keys::Key::new_version_expiry(height as u64, expiry_seq),
+                        &store::Value::VersionExpiryValue(&key.serialize()?),
+                    )
+                    .await?;
+                expiry_seq += 1;
+            }
+        }
+
+        self.set_history_metadata(&HistoryMetadata {
+            start_height,
+            height,
+        })
+        .await
+    }
+
+    /// Remove versions that were superseded at or before the cutoff, as the versions
+    /// that superseded them are enough to read every height in the retention window
+    async fn prune_history(&self, cutoff: usize) -> Result<()> {
+        let lower = keys::Key::new_version_expiry(0, 0);
+        let upper = keys::Key::new_version_expiry(cutoff as u64 + 1, 0);
+
+        for entry in self.store.list(&lower, &upper, false)? {
+            let (key, version) = entry?;
+            self.store
+                .delete(&keys::Key::deserialize(&version)?)
+                .await?;
+            self.store.delete(&keys::Key::deserialize(&key)?).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The seq of the next version expiry key at a height, after any keys committed
+    /// at the same height
+    fn next_version_expiry_seq(&self, height: usize) -> Result<u32> {
+        let lower = keys::Key::new_version_expiry(height as u64, 0);
+        let upper = keys::Key::new_version_expiry(height as u64, u32::MAX);
+        let Some(entry) = self.store.list(&lower, &upper, true)?.next() else {
+            return Ok(0);
+        };
+
+        match keys::Key::deserialize(&entry?.0)? {
+            keys::Key::VersionExpiry { seq, .. } => Ok(seq + 1),
+            _ => Ok(0),
+        }
+    }
+
+    /// Committed version keys of a record, ordered by height
+    fn version_keys(&self, collection_id: &str, record_id: &str) -> Result<Vec<(u64, Box<[u8]>)>> {
+        let lower = keys::Key::new_version(collection_id.to_string(), record_id.to_string(), 0)?;
+        let upper =
+            keys::Key::new_version(collection_id.to_string(), record_id.to_string(), u64::MAX)?;
+
+        let mut versions = vec![];
+        for entry in self.store.list(&lower, &upper, false)? {
+            let (key, _) = entry?;
+            let keys::Key::Version { height, .. } = keys::Key::deserialize(&key)? else {
+                continue;
+            };
+            versions.push((height, key));
+        }
+
+        Ok(versions)
+    }
+
+    async fn _get_at_height(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+        height: usize,
+    ) -> Result<Option<RecordRoot>> {
+        let unavailable = || adaptor::Error::HistoryUnavailable { height };
+
+        let Some(retention) = self.history_retention else {
+            return Err(unavailable())?;
+        };
+        let Some(metadata) = self.get_history_metadata().await? else {
+            return Err(unavailable())?;
+        };
+        let oldest_height = metadata
+            .start_height
+            .max(metadata.height.saturating_sub(retention));
+        if height < oldest_height || height > metadata.height {
+            return Err(unavailable())?;
+        }
+
+        let lower = keys::Key::new_version(collection_id.to_string(), record_id.to_string(), 0)?;
+        let upper =
+            keys::Key::new_version(collection_id.to_string(), record_id.to_string(), u64::MAX)?;
+
+        let mut has_later_versions = false;
+        for entry in self.store.list(&lower, &upper, true)? {
+            let (key, value) = entry?;
+            let keys::Key::Version {
+                height: version, ..
+            } = keys::Key::deserialize(&key)?
+            else {
+                continue;
+            };
+            if version <= height as u64 {
+                return Ok(bincode::deserialize(&value)?);
+            }
+            has_later_versions = true;
+        }
+
+        // A record without any versions has not changed since history was enabled
+        if has_later_versions {
+            Ok(None)
+        } else {
+            self._get(collection_id, record_id).await
+        }
+    }
+
+    async fn get_history_metadata(&self) -> Result<Option<HistoryMetadata>> {
+        let Some(record) = self._get_system_record("history/metadata").await? else {
+            return Ok(None);
+        };
+
+        let height_field = |field: &'static str| -> Result<usize> {
+            match record.get_path(&FieldPath::from(field)) {
+                Some(RecordValue::String(s)) => Ok(s.parse::<usize>()?),
+                _ => Err(Error::HistoryMetadataMissingField { field }),
+            }
+        };
+
+        Ok(Some(HistoryMetadata {
+            start_height: height_field("startHeight")?,
+            height: height_field("height")?,
+        }))
+    }
+
+    async fn set_history_metadata(&self, metadata: &HistoryMetadata) -> Result<()> {
+        self._set_system_record(
+            "history/metadata",
+            &RecordRoot(
+                [
+                    (
+                        "startHeight".to_string(),
+                        RecordValue::String(metadata.start_height.to_string()),
+                    ),
+                    (
+                        "height".to_string(),
+                        RecordValue::String(metadata.height.to_string()),
+                    ),
+                ]
+                .into(),
+            ),
+        )
+        .await
     }
 
     pub async fn _get(&self, collection_id: &str, record_id: &str) -> Result<Option<RecordRoot>> {
@@ -117,6 +623,7 @@ impl RocksDBAdaptor {
         where_query: WhereQuery<'_>,
         order_by: &[IndexField],
         reverse: bool,
+        cursor: Option<&Cursor<'_>>,
     ) -> Result<Pin<Box<dyn futures::Stream<Item = RecordRoot> + '_ + Send>>> {
         let schema = self.get_schema(collection_id).await?.unwrap();
 
@@ -141,7 +648,7 @@ impl RocksDBAdaptor {
         )?;
 
         // Owned key range of the query
-        let key_range = KeyRange {
+        let mut key_range = KeyRange {
             lower: key_range.lower.with_static(),
             upper: key_range.upper.with_static(),
         };
@@ -157,6 +664,31 @@ impl RocksDBAdaptor {
             reverse_index
         };
 
+        // Seek to the cursor's key in the index, the cursor record itself is excluded
+        if let Some(cursor) = cursor {
+            let cursor_key = keys::Key::new_index(
+                collection_id.to_string(),
+                &index.fields.iter().map(|f| &f.path).collect::<Vec<_>>(),
+                &index.fields.iter().map(|f| f.direction).collect::<Vec<_>>(),
+                cursor_index_values(cursor, &index.fields),
+            )?;
+
+            if reverse_index {
+                if keys::comparator(&cursor_key.serialize()?, &key_range.upper.serialize()?)
+                    == Ordering::Less
+                {
+                    key_range.upper = cursor_key;
+                }
+            } else {
+                let cursor_key = cursor_key.wildcard();
+                if keys::comparator(&cursor_key.serialize()?, &key_range.lower.serialize()?)
+                    == Ordering::Greater
+                {
+                    key_range.lower = cursor_key;
+                }
+            }
+        }
+
         let res = futures::stream::iter(self.store.list(
             &key_range.lower,
             &key_range.upper,
@@ -207,7 +739,8 @@ impl RocksDBAdaptor {
             .set(&data_key, &store::Value::DataValue(record))
             .await?;
 
-        self.update_metadata(collection_id, &SystemTime::now())
+        let record_count_delta = if old_record.is_none() { 1 } else { 0 };
+        self.update_metadata(collection_id, &SystemTime::now(), record_count_delta)
             .await?;
 
         self.update_record_metadata(collection_id, record_id, &SystemTime::now())
@@ -261,25 +794,70 @@ impl RocksDBAdaptor {
             _ => return Err(Error::MetadataMissingLastRecordUpdatedAt),
         };
 
+        // Metadata written before record counts were tracked has no recordCount, so the
+        // records are counted until the count is written by the next update
+        let record_count = match record.get_path(&FieldPath::from("recordCount")) {
+            Some(RecordValue::String(s)) => s.parse()?,
+            _ => self.count_records(collection_id)?,
+        };
+
         Ok(Some(CollectionMetadata {
             last_record_updated_at,
+            record_count,
         }))
     }
 
-    async fn update_metadata(&self, collection_id: &str, time: &SystemTime) -> Result<()> {
+    /// Count the committed records of a collection, using its id index
+    fn count_records(&self, collection_id: &str) -> Result<u64> {
+        let start_key = keys::Key::new_index(
+            collection_id.to_string(),
+            &[&"id".into()],
+            &[IndexDirection::Ascending],
+            vec![],
+        )?;
+        let end_key = start_key.clone().wildcard();
+
+        let mut count = 0;
+        for entry in self.store.list(&start_key, &end_key, false)? {
+            entry?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    async fn update_metadata(
+        &self,
+        collection_id: &str,
+        time: &SystemTime,
+        record_count_delta: i64,
+    ) -> Result<()> {
         let collection_metadata_key = &format!("{}/metadata", collection_id);
 
+        let record_count = self
+            .get_metadata(collection_id)
+            .await?
+            .map(|m| m.record_count)
+            .unwrap_or(0)
+            .saturating_add_signed(record_count_delta);
+
         self._set_system_record(
             collection_metadata_key,
             &RecordRoot(
-                [(
-                    "lastRecordUpdatedAt".to_string(),
-                    RecordValue::String(
-                        time.duration_since(SystemTime::UNIX_EPOCH)?
-                            .as_millis()
-                            .to_string(),
+                [
+                    (
+                        "lastRecordUpdatedAt".to_string(),
+                        RecordValue::String(
+                            time.duration_since(SystemTime::UNIX_EPOCH)?
+                                .as_millis()
+                                .to_string(),
+                        ),
                     ),
-                )]
+                    (
+                        "recordCount".to_string(),
+                        RecordValue::String(record_count.to_string()),
+                    ),
+                ]
                 .into(),
             ),
         )
@@ -356,14 +934,16 @@ impl RocksDBAdaptor {
 
         for index in schema.indexes.iter() {
             if let Err(indexing_failure) = async {
-                let index_key = keys::index_record_key_with_record(
+                let index_keys = keys::index_record_keys_with_record(
                     collection_id.to_string(),
                     &index.fields.iter().map(|f| &f.path).collect::<Vec<_>>(),
                     &index.fields.iter().map(|f| f.direction).collect::<Vec<_>>(),
                     record,
                 )?;
 
-                self.store.set(&index_key, &index_value).await?;
+                for index_key in index_keys {
+                    self.store.set(&index_key, &index_value).await?;
+                }
 
                 Ok::<_, Error>(())
             }
@@ -392,14 +972,16 @@ impl RocksDBAdaptor {
     ) {
         for index in schema.indexes.iter() {
             if let Err(deindexing_failure) = async {
-                let index_key = keys::index_record_key_with_record(
+                let index_keys = keys::index_record_keys_with_record(
                     collection_id.to_string(),
                     &index.fields.iter().map(|f| &f.path).collect::<Vec<_>>(),
                     &index.fields.iter().map(|f| f.direction).collect::<Vec<_>>(),
                     record,
                 )?;
 
-                self.store.delete(&index_key).await?;
+                for index_key in index_keys {
+                    self.store.delete(&index_key).await?;
+                }
 
                 Ok::<_, Error>(())
             }
@@ -425,21 +1007,53 @@ impl RocksDBAdaptor {
         record_id: &str,
         schema: &Schema,
     ) -> Result<()> {
-        let Some(record) = self._get(collection_id, record_id).await? else {
+        let Some(record) = self.delete_data(collection_id, record_id).await? else {
             return Ok(());
         };
 
+        self.delete_indexes(collection_id, record_id, &record, schema)
+            .await;
+
+        Ok(())
+    }
+
+    /// Delete a record without deleting its index entries, returning the deleted record
+    async fn delete_data(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+    ) -> Result<Option<RecordRoot>> {
+        let Some(record) = self._get(collection_id, record_id).await? else {
+            return Ok(None);
+        };
+
         let key = keys::Key::new_data(collection_id.to_string(), record_id.to_string())?;
 
         self.store.delete(&key).await?;
 
         let now = SystemTime::now();
-        self.update_metadata(collection_id, &now).await?;
-        self.update_record_metadata(collection_id, record_id.clone(), &now)
+        self.update_metadata(collection_id, &now, -1).await?;
+        self.update_record_metadata(collection_id, record_id, &now)
             .await?;
 
-        self.delete_indexes(collection_id, record_id, &record, schema)
-            .await;
+        Ok(Some(record))
+    }
+
+    /// Delete the index entries of every record of a collection. The entries of each
+    /// index are stored together, so they are deleted with a range delete instead of
+    /// reading each record. Data keys are hashed per record, so they can't be deleted
+    /// the same way.
+    async fn delete_collection_indexes(&self, collection_id: &str, schema: &Schema) -> Result<()> {
+        for index in schema.indexes.iter() {
+            let lower_bound = keys::Key::new_index(
+                collection_id.to_string(),
+                &index.fields.iter().map(|f| &f.path).collect::<Vec<_>>(),
+                &index.fields.iter().map(|f| f.direction).collect::<Vec<_>>(),
+                vec![],
+            )?;
+            let upper_bound = lower_bound.clone().wildcard();
+            self.store.delete_range(&lower_bound, &upper_bound).await?;
+        }
 
         Ok(())
     }
@@ -510,37 +1124,128 @@ impl RocksDBAdaptor {
         Ok(())
     }
 
-    pub async fn store_commit(&self) -> Result<()> {
-        Ok(self.store.commit().await?)
-    }
-}
+    /// Rebuild every index of a collection from its current schema. All existing index
+    /// entries of the collection are removed and re-created from the records, so indexes that
+    /// are missing entries for existing records (e.g. a newly added index) become queryable.
+    #[tracing::instrument(skip(self))]
+    pub async fn rebuild_indexes(&self, collection_id: &str) -> Result<()> {
+        let _guard = self.commit_lock.lock().await;
 
-#[async_trait::async_trait]
-impl IndexerAdaptor for RocksDBAdaptor {
-    async fn commit(&self, height: usize, changes: Vec<IndexerChange>) -> adaptor::Result<()> {
-        let mut schemas = HashMap::<String, Schema>::new();
+        let schema = self
+            .get_schema(collection_id)
+            .await?
+            .ok_or(Error::CollectionNotFound)?;
 
-        for change in changes.iter() {
-            match change {
-                IndexerChange::Set {
-                    collection_id,
-                    record_id,
-                    record,
-                } => {
-                    if collection_id == "Collection" && !schemas.contains_key(record_id) {
-                        let schema = Schema::from_record(record)?;
-                        schemas.insert(record_id.to_string(), schema);
-                    }
+        // Find all records using the id index, before the index entries are removed
+        let start_key = keys::Key::new_index(
+            collection_id.to_string(),
+            &[&"id".into()],
+            &[IndexDirection::Ascending],
+            vec![],
+        )?;
+        let end_key = start_key.clone().wildcard();
 
-                    let schema = match schemas.entry(collection_id.to_string()) {
-                        std::collections::hash_map::Entry::Occupied(o) => o.into_mut(),
-                        std::collections::hash_map::Entry::Vacant(v) => {
-                            let fetched_schema = self
-                                .get_schema(collection_id)
-                                .await?
-                                .ok_or(Error::CollectionNotFound)?;
-                            v.insert(fetched_schema.clone())
-                        }
+        let mut data_keys = vec![];
+        for entry in self.store.list(&start_key, &end_key, false)? {
+            let (_, value) = entry?;
+            data_keys.push(proto::IndexRecord::decode(&value[..])?.id);
+        }
+
+        // Remove every index entry of the collection's records, including entries of indexes
+        // no longer in the schema. Index keys aren't prefixed by collection, so the entries of
+        // all collections are checked against the collection's records.
+        let collection_keys = data_keys.iter().map(|key| &key[..]).collect::<HashSet<_>>();
+        let (lower_bound, upper_bound) = keys::index_bounds();
+        for entry in self
+            .store
+            .list_serialized(lower_bound, upper_bound, false)?
+        {
+            let (key, value) = entry?;
+            let index_record = proto::IndexRecord::decode(&value[..])?;
+            if collection_keys.contains(&index_record.id[..]) {
+                self.store.delete(&keys::Key::deserialize(&key)?).await?;
+            }
+        }
+
+        for data_key in data_keys {
+            let data_key = keys::Key::deserialize(&data_key)?;
+
+            let Some(record) = self.store.get(&data_key).await? else {
+                warn!(collection_id = collection_id, "Record is missing, skipping");
+                continue;
+            };
+
+            let Ok(record_id) = record.id() else {
+                warn!(
+                    collection_id = collection_id,
+                    "Record is missing id, skipping"
+                );
+                continue;
+            };
+
+            self.add_indexes(collection_id, record_id, &data_key, &record, &schema)
+                .await;
+        }
+
+        self.store_commit().await
+    }
+
+    /// Write the changes (and their indexes, history and audit log entries) to the store
+    /// and commit them. Must be called while holding the commit lock.
+    async fn apply_and_commit(
+        &self,
+        height: usize,
+        changes: Vec<IndexerChange>,
+    ) -> adaptor::Result<()> {
+        // Deleting a Collection record deletes the collection, the indexer adds a delete
+        // for each of its records before it. Changes after it belong to a re-created
+        // collection.
+        let deleted_collections = changes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, change)| match change {
+                IndexerChange::Delete {
+                    collection_id,
+                    record_id,
+                } if collection_id == "Collection" => Some((record_id.clone(), i)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        if let Some(retention) = self.history_retention {
+            self.append_history(height, &changes, retention, &deleted_collections)
+                .await?;
+        }
+
+        let audit_entries = if self.audit_log {
+            Some(self.audit_entries(height, &changes).await)
+        } else {
+            None
+        };
+
+        let mut schemas = HashMap::<String, Schema>::new();
+
+        for (i, change) in changes.iter().enumerate() {
+            match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    record,
+                } => {
+                    if collection_id == "Collection" && !schemas.contains_key(record_id) {
+                        let schema = Schema::from_record(record)?;
+                        schemas.insert(record_id.to_string(), schema);
+                    }
+
+                    let schema = match schemas.entry(collection_id.to_string()) {
+                        std::collections::hash_map::Entry::Occupied(o) => o.into_mut(),
+                        std::collections::hash_map::Entry::Vacant(v) => {
+                            let fetched_schema = self
+                                .get_schema(collection_id)
+                                .await?
+                                .ok_or(Error::CollectionNotFound)?;
+                            v.insert(fetched_schema.clone())
+                        }
                     };
 
                     self.set(collection_id, record_id, record, schema).await?;
@@ -553,14 +1258,73 @@ impl IndexerAdaptor for RocksDBAdaptor {
                         .get_schema(collection_id)
                         .await?
                         .ok_or(Error::CollectionNotFound)?;
-                    self.delete(collection_id, record_id, &schema).await?;
+
+                    match deleted_collections.get(collection_id) {
+                        // The index entries are removed when the collection is deleted
+                        Some(&deleted_at) if i < deleted_at => {
+                            self.delete_data(collection_id, record_id).await?;
+                        }
+                        _ => {
+                            if collection_id == "Collection"
+                                && deleted_collections.get(record_id) == Some(&i)
+                            {
+                                let deleted_schema = self
+                                    .get_schema(record_id)
+                                    .await?
+                                    .ok_or(Error::CollectionNotFound)?;
+                                self.delete_collection_indexes(record_id, &deleted_schema)
+                                    .await?;
+                            }
+
+                            self.delete(collection_id, record_id, &schema).await?;
+                        }
+                    }
                 }
             }
         }
+
+        if let Some(entries) = audit_entries {
+            // The audit log is best-effort, it should never cause the commit to fail
+            let result = match entries {
+                Ok(entries) => self.append_audit_log(height, &entries).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                error!(height, ?err, "Failed to append audit log entries");
+            }
+        }
+
         self.store_commit().await?;
         Ok(())
     }
 
+    pub async fn store_commit(&self) -> Result<()> {
+        Ok(self.store.commit().await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl IndexerAdaptor for RocksDBAdaptor {
+    async fn commit(&self, height: usize, changes: Vec<IndexerChange>) -> adaptor::Result<()> {
+        let _guard = self.commit_lock.lock().await;
+
+        let savepoint = self.store.savepoint();
+        let result = self.apply_and_commit(height, changes).await;
+        if result.is_err() {
+            // Discard the writes of the failed commit, so a retry starts from the same
+            // state and they aren't committed with a later commit
+            self.store.rollback(savepoint);
+        }
+
+        result
+    }
+
+    async fn discard_uncommitted(&self) -> adaptor::Result<()> {
+        let _guard = self.commit_lock.lock().await;
+        self.store.discard_pending();
+        Ok(())
+    }
+
     async fn get(
         &self,
         collection_id: &str,
@@ -569,6 +1333,17 @@ impl IndexerAdaptor for RocksDBAdaptor {
         Ok(self._get(collection_id, record_id).await?)
     }
 
+    async fn get_at_height(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+        height: usize,
+    ) -> adaptor::Result<Option<RecordRoot>> {
+        Ok(self
+            ._get_at_height(collection_id, record_id, height)
+            .await?)
+    }
+
     async fn list(
         &self,
         collection_id: &str,
@@ -576,9 +1351,10 @@ impl IndexerAdaptor for RocksDBAdaptor {
         where_query: WhereQuery<'_>,
         order_by: &[IndexField],
         reverse: bool,
+        cursor: Option<&Cursor<'_>>,
     ) -> adaptor::Result<Pin<Box<dyn futures::Stream<Item = RecordRoot> + '_ + Send>>> {
         Ok(self
-            ._list(collection_id, limit, where_query, order_by, reverse)
+            ._list(collection_id, limit, where_query, order_by, reverse, cursor)
             .await?)
     }
 
@@ -614,6 +1390,17 @@ impl IndexerAdaptor for RocksDBAdaptor {
         Ok(metadata.map(|m| m.last_record_updated_at))
     }
 
+    async fn collection_stats(&self, collection_id: &str) -> adaptor::Result<CollectionStats> {
+        let metadata = self.get_metadata(collection_id).await?;
+        Ok(match metadata {
+            Some(m) => CollectionStats {
+                record_count: m.record_count,
+                last_updated: Some(m.last_record_updated_at),
+            },
+            None => CollectionStats::default(),
+        })
+    }
+
     async fn set_system_key(&self, key: &str, data: &RecordRoot) -> adaptor::Result<()> {
         Ok(self._set_system_record(key, data).await?)
     }
@@ -622,6 +1409,51 @@ impl IndexerAdaptor for RocksDBAdaptor {
         Ok(self._get_system_record(key).await?)
     }
 
+    async fn delete_system_key(&self, key: &str) -> adaptor::Result<()> {
+        let key = keys::Key::new_system_data(key.to_string()).map_err(Error::from)?;
+        Ok(self.store.delete(&key).await.map_err(Error::from)?)
+    }
+
+    async fn set_reference(&self, target: &RecordKey, source: &RecordKey) -> adaptor::Result<()> {
+        let key = keys::Key::new_referrer(
+            target.0.clone(),
+            target.1.clone(),
+            Some((&source.0, &source.1)),
+        )
+        .map_err(Error::from)?;
+        Ok(self
+            .store
+            .set(&key, &store::Value::ReferrerValue)
+            .await
+            .map_err(Error::from)?)
+    }
+
+    async fn delete_reference(
+        &self,
+        target: &RecordKey,
+        source: &RecordKey,
+    ) -> adaptor::Result<()> {
+        let key = keys::Key::new_referrer(
+            target.0.clone(),
+            target.1.clone(),
+            Some((&source.0, &source.1)),
+        )
+        .map_err(Error::from)?;
+        Ok(self.store.delete(&key).await.map_err(Error::from)?)
+    }
+
+    async fn list_referrers(&self, target: &RecordKey) -> adaptor::Result<Vec<RecordKey>> {
+        Ok(self._list_referrers(target)?)
+    }
+
+    async fn set_local_key(&self, key: &str, data: &RecordRoot) -> adaptor::Result<()> {
+        Ok(self.store.set_local(key, data).await.map_err(Error::from)?)
+    }
+
+    async fn get_local_key(&self, key: &str) -> adaptor::Result<Option<RecordRoot>> {
+        Ok(self.store.get_local(key).await.map_err(Error::from)?)
+    }
+
     async fn snapshot(
         &self,
         chunk_size: usize,
@@ -637,11 +1469,46 @@ impl IndexerAdaptor for RocksDBAdaptor {
     }
 
     async fn restore(&self, chunk: Vec<SnapshotValue>) -> adaptor::Result<()> {
-        Ok(self.store.restore(chunk).map_err(Error::from)?)
+        Ok(self.store.restore(chunk).await.map_err(Error::from)?)
+    }
+
+    fn snapshot_system_key(
+        &self,
+        values: &[SnapshotValue],
+        key: &str,
+    ) -> adaptor::Result<Option<RecordRoot>> {
+        let key = keys::Key::new_system_data(key.to_string())
+            .and_then(|key| key.serialize())
+            .map_err(Error::from)?;
+
+        let Some(value) = values.iter().find(|value| *value.key == *key) else {
+            return Ok(None);
+        };
+
+        Ok(Some(
+            bincode::deserialize(&value.value).map_err(Error::from)?,
+        ))
     }
 
     async fn reset(&self) -> adaptor::Result<()> {
-        Ok(self.store.reset().map_err(Error::from)?)
+        Ok(self.store.reset().await.map_err(Error::from)?)
+    }
+
+    async fn compact(&self) -> adaptor::Result<()> {
+        Ok(self.store.compact().await.map_err(Error::from)?)
+    }
+
+    async fn rebuild_indexes(&self, collection_id: &str) -> adaptor::Result<()> {
+        Ok(RocksDBAdaptor::rebuild_indexes(self, collection_id).await?)
+    }
+
+    async fn audit_log(
+        &self,
+        collection_id: Option<&str>,
+        from_height: usize,
+        limit: usize,
+    ) -> adaptor::Result<Vec<AuditEntry>> {
+        Ok(self._audit_log(collection_id, from_height, limit)?)
     }
 }
 
@@ -649,7 +1516,1441 @@ impl From<Error> for adaptor::Error {
     fn from(err: Error) -> Self {
         match err {
             Error::IndexerAdaptor(e) => e,
+            _ if err.is_retryable() => Self::TransientStore(Box::new(err)),
             _ => Self::Store(Box::new(err)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::TestStore;
+    use indexer::adaptor::AuditOp;
+    use indexer::cursor::WrappedCursor;
+    use indexer::diff::RecordDiff;
+    use indexer::where_query::{WhereNode, WhereValue};
+    use schema::index_value::IndexValue;
+    use std::borrow::Cow;
+
+    fn collection_record(code: &str) -> RecordRoot {
+        let mut program = None;
+        let (_, ast) = polylang::parse(code, "ns", &mut program).unwrap();
+
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String("ns/Person".into()));
+        record.insert(
+            "ast".to_string(),
+            RecordValue::String(serde_json::to_string(&ast).unwrap()),
+        );
+        record
+    }
+
+    fn person(id: &str, name: &str, age: f64) -> RecordRoot {
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String(id.into()));
+        record.insert("name".to_string(), RecordValue::String(name.into()));
+        record.insert("age".to_string(), RecordValue::Number(age));
+        record
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_indexes() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                0,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        adaptor
+            .commit(
+                1,
+                [
+                    ("1", "John", 30.0),
+                    ("2", "Jane", 25.0),
+                    ("3", "John", 20.0),
+                ]
+                .into_iter()
+                .map(|(id, name, age)| IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: id.to_string(),
+                    record: person(id, name, age),
+                })
+                .collect(),
+            )
+            .await
+            .unwrap();
+
+        // Add an index by writing the collection record directly, so the existing
+        // records are not indexed
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+
+                @index(name, age);
+            }
+        "#;
+        adaptor
+            .store
+            .set(
+                &keys::Key::new_data("Collection".to_string(), "ns/Person".to_string()).unwrap(),
+                &store::Value::DataValue(&collection_record(code)),
+            )
+            .await
+            .unwrap();
+        adaptor.store_commit().await.unwrap();
+
+        let list_johns = || {
+            let adaptor = &adaptor;
+            async move {
+                let where_query = WhereQuery(
+                    [(
+                        "name".into(),
+                        WhereNode::Equality(WhereValue(IndexValue::String(Cow::Borrowed("John")))),
+                    )]
+                    .into(),
+                );
+                adaptor
+                    ._list(
+                        "ns/Person",
+                        None,
+                        where_query,
+                        &[IndexField::new_asc("age".into())],
+                        false,
+                        None,
+                    )
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+
+        assert_eq!(list_johns().await, vec![]);
+
+        // Remove a record's data but not its index entries, so the entries are stale
+        let stale_key = keys::Key::new_data("ns/Person".to_string(), "2".to_string()).unwrap();
+        adaptor.store.delete(&stale_key).await.unwrap();
+        adaptor.store_commit().await.unwrap();
+
+        adaptor.rebuild_indexes("ns/Person").await.unwrap();
+
+        assert_eq!(
+            list_johns().await,
+            vec![person("3", "John", 20.0), person("1", "John", 30.0)]
+        );
+
+        let stale_key = stale_key.serialize().unwrap();
+        let (lower_bound, upper_bound) = keys::index_bounds();
+        let stale_entries = adaptor
+            .store
+            .list_serialized(lower_bound, upper_bound, false)
+            .unwrap()
+            .filter(|entry| {
+                let (_, value) = entry.as_ref().unwrap();
+                proto::IndexRecord::decode(&value[..]).unwrap().id == stale_key
+            })
+            .count();
+        assert_eq!(stale_entries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_starts_with_scans_prefix_range() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                0,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        adaptor
+            .commit(
+                1,
+                ["use", "user", "user/a", "user/b", "user0", "users"]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: i.to_string(),
+                        record: person(&i.to_string(), name, 30.0),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let records = adaptor
+            ._list(
+                "ns/Person",
+                None,
+                serde_json::from_str(r#"{"name":{"$startsWith":"user/"}}"#).unwrap(),
+                &[IndexField::new_asc("name".into())],
+                false,
+                None,
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            records,
+            vec![person("2", "user/a", 30.0), person("3", "user/b", 30.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_seeks_to_cursor() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                0,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        adaptor
+            .commit(
+                1,
+                [
+                    ("a", 30.0),
+                    ("b", 20.0),
+                    ("c", 30.0),
+                    ("d", 20.0),
+                    ("e", 40.0),
+                ]
+                .into_iter()
+                .map(|(id, age)| IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: id.to_string(),
+                    record: person(id, id, age),
+                })
+                .collect(),
+            )
+            .await
+            .unwrap();
+
+        let order_by = [IndexField::new_desc("age".into())];
+        let list = |cursor: Option<Cursor<'static>>, reverse| {
+            let adaptor = &adaptor;
+            let order_by = &order_by;
+            async move {
+                adaptor
+                    ._list(
+                        "ns/Person",
+                        Some(2),
+                        WhereQuery::default(),
+                        order_by,
+                        reverse,
+                        cursor.as_ref(),
+                    )
+                    .await
+                    .unwrap()
+                    .map(|record| record.get("id").unwrap().clone())
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+        let cursor = |id: &str, age: f64| {
+            Cursor(
+                WrappedCursor::from_record(
+                    &person(id, id, age),
+                    &WhereQuery::default(),
+                    &[IndexField::new_asc("age".into())],
+                )
+                .unwrap(),
+            )
+        };
+        let ids = |ids: &[&str]| {
+            ids.iter()
+                .map(|id| RecordValue::String(id.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        // The age index is scanned in reverse, so records with the same age are listed
+        // by descending id and the cursor lands between them
+        assert_eq!(list(None, false).await, ids(&["e", "c"]));
+        assert_eq!(list(Some(cursor("c", 30.0)), false).await, ids(&["a", "d"]));
+        assert_eq!(list(Some(cursor("d", 20.0)), false).await, ids(&["b"]));
+        assert_eq!(list(Some(cursor("d", 20.0)), true).await, ids(&["a", "c"]));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_changes_in_order() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: true,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        adaptor
+            .commit(
+                2,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                    record: person("1", "John", 30.0),
+                }],
+            )
+            .await
+            .unwrap();
+
+        adaptor
+            .commit(
+                3,
+                vec![IndexerChange::Delete {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        let entries = adaptor._audit_log(Some("ns/Person"), 0, 100).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.height, e.record_id.as_str(), e.op))
+                .collect::<Vec<_>>(),
+            vec![(2, "1", AuditOp::Set), (3, "1", AuditOp::Delete)]
+        );
+
+        // All collections, from a height
+        let entries = adaptor._audit_log(None, 2, 100).unwrap();
+        assert_eq!(entries.len(), 2);
+        let entries = adaptor._audit_log(None, 0, 100).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].collection_id, "Collection");
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_appends_changes_committed_at_the_same_height() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: true,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                1,
+                vec![
+                    IndexerChange::Set {
+                        collection_id: "Collection".to_string(),
+                        record_id: "ns/Person".to_string(),
+                        record: collection_record(code),
+                    },
+                    IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "1".to_string(),
+                        record: person("1", "John", 30.0),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        // e.g. an import, which is committed at the last committed height
+        adaptor
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "2".to_string(),
+                    record: person("2", "Jane", 25.0),
+                }],
+            )
+            .await
+            .unwrap();
+
+        let entries = adaptor._audit_log(None, 0, 100).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.height, e.record_id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(1, "ns/Person"), (1, "1"), (1, "2")]
+        );
+
+        let entries = adaptor._audit_log(Some("ns/Person"), 0, 100).unwrap();
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_changes_pages_end_at_the_end_of_a_height() {
+        let store = TestStore::default();
+        let indexer = indexer::Indexer::new(RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: true,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        });
+
+        let code = r#"
+            @public
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        indexer
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // More changes at height 2 than fit in a page
+        indexer
+            .commit(
+                2,
+                (0..5)
+                    .map(|i| IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: i.to_string(),
+                        record: person(&i.to_string(), "John", 30.0),
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        indexer
+            .commit(
+                3,
+                vec![IndexerChange::Delete {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "0".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        let heights = |changes: &indexer::Changes| {
+            changes
+                .changes
+                .iter()
+                .map(|change| change.height)
+                .collect::<Vec<_>>()
+        };
+
+        // Height 2 doesn't fit in the page, so it's left for the next page
+        let changes = indexer.changes(0, 2, None).await.unwrap();
+        assert_eq!(heights(&changes), vec![1]);
+        assert_eq!(changes.height, 1);
+
+        // A page that only has a single height is extended to include all of it
+        let changes = indexer.changes(changes.height, 2, None).await.unwrap();
+        assert_eq!(heights(&changes), vec![2, 2, 2, 2, 2, 3]);
+        assert_eq!(changes.height, 3);
+
+        let changes = indexer.changes(changes.height, 2, None).await.unwrap();
+        assert!(changes.changes.is_empty());
+        assert_eq!(changes.height, 3);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_backfills_collection_keys() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: true,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        // An entry appended before entries were also written under their collection
+        let entry = AuditEntry::from_change(
+            1,
+            &IndexerChange::Delete {
+                collection_id: "ns/Person".to_string(),
+                record_id: "1".to_string(),
+            },
+            None,
+            SystemTime::now(),
+        );
+        adaptor
+            .store
+            .set(
+                &keys::Key::new_audit(1, 0),
+                &store::Value::AuditValue(&entry),
+            )
+            .await
+            .unwrap();
+        adaptor.store.commit().await.unwrap();
+        assert!(adaptor
+            ._audit_log(Some("ns/Person"), 0, 100)
+            .unwrap()
+            .is_empty());
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                2,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        let entries = adaptor._audit_log(Some("ns/Person"), 0, 100).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.height, e.record_id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(1, "1")]
+        );
+        let entries = adaptor._audit_log(Some("Collection"), 0, 100).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.height, e.record_id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(2, "ns/Person")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deleted_collection_keeps_audit_log_and_removes_history() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: true,
+            history_retention: Some(10),
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        for (height, age) in [(2, 30.0), (3, 31.0)] {
+            adaptor
+                .commit(
+                    height,
+                    vec![IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "1".to_string(),
+                        record: person("1", "John", age),
+                    }],
+                )
+                .await
+                .unwrap();
+        }
+
+        // The indexer deletes the collection's records with the Collection record
+        adaptor
+            .commit(
+                4,
+                vec![
+                    IndexerChange::Delete {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "1".to_string(),
+                    },
+                    IndexerChange::Delete {
+                        collection_id: "Collection".to_string(),
+                        record_id: "ns/Person".to_string(),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        // The audit log is append-only, so the collection's entries are kept and its
+        // records' deletes are recorded
+        assert_eq!(
+            adaptor
+                ._audit_log(Some("ns/Person"), 0, 100)
+                .unwrap()
+                .iter()
+                .map(|e| (e.height, e.op))
+                .collect::<Vec<_>>(),
+            vec![(2, AuditOp::Set), (3, AuditOp::Set), (4, AuditOp::Delete)]
+        );
+        assert_eq!(
+            adaptor
+                ._audit_log(None, 0, 100)
+                .unwrap()
+                .iter()
+                .map(|e| (e.height, e.collection_id.as_str(), e.op))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "Collection", AuditOp::Set),
+                (2, "ns/Person", AuditOp::Set),
+                (3, "ns/Person", AuditOp::Set),
+                (4, "ns/Person", AuditOp::Delete),
+                (4, "Collection", AuditOp::Delete)
+            ]
+        );
+        assert!(adaptor.version_keys("ns/Person", "1").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deleted_collection_indexes_are_range_deleted() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: Some(10),
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+        let set_collection = || IndexerChange::Set {
+            collection_id: "Collection".to_string(),
+            record_id: "ns/Person".to_string(),
+            record: collection_record(code),
+        };
+        let set = |id: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+            record: person(id, "John", 30.0),
+        };
+        let delete = |collection_id: &str, record_id: &str| IndexerChange::Delete {
+            collection_id: collection_id.to_string(),
+            record_id: record_id.to_string(),
+        };
+
+        adaptor
+            .commit(1, vec![set_collection(), set("1"), set("2")])
+            .await
+            .unwrap();
+
+        // The collection is deleted and re-created in the same commit
+        adaptor
+            .commit(
+                2,
+                vec![
+                    delete("ns/Person", "1"),
+                    delete("ns/Person", "2"),
+                    delete("Collection", "ns/Person"),
+                    set_collection(),
+                    set("3"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let lower_bound = keys::Key::new_index(
+            "ns/Person".to_string(),
+            &[&"name".into()],
+            &[IndexDirection::Ascending],
+            vec![],
+        )
+        .unwrap();
+        let upper_bound = lower_bound.clone().wildcard();
+        assert_eq!(
+            adaptor
+                .store
+                .list(&lower_bound, &upper_bound, false)
+                .unwrap()
+                .count(),
+            1
+        );
+        assert_eq!(
+            list_by_name(&adaptor, "John")
+                .await
+                .iter()
+                .map(|record| record.id().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            vec!["3".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collection_stats_counts_records_without_record_count() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+        let set = |id: &str| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+            record: person(id, "John", 30.0),
+        };
+
+        adaptor
+            .commit(
+                1,
+                vec![
+                    IndexerChange::Set {
+                        collection_id: "Collection".to_string(),
+                        record_id: "ns/Person".to_string(),
+                        record: collection_record(code),
+                    },
+                    set("1"),
+                    set("2"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        // Metadata written before record counts were tracked
+        adaptor
+            ._set_system_record(
+                "ns/Person/metadata",
+                &RecordRoot(
+                    [(
+                        "lastRecordUpdatedAt".to_string(),
+                        RecordValue::String("0".to_string()),
+                    )]
+                    .into(),
+                ),
+            )
+            .await
+            .unwrap();
+        adaptor.store_commit().await.unwrap();
+
+        assert_eq!(
+            adaptor
+                .collection_stats("ns/Person")
+                .await
+                .unwrap()
+                .record_count,
+            2
+        );
+
+        adaptor.commit(2, vec![set("3")]).await.unwrap();
+        assert_eq!(
+            adaptor
+                .collection_stats("ns/Person")
+                .await
+                .unwrap()
+                .record_count,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_diffs_changed_fields() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: true,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        let set = |record| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: "1".to_string(),
+            record,
+        };
+
+        adaptor
+            .commit(
+                1,
+                vec![
+                    IndexerChange::Set {
+                        collection_id: "Collection".to_string(),
+                        record_id: "ns/Person".to_string(),
+                        record: collection_record(code),
+                    },
+                    set(person("1", "John", 30.0)),
+                ],
+            )
+            .await
+            .unwrap();
+
+        // The second change is diffed against the first change in the same commit
+        adaptor
+            .commit(
+                2,
+                vec![
+                    set(person("1", "John", 31.0)),
+                    set(person("1", "Jane", 31.0)),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let entries = adaptor._audit_log(Some("ns/Person"), 0, 100).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.diff.clone()).collect::<Vec<_>>(),
+            vec![
+                RecordDiff {
+                    added: ["age", "id", "name"].map(FieldPath::from).to_vec(),
+                    ..Default::default()
+                },
+                RecordDiff {
+                    changed: vec![FieldPath::from("age")],
+                    ..Default::default()
+                },
+                RecordDiff {
+                    changed: vec![FieldPath::from("name")],
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_at_height() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: Some(2),
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        for (height, age) in [(2, 30.0), (3, 31.0), (4, 32.0)] {
+            adaptor
+                .commit(
+                    height,
+                    vec![IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "1".to_string(),
+                        record: person("1", "John", age),
+                    }],
+                )
+                .await
+                .unwrap();
+        }
+
+        let get_at_height = |height| adaptor._get_at_height("ns/Person", "1", height);
+        assert_eq!(
+            get_at_height(2).await.unwrap(),
+            Some(person("1", "John", 30.0))
+        );
+        assert_eq!(
+            get_at_height(3).await.unwrap(),
+            Some(person("1", "John", 31.0))
+        );
+        assert_eq!(
+            get_at_height(4).await.unwrap(),
+            Some(person("1", "John", 32.0))
+        );
+
+        adaptor
+            .commit(
+                5,
+                vec![IndexerChange::Delete {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // Heights before the retention window, or after the last commit, are not available
+        assert!(matches!(
+            get_at_height(2).await,
+            Err(Error::IndexerAdaptor(adaptor::Error::HistoryUnavailable {
+                height: 2
+            }))
+        ));
+        assert!(get_at_height(6).await.is_err());
+        assert_eq!(
+            get_at_height(3).await.unwrap(),
+            Some(person("1", "John", 31.0))
+        );
+        assert_eq!(get_at_height(5).await.unwrap(), None);
+
+        // Only the versions needed to read the retention window are kept
+        assert_eq!(
+            adaptor
+                .version_keys("ns/Person", "1")
+                .unwrap()
+                .into_iter()
+                .map(|(height, _)| height)
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_history_prunes_versions_of_unchanged_records() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: Some(2),
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        for (height, age) in [(2, 30.0), (3, 31.0)] {
+            adaptor
+                .commit(
+                    height,
+                    vec![IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "1".to_string(),
+                        record: person("1", "John", age),
+                    }],
+                )
+                .await
+                .unwrap();
+        }
+
+        // Later commits only change other records
+        for height in 4..=7 {
+            adaptor
+                .commit(
+                    height,
+                    vec![IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "2".to_string(),
+                        record: person("2", "Jane", height as f64),
+                    }],
+                )
+                .await
+                .unwrap();
+        }
+
+        let heights = |record_id| {
+            adaptor
+                .version_keys("ns/Person", record_id)
+                .unwrap()
+                .into_iter()
+                .map(|(height, _)| height)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(heights("1"), vec![3]);
+        assert_eq!(heights("2"), vec![5, 6, 7]);
+        assert_eq!(
+            adaptor._get_at_height("ns/Person", "1", 5).await.unwrap(),
+            Some(person("1", "John", 31.0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_history_prunes_deleted_records_and_same_height_commits() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: Some(2),
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+        let set = |id: &str, age: f64| IndexerChange::Set {
+            collection_id: "ns/Person".to_string(),
+            record_id: id.to_string(),
+            record: person(id, "John", age),
+        };
+
+        adaptor
+            .commit(
+                1,
+                vec![
+                    IndexerChange::Set {
+                        collection_id: "Collection".to_string(),
+                        record_id: "ns/Person".to_string(),
+                        record: collection_record(code),
+                    },
+                    set("1", 30.0),
+                    set("2", 30.0),
+                ],
+            )
+            .await
+            .unwrap();
+
+        // Both commits at the same height supersede a version
+        adaptor.commit(2, vec![set("1", 31.0)]).await.unwrap();
+        adaptor.commit(2, vec![set("2", 31.0)]).await.unwrap();
+
+        adaptor
+            .commit(
+                3,
+                vec![IndexerChange::Delete {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                }],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            adaptor._get_at_height("ns/Person", "1", 3).await.unwrap(),
+            None
+        );
+
+        for height in 4..=6 {
+            adaptor.commit(height, vec![]).await.unwrap();
+        }
+
+        let heights = |record_id| {
+            adaptor
+                .version_keys("ns/Person", record_id)
+                .unwrap()
+                .into_iter()
+                .map(|(height, _)| height)
+                .collect::<Vec<_>>()
+        };
+        // The tombstone is removed with the versions it superseded, and the versions
+        // superseded by either commit at height 2 are removed
+        assert!(heights("1").is_empty());
+        assert_eq!(heights("2"), vec![2]);
+        assert_eq!(
+            adaptor._get_at_height("ns/Person", "1", 5).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            adaptor._get_at_height("ns/Person", "2", 5).await.unwrap(),
+            Some(person("2", "John", 31.0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_at_height_before_record_created() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: Some(10),
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                1,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        adaptor
+            .commit(
+                3,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "1".to_string(),
+                    record: person("1", "John", 30.0),
+                }],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            adaptor._get_at_height("ns/Person", "1", 1).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            adaptor._get_at_height("ns/Person", "1", 2).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            adaptor._get_at_height("ns/Person", "1", 3).await.unwrap(),
+            Some(person("1", "John", 30.0))
+        );
+    }
+
+    async fn list_by_name(adaptor: &RocksDBAdaptor, name: &str) -> Vec<RecordRoot> {
+        adaptor
+            ._list(
+                "ns/Person",
+                None,
+                serde_json::from_value(serde_json::json!({ "name": name })).unwrap(),
+                &[IndexField::new_asc("name".into())],
+                false,
+                None,
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_referrers_are_listed_per_target() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+        let key = |collection_id: &str, record_id: &str| {
+            (collection_id.to_string(), record_id.to_string())
+        };
+
+        // Sources whose collection and record ids would be ambiguous if concatenated
+        for source in [key("ns/a", "b/c"), key("ns/a/b", "c")] {
+            adaptor
+                .set_reference(&key("ns/Person", "1"), &source)
+                .await
+                .unwrap();
+        }
+        adaptor
+            .set_reference(&key("ns/Person", "2"), &key("ns/a", "d"))
+            .await
+            .unwrap();
+        adaptor.commit(1, vec![]).await.unwrap();
+
+        assert_eq!(
+            adaptor
+                .list_referrers(&key("ns/Person", "1"))
+                .await
+                .unwrap(),
+            vec![key("ns/a", "b/c"), key("ns/a/b", "c")]
+        );
+
+        adaptor
+            .delete_reference(&key("ns/Person", "1"), &key("ns/a", "b/c"))
+            .await
+            .unwrap();
+        adaptor.commit(2, vec![]).await.unwrap();
+
+        assert_eq!(
+            adaptor
+                .list_referrers(&key("ns/Person", "1"))
+                .await
+                .unwrap(),
+            vec![key("ns/a/b", "c")]
+        );
+        assert_eq!(
+            adaptor
+                .list_referrers(&key("ns/Person", "2"))
+                .await
+                .unwrap(),
+            vec![key("ns/a", "d")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_commits_are_serialized() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                0,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // Both commits change the same records, so if their writes were interleaved the
+        // index entries of one would be left behind for the records of the other
+        let changes = |name: &str| {
+            (0..20)
+                .map(|i| IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: i.to_string(),
+                    record: person(&i.to_string(), name, 30.0),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for _ in 0..10 {
+            let (a, b) = tokio::join!(
+                adaptor.commit(1, changes("John")),
+                adaptor.commit(1, changes("Jane"))
+            );
+            a.unwrap();
+            b.unwrap();
+
+            let johns = list_by_name(&adaptor, "John").await;
+            let janes = list_by_name(&adaptor, "Jane").await;
+            assert!(
+                (johns.len(), janes.len()) == (20, 0) || (johns.len(), janes.len()) == (0, 20),
+                "johns: {}, janes: {}",
+                johns.len(),
+                janes.len()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_commit_is_rolled_back() {
+        let store = TestStore::default();
+        let adaptor = RocksDBAdaptor {
+            store: (*store).clone(),
+            audit_log: false,
+            history_retention: None,
+            commit_lock: Arc::default(),
+        };
+
+        let code = r#"
+            collection Person {
+                id: string;
+                name: string;
+                age: number;
+            }
+        "#;
+
+        adaptor
+            .commit(
+                0,
+                vec![IndexerChange::Set {
+                    collection_id: "Collection".to_string(),
+                    record_id: "ns/Person".to_string(),
+                    record: collection_record(code),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // The second change fails, as the collection doesn't exist
+        assert!(adaptor
+            .commit(
+                1,
+                vec![
+                    IndexerChange::Set {
+                        collection_id: "ns/Person".to_string(),
+                        record_id: "1".to_string(),
+                        record: person("1", "John", 30.0),
+                    },
+                    IndexerChange::Set {
+                        collection_id: "ns/Missing".to_string(),
+                        record_id: "1".to_string(),
+                        record: person("1", "John", 30.0),
+                    },
+                ],
+            )
+            .await
+            .is_err());
+
+        adaptor
+            .commit(
+                2,
+                vec![IndexerChange::Set {
+                    collection_id: "ns/Person".to_string(),
+                    record_id: "2".to_string(),
+                    record: person("2", "Jane", 30.0),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // The first change of the failed commit was not committed with the next commit
+        assert_eq!(adaptor._get("ns/Person", "1").await.unwrap(), None);
+        assert!(list_by_name(&adaptor, "John").await.is_empty());
+        assert_eq!(
+            adaptor._get("ns/Person", "2").await.unwrap(),
+            Some(person("2", "Jane", 30.0))
+        );
+    }
+}
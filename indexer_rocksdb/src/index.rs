@@ -43,6 +43,7 @@ pub(crate) fn byte_prefix(index_value: &IndexValue) -> u8 {
         IndexValue::Boolean(_) => keys::BYTE_BOOLEAN,
         IndexValue::PublicKey(_) => keys::BYTE_PUBLIC_KEY,
         IndexValue::ForeignRecordReference(_) => keys::BYTE_FOREIGN_RECORD_REFERENCE,
+        IndexValue::Bytes(_) => keys::BYTE_BYTES,
     }
 }
 
@@ -61,6 +62,7 @@ pub(crate) fn serialize(index_value: &IndexValue, mut w: impl std::io::Write) ->
         IndexValue::Null => Cow::Borrowed(&[0x00]),
         IndexValue::PublicKey(jwk) => Cow::Owned(jwk.to_indexable()),
         IndexValue::ForeignRecordReference(frr) => Cow::Owned(frr.to_indexable()),
+        IndexValue::Bytes(b) => Cow::Borrowed(&b[..]),
     };
 
     let len = 1 + u16::try_from(value.len())?;
@@ -89,6 +91,7 @@ pub(crate) fn deserialize(bytes: &[u8]) -> Result<IndexValue> {
         keys::BYTE_FOREIGN_RECORD_REFERENCE => IndexValue::ForeignRecordReference(Cow::Owned(
             ForeignRecordReference::from_indexable(value)?,
         )),
+        keys::BYTE_BYTES => IndexValue::Bytes(Cow::Owned(value.to_vec())),
         b => return Err(Error::InvalidTypePrefix { b }),
     };
 
@@ -1,5 +1,5 @@
 use crate::keys;
-use indexer::where_query::{WhereNode, WhereQuery};
+use indexer::where_query::{WhereContains, WhereNode, WhereQuery};
 use schema::{field_path::FieldPath, index::IndexDirection, index_value::IndexValue, Schema};
 use std::borrow::Cow;
 
@@ -60,7 +60,9 @@ pub(crate) fn key_range<'a>(
             }
 
             match node {
-                WhereNode::Equality(value) => {
+                // Array elements are indexed individually, so contains is an equality match
+                WhereNode::Equality(value)
+                | WhereNode::Contains(WhereContains { contains: value }) => {
                     lower_values.push(Cow::Owned(value.0.clone()));
                     upper_values.push(Cow::Owned(value.0.clone()));
                 }
@@ -42,6 +42,15 @@ pub enum KeysError {
 
     #[error("index error")]
     IndexError(#[from] crate::index::Error),
+
+    #[error("invalid audit key")]
+    InvalidAuditKey,
+
+    #[error("invalid version key")]
+    InvalidVersionKey,
+
+    #[error("invalid referrer key")]
+    InvalidReferrerKey,
 }
 
 const MULTICODEC_PROTOBUF: u64 = 0x50;
@@ -55,13 +64,17 @@ const BYTE_DATA: u8 = 0x01;
 const BYTE_INDEX: u8 = 0x02;
 const BYTE_WILDCARD: u8 = 0x03;
 const BYTE_SYSTEM_DATA: u8 = 0x04;
+const BYTE_AUDIT: u8 = 0x05;
+const BYTE_VERSION: u8 = 0x06;
+const BYTE_COLLECTION_AUDIT: u8 = 0x07;
+const BYTE_VERSION_EXPIRY: u8 = 0x08;
+const BYTE_REFERRER: u8 = 0x09;
 
 // Data type prefixes
 pub(crate) const BYTE_NULL: u8 = 0x00;
 pub(crate) const BYTE_STRING: u8 = 0x04;
 pub(crate) const BYTE_NUMBER: u8 = 0x05;
 pub(crate) const BYTE_BOOLEAN: u8 = 0x06;
-#[allow(dead_code)]
 pub(crate) const BYTE_BYTES: u8 = 0x07;
 pub(crate) const BYTE_PUBLIC_KEY: u8 = 0x08;
 pub(crate) const BYTE_FOREIGN_RECORD_REFERENCE: u8 = 0x09;
@@ -174,6 +187,32 @@ pub(crate) enum Key<'a> {
         directions: Cow<'a, [IndexDirection]>,
         values: Vec<Cow<'a, IndexValue<'a>>>,
     },
+    /// An audit key points to an entry in the audit log. Audit keys are shorter than
+    /// the CID prefix, so they are compared byte-wise and sort by height then sequence.
+    Audit { height: u64, seq: u32 },
+    /// A version key points to the value of a record as of a block height. The height
+    /// is stored as a field after the CID, so versions of a record sort by height.
+    Version { cid: Cow<'a, [u8]>, height: u64 },
+    /// A collection audit key points to a collection's entry in the audit log. The height
+    /// and sequence are stored as fields after the CID, so a collection's entries sort
+    /// like the audit log and can be listed without scanning other collections.
+    CollectionAudit {
+        cid: Cow<'a, [u8]>,
+        height: u64,
+        seq: u32,
+    },
+    /// A version expiry key points to a version that was superseded at the height, so
+    /// versions can be removed in height order once they leave the retention window.
+    /// Like audit keys, they are compared byte-wise and sort by height then sequence.
+    VersionExpiry { height: u64, seq: u32 },
+    /// A referrer key records that the source record references the record of the CID.
+    /// The source collection id and record id are stored as fields after the CID, so the
+    /// referrers of a record can be listed with a prefix scan. A key without a source is
+    /// the prefix of all of the record's referrer keys.
+    Referrer {
+        cid: Cow<'a, [u8]>,
+        source: Option<(Cow<'a, str>, Cow<'a, str>)>,
+    },
 }
 
 impl<'a> fmt::Debug for Key<'a> {
@@ -187,6 +226,13 @@ impl<'a> fmt::Debug for Key<'a> {
                 directions,
                 values,
             } => write!(f, "Index({cid:?}, {directions:?}, {values:?})"),
+            Key::Audit { height, seq } => write!(f, "Audit({height}, {seq})"),
+            Key::Version { cid, height } => write!(f, "Version({cid:?}, {height})"),
+            Key::CollectionAudit { cid, height, seq } => {
+                write!(f, "CollectionAudit({cid:?}, {height}, {seq})")
+            }
+            Key::VersionExpiry { height, seq } => write!(f, "VersionExpiry({height}, {seq})"),
+            Key::Referrer { cid, source } => write!(f, "Referrer({cid:?}, {source:?})"),
         }
     }
 }
@@ -243,6 +289,62 @@ impl<'a> Key<'a> {
         })
     }
 
+    pub(crate) fn new_audit(height: u64, seq: u32) -> Self {
+        Key::Audit { height, seq }
+    }
+
+    pub(crate) fn new_version_expiry(height: u64, seq: u32) -> Self {
+        Key::VersionExpiry { height, seq }
+    }
+
+    pub(crate) fn new_collection_audit(namespace: String, height: u64, seq: u32) -> Result<Self> {
+        let data = proto::DataKey {
+            namespace,
+            id: String::new(),
+        };
+        let mut cid = Vec::with_capacity(36);
+        generate_cid(&data.encode_to_vec(), &mut cid)?;
+
+        Ok(Key::CollectionAudit {
+            cid: Cow::Owned(cid),
+            height,
+            seq,
+        })
+    }
+
+    /// Key recording that the `source` record references the target record, or the
+    /// prefix of the target's referrer keys if `source` is None
+    pub(crate) fn new_referrer(
+        target_namespace: String,
+        target_id: String,
+        source: Option<(&'a str, &'a str)>,
+    ) -> Result<Self> {
+        let data = proto::DataKey {
+            namespace: target_namespace,
+            id: target_id,
+        };
+        let mut cid = Vec::with_capacity(36);
+        generate_cid(&data.encode_to_vec(), &mut cid)?;
+
+        Ok(Key::Referrer {
+            cid: Cow::Owned(cid),
+            source: source.map(|(collection_id, record_id)| {
+                (Cow::Borrowed(collection_id), Cow::Borrowed(record_id))
+            }),
+        })
+    }
+
+    pub(crate) fn new_version(namespace: String, id: String, height: u64) -> Result<Self> {
+        let data = proto::DataKey { namespace, id };
+        let mut cid = Vec::with_capacity(36);
+        generate_cid(&data.encode_to_vec(), &mut cid)?;
+
+        Ok(Key::Version {
+            cid: Cow::Owned(cid),
+            height,
+        })
+    }
+
     pub(crate) fn wildcard(self) -> Self {
         Key::Wildcard(Box::new(self))
     }
@@ -285,17 +387,124 @@ impl<'a> Key<'a> {
                 }
                 Ok(key)
             }
+            Key::Audit { height, seq } => {
+                let mut key = Vec::with_capacity(1 + 8 + 4);
+                key.push(BYTE_AUDIT);
+                key.extend_from_slice(&height.to_be_bytes());
+                key.extend_from_slice(&seq.to_be_bytes());
+                Ok(key)
+            }
+            Key::VersionExpiry { height, seq } => {
+                let mut key = Vec::with_capacity(1 + 8 + 4);
+                key.push(BYTE_VERSION_EXPIRY);
+                key.extend_from_slice(&height.to_be_bytes());
+                key.extend_from_slice(&seq.to_be_bytes());
+                Ok(key)
+            }
+            Key::Version { cid, height } => {
+                let mut key = Vec::with_capacity(cid.len() + 1 + 2 + 8);
+                key.push(BYTE_VERSION);
+                key.extend_from_slice(cid);
+                key.extend_from_slice(&8u16.to_le_bytes());
+                key.extend_from_slice(&height.to_be_bytes());
+                Ok(key)
+            }
+            Key::CollectionAudit { cid, height, seq } => {
+                let mut key = Vec::with_capacity(cid.len() + 1 + 2 + 8 + 2 + 4);
+                key.push(BYTE_COLLECTION_AUDIT);
+                key.extend_from_slice(cid);
+                key.extend_from_slice(&8u16.to_le_bytes());
+                key.extend_from_slice(&height.to_be_bytes());
+                key.extend_from_slice(&4u16.to_le_bytes());
+                key.extend_from_slice(&seq.to_be_bytes());
+                Ok(key)
+            }
+            Key::Referrer { cid, source } => {
+                let mut key = Vec::with_capacity(cid.len() + 1);
+                key.push(BYTE_REFERRER);
+                key.extend_from_slice(cid);
+                if let Some((collection_id, record_id)) = source {
+                    for field in [collection_id, record_id] {
+                        key.extend_from_slice(&u16::try_from(field.len())?.to_le_bytes());
+                        key.extend_from_slice(field.as_bytes());
+                    }
+                }
+                Ok(key)
+            }
         }
     }
 
     pub(crate) fn deserialize(key: &'a [u8]) -> Result<Self> {
         let key_type = *key.first().ok_or(KeysError::KeyMissingKeyType)?;
+        if key_type == BYTE_AUDIT || key_type == BYTE_VERSION_EXPIRY {
+            let height: [u8; 8] = key
+                .get(1..9)
+                .and_then(|b| b.try_into().ok())
+                .ok_or(KeysError::InvalidAuditKey)?;
+            let seq: [u8; 4] = key
+                .get(9..13)
+                .and_then(|b| b.try_into().ok())
+                .ok_or(KeysError::InvalidAuditKey)?;
+
+            let (height, seq) = (u64::from_be_bytes(height), u32::from_be_bytes(seq));
+            return Ok(match key_type {
+                BYTE_AUDIT => Key::Audit { height, seq },
+                _ => Key::VersionExpiry { height, seq },
+            });
+        }
+
         let cid = key.get(1..37).ok_or(KeysError::KeyMissingCid)?;
 
         match key_type {
             BYTE_DATA => Ok(Key::Data {
                 cid: Cow::Borrowed(cid),
             }),
+            BYTE_VERSION => {
+                let (height, _) = eat_field(&key[37..]);
+                let height: [u8; 8] = height
+                    .try_into()
+                    .map_err(|_| KeysError::InvalidVersionKey)?;
+
+                Ok(Key::Version {
+                    cid: Cow::Borrowed(cid),
+                    height: u64::from_be_bytes(height),
+                })
+            }
+            BYTE_COLLECTION_AUDIT => {
+                let (height, rest) = eat_field(&key[37..]);
+                let (seq, _) = eat_field(rest);
+                let height: [u8; 8] = height.try_into().map_err(|_| KeysError::InvalidAuditKey)?;
+                let seq: [u8; 4] = seq.try_into().map_err(|_| KeysError::InvalidAuditKey)?;
+
+                Ok(Key::CollectionAudit {
+                    cid: Cow::Borrowed(cid),
+                    height: u64::from_be_bytes(height),
+                    seq: u32::from_be_bytes(seq),
+                })
+            }
+            BYTE_REFERRER => {
+                let (collection_id, rest) = eat_field(&key[37..]);
+                let (record_id, _) = eat_field(rest);
+                let source = if key.len() > 37 {
+                    Some((
+                        Cow::Borrowed(
+                            std::str::from_utf8(collection_id)
+                                .map_err(|_| KeysError::InvalidReferrerKey)?,
+                        ),
+                        Cow::Borrowed(
+                            std::str::from_utf8(record_id)
+                                .map_err(|_| KeysError::InvalidReferrerKey)?,
+                        ),
+                    ))
+                } else {
+                    None
+                };
+
+                Ok(Key::Referrer {
+                    cid: Cow::Borrowed(cid),
+                    source,
+                })
+            }
             BYTE_INDEX => {
                 let directions_len = u16::from_le_bytes([key[37], key[38]]) as usize;
 
@@ -346,6 +555,26 @@ impl<'a> Key<'a> {
                     .map(|v| Cow::Owned(v.into_owned().with_static()))
                     .collect(),
             },
+            Key::Audit { height, seq } => Key::Audit { height, seq },
+            Key::Version { cid, height } => Key::Version {
+                cid: Cow::Owned(cid.into_owned()),
+                height,
+            },
+            Key::CollectionAudit { cid, height, seq } => Key::CollectionAudit {
+                cid: Cow::Owned(cid.into_owned()),
+                height,
+                seq,
+            },
+            Key::VersionExpiry { height, seq } => Key::VersionExpiry { height, seq },
+            Key::Referrer { cid, source } => Key::Referrer {
+                cid: Cow::Owned(cid.into_owned()),
+                source: source.map(|(collection_id, record_id)| {
+                    (
+                        Cow::Owned(collection_id.into_owned()),
+                        Cow::Owned(record_id.into_owned()),
+                    )
+                }),
+            },
         }
     }
 
@@ -354,6 +583,11 @@ impl<'a> Key<'a> {
             Key::Wildcard(_) => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
             Key::Data { .. } => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
             Key::SystemData { .. } => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
+            Key::Audit { .. } => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
+            Key::Version { .. } => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
+            Key::CollectionAudit { .. } => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
+            Key::VersionExpiry { .. } => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
+            Key::Referrer { .. } => Err(KeysError::KeyDoesNotHaveImmediateSuccessor),
             Key::Index {
                 cid: _,
                 directions: _,
@@ -366,6 +600,12 @@ impl<'a> Key<'a> {
     }
 }
 
+/// Serialized bounds that span the index keys of every collection. Keys shorter than the
+/// CID prefix are compared byte-wise, so these sort before and after all index keys.
+pub(crate) fn index_bounds() -> (Vec<u8>, Vec<u8>) {
+    (vec![BYTE_INDEX], vec![BYTE_INDEX + 1])
+}
+
 fn index_direction_to_u8(d: &IndexDirection) -> u8 {
     match d {
         IndexDirection::Ascending => 0x00,
@@ -381,24 +621,29 @@ fn u8_to_index_directon(d: u8) -> Result<IndexDirection> {
     }
 }
 
-pub(crate) fn index_record_key_with_record<'a>(
+/// Returns the index keys for a record. Array fields are indexed per element, so a record
+/// has one key for each (unique) element of the array.
+pub(crate) fn index_record_keys_with_record<'a>(
     namespace: String,
     paths: &[&FieldPath],
     directions: &[IndexDirection],
     record: &'a RecordRoot,
-) -> Result<Key<'a>> {
+) -> Result<Vec<Key<'a>>> {
     if paths.len() != directions.len() {
         return Err(KeysError::PathAndDirectionsLengthMismatch)?;
     }
 
-    let mut found_values = vec![];
+    let mut found_values: Vec<Vec<IndexValue<'a>>> = vec![vec![]; paths.len()];
     for (k, v) in record.iter() {
         #[allow(clippy::unwrap_used)]
         v.walk::<std::convert::Infallible>(&mut vec![Cow::Borrowed(k)], &mut |path, value| {
-            if let Some(found) = paths.iter().find(|p| {
-                p.len() == path.len() && p.iter().zip(path.iter()).all(|(p, v)| p == v.as_ref())
-            }) {
-                found_values.push((found, value));
+            if let Some(i) = paths
+                .iter()
+                .position(|p| is_path_match(p, path) || is_array_element_path_match(p, path))
+            {
+                if !found_values[i].contains(&value) {
+                    found_values[i].push(value);
+                }
             }
 
             Ok(())
@@ -406,30 +651,48 @@ pub(crate) fn index_record_key_with_record<'a>(
         .unwrap();
     }
 
-    let missing_fields = paths
-        .iter()
-        .filter(|p| !found_values.iter().any(|(fp, _)| fp == p))
-        .collect::<Vec<_>>();
+    // Create a set of values for each combination of the found values, missing fields
+    // (and empty arrays) are indexed as null
+    let mut keys_values: Vec<Vec<Cow<'a, IndexValue<'a>>>> = vec![vec![]];
+    for values in found_values {
+        let values = if values.is_empty() {
+            vec![IndexValue::Null]
+        } else {
+            values
+        };
 
-    for missing_field in &missing_fields {
-        found_values.push((missing_field, IndexValue::Null));
+        keys_values = keys_values
+            .into_iter()
+            .flat_map(|key_values| {
+                values.iter().map(move |value| {
+                    let mut key_values = key_values.clone();
+                    key_values.push(Cow::Owned(value.clone()));
+                    key_values
+                })
+            })
+            .collect();
     }
 
-    found_values.sort_by(|(p1, _), (p2, _)| {
-        paths
-            .iter()
-            .position(|p| &p == p1)
-            .cmp(&paths.iter().position(|p| p == *p2))
-    });
-
-    let found_values = found_values
+    keys_values
         .into_iter()
-        .map(|(_, v)| Cow::Owned(v))
-        .collect::<Vec<_>>();
+        .map(|values| Key::new_index(namespace.clone(), paths, directions, values))
+        .collect()
+}
 
-    let key = Key::new_index(namespace, paths, directions, found_values)?;
+fn is_path_match(index_path: &FieldPath, path: &[Cow<str>]) -> bool {
+    index_path.len() == path.len()
+        && index_path
+            .iter()
+            .zip(path.iter())
+            .all(|(p, v)| p == v.as_ref())
+}
 
-    Ok(key)
+/// Array elements are walked with their position appended to the path, e.g. tags.0
+fn is_array_element_path_match(index_path: &FieldPath, path: &[Cow<str>]) -> bool {
+    match path.split_last() {
+        Some((last, parent)) => is_path_match(index_path, parent) && last.parse::<usize>().is_ok(),
+        None => false,
+    }
 }
 
 #[cfg(test)]
@@ -912,6 +1175,181 @@ mod test {
         Ordering::Greater
     );
 
+    test_comparator!(
+        test_comparator_audit_height,
+        Key::new_audit(2, 0),
+        Key::new_audit(256, 0),
+        Ordering::Less
+    );
+
+    test_comparator!(
+        test_comparator_audit_seq,
+        Key::new_audit(2, 1),
+        Key::new_audit(2, 0),
+        Ordering::Greater
+    );
+
+    test_comparator!(
+        test_comparator_audit_after_data,
+        Key::new_audit(0, 0),
+        Key::new_data("namespace".to_string(), "id1".to_string()).unwrap(),
+        Ordering::Greater
+    );
+
+    #[test]
+    fn test_audit_key_roundtrip() {
+        let key = Key::new_audit(300, 7);
+        let bytes = key.serialize().unwrap();
+        assert_eq!(Key::deserialize(&bytes).unwrap(), key);
+    }
+
+    test_comparator!(
+        test_comparator_collection_audit_height,
+        Key::new_collection_audit("namespace".to_string(), 2, 7).unwrap(),
+        Key::new_collection_audit("namespace".to_string(), 256, 0).unwrap(),
+        Ordering::Less
+    );
+
+    #[test]
+    fn test_version_expiry_key_roundtrip() {
+        let key = Key::new_version_expiry(300, 7);
+        let bytes = key.serialize().unwrap();
+        assert_eq!(Key::deserialize(&bytes).unwrap(), key);
+    }
+
+    #[test]
+    fn test_collection_audit_key_roundtrip() {
+        let key = Key::new_collection_audit("namespace".to_string(), 300, 7).unwrap();
+        let bytes = key.serialize().unwrap();
+        assert_eq!(Key::deserialize(&bytes).unwrap(), key);
+    }
+
+    #[test]
+    fn test_referrer_key_roundtrip() {
+        let key = Key::new_referrer(
+            "ns/Person".to_string(),
+            "id1".to_string(),
+            Some(("ns/Account", "a")),
+        )
+        .unwrap();
+        let bytes = key.serialize().unwrap();
+        assert_eq!(Key::deserialize(&bytes).unwrap(), key);
+    }
+
+    test_comparator!(
+        test_comparator_referrer_prefix,
+        Key::new_referrer("ns/Person".to_string(), "id1".to_string(), None).unwrap(),
+        Key::new_referrer(
+            "ns/Person".to_string(),
+            "id1".to_string(),
+            Some(("ns/Account", "a"))
+        )
+        .unwrap(),
+        Ordering::Less
+    );
+
+    test_comparator!(
+        test_comparator_referrer_prefix_wildcard,
+        Key::new_referrer("ns/Person".to_string(), "id1".to_string(), None)
+            .unwrap()
+            .wildcard(),
+        Key::new_referrer(
+            "ns/Person".to_string(),
+            "id1".to_string(),
+            Some(("ns/Account", "a"))
+        )
+        .unwrap(),
+        Ordering::Greater
+    );
+
+    test_comparator!(
+        test_comparator_version_height,
+        Key::new_version("namespace".to_string(), "id1".to_string(), 2).unwrap(),
+        Key::new_version("namespace".to_string(), "id1".to_string(), 256).unwrap(),
+        Ordering::Less
+    );
+
+    test_comparator!(
+        test_comparator_version_after_data,
+        Key::new_version("namespace".to_string(), "id1".to_string(), 0).unwrap(),
+        Key::new_data("namespace".to_string(), "id1".to_string()).unwrap(),
+        Ordering::Greater
+    );
+
+    #[test]
+    fn test_version_key_roundtrip() {
+        let key = Key::new_version("namespace".to_string(), "id1".to_string(), 300).unwrap();
+        let bytes = key.serialize().unwrap();
+        assert_eq!(Key::deserialize(&bytes).unwrap(), key);
+    }
+
+    #[test]
+    fn test_index_record_keys_with_array_field() {
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), record::RecordValue::String("id1".into()));
+        record.insert(
+            "tags".to_string(),
+            record::RecordValue::Array(vec![
+                record::RecordValue::String("rust".into()),
+                record::RecordValue::String("db".into()),
+                record::RecordValue::String("rust".into()),
+            ]),
+        );
+
+        let paths: [&FieldPath; 2] = [&"tags".into(), &"id".into()];
+        let directions = [IndexDirection::Ascending, IndexDirection::Ascending];
+
+        let keys =
+            index_record_keys_with_record("namespace".to_string(), &paths, &directions, &record)
+                .unwrap();
+
+        let expected = ["rust", "db"]
+            .into_iter()
+            .map(|tag| {
+                Key::new_index(
+                    "namespace".to_string(),
+                    &paths,
+                    &directions,
+                    vec![
+                        Cow::Owned(IndexValue::String(tag.into())),
+                        Cow::Owned(IndexValue::String("id1".into())),
+                    ],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_index_record_keys_with_empty_array_field() {
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), record::RecordValue::String("id1".into()));
+        record.insert("tags".to_string(), record::RecordValue::Array(vec![]));
+
+        let paths: [&FieldPath; 2] = [&"tags".into(), &"id".into()];
+        let directions = [IndexDirection::Ascending, IndexDirection::Ascending];
+
+        let keys =
+            index_record_keys_with_record("namespace".to_string(), &paths, &directions, &record)
+                .unwrap();
+
+        assert_eq!(
+            keys,
+            vec![Key::new_index(
+                "namespace".to_string(),
+                &paths,
+                &directions,
+                vec![
+                    Cow::Owned(IndexValue::Null),
+                    Cow::Owned(IndexValue::String("id1".into())),
+                ],
+            )
+            .unwrap()]
+        );
+    }
+
     test_comparator!(
         test_comparator_7,
         Key::new_index(
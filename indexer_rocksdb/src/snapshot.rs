@@ -14,9 +14,14 @@ pub enum Error {
 
 pub type SnapshotChunk = Vec<SnapshotValue>;
 
+/// Iterates over every key/value pair in the database, in chunks of up to `chunk_size`
+/// bytes (see `serialized_size`). A pair that is larger than `chunk_size` on its own is sent in a
+/// chunk by itself.
 pub struct SnapshotIterator<'a> {
     chunk_size: usize,
     iter: rocksdb::DBIteratorWithThreadMode<'a, rocksdb::DB>,
+    /// Pair read from the database that didn't fit in the previous chunk
+    next_value: Option<SnapshotValue>,
 }
 
 impl<'a> SnapshotIterator<'a> {
@@ -24,24 +29,46 @@ impl<'a> SnapshotIterator<'a> {
         SnapshotIterator {
             chunk_size,
             iter: db.iterator(IteratorMode::Start),
+            next_value: None,
+        }
+    }
+
+    fn next_value(&mut self) -> Option<Result<SnapshotValue>> {
+        if let Some(value) = self.next_value.take() {
+            return Some(Ok(value));
+        }
+
+        match self.iter.next()? {
+            Ok((key, value)) => Some(Ok(SnapshotValue { key, value })),
+            Err(e) => Some(Err(e.into())),
         }
     }
 }
 
+/// Size of the pair as stored in the database, i.e. the serialized key and value
+pub(crate) fn serialized_size(value: &SnapshotValue) -> usize {
+    value.key.len() + value.value.len()
+}
+
 impl<'a> Iterator for SnapshotIterator<'a> {
     type Item = Result<Vec<SnapshotValue>>;
     fn next(&mut self) -> Option<Self::Item> {
         let mut batch = Vec::new();
         let mut bytes = 0;
-        while bytes < self.chunk_size {
-            match self.iter.next() {
-                Some(Ok((key, value))) => {
-                    bytes += key.len() + value.len();
-                    batch.push(SnapshotValue { key, value });
-                }
-                Some(Err(e)) => return Some(Err(e.into())),
-                None => break,
+        while let Some(value) = self.next_value() {
+            let value = match value {
+                Ok(value) => value,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let size = serialized_size(&value);
+            if !batch.is_empty() && bytes + size > self.chunk_size {
+                self.next_value = Some(value);
+                break;
             }
+
+            bytes += size;
+            batch.push(value);
         }
         if batch.is_empty() {
             None
@@ -50,3 +77,61 @@ impl<'a> Iterator for SnapshotIterator<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keys::Key,
+        store::{tests::TestStore, Value},
+    };
+    use schema::record::{RecordRoot, RecordValue};
+
+    #[tokio::test]
+    async fn test_chunks_are_within_byte_budget() {
+        const CHUNK_SIZE: usize = 4096;
+
+        let store = TestStore::default();
+        // Mix of small and large values, including one larger than the chunk size
+        for (i, size) in [10, 3000, 50, 2000, 2500, 8000, 1, 4000, 100, 900]
+            .into_iter()
+            .cycle()
+            .take(50)
+            .enumerate()
+        {
+            let mut record = RecordRoot::new();
+            record.insert("data".to_string(), RecordValue::Bytes(vec![0; size]));
+            store
+                .set(
+                    &Key::new_system_data(format!("key-{i:02}")).unwrap(),
+                    &Value::DataValue(&record),
+                )
+                .await
+                .unwrap();
+        }
+        store.commit().await.unwrap();
+
+        let chunks = store
+            .snapshot(CHUNK_SIZE)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let sizes = chunks
+            .iter()
+            .map(|chunk| chunk.iter().map(serialized_size).sum::<usize>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(chunks.iter().map(Vec::len).sum::<usize>(), 50);
+        for (i, chunk) in chunks.iter().enumerate() {
+            // Only a single pair can exceed the budget
+            assert!(sizes[i] <= CHUNK_SIZE || chunk.len() == 1, "{sizes:?}");
+
+            // Chunks are only emitted early when the next pair doesn't fit
+            if let Some(next) = chunks.get(i + 1) {
+                assert!(
+                    sizes[i] + serialized_size(&next[0]) > CHUNK_SIZE,
+                    "{sizes:?}"
+                );
+            }
+        }
+    }
+}
@@ -3,16 +3,23 @@ use crate::{
     keys::{self, Key},
     proto,
 };
+use indexer::adaptor::AuditEntry;
 use parking_lot::Mutex;
 use prost::Message;
 use rocksdb::WriteBatch;
 use schema::record::RecordRoot;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::mem;
 use std::{convert::AsRef, path::Path, sync::Arc};
+use tracing::warn;
 
 pub type Result<T> = std::result::Result<T, StoreError>;
 
+/// Column family for keys that are local to this node, it uses the default comparator
+/// and is not included in snapshots
+const LOCAL_CF: &str = "local";
+
 #[derive(Debug, thiserror::Error)]
 pub enum StoreError {
     #[error("invalid key/value combination")]
@@ -24,6 +31,9 @@ pub enum StoreError {
     #[error("RocksDB error")]
     RocksDBError(#[from] rocksdb::Error),
 
+    #[error("store is corrupted")]
+    Corrupted(#[source] rocksdb::Error),
+
     #[error("bincode error")]
     BincodeError(#[from] bincode::Error),
 
@@ -35,12 +45,38 @@ pub enum StoreError {
 
     #[error("prost decode error")]
     ProstDecode(#[from] prost::DecodeError),
+
+    #[error("column family {0:?} not found")]
+    ColumnFamilyNotFound(&'static str),
+}
+
+impl StoreError {
+    /// Whether the operation may succeed if it is retried, e.g. the write conflicted
+    /// with another write or timed out
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            StoreError::RocksDBError(err) => matches!(
+                err.kind(),
+                rocksdb::ErrorKind::Busy
+                    | rocksdb::ErrorKind::TryAgain
+                    | rocksdb::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(crate) enum Value<'a> {
     DataValue(&'a RecordRoot),
     IndexValue(proto::IndexRecord),
+    AuditValue(&'a AuditEntry),
+    /// A record as of a block height, `None` if the record was deleted
+    VersionValue(Option<&'a RecordRoot>),
+    /// The serialized key of a superseded version
+    VersionExpiryValue(&'a [u8]),
+    /// Referrer keys have no value, the reference is stored in the key
+    ReferrerValue,
 }
 
 impl<'a> Value<'a> {
@@ -48,6 +84,10 @@ impl<'a> Value<'a> {
         match self {
             Value::DataValue(value) => Ok(bincode::serialize(value)?),
             Value::IndexValue(value) => Ok(value.encode_to_vec()),
+            Value::AuditValue(value) => Ok(value.encode()?),
+            Value::VersionValue(value) => Ok(bincode::serialize(value)?),
+            Value::VersionExpiryValue(value) => Ok(value.to_vec()),
+            Value::ReferrerValue => Ok(vec![]),
         }
     }
 }
@@ -58,30 +98,61 @@ pub(crate) struct Store {
     state: Arc<Mutex<StoreState>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum StoreOp {
     Put(Vec<u8>),
     Delete,
 }
 
+/// Writes that were pending when the savepoint was created
+pub(crate) struct Savepoint(HashMap<Vec<u8>, StoreOp>, Vec<(Vec<u8>, Vec<u8>)>);
+
 pub(crate) struct StoreState {
     // batch: WriteBatch,
     pending: HashMap<Vec<u8>, StoreOp>,
+    /// Ranges of keys deleted by `delete_range`, they are deleted before the pending writes
+    /// are applied
+    deleted_ranges: Vec<(Vec<u8>, Vec<u8>)>,
 }
 
 impl Store {
-    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+    /// Open the store, creating it if missing. A corrupted store is repaired before it's
+    /// opened again if `repair_on_corruption` is set, otherwise opening fails with
+    /// `StoreError::Corrupted`.
+    pub fn open(path: impl AsRef<Path>, repair_on_corruption: bool) -> Result<Self> {
+        let path = path.as_ref();
         let mut options = rocksdb::Options::default();
         options.create_if_missing(true);
+        options.create_missing_column_families(true);
         options.set_comparator("polybase", keys::comparator);
 
-        let db = rocksdb::DB::open(&options, path)?;
+        let column_families = || {
+            vec![
+                rocksdb::ColumnFamilyDescriptor::new(
+                    rocksdb::DEFAULT_COLUMN_FAMILY_NAME,
+                    options.clone(),
+                ),
+                rocksdb::ColumnFamilyDescriptor::new(LOCAL_CF, rocksdb::Options::default()),
+            ]
+        };
+
+        let db = match rocksdb::DB::open_cf_descriptors(&options, path, column_families()) {
+            Ok(db) => db,
+            Err(err) if repair_on_corruption && is_corruption(&err) => {
+                warn!(?err, ?path, "Store is corrupted, attempting to repair");
+                rocksdb::DB::repair(&options, path).map_err(StoreError::Corrupted)?;
+                rocksdb::DB::open_cf_descriptors(&options, path, column_families())
+                    .map_err(open_error)?
+            }
+            Err(err) => return Err(open_error(err)),
+        };
 
         Ok(Self {
             db: Arc::new(db),
             state: Arc::new(Mutex::new(StoreState {
                 // batch: WriteBatch::default(),
                 pending: HashMap::new(),
+                deleted_ranges: vec![],
             })),
         })
     }
@@ -91,14 +162,22 @@ impl Store {
         // let batch = Arc::clone(&self.batch);
         let db = Arc::clone(&self.db);
 
-        let pending = {
+        let (pending, deleted_ranges) = {
             let mut state = self.state.lock();
-            mem::take(&mut state.pending)
+            (
+                mem::take(&mut state.pending),
+                mem::take(&mut state.deleted_ranges),
+            )
         };
 
         let mut db_batch = WriteBatch::default();
 
         tokio::task::spawn_blocking(move || {
+            // Writes in a deleted range were added after the range was deleted, so the
+            // ranges are deleted first
+            for (lower_bound, upper_bound) in deleted_ranges {
+                db_batch.delete_range(lower_bound, upper_bound);
+            }
             for (key, op) in pending {
                 match op {
                     StoreOp::Put(value) => db_batch.put(key, value),
@@ -112,12 +191,39 @@ impl Store {
         Ok(())
     }
 
+    /// Create a savepoint of the pending writes, so writes added after it can be discarded
+    /// with `rollback`
+    pub(crate) fn savepoint(&self) -> Savepoint {
+        let state = self.state.lock();
+        Savepoint(state.pending.clone(), state.deleted_ranges.clone())
+    }
+
+    /// Discard the writes added since the savepoint was created, including any that were
+    /// taken by a failed `commit`
+    pub(crate) fn rollback(&self, savepoint: Savepoint) {
+        let mut state = self.state.lock();
+        state.pending = savepoint.0;
+        state.deleted_ranges = savepoint.1;
+    }
+
+    /// Discard every pending write
+    pub(crate) fn discard_pending(&self) {
+        let mut state = self.state.lock();
+        state.pending.clear();
+        state.deleted_ranges.clear();
+    }
+
     #[tracing::instrument(skip(self))]
     pub(crate) async fn set(&self, key: &Key<'_>, value: &Value<'_>) -> Result<()> {
         match (key, value) {
             (Key::Data { .. }, Value::DataValue(_)) => {}
             (Key::SystemData { .. }, Value::DataValue(_)) => {}
             (Key::Index { .. }, Value::IndexValue(_)) => {}
+            (Key::Audit { .. }, Value::AuditValue(_)) => {}
+            (Key::CollectionAudit { .. }, Value::AuditValue(_)) => {}
+            (Key::Version { .. }, Value::VersionValue(_)) => {}
+            (Key::VersionExpiry { .. }, Value::VersionExpiryValue(_)) => {}
+            (Key::Referrer { .. }, Value::ReferrerValue) => {}
             _ => return Err(StoreError::InvalidKeyValueCombination),
         }
 
@@ -138,13 +244,17 @@ impl Store {
         let db = Arc::clone(&self.db);
         let state = Arc::clone(&self.state);
 
-        tokio::task::spawn_blocking(move || match state.lock().pending.get(&key) {
-            Some(StoreOp::Put(value)) => Ok(Some(bincode::deserialize_from(value.as_slice())?)),
-            Some(StoreOp::Delete) => Ok(None),
-            None => match db.get_pinned(key)? {
-                Some(slice) => Ok(Some(bincode::deserialize_from(slice.as_ref())?)),
-                None => Ok(None),
-            },
+        tokio::task::spawn_blocking(move || {
+            let state = state.lock();
+            match state.pending.get(&key) {
+                Some(StoreOp::Put(value)) => Ok(Some(bincode::deserialize_from(value.as_slice())?)),
+                Some(StoreOp::Delete) => Ok(None),
+                None if state.is_range_deleted(&key) => Ok(None),
+                None => match db.get_pinned(key)? {
+                    Some(slice) => Ok(Some(bincode::deserialize_from(slice.as_ref())?)),
+                    None => Ok(None),
+                },
+            }
         })
         .await?
     }
@@ -161,16 +271,84 @@ impl Store {
         Ok(())
     }
 
+    /// Delete every key from the lower bound (inclusive) to the upper bound (exclusive).
+    /// Pending writes in the range are discarded, writes added later are kept.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn delete_range(
+        &self,
+        lower_bound: &Key<'_>,
+        upper_bound: &Key<'_>,
+    ) -> Result<()> {
+        let lower_bound = lower_bound.serialize()?;
+        let upper_bound = upper_bound.serialize()?;
+        let state = Arc::clone(&self.state);
+        tokio::task::spawn_blocking(move || {
+            let mut state = state.lock();
+            state
+                .pending
+                .retain(|key, _| !in_range(key, &lower_bound, &upper_bound));
+            state.deleted_ranges.push((lower_bound, upper_bound));
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Write a node-local key immediately, without waiting for `commit`
+    #[tracing::instrument(skip(self, value))]
+    pub(crate) async fn set_local(&self, key: &str, value: &RecordRoot) -> Result<()> {
+        let key = key.as_bytes().to_vec();
+        let value = bincode::serialize(value)?;
+        let db = Arc::clone(&self.db);
+
+        tokio::task::spawn_blocking(move || {
+            let cf = db
+                .cf_handle(LOCAL_CF)
+                .ok_or(StoreError::ColumnFamilyNotFound(LOCAL_CF))?;
+            db.put_cf(cf, key, value)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn get_local(&self, key: &str) -> Result<Option<RecordRoot>> {
+        let key = key.as_bytes().to_vec();
+        let db = Arc::clone(&self.db);
+
+        tokio::task::spawn_blocking(move || {
+            let cf = db
+                .cf_handle(LOCAL_CF)
+                .ok_or(StoreError::ColumnFamilyNotFound(LOCAL_CF))?;
+            match db.get_pinned_cf(cf, key)? {
+                Some(slice) => Ok(Some(bincode::deserialize_from(slice.as_ref())?)),
+                None => Ok(None),
+            }
+        })
+        .await?
+    }
+
     #[tracing::instrument(skip(self))]
     pub(crate) fn list(
         &self,
         lower_bound: &Key,
         upper_bound: &Key,
         reverse: bool,
+    ) -> Result<impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>)>> + '_> {
+        self.list_serialized(lower_bound.serialize()?, upper_bound.serialize()?, reverse)
+    }
+
+    /// List keys between already serialized bounds, e.g. bounds that span every key of
+    /// a type
+    pub(crate) fn list_serialized(
+        &self,
+        lower_bound: Vec<u8>,
+        upper_bound: Vec<u8>,
+        reverse: bool,
     ) -> Result<impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>)>> + '_> {
         let mut opts = rocksdb::ReadOptions::default();
-        opts.set_iterate_lower_bound(lower_bound.serialize()?);
-        opts.set_iterate_upper_bound(upper_bound.serialize()?);
+        opts.set_iterate_lower_bound(lower_bound);
+        opts.set_iterate_upper_bound(upper_bound);
 
         Ok(self
             .db
@@ -196,15 +374,29 @@ impl Store {
         Ok(())
     }
 
-    pub fn reset(&self) -> Result<()> {
-        let iter = SnapshotIterator::new(&self.db, 100 * 1024 * 1024);
-        for entry in iter {
-            let mut batch = WriteBatch::default();
-            for entry in entry? {
-                batch.delete(entry.key);
+    #[tracing::instrument(skip(self))]
+    pub async fn reset(&self) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        tokio::task::spawn_blocking(move || {
+            let iter = SnapshotIterator::new(&db, 100 * 1024 * 1024);
+            for entry in iter {
+                let mut batch = WriteBatch::default();
+                for entry in entry? {
+                    batch.delete(entry.key);
+                }
+                db.write(batch)?;
             }
-            self.db.write(batch)?;
-        }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Compact the entire key range, so space used by deleted or overwritten
+    /// keys is reclaimed (e.g. after a reset and restore)
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn compact(&self) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        tokio::task::spawn_blocking(move || db.compact_range(None::<&[u8]>, None::<&[u8]>)).await?;
 
         Ok(())
     }
@@ -216,13 +408,42 @@ impl Store {
 
     // TODO:
     #[tracing::instrument(skip(self))]
-    pub fn restore(&self, chunk: SnapshotChunk) -> Result<()> {
-        let mut batch = WriteBatch::default();
-        for entry in chunk {
-            batch.put(entry.key, entry.value);
-        }
-        self.db.write(batch)?;
-        Ok(())
+    pub async fn restore(&self, chunk: SnapshotChunk) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        tokio::task::spawn_blocking(move || {
+            let mut batch = WriteBatch::default();
+            for entry in chunk {
+                batch.put(entry.key, entry.value);
+            }
+            db.write(batch)?;
+            Ok(())
+        })
+        .await?
+    }
+}
+
+impl StoreState {
+    fn is_range_deleted(&self, key: &[u8]) -> bool {
+        self.deleted_ranges
+            .iter()
+            .any(|(lower_bound, upper_bound)| in_range(key, lower_bound, upper_bound))
+    }
+}
+
+fn in_range(key: &[u8], lower_bound: &[u8], upper_bound: &[u8]) -> bool {
+    keys::comparator(key, lower_bound) != Ordering::Less
+        && keys::comparator(key, upper_bound) == Ordering::Less
+}
+
+fn is_corruption(err: &rocksdb::Error) -> bool {
+    matches!(err.kind(), rocksdb::ErrorKind::Corruption)
+}
+
+fn open_error(err: rocksdb::Error) -> StoreError {
+    if is_corruption(&err) {
+        StoreError::Corrupted(err)
+    } else {
+        StoreError::RocksDBError(err)
     }
 }
 
@@ -233,7 +454,7 @@ pub(crate) mod tests {
         ops::{Deref, DerefMut},
     };
 
-    use schema::{index::IndexDirection, index_value::IndexValue};
+    use schema::{index::IndexDirection, index_value::IndexValue, record::RecordValue};
 
     use super::*;
 
@@ -247,7 +468,7 @@ pub(crate) mod tests {
                 rand::random::<u32>()
             ));
 
-            Self(Some(Store::open(path).unwrap()))
+            Self(Some(Store::open(path, false).unwrap()))
         }
     }
 
@@ -301,4 +522,138 @@ pub(crate) mod tests {
             assert_eq!(value, proto::IndexRecord::default());
         }
     }
+
+    #[tokio::test]
+    async fn test_delete_range_keeps_later_writes() {
+        let store = TestStore::default();
+        let index = |namespace: &str, name: &str| {
+            Key::new_index(
+                namespace.to_string(),
+                &[&"name".into()],
+                &[IndexDirection::Ascending],
+                vec![Cow::Owned(IndexValue::String(name.to_string().into()))],
+            )
+            .unwrap()
+        };
+        let value = Value::IndexValue(proto::IndexRecord::default());
+        let count = |namespace: &str| {
+            let lower_bound = Key::new_index(
+                namespace.to_string(),
+                &[&"name".into()],
+                &[IndexDirection::Ascending],
+                vec![],
+            )
+            .unwrap();
+            let upper_bound = lower_bound.clone().wildcard();
+            store
+                .list(&lower_bound, &upper_bound, false)
+                .unwrap()
+                .count()
+        };
+
+        store.set(&index("ns", "John"), &value).await.unwrap();
+        store.set(&index("other", "John"), &value).await.unwrap();
+        store.commit().await.unwrap();
+
+        let lower_bound = Key::new_index(
+            "ns".to_string(),
+            &[&"name".into()],
+            &[IndexDirection::Ascending],
+            vec![],
+        )
+        .unwrap();
+        store.set(&index("ns", "Jane"), &value).await.unwrap();
+        store
+            .delete_range(&lower_bound, &lower_bound.clone().wildcard())
+            .await
+            .unwrap();
+        store.set(&index("ns", "Bob"), &value).await.unwrap();
+        store.commit().await.unwrap();
+
+        // Only the entry written after the range was deleted is left
+        assert_eq!(count("ns"), 1);
+        assert_eq!(count("other"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_open_repairs_corrupted_store() {
+        let path = std::env::temp_dir().join(format!(
+            "test-indexer-rocksdb-store-{}",
+            rand::random::<u32>()
+        ));
+        let key = Key::new_data("ns/Col".to_string(), "id1".to_string()).unwrap();
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String("id1".to_string()));
+
+        {
+            let store = Store::open(&path, false).unwrap();
+            store.set(&key, &Value::DataValue(&record)).await.unwrap();
+            store.commit().await.unwrap();
+        }
+
+        // CURRENT must end with a newline, so RocksDB reports the store as corrupted
+        std::fs::write(path.join("CURRENT"), "corrupted").unwrap();
+
+        assert!(matches!(
+            Store::open(&path, false),
+            Err(StoreError::Corrupted(_))
+        ));
+
+        let store = TestStore(Some(Store::open(&path, true).unwrap()));
+        assert_eq!(store.get(&key).await.unwrap(), Some(record));
+    }
+
+    #[tokio::test]
+    async fn test_local_keys_are_written_immediately_and_not_snapshotted() {
+        let store = TestStore::default();
+        let mut record = RecordRoot::new();
+        record.insert("hits".to_string(), RecordValue::Number(1.0));
+
+        store.set_local("usage", &record).await.unwrap();
+        assert_eq!(store.get_local("usage").await.unwrap(), Some(record));
+        assert_eq!(store.get_local("missing").await.unwrap(), None);
+
+        let chunks = store
+            .snapshot(1024)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(chunks.iter().all(|chunk| chunk.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_store_compact() {
+        let store = TestStore::default();
+
+        let keys = (0..1000)
+            .map(|i| Key::new_data("ns/Col".to_string(), format!("id{i}")).unwrap())
+            .collect::<Vec<_>>();
+
+        for (i, key) in keys.iter().enumerate() {
+            let mut record = RecordRoot::new();
+            record.insert("id".to_string(), RecordValue::String(format!("id{i}")));
+            store.set(key, &Value::DataValue(&record)).await.unwrap();
+        }
+        store.commit().await.unwrap();
+
+        // Delete all but every 10th record
+        for (i, key) in keys.iter().enumerate() {
+            if i % 10 != 0 {
+                store.delete(key).await.unwrap();
+            }
+        }
+        store.commit().await.unwrap();
+
+        store.compact().await.unwrap();
+
+        for (i, key) in keys.iter().enumerate() {
+            let record = store.get(key).await.unwrap();
+            if i % 10 == 0 {
+                let mut expected = RecordRoot::new();
+                expected.insert("id".to_string(), RecordValue::String(format!("id{i}")));
+                assert_eq!(record, Some(expected));
+            } else {
+                assert_eq!(record, None);
+            }
+        }
+    }
 }
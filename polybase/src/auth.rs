@@ -2,7 +2,7 @@ use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
 use sha3::Digest;
 use std::{
     future::ready,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use actix_web::{http::header::CONTENT_LENGTH, FromRequest};
@@ -65,6 +65,12 @@ pub enum AuthUserError {
     #[error("signature expired")]
     SignatureExpired,
 
+    #[error("signature timestamp is in the future")]
+    SignatureTimestampInFuture,
+
+    #[error("signature has already been used")]
+    SignatureReplayed,
+
     #[error("failed to decode hex parameter {parameter:?}")]
     FailedToDecodeHexParameter {
         parameter: String,
@@ -90,15 +96,111 @@ pub enum AuthUserError {
     FailedToParseBody(#[source] serde_json::Error),
 }
 
-const TIME_TOLERANCE: u64 = 5 * 60; // 5 minutes
+/// Signature timestamps at or above this are in microseconds rather than milliseconds,
+/// as a millisecond timestamp this large would be thousands of years in the future
+const MICROSECOND_TIMESTAMP_THRESHOLD: u64 = 100_000_000_000_000;
+
+/// Window of time in which a request signature is accepted, so that signed requests
+/// can't be replayed indefinitely
+#[derive(Debug, Clone, Copy)]
+pub struct SignatureConfig {
+    /// Maximum age of a signature
+    pub max_age: Duration,
+    /// How far a signature timestamp can be in the future, to allow for clock skew
+    pub max_future_skew: Duration,
+}
+
+impl Default for SignatureConfig {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(5 * 60),
+            max_future_skew: Duration::from_secs(30),
+        }
+    }
+}
 
 pub(crate) struct Auth {
     pub(crate) public_key: PublicKey,
+    /// Keys from any further signatures on the request
+    pub(crate) additional_public_keys: Vec<PublicKey>,
+    /// One for each signature on the request, used to reject replayed requests
+    pub(crate) signed_requests: Vec<SignedRequest>,
+}
+
+/// Identifies a signed request, two requests with the same public key and message hash
+/// are the same request
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedRequest {
+    pub public_key: PublicKey,
+    pub signed_at: SystemTime,
+    /// Hash of the signed message, i.e. the timestamp and body
+    pub message_hash: [u8; 32],
+}
+
+impl Auth {
+    /// Verifies each signature header against the body. The first signature provides the
+    /// primary public key, the rest are additional keys that can also authorise the request.
+    fn from_headers(
+        headers: &[String],
+        body: &[u8],
+        now: SystemTime,
+        config: SignatureConfig,
+    ) -> Result<Option<Self>> {
+        let signed_requests = headers
+            .iter()
+            .map(|header| {
+                let sig = Signature::deserialize(header)?;
+
+                let public_key = match &sig.public_key {
+                    Some(public_key)
+                        if std::option_env!("DEV_SKIP_SIGNATURE_VERIFICATION") == Some("1") =>
+                    {
+                        // this is a dev-only feature
+                        sig.check_timestamp(now, config)?;
+                        public_key.clone()
+                    }
+                    _ => recover_public_key(header, body, now, config)?,
+                };
+
+                Ok(SignedRequest {
+                    public_key,
+                    // The timestamp has been checked, so is always valid
+                    signed_at: sig.signed_at().unwrap_or(now),
+                    message_hash: sig.message_hash(body),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut public_keys = signed_requests.iter().map(|r| r.public_key.clone());
+        let Some(public_key) = public_keys.next() else {
+            return Ok(None);
+        };
+
+        Ok(Some(Auth {
+            public_key,
+            additional_public_keys: public_keys.collect(),
+            signed_requests,
+        }))
+    }
+}
+
+/// Recovers the public key that signed `body`, from an `X-Polybase-Signature` header value.
+/// The signature timestamp must be within the `config` window around `now`, and the signature
+/// must match the public key in the header (if one is given).
+pub(crate) fn recover_public_key(
+    header: &str,
+    body: &[u8],
+    now: SystemTime,
+    config: SignatureConfig,
+) -> Result<PublicKey> {
+    let signature = Signature::deserialize(header)?;
+    signature.check_timestamp(now, config)?;
+    signature.verify(body)
 }
 
 impl From<Auth> for AuthUser {
     fn from(auth: Auth) -> Self {
-        Self::new(auth.public_key)
+        Self::with_additional_public_keys(auth.public_key, auth.additional_public_keys)
     }
 }
 
@@ -106,7 +208,7 @@ impl From<Auth> for AuthUser {
 struct Signature {
     public_key: Option<PublicKey>,
     sig: RecoverableSignature,
-    /// Unix timestamp in *milliseconds*.
+    /// Unix timestamp in *milliseconds* (or microseconds, see `signed_at`).
     timestamp: u64,
     version: String,
     hash: String,
@@ -201,7 +303,8 @@ impl Signature {
         })
     }
 
-    fn verify(&self, body: &[u8]) -> Result<PublicKey> {
+    /// Hash of the message signed by the client, i.e. the timestamp and body
+    fn message_hash(&self, body: &[u8]) -> [u8; 32] {
         let timestamp = self.timestamp.to_string();
         let timestamp_body_len = (timestamp.len() + 1 + body.len()).to_string();
         let message_parts = &[
@@ -217,7 +320,11 @@ impl Signature {
             hasher.update(part);
         }
 
-        let message_hash = hasher.finalize();
+        hasher.finalize().into()
+    }
+
+    fn verify(&self, body: &[u8]) -> Result<PublicKey> {
+        let message_hash = self.message_hash(body);
 
         let sig_pk = self
             .sig
@@ -232,26 +339,38 @@ impl Signature {
         Ok(sig_pk)
     }
 
-    fn from_req(req: &actix_web::HttpRequest) -> Result<Option<Self>> {
-        let Some(signature) = req
-            .headers()
-            .get("X-Polybase-Signature") else { return Ok(None); };
-
-        let signature = signature.to_str()?;
+    /// Time the signature was created. Most clients send the timestamp in milliseconds,
+    /// but some (e.g. the explorer) send it in microseconds.
+    fn signed_at(&self) -> Option<SystemTime> {
+        let since_epoch = if self.timestamp >= MICROSECOND_TIMESTAMP_THRESHOLD {
+            Duration::from_micros(self.timestamp)
+        } else {
+            Duration::from_millis(self.timestamp)
+        };
 
-        let signature = Signature::deserialize(signature)?;
+        UNIX_EPOCH.checked_add(since_epoch)
+    }
 
-        #[allow(clippy::unwrap_used)] // this should never error
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    fn check_timestamp(&self, now: SystemTime, config: SignatureConfig) -> Result<()> {
+        let Some(signed_at) = self.signed_at() else {
+            return Err(AuthUserError::SignatureTimestampInFuture.into());
+        };
 
-        if signature.timestamp / 1000 + TIME_TOLERANCE < now {
+        if now
+            .duration_since(signed_at)
+            .map_or(false, |age| age > config.max_age)
+        {
             return Err(AuthUserError::SignatureExpired.into());
         }
 
-        Ok(Some(signature))
+        if signed_at
+            .duration_since(now)
+            .map_or(false, |ahead| ahead > config.max_future_skew)
+        {
+            return Err(AuthUserError::SignatureTimestampInFuture.into());
+        }
+
+        Ok(())
     }
 }
 
@@ -268,10 +387,21 @@ impl<T: DeserializeOwned + 'static> FromRequest for SignedJSON<T> {
         req: &actix_web::HttpRequest,
         payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
-        let sig = match Signature::from_req(req) {
-            Ok(sig) => sig,
+        // A request can be signed by multiple keys by sending one header per key
+        let headers = match req
+            .headers()
+            .get_all("X-Polybase-Signature")
+            .map(|header| -> Result<String> { Ok(header.to_str()?.to_string()) })
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(headers) => headers,
             Err(e) => return Box::pin(ready(Err(e.into()))),
         };
+        let now = SystemTime::now();
+        let signature_config = req
+            .app_data::<SignatureConfig>()
+            .copied()
+            .unwrap_or_default();
 
         let length = req
             .headers()
@@ -290,23 +420,7 @@ impl<T: DeserializeOwned + 'static> FromRequest for SignedJSON<T> {
                 data: serde_json::from_slice(if body.is_empty() { b"null" } else { &body })
                     .map_err(AuthUserError::FailedToParseBody)
                     .map_err(AuthError::from)?,
-                auth: sig
-                    .map(|sig| {
-                        if std::option_env!("DEV_SKIP_SIGNATURE_VERIFICATION") == Some("1")
-                            && sig.public_key.is_some()
-                        {
-                            #[allow(clippy::unwrap_used)]
-                            // we know public_key is Some, and this is a dev-only feature
-                            return Ok(Auth {
-                                public_key: sig.public_key.unwrap(),
-                            });
-                        }
-
-                        Ok::<_, AuthError>(Auth {
-                            public_key: sig.verify(&body)?,
-                        })
-                    })
-                    .transpose()?,
+                auth: Auth::from_headers(&headers, &body, now, signature_config)?,
             })
         })
     }
@@ -395,4 +509,115 @@ mod tests {
 
         assert_eq!(signature.verify(body.as_bytes()).unwrap(), public);
     }
+
+    /// Signs the body, returning the X-Polybase-Signature header value
+    fn sign_header(key: &secp256k1::SecretKey, time: SystemTime, body: &str) -> String {
+        let timestamp = time.duration_since(UNIX_EPOCH).unwrap().as_millis();
+        let message_content = format!("{timestamp}.{body}");
+
+        let mut hasher = sha3::Keccak256::new();
+        hasher.update("\u{19}Ethereum Signed Message:\n".as_bytes());
+        hasher.update(message_content.len().to_string().as_bytes());
+        hasher.update(message_content.as_bytes());
+        let message = secp256k1::Message::from_slice(&hasher.finalize()).unwrap();
+
+        let (rec_id, sig) = secp256k1::global::SECP256K1
+            .sign_ecdsa_recoverable(&message, key)
+            .serialize_compact();
+        let mut sig = sig.to_vec();
+        sig.push(rec_id.to_i32() as u8 + 27);
+
+        let pk = key
+            .public_key(secp256k1::global::SECP256K1)
+            .serialize_uncompressed();
+
+        format!(
+            "pk=0x{},sig=0x{},t={timestamp},v=0,h=eth-personal-sign",
+            hex::encode(pk),
+            hex::encode(sig),
+        )
+    }
+
+    #[test]
+    fn test_recover_public_key() {
+        let (private, public) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let public = PublicKey::from_secp256k1_key(&public).unwrap();
+        let now = SystemTime::now();
+        let body = r#"{"message":"hello world"}"#;
+
+        let header = sign_header(&private, now, body);
+        assert_eq!(
+            recover_public_key(&header, body.as_bytes(), now, SignatureConfig::default()).unwrap(),
+            public
+        );
+    }
+
+    #[test]
+    fn test_recover_public_key_expired() {
+        let (private, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let now = SystemTime::now();
+        let body = r#"{"message":"hello world"}"#;
+
+        let config = SignatureConfig::default();
+
+        let signed_at = now - config.max_age - Duration::from_secs(1);
+        let header = sign_header(&private, signed_at, body);
+        assert!(matches!(
+            recover_public_key(&header, body.as_bytes(), now, config),
+            Err(AuthError::User(AuthUserError::SignatureExpired))
+        ));
+    }
+
+    #[test]
+    fn test_recover_public_key_future_timestamp() {
+        let (private, public) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let public = PublicKey::from_secp256k1_key(&public).unwrap();
+        let now = SystemTime::now();
+        let body = r#"{"message":"hello world"}"#;
+        let config = SignatureConfig::default();
+
+        // Small clock skew is allowed
+        let header = sign_header(&private, now + Duration::from_secs(5), body);
+        assert_eq!(
+            recover_public_key(&header, body.as_bytes(), now, config).unwrap(),
+            public
+        );
+
+        let signed_at = now + config.max_future_skew + Duration::from_secs(1);
+        let header = sign_header(&private, signed_at, body);
+        assert!(matches!(
+            recover_public_key(&header, body.as_bytes(), now, config),
+            Err(AuthError::User(AuthUserError::SignatureTimestampInFuture))
+        ));
+    }
+
+    #[test]
+    fn test_signed_at_accepts_milliseconds_and_microseconds() {
+        let signature = |timestamp| {
+            let mut signature = Signature::deserialize("sig=0x043705a6972c80f44ac338c3bf8917899773eb2e119fc52da13c02f98da6abe33a31aa1d600f753d01e3848d57b381395ad2b43a58c40ee3d951036d9345683600,t=0,v=1,h=deadbeef").unwrap();
+            signature.timestamp = timestamp;
+            signature
+        };
+
+        let expected = UNIX_EPOCH + Duration::from_millis(1_677_023_964_425);
+        assert_eq!(signature(1_677_023_964_425).signed_at(), Some(expected));
+        assert_eq!(signature(1_677_023_964_425_000).signed_at(), Some(expected));
+    }
+
+    #[test]
+    fn test_recover_public_key_tampered_body() {
+        let (private, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let now = SystemTime::now();
+
+        let header = sign_header(&private, now, r#"{"amount":1}"#);
+        assert!(matches!(
+            recover_public_key(
+                &header,
+                br#"{"amount":1000}"#,
+                now,
+                SignatureConfig::default()
+            ),
+            Err(AuthError::User(AuthUserError::SignaturePublicKeyMismatch))
+        ));
+    }
 }
@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand, ValueEnum};
+use solid::config::QuorumRule;
 use std::fmt;
 
 /// Polybase is a p2p decentralized database
@@ -35,6 +36,18 @@ pub struct Config {
     #[arg(long, env = "SECRET_KEY")]
     pub secret_key: Option<String>,
 
+    /// Memory cost (in KiB) of the key derivation used to encrypt the secret key
+    #[arg(long, env = "KEY_KDF_MEMORY_COST", default_value = "19456")]
+    pub key_kdf_memory_cost: u32,
+
+    /// Number of iterations of the key derivation used to encrypt the secret key
+    #[arg(long, env = "KEY_KDF_TIME_COST", default_value = "2")]
+    pub key_kdf_time_cost: u32,
+
+    /// Parallelism of the key derivation used to encrypt the secret key
+    #[arg(long, env = "KEY_KDF_PARALLELISM", default_value = "1")]
+    pub key_kdf_parallelism: u32,
+
     /// Peer listen address
     #[arg(
         long,
@@ -55,6 +68,11 @@ pub struct Config {
     )]
     pub dial_addr: Vec<String>,
 
+    /// Maximum number of network events queued in each direction, when full pings and
+    /// txns are dropped and other events wait for space
+    #[arg(long, env = "NETWORK_EVENT_QUEUE_CAPACITY", default_value = "1024")]
+    pub network_event_queue_capacity: usize,
+
     /// Validator peers
     #[arg(
         long,
@@ -69,14 +87,114 @@ pub struct Config {
     #[arg(long, env = "BLOCK_CACHE_SIZE", default_value = "1024")]
     pub block_cache_count: usize,
 
+    /// Number of accepts needed for a block: majority, two-thirds or a fixed number of peers
+    #[arg(long, env = "ACCEPT_QUORUM", default_value = "majority")]
+    pub accept_quorum: QuorumRule,
+
     /// Maximum number of txns to include in a block
     #[arg(long, env = "BLOCK_TXN_COUNT", default_value = "1024")]
     pub block_txns_count: usize,
 
-    /// Size of the chunks of data sent during snapshot load
+    /// Maximum size, in bytes, of the chunks of data sent during snapshot load (a single
+    /// key/value pair larger than this is sent in a chunk by itself)
     #[arg(long, env = "SNAPSHOT_CHUNK_SIZE", default_value = "4194304")]
     pub snapshot_chunk_size: usize,
 
+    /// Maximum number of snapshot chunks sent to a peer before waiting for them to be acknowledged
+    #[arg(long, env = "SNAPSHOT_MAX_INFLIGHT_CHUNKS", default_value = "4")]
+    pub snapshot_max_inflight_chunks: usize,
+
+    /// Delay (in ms) between sending each snapshot chunk
+    #[arg(long, env = "SNAPSHOT_CHUNK_DELAY", default_value = "0")]
+    pub snapshot_chunk_delay: u64,
+
+    /// Time (in ms) to wait for a peer to acknowledge each snapshot chunk before aborting
+    /// the snapshot, 0 waits indefinitely
+    #[arg(long, env = "SNAPSHOT_ACK_TIMEOUT", default_value = "30000")]
+    pub snapshot_ack_timeout: u64,
+
+    /// Number of threads used by the async runtime for network, consensus and RPC tasks,
+    /// defaults to the number of CPU cores
+    #[arg(long, env = "WORKER_THREADS")]
+    pub worker_threads: Option<usize>,
+
+    /// Number of V8 isolates used to run collection functions concurrently
+    #[arg(long, env = "GATEWAY_POOL_SIZE", default_value = "4")]
+    pub gateway_pool_size: usize,
+
+    /// Maximum number of collection methods a single function call can invoke when
+    /// accepting a txn, at most the limit of 100 used to apply blocks
+    #[arg(long, env = "FUNCTION_CALL_LIMIT", default_value = "100")]
+    pub function_call_limit: u32,
+
+    /// Time (in ms) after which a function call is terminated when accepting a txn, at
+    /// most the 30s timeout used to apply blocks (also used if 0)
+    #[arg(long, env = "FUNCTION_TIMEOUT", default_value = "5000")]
+    pub function_timeout: u64,
+
+    /// Maximum heap size (in bytes) of each V8 isolate, 0 uses the V8 default. Only
+    /// enforced when accepting txns.
+    #[arg(long, env = "FUNCTION_MEMORY_LIMIT", default_value = "0")]
+    pub function_memory_limit: usize,
+
+    /// Maximum number of collection functions run concurrently while applying a block
+    #[arg(long, env = "MAX_BLOCK_CONCURRENT_CALLS", default_value = "16")]
+    pub max_block_concurrent_calls: usize,
+
+    /// Maximum number of txns waiting in the mempool, new txns are rejected once full
+    #[arg(long, env = "MAX_MEMPOOL_TXNS", default_value = "100000")]
+    pub max_mempool_txns: usize,
+
+    /// Maximum number of records to cache in memory for reads
+    #[arg(long, env = "RECORD_CACHE_SIZE", default_value = "10000")]
+    pub record_cache_size: usize,
+
+    /// Maximum number of records returned by a single list query, larger lists are
+    /// truncated and must be paged using the returned cursor
+    #[arg(long, env = "MAX_LIST_LIMIT", default_value = "1000")]
+    pub max_list_limit: usize,
+
+    /// Maximum number of reads in a single /v0/batch request, larger batches are rejected
+    #[arg(long, env = "MAX_BATCH_READS", default_value = "100")]
+    pub max_batch_reads: usize,
+
+    /// Maximum size of a /v0/admin/collections/{collection}/import body, in bytes
+    #[arg(long, env = "MAX_IMPORT_BYTES", default_value = "104857600")]
+    pub max_import_bytes: usize,
+
+    /// Maximum size of a /v0/admin/restore snapshot, in bytes. The snapshot is verified
+    /// in memory before it's restored.
+    #[arg(long, env = "MAX_RESTORE_BYTES", default_value = "1073741824")]
+    pub max_restore_bytes: usize,
+
+    /// Attempt to repair the indexer store if it's corrupted when the node starts,
+    /// otherwise the node fails to start
+    #[arg(long, env = "REPAIR_ON_CORRUPTION", default_value = "false")]
+    pub repair_on_corruption: bool,
+
+    /// Record every committed change in an audit log, queryable via /v0/admin/audit
+    #[arg(long, env = "AUDIT_LOG", default_value = "false")]
+    pub audit_log: bool,
+
+    /// Count record reads and writes for each collection, queryable via /v0/admin/usage
+    #[arg(long, env = "USAGE_METERING", default_value = "false")]
+    pub usage_metering: bool,
+
+    /// Number of blocks of record history to keep, so records can be read as of a
+    /// previous height (0 disables record history)
+    #[arg(long, env = "RECORD_HISTORY_BLOCKS", default_value = "0")]
+    pub record_history_blocks: usize,
+
+    /// Time (in ms) after which a commit that has not completed marks the node as unhealthy,
+    /// 0 disables the timeout
+    #[arg(long, env = "COMMIT_TIMEOUT", default_value = "30000")]
+    pub commit_timeout: u64,
+
+    /// Maximum number of blocks an out of sync node can be behind while reporting as
+    /// degraded in the health check, rather than unhealthy
+    #[arg(long, env = "MAX_DEGRADED_SYNC_LAG", default_value = "10")]
+    pub max_degraded_sync_lag: usize,
+
     /// Minimum duration of time (in ms), since the last commit, before attempting a new proposal
     #[arg(long, env = "MIN_BLOCK_DURATION", default_value = "500")]
     pub min_block_duration: u64,
@@ -93,6 +211,14 @@ pub struct Config {
     #[arg(long, env = "RESTRICT_NAMESPACES", default_value = "false")]
     pub restrict_namespaces: bool,
 
+    /// Maximum age (in seconds) of a request signature, older signatures are rejected
+    #[arg(long, env = "SIGNATURE_MAX_AGE", default_value = "300")]
+    pub signature_max_age: u64,
+
+    /// Key required to access the admin API (admin API is disabled if not set)
+    #[arg(long, env = "ADMIN_KEY")]
+    pub admin_key: Option<String>,
+
     /// Restrict namespaces to pk/<pk>/<collection_name>
     #[arg(long, env = "MIGRATION_BATCH_SIZE", default_value = "1000")]
     pub migration_batch_size: usize,
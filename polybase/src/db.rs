@@ -1,31 +1,56 @@
 use crate::hash;
-use crate::mempool::Mempool;
+use crate::js_cache::JsCodeCache;
+use crate::mempool::{Mempool, MempoolError};
 use crate::txn::{self, CallTxn};
 use futures_util::{future, StreamExt};
-use gateway::Gateway;
+use gateway::{CallOptions, Gateway, ReadableRecords};
 use indexer::{
-    adaptor::{IndexerAdaptor, SnapshotValue},
+    adaptor::{AuditEntry, IndexerAdaptor, SnapshotValue},
     IndexerChange,
 };
-use indexer::{auth_user::AuthUser, list_query::ListQuery, Indexer};
+use indexer::{
+    auth_user::AuthUser,
+    index_stats::IndexStats,
+    list_query::ListQuery,
+    references::{find_references, find_value_references, RecordKey},
+    usage::Usage,
+    where_query::{WhereInequality, WhereNode, WhereQuery, WhereValue},
+    Indexer, QueryPlan,
+};
 use parking_lot::Mutex;
 use schema::{
-    self, methods,
+    self,
+    directive::DirectiveKind,
+    field_path::FieldPath,
+    index_value::IndexValue,
+    methods,
     record::{
         self, foreign_record_to_json, json_to_record, record_to_json, ForeignRecordReference,
         RecordReference, RecordRoot, RecordValue,
     },
     Schema,
 };
+use sha3::{Digest, Sha3_256};
 use solid::proposal::{self};
 use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc;
 use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::Notify;
+use tokio::sync::Semaphore;
+use tracing::{error, info, Instrument};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Function name used by patch txns, `$` is not allowed in collection function names,
+/// so this can never clash with a function defined by the collection
+pub const PATCH_FUNCTION_NAME: &str = "$patch";
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("schema error: {0}")]
@@ -58,8 +83,14 @@ pub enum Error {
     #[error("tokio send error")]
     TokioSend(#[from] mpsc::error::SendError<CallTxn>),
 
+    #[error(transparent)]
+    Mempool(#[from] MempoolError),
+
     #[error("invalid function args response")]
     InvalidFunctionArgsResponse,
+
+    #[error("invalid state digest")]
+    InvalidStateDigest,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -88,8 +119,15 @@ pub enum UserError {
         collection_id: String,
     },
 
-    // #[error("record ID was modified during call")]
-    // RecordIDModified,
+    #[error("record ID was modified")]
+    RecordIDModified,
+
+    #[error("patch must be an object")]
+    PatchNotObject,
+
+    #[error("records in the Collection collection cannot be patched")]
+    CollectionPatchNotAllowed,
+
     #[error("record does not have a id field")]
     RecordIdNotFound,
 
@@ -108,6 +146,23 @@ pub enum UserError {
 
     #[error("you do not have permission to call this function")]
     UnauthorizedCall,
+
+    #[error("you do not have permission to modify this record")]
+    UnauthorizedWrite,
+
+    #[error("record is too large, {size} bytes exceeds the maximum of {max} bytes")]
+    RecordTooLarge { size: u64, max: usize },
+}
+
+/// Health of the node, based on how far behind the rest of the network it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// Node is up to date
+    Ok,
+    /// Node is out of sync, but close enough behind that it can still serve reads
+    Degraded,
+    /// Node is too far behind (or a commit has stalled) to serve reads
+    Unhealthy,
 }
 
 pub enum DbWaitResult<T> {
@@ -115,10 +170,42 @@ pub enum DbWaitResult<T> {
     NotModified,
 }
 
+/// Maximum size (in bytes) of a serialized record. Records larger than this are invalid,
+/// and blocks are checked against it, so every node must use the same limit.
+pub const MAX_RECORD_BYTES: usize = 1024 * 1024;
+
+/// Limits of the functions run when applying a block. Every node must get the same result
+/// for a block, so these are protocol constants, and the node's `call_options` only apply
+/// when accepting txns (capped at these limits). The timeout stops a function that never
+/// returns from stalling every node, so it's well above the default call timeout. The
+/// memory limit is not enforced.
+pub const BLOCK_CALL_OPTIONS: CallOptions = CallOptions {
+    call_limit: 100,
+    timeout: Some(Duration::from_secs(30)),
+    memory_limit: 0,
+};
+
 #[derive(Debug)]
 pub struct DbConfig {
     pub block_txns_count: usize,
     pub migration_batch_size: usize,
+    /// Maximum number of collections to cache generated JS code for
+    pub js_code_cache_size: usize,
+    /// Time after which a commit that has not completed marks the node as unhealthy
+    pub commit_timeout: Option<Duration>,
+    /// Number of V8 isolates used to run collection functions concurrently
+    pub gateway_pool_size: usize,
+    /// Limits applied to collection function calls when accepting txns, capped at the
+    /// limits used to apply blocks (see `BLOCK_CALL_OPTIONS`)
+    pub call_options: CallOptions,
+    /// Maximum number of collection functions run concurrently while applying a block,
+    /// the remaining txns wait for a running function to finish
+    pub max_block_concurrent_calls: usize,
+    /// Maximum number of txns waiting in the mempool
+    pub max_mempool_txns: usize,
+    /// Maximum number of blocks an out of sync node can be behind the highest seen
+    /// block while reporting as degraded, rather than unhealthy
+    pub max_degraded_sync_lag: usize,
 }
 
 impl Default for DbConfig {
@@ -126,6 +213,13 @@ impl Default for DbConfig {
         DbConfig {
             block_txns_count: 2000,
             migration_batch_size: 1000,
+            js_code_cache_size: 1000,
+            commit_timeout: None,
+            gateway_pool_size: 4,
+            call_options: CallOptions::default(),
+            max_block_concurrent_calls: 16,
+            max_mempool_txns: 100_000,
+            max_degraded_sync_lag: 10,
         }
     }
 }
@@ -133,25 +227,59 @@ impl Default for DbConfig {
 pub struct Db<A: IndexerAdaptor> {
     mempool: Mempool<[u8; 32], CallTxn, usize, [u8; 32]>,
     gateway: Gateway,
+    js_code_cache: JsCodeCache,
     indexer: Indexer<A>,
     sender: AsyncMutex<mpsc::Sender<CallTxn>>,
     receiver: AsyncMutex<mpsc::Receiver<CallTxn>>,
     config: DbConfig,
     out_of_sync_height: Mutex<Option<usize>>,
+    max_seen_height: AtomicUsize,
+    restored: Notify,
+    commit_timeouts: AtomicUsize,
+    commit_stalled: AtomicBool,
+    /// Held while a block is committed, or while the store is replaced by a restore
+    commit_lock: AsyncMutex<()>,
+    /// Permits to run a function while applying a block
+    block_calls: Semaphore,
+    /// Most permits of `block_calls` that have been held at once
+    #[cfg(test)]
+    max_block_calls_running: AtomicUsize,
 }
 
 impl<A: IndexerAdaptor> Db<A> {
     pub async fn new(indexer: Indexer<A>, config: DbConfig) -> Result<Self> {
         let (sender, receiver) = mpsc::channel::<CallTxn>(100);
 
+        // Txns accepted by the node must also run within the limits used to apply blocks
+        let call_options = CallOptions {
+            call_limit: config
+                .call_options
+                .call_limit
+                .min(BLOCK_CALL_OPTIONS.call_limit),
+            timeout: match (config.call_options.timeout, BLOCK_CALL_OPTIONS.timeout) {
+                (Some(timeout), Some(max)) => Some(timeout.min(max)),
+                (timeout, max) => timeout.or(max),
+            },
+            memory_limit: config.call_options.memory_limit,
+        };
+
         Ok(Self {
-            mempool: Mempool::new(),
-            gateway: gateway::initialize(),
+            block_calls: Semaphore::new(config.max_block_concurrent_calls.max(1)),
+            #[cfg(test)]
+            max_block_calls_running: AtomicUsize::new(0),
+            mempool: Mempool::new(config.max_mempool_txns),
+            gateway: gateway::initialize(config.gateway_pool_size, call_options),
+            js_code_cache: JsCodeCache::new(config.js_code_cache_size),
             indexer,
             sender: AsyncMutex::new(sender),
             receiver: AsyncMutex::new(receiver),
             config,
             out_of_sync_height: Mutex::new(None),
+            max_seen_height: AtomicUsize::new(0),
+            restored: Notify::new(),
+            commit_timeouts: AtomicUsize::new(0),
+            commit_stalled: AtomicBool::new(false),
+            commit_lock: AsyncMutex::new(()),
         })
     }
 
@@ -160,9 +288,41 @@ impl<A: IndexerAdaptor> Db<A> {
         self.out_of_sync_height.lock().is_none()
     }
 
-    /// Set the node as out of sync
-    pub fn out_of_sync(&self, height: usize) {
+    /// Set the node as out of sync, `max_seen_height` is the highest block height seen
+    /// from the rest of the network
+    pub fn out_of_sync(&self, height: usize, max_seen_height: usize) {
         self.out_of_sync_height.lock().replace(height);
+        self.max_seen_height
+            .fetch_max(max_seen_height, Ordering::Relaxed);
+    }
+
+    /// Health of the node, an out of sync node is degraded until it lags behind the
+    /// highest seen block by more than `max_degraded_sync_lag` blocks
+    pub async fn health(&self) -> Result<HealthStatus> {
+        if self.commit_stalled.load(Ordering::Relaxed) {
+            return Ok(HealthStatus::Unhealthy);
+        }
+
+        if self.is_healthy() {
+            return Ok(HealthStatus::Ok);
+        }
+
+        let height = self.get_manifest().await?.map(|m| m.height).unwrap_or(0);
+        let lag = self
+            .max_seen_height
+            .load(Ordering::Relaxed)
+            .saturating_sub(height);
+
+        Ok(if lag <= self.config.max_degraded_sync_lag {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Unhealthy
+        })
+    }
+
+    /// Number of commits that have exceeded the commit timeout
+    pub fn commit_timeouts(&self) -> usize {
+        self.commit_timeouts.load(Ordering::Relaxed)
     }
 
     pub async fn next(&self) -> Option<CallTxn> {
@@ -189,10 +349,39 @@ impl<A: IndexerAdaptor> Db<A> {
         record_id: &str,
         auth: Option<AuthUser>,
     ) -> Result<Option<RecordRoot>> {
-        let public_key = auth.as_ref().map(|a| a.public_key());
+        let auth = auth.as_ref();
+        Ok(self.indexer.get(collection_id, record_id, auth).await?)
+    }
+
+    /// Gets multiple records, checking read permissions for each record. Results are
+    /// returned in the same order as `ids`.
+    pub async fn get_many(
+        &self,
+        ids: &[(String, String)],
+        auth: Option<AuthUser>,
+    ) -> Vec<Result<Option<RecordRoot>>> {
+        self.indexer
+            .get_many(ids, auth.as_ref())
+            .await
+            .into_iter()
+            .map(|result| result.map_err(Error::from))
+            .collect()
+    }
+
+    /// Gets the schema for a collection, errors if the collection does not exist
+    pub async fn get_schema(&self, collection_id: &str) -> Result<Schema> {
+        Ok(self.indexer.get_schema_required(collection_id).await?)
+    }
+
+    /// Gets the time a record was last updated
+    pub async fn last_record_update(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+    ) -> Result<Option<SystemTime>> {
         Ok(self
             .indexer
-            .get(collection_id, record_id, public_key)
+            .last_record_update(collection_id, record_id)
             .await?)
     }
 
@@ -205,7 +394,7 @@ impl<A: IndexerAdaptor> Db<A> {
         since: f64,
         wait_for: Duration,
     ) -> Result<DbWaitResult<Option<RecordRoot>>> {
-        let public_key = auth.as_ref().map(|a| a.public_key());
+        let auth = auth.as_ref();
 
         // Wait for a record to create/update for a given amount of time, returns true if the record was created or
         // updated within the given time.
@@ -218,16 +407,23 @@ impl<A: IndexerAdaptor> Db<A> {
         .await?;
 
         Ok(if updated {
-            DbWaitResult::Updated(
-                self.indexer
-                    .get(collection_id, record_id, public_key)
-                    .await?,
-            )
+            DbWaitResult::Updated(self.indexer.get(collection_id, record_id, auth).await?)
         } else {
             DbWaitResult::NotModified
         })
     }
 
+    /// Returns the index that would be used for a list query
+    #[tracing::instrument(skip(self, query))]
+    pub async fn explain(&self, collection_id: &str, query: &ListQuery<'_>) -> Result<QueryPlan> {
+        Ok(self.indexer.explain(collection_id, query).await?)
+    }
+
+    /// Number of list queries that used each of the collection's indexes
+    pub async fn index_stats(&self, collection_id: &str) -> Result<Vec<IndexStats>> {
+        Ok(self.indexer.index_stats(collection_id).await?)
+    }
+
     #[tracing::instrument(skip(self, query))]
     pub async fn list(
         &self,
@@ -235,12 +431,71 @@ impl<A: IndexerAdaptor> Db<A> {
         query: ListQuery<'_>,
         auth: Option<AuthUser>,
     ) -> Result<Vec<RecordRoot>> {
-        let public_key = auth.as_ref().map(|a| a.public_key());
-        let stream = self.indexer.list(collection_id, query, public_key).await?;
+        let auth = auth.as_ref();
+        let stream = self.indexer.list(collection_id, query, auth).await?;
 
         Ok(stream.collect::<Vec<RecordRoot>>().await)
     }
 
+    /// Lists the collections in a namespace, i.e. the Collection records with an
+    /// id of `<namespace>/<name>`
+    #[tracing::instrument(skip(self))]
+    pub async fn list_namespace_collections(
+        &self,
+        namespace: &str,
+        auth: Option<AuthUser>,
+    ) -> Result<Vec<RecordRoot>> {
+        // Scan the ids prefixed with "<namespace>/", '0' is the character after '/'.
+        // Lists are limited to max_list_limit records, so page through the ids until
+        // no more collections are returned
+        let mut collections = vec![];
+        let mut after = format!("{namespace}/");
+        loop {
+            let where_query = WhereQuery(
+                [(
+                    FieldPath::id(),
+                    WhereNode::Inequality(Box::new(WhereInequality {
+                        gt: Some(WhereValue(IndexValue::String(after.into()))),
+                        lt: Some(WhereValue(IndexValue::String(
+                            format!("{namespace}0").into(),
+                        ))),
+                        ..Default::default()
+                    })),
+                )]
+                .into(),
+            );
+
+            let page = self
+                .list(
+                    "Collection",
+                    ListQuery {
+                        limit: None,
+                        where_query,
+                        order_by: &[],
+                        cursor_before: None,
+                        cursor_after: None,
+                    },
+                    auth.clone(),
+                )
+                .await?;
+
+            let last_id = page.last().and_then(|c| c.id().ok()).map(str::to_string);
+            collections.extend(page);
+            match last_id {
+                Some(last_id) => after = last_id,
+                None => break,
+            }
+        }
+
+        // Exclude collections in nested namespaces, e.g. <namespace>/sub/<name>
+        Ok(collections
+            .into_iter()
+            .filter(|c| {
+                matches!(c.id().map(|id| id.rsplit_once('/')), Ok(Some((ns, _))) if ns == namespace)
+            })
+            .collect())
+    }
+
     #[tracing::instrument(skip(self, query))]
     pub async fn list_wait(
         &self,
@@ -267,42 +522,118 @@ impl<A: IndexerAdaptor> Db<A> {
     /// Applies a call txn
     #[tracing::instrument(skip(self))]
     pub async fn call(&self, txn: CallTxn) -> Result<String> {
-        let (record_id, changes) = self.call_changes(&txn).await?;
+        let (record_id, changes) = self.call_changes(&txn, None).await?;
         let hash = txn.hash()?;
 
+        // Add to the mempool before sending the txn event, so a txn rejected by a full
+        // mempool is not sent to other nodes
+        let committed = self
+            .mempool
+            .add_wait(hash, txn.clone(), to_change_keys(&changes))?;
+
         // Send txn event
-        self.sender.lock().await.send(txn.clone()).await?;
+        self.sender.lock().await.send(txn).await?;
 
         // Wait for txn to be committed
-        self.mempool
-            .add_wait(hash, txn, to_change_keys(&changes))
-            .await;
+        committed.await;
 
         Ok(record_id)
     }
 
+    /// Runs a call txn without submitting it, returning the record the call would produce
+    #[tracing::instrument(skip(self))]
+    pub async fn dry_run(&self, txn: CallTxn) -> Result<Option<RecordRoot>> {
+        let (record_id, changes) = self.call_changes(&txn, None).await?;
+
+        // The change to the called record is pushed last, after any changes to args
+        for change in changes.into_iter().rev() {
+            match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id: id,
+                    record,
+                } if collection_id == txn.collection_id && id == record_id => {
+                    return Ok(Some(record))
+                }
+                IndexerChange::Delete {
+                    collection_id,
+                    record_id: id,
+                } if collection_id == txn.collection_id && id == record_id => return Ok(None),
+                _ => {}
+            }
+        }
+
+        // Record was not changed by the call
+        self.get_without_auth_check(&txn.collection_id, &record_id)
+            .await
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn add_txn(&self, txn: CallTxn) -> Result<String> {
-        let (record_id, changes) = self.call_changes(&txn).await?;
+        let (record_id, changes) = self.call_changes(&txn, None).await?;
         let hash = txn.hash()?;
 
-        // Wait for txn to be committed
-        self.mempool.add(hash, txn, to_change_keys(&changes));
+        self.mempool.add(hash, txn, to_change_keys(&changes))?;
 
         Ok(record_id)
     }
 
-    async fn call_changes(&self, txn: &CallTxn) -> Result<(String, Vec<IndexerChange>)> {
+    /// Checks the user can modify a record passed by reference to a function, as the
+    /// function can change it just like the record being called
+    async fn check_reference_write(
+        &self,
+        collection_id: &str,
+        schema: &Schema,
+        record_id: &str,
+        auth: Option<&AuthUser>,
+    ) -> Result<()> {
+        let record = self
+            .indexer
+            .get_without_auth_check(collection_id, record_id)
+            .await?
+            .ok_or_else(|| UserError::RecordNotFound {
+                collection_id: collection_id.to_string(),
+                record_id: record_id.to_string(),
+            })?;
+
+        if !self
+            .indexer
+            .verify_write(collection_id, schema, &record, auth)
+            .await
+        {
+            return Err(UserError::UnauthorizedWrite)?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes made by a call txn. Functions are run with `call_options`, or the node's
+    /// call options if `None`.
+    async fn call_changes(
+        &self,
+        txn: &CallTxn,
+        call_options: Option<&CallOptions>,
+    ) -> Result<(String, Vec<IndexerChange>)> {
         let CallTxn {
             collection_id,
             record_id,
             function_name: method,
             args,
             auth,
+            ..
         } = txn;
 
+        // Read before the schema, so code generated from a schema that is replaced by a
+        // concurrent commit is not cached
+        let js_generation = self.js_code_cache.generation();
         let schema = std::sync::Arc::new(self.indexer.get_schema_required(collection_id).await?);
-        let public_key = auth.as_ref().map(|a| a.public_key());
+        let auth = auth.as_ref();
+
+        if method == PATCH_FUNCTION_NAME {
+            return self
+                .patch_changes(collection_id, record_id, &schema, args, auth)
+                .await;
+        }
 
         // Get the method
         let method = match schema.get_method(method) {
@@ -334,17 +665,15 @@ impl<A: IndexerAdaptor> Db<A> {
         }
 
         // Get the js code to run
-        let js_code = schema.generate_js();
+        let js_code = self
+            .js_code_cache
+            .get_or_generate(collection_id, js_generation, || schema.generate_js());
 
         // Get current record instance
         let record = if method.name == "constructor" {
             RecordRoot::new()
         } else {
-            match self
-                .indexer
-                .get(collection_id, record_id, public_key)
-                .await?
-            {
+            match self.indexer.get(collection_id, record_id, auth).await? {
                 Some(record) => record,
                 None => {
                     return Err(UserError::RecordNotFound {
@@ -359,12 +688,19 @@ impl<A: IndexerAdaptor> Db<A> {
         if method.name != "constructor"
             && !self
                 .indexer
-                .verify_call(collection_id, &method.name, &schema, &record, public_key)
+                .verify_call(collection_id, &method.name, &schema, &record, auth)
                 .await
         {
             return Err(UserError::UnauthorizedCall)?;
         }
 
+        // Check user has permission to modify the record, only enforced if the call changes it
+        let can_write = method.name == "constructor"
+            || self
+                .indexer
+                .verify_write(collection_id, &schema, &record, auth)
+                .await;
+
         // Get args as RecordValues (so we can validate them and find the references)
         let input_args = method.args_from_json(args).map_err(Error::from)?;
 
@@ -377,26 +713,22 @@ impl<A: IndexerAdaptor> Db<A> {
                         id,
                         collection_id,
                     }) => {
-                        let record = self
-                            .indexer
-                            .get(collection_id, id, public_key)
-                            .await?
-                            .ok_or(UserError::RecordNotFound {
+                        let record = self.indexer.get(collection_id, id, auth).await?.ok_or(
+                            UserError::RecordNotFound {
                                 collection_id: collection_id.to_string(),
                                 record_id: id.to_string(),
-                            })?;
+                            },
+                        )?;
                         let record = foreign_record_to_json(record, collection_id);
                         Ok(record)
                     }
                     RecordValue::RecordReference(RecordReference { id }) => {
-                        let record = self
-                            .indexer
-                            .get(collection_id, id, public_key)
-                            .await?
-                            .ok_or(UserError::RecordNotFound {
+                        let record = self.indexer.get(collection_id, id, auth).await?.ok_or(
+                            UserError::RecordNotFound {
                                 collection_id: collection_id.to_string(),
                                 record_id: id.to_string(),
-                            })?;
+                            },
+                        )?;
                         Ok(record_to_json(record))
                     }
                     // Keep all other values as JSON
@@ -407,23 +739,52 @@ impl<A: IndexerAdaptor> Db<A> {
             .into_iter()
             .collect::<Result<Vec<serde_json::Value>>>()?;
 
+        let readable_records = if gateway::reads_records(&js_code) {
+            self.readable_records(collection_id, &record, &input_args, auth)
+                .await
+        } else {
+            ReadableRecords::new()
+        };
+
         let json_record = &record_to_json(record);
 
         // Get changes
-        let output = self
-            .gateway
-            .call(
-                collection_id,
-                &js_code,
-                &method.name,
-                json_record,
-                &extended_input_args,
-                auth.as_ref(),
-            )
-            .await?;
+        let output = match call_options {
+            Some(call_options) => {
+                self.gateway
+                    .call_with_options(
+                        collection_id,
+                        &js_code,
+                        &method.name,
+                        json_record,
+                        &extended_input_args,
+                        &readable_records,
+                        auth,
+                        call_options,
+                    )
+                    .await?
+            }
+            None => {
+                self.gateway
+                    .call(
+                        collection_id,
+                        &js_code,
+                        &method.name,
+                        json_record,
+                        &extended_input_args,
+                        &readable_records,
+                        auth,
+                    )
+                    .await?
+            }
+        };
 
         let output_record_changed = &output.instance != json_record;
 
+        if (output_record_changed || output.self_destruct) && !can_write {
+            return Err(UserError::UnauthorizedWrite)?;
+        }
+
         // Output record
         let output_record = json_to_record(&schema, output.instance, false)?;
 
@@ -458,8 +819,8 @@ impl<A: IndexerAdaptor> Db<A> {
         // TODO: check we can't modify records in other collections
 
         // Validate schema change
-        // Update of schema
-        if collection_id == "Collection" {
+        // Update of schema (deleting a collection doesn't change its schema)
+        if collection_id == "Collection" && !output.self_destruct {
             // Check schema is valid
             let new_schema = Schema::from_record(&output_record).map_err(|err| match err {
                 schema::Error::CollectionNotFoundInAST { name } => {
@@ -467,13 +828,16 @@ impl<A: IndexerAdaptor> Db<A> {
                 }
                 _ => Error::from(err),
             })?;
-            new_schema.validate()?;
 
-            // Existing schema update, validate its allowed
+            // Existing schema update, validate its allowed. This is checked first, so
+            // breaking changes (e.g. removing an indexed field) are reported as such
+            // rather than as an invalid schema.
             if method.name != "constructor" {
                 let old_schema = self.indexer.get_schema_required(record_id).await?;
-                old_schema.validate_schema_change(new_schema)?;
+                old_schema.validate_schema_change(&new_schema)?;
             }
+
+            new_schema.validate()?;
         }
 
         // Find changes in the args
@@ -499,6 +863,8 @@ impl<A: IndexerAdaptor> Db<A> {
                             }) => {
                                 let schema =
                                     self.indexer.get_schema_required(&collection_id).await?;
+                                self.check_reference_write(&collection_id, &schema, &id, auth)
+                                    .await?;
                                 Ok(IndexerChange::Set {
                                     collection_id,
                                     record_id: id,
@@ -506,6 +872,8 @@ impl<A: IndexerAdaptor> Db<A> {
                                 })
                             }
                             RecordValue::RecordReference(RecordReference { id }) => {
+                                self.check_reference_write(collection_id, &schema, &id, auth)
+                                    .await?;
                                 Ok(IndexerChange::Set {
                                     collection_id: collection_id.to_string(),
                                     record_id: id,
@@ -535,9 +903,111 @@ impl<A: IndexerAdaptor> Db<A> {
             });
         };
 
+        // Reject any changes that would store an oversized record
+        for change in &changes {
+            if let IndexerChange::Set { record, .. } = change {
+                check_record_size(record, MAX_RECORD_BYTES)?;
+            }
+        }
+
         Ok((output_instance_id.to_string(), changes))
     }
 
+    /// Records referenced by the record or args, that a function can read with
+    /// `$$__getRecord`. Records the user is not allowed to read are left out.
+    async fn readable_records(
+        &self,
+        collection_id: &str,
+        record: &RecordRoot,
+        args: &[RecordValue],
+        auth: Option<&AuthUser>,
+    ) -> ReadableRecords {
+        let mut refs = find_references(collection_id, record);
+        for arg in args {
+            find_value_references(collection_id, arg, &mut refs);
+        }
+
+        let ids = refs.into_iter().collect::<Vec<_>>();
+        let records = self.indexer.get_many(&ids, auth).await;
+
+        ids.into_iter()
+            .zip(records)
+            .filter_map(|(key, record)| match record {
+                Ok(Some(record)) => Some((key, record_to_json(record))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Changes for a patch txn, which deep-merges the partial object in `args` into the
+    /// record without running any of the collection's functions
+    async fn patch_changes(
+        &self,
+        collection_id: &str,
+        record_id: &str,
+        schema: &Schema,
+        args: &[serde_json::Value],
+        auth: Option<&AuthUser>,
+    ) -> Result<(String, Vec<IndexerChange>)> {
+        // Collection records must be updated via their functions, so schema changes
+        // are validated
+        if collection_id == "Collection" {
+            return Err(UserError::CollectionPatchNotAllowed)?;
+        }
+
+        let [patch @ serde_json::Value::Object(_)] = args else {
+            return Err(UserError::PatchNotObject)?;
+        };
+
+        let record = match self.indexer.get(collection_id, record_id, auth).await? {
+            Some(record) => record,
+            None => {
+                return Err(UserError::RecordNotFound {
+                    record_id: record_id.to_string(),
+                    collection_id: collection_id.to_string(),
+                })?
+            }
+        };
+
+        // Patches bypass the collection's functions, so only allow them on collections
+        // that restrict writes with @write fields (collections without them can be written
+        // by anyone, and rely on their functions to check the caller)
+        if schema.fields_auth(&[DirectiveKind::Write]).next().is_none()
+            || !self
+                .indexer
+                .verify_write(collection_id, schema, &record, auth)
+                .await
+        {
+            return Err(UserError::UnauthorizedWrite)?;
+        }
+
+        let json_record = record_to_json(record);
+        if let Some(id) = patch.get("id") {
+            if Some(id) != json_record.get("id") {
+                return Err(UserError::RecordIDModified)?;
+            }
+        }
+
+        let mut merged = json_record.clone();
+        merge_json(&mut merged, patch.clone());
+        if merged == json_record {
+            return Ok((record_id.to_string(), vec![]));
+        }
+
+        // Validate the merged record against the schema
+        let output_record = json_to_record(schema, merged, false)?;
+        check_record_size(&output_record, MAX_RECORD_BYTES)?;
+
+        Ok((
+            record_id.to_string(),
+            vec![IndexerChange::Set {
+                collection_id: collection_id.to_string(),
+                record_id: record_id.to_string(),
+                record: output_record,
+            }],
+        ))
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn propose_txns(&self, height: usize) -> Result<Vec<solid::txn::Txn>> {
         type TxnList = Vec<([u8; 32], CallTxn)>;
@@ -579,16 +1049,46 @@ impl<A: IndexerAdaptor> Db<A> {
 
     #[tracing::instrument(skip(self))]
     pub async fn commit(&self, manifest: proposal::ProposalManifest) -> Result<()> {
-        let txns = &manifest.txns;
+        let _commit_guard = self.commit_lock.lock().await;
+        let height = manifest.height;
+        self.with_commit_watchdog(height, self.commit_manifest(manifest))
+            .await
+    }
 
-        // Convert txns into CallTxn
-        let call_txns = txns
-            .iter()
-            .map(|txn| {
-                let call_txn = CallTxn::deserialize(&txn.data)?;
-                Ok(call_txn)
-            })
-            .collect::<Result<Vec<_>>>()?;
+    /// Waits for the commit, marking the node as unhealthy if it has not completed within
+    /// the commit timeout. The commit is not cancelled, as it may have been partially
+    /// applied, so the node becomes healthy again if the commit eventually completes.
+    async fn with_commit_watchdog(
+        &self,
+        height: usize,
+        commit: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        let Some(timeout) = self.config.commit_timeout else {
+            return commit.await;
+        };
+
+        tokio::pin!(commit);
+        tokio::select! {
+            res = &mut commit => return res,
+            _ = tokio::time::sleep(timeout) => {}
+        }
+
+        error!(
+            height,
+            timeout_ms = timeout.as_millis() as u64,
+            "Commit timed out, marking node as unhealthy"
+        );
+        self.commit_timeouts.fetch_add(1, Ordering::Relaxed);
+        self.out_of_sync(height + 1, height);
+
+        self.commit_stalled.store(true, Ordering::Relaxed);
+        let res = commit.await;
+        self.commit_stalled.store(false, Ordering::Relaxed);
+        res
+    }
+
+    async fn commit_manifest(&self, manifest: proposal::ProposalManifest) -> Result<()> {
+        let mut call_txns = manifest_call_txns(&manifest)?;
 
         // Get a list of keys to remove from the mempool
         let keys = call_txns
@@ -599,39 +1099,61 @@ impl<A: IndexerAdaptor> Db<A> {
             })
             .collect::<Result<Vec<_>>>()?;
 
-        // Get a list of changes for the indexer
-        let mut changes = future::join_all(
-            call_txns
-                .iter()
-                .map(|txn| async move { Ok(self.call_changes(txn).await?.1) }),
-        )
-        .await
-        .into_iter()
-        .collect::<Result<Vec<_>>>()?
-        .into_iter()
-        .flatten()
-        .collect::<Vec<_>>();
+        // Request ids are not part of the txn data, so they're taken from the txns this
+        // node received from clients
+        for (call_txn, key) in call_txns.iter_mut().zip(&keys) {
+            call_txn.request_id = self.mempool.get(key).and_then(|txn| txn.request_id);
+        }
 
-        // Sort collection changes first
-        changes.sort_by_key(|item| {
-            if match item {
-                IndexerChange::Set { collection_id, .. } => collection_id,
-                IndexerChange::Delete { collection_id, .. } => collection_id,
-            } == "Collection"
-            {
-                0
-            } else {
-                1
-            }
-        });
+        // Get a list of changes for the indexer
+        let changes = self.block_changes(&call_txns).await?;
 
         let height = manifest.height;
+        let state_digest = self.state_digest().await?.apply(height, &changes);
 
-        // Update the txn manifest in rocksdb
+        // Update the txn manifest and state digest in rocksdb, these are written
+        // in the same batch as the changes
         self.set_manifest(manifest).await?;
+        self.set_state_digest(&state_digest).await?;
+
+        // Collection code may have changed, so remove any cached JS code. Invalidated
+        // after the commit (even if it failed), so code generated from a schema read
+        // during the commit is not cached.
+        let collection_ids = changes
+            .iter()
+            .filter_map(|change| match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    ..
+                }
+                | IndexerChange::Delete {
+                    collection_id,
+                    record_id,
+                } if collection_id == "Collection" => Some(record_id.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
 
         // Commit all txns
-        self.indexer.commit(height, changes).await?;
+        let result = self.indexer.commit(height, changes).await;
+        for collection_id in &collection_ids {
+            self.js_code_cache.invalidate(collection_id);
+        }
+        result?;
+
+        for call_txn in &call_txns {
+            if let Some(request_id) = &call_txn.request_id {
+                info!(
+                    height,
+                    request_id = %request_id,
+                    collection_id = %call_txn.collection_id,
+                    record_id = %call_txn.record_id,
+                    function_name = %call_txn.function_name,
+                    "Committed txn"
+                );
+            }
+        }
 
         // Commit changes in mempool (releasing unused txns and removing used ones). This will
         // also release all requests that were waiting for these txns to be committed.
@@ -648,9 +1170,216 @@ impl<A: IndexerAdaptor> Db<A> {
         Ok(())
     }
 
+    /// Computes the state digest that committing the proposal would produce, without
+    /// applying any of its changes. Used to compare the expected state for a block
+    /// across nodes, e.g. when debugging divergence.
+    #[tracing::instrument(skip(self))]
+    pub async fn dry_run_commit(
+        &self,
+        manifest: &proposal::ProposalManifest,
+    ) -> Result<StateDigest> {
+        let call_txns = manifest_call_txns(manifest)?;
+        let changes = self.block_changes(&call_txns).await?;
+        Ok(self.state_digest().await?.apply(manifest.height, &changes))
+    }
+
+    /// Changes for all txns in a block, in the order they are committed. At most
+    /// `max_block_concurrent_calls` txns are run at once, the rest are queued in order.
+    async fn block_changes(&self, call_txns: &[CallTxn]) -> Result<Vec<IndexerChange>> {
+        let changes = future::join_all(call_txns.iter().map(|txn| async move {
+            // The semaphore is never closed, so the permit is always acquired
+            let _permit = self.block_calls.acquire().await;
+            #[cfg(test)]
+            self.max_block_calls_running.fetch_max(
+                self.config.max_block_concurrent_calls.max(1)
+                    - self.block_calls.available_permits(),
+                Ordering::SeqCst,
+            );
+
+            let result = self
+                .call_changes(txn, Some(&BLOCK_CALL_OPTIONS))
+                .instrument(tracing::info_span!(
+                    "txn",
+                    request_id = txn.request_id.as_deref().map(tracing::field::display)
+                ))
+                .await;
+
+            Ok(result?.1)
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        // Sort collection changes first. Collection deletes are kept in order, so the
+        // records changed before the delete are deleted with the collection, and a
+        // collection re-created after it starts without them.
+        let mut segment = 0;
+        let mut changes = changes
+            .into_iter()
+            .map(|item| {
+                let key = match &item {
+                    IndexerChange::Delete { collection_id, .. }
+                        if collection_id == "Collection" =>
+                    {
+                        segment += 1;
+                        let key = (segment, 0);
+                        segment += 1;
+                        key
+                    }
+                    IndexerChange::Set { collection_id, .. } if collection_id == "Collection" => {
+                        (segment, 0)
+                    }
+                    _ => (segment, 1),
+                };
+                (key, item)
+            })
+            .collect::<Vec<_>>();
+        changes.sort_by_key(|(key, _)| *key);
+
+        Ok(changes.into_iter().map(|(_, item)| item).collect())
+    }
+
     /// Reset all data in the database
     pub async fn reset(&self) -> Result<()> {
-        Ok(self.indexer.reset().await?)
+        let result = self.indexer.reset().await;
+        self.js_code_cache.clear();
+        Ok(result?)
+    }
+
+    /// Compact the underlying store, to reclaim space after large deletes or restores
+    pub async fn compact(&self) -> Result<()> {
+        Ok(self.indexer.compact().await?)
+    }
+
+    /// Rebuild all indexes of a collection, e.g. when index entries are missing or stale
+    pub async fn rebuild_indexes(&self, collection_id: &str) -> Result<()> {
+        // Check the collection exists, so a missing collection is a user error
+        self.indexer.get_schema_required(collection_id).await?;
+        Ok(self.indexer.rebuild_indexes(collection_id).await?)
+    }
+
+    /// Usage of each collection in the namespace (including nested namespaces), keyed
+    /// by collection id
+    pub async fn namespace_usage(&self, namespace: &str) -> Result<HashMap<String, Usage>> {
+        let prefix = format!("{namespace}/");
+        Ok(self
+            .indexer
+            .usage(|collection_id| collection_id.starts_with(&prefix))
+            .await?)
+    }
+
+    /// List the audit log of committed changes, from the given height
+    pub async fn audit_log(
+        &self,
+        collection_id: Option<&str>,
+        from_height: usize,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .indexer
+            .audit_log(collection_id, from_height, limit)
+            .await?)
+    }
+
+    /// Changes committed after the `since` height that the user can read
+    pub async fn changes(
+        &self,
+        since: usize,
+        limit: usize,
+        auth: Option<AuthUser>,
+    ) -> Result<indexer::Changes> {
+        Ok(self.indexer.changes(since, limit, auth.as_ref()).await?)
+    }
+
+    /// Validate a record to be imported into a collection, with the same checks as a
+    /// record output by a function call
+    pub fn import_change(
+        &self,
+        collection_id: &str,
+        schema: &Schema,
+        value: serde_json::Value,
+    ) -> Result<IndexerChange> {
+        let record = json_to_record(schema, value, false)?;
+        check_record_size(&record, MAX_RECORD_BYTES)?;
+
+        let record_id = match record.get("id") {
+            Some(RecordValue::String(id)) => id.clone(),
+            Some(_) => return Err(UserError::RecordIdNotString)?,
+            None => return Err(UserError::RecordIdNotFound)?,
+        };
+
+        Ok(IndexerChange::Set {
+            collection_id: collection_id.to_string(),
+            record_id,
+            record,
+        })
+    }
+
+    /// The first record referenced by an imported record that doesn't exist, and isn't
+    /// one of the `imported` records
+    pub async fn missing_import_reference(
+        &self,
+        change: &IndexerChange,
+        imported: &HashSet<RecordKey>,
+    ) -> Result<Option<RecordKey>> {
+        let IndexerChange::Set {
+            collection_id,
+            record,
+            ..
+        } = change
+        else {
+            return Ok(None);
+        };
+
+        for target in find_references(collection_id, record) {
+            if imported.contains(&target) {
+                continue;
+            }
+
+            let (target_collection_id, target_record_id) = &target;
+            if self
+                .indexer
+                .get_without_auth_check(target_collection_id, target_record_id)
+                .await?
+                .is_none()
+            {
+                return Ok(Some(target));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Write imported records, replacing any existing records with the same id. Like
+    /// restoring a snapshot, imports bypass consensus, so they are only allowed on a
+    /// node without peers (see `admin_import`). The changes are committed at the last committed height,
+    /// and included in the state digest. The caller must hold the commit lock (see
+    /// `lock_commits`), so the import can't overlap a block's commit.
+    pub async fn import(&self, changes: Vec<IndexerChange>) -> Result<()> {
+        let height = self
+            .get_manifest()
+            .await?
+            .map_or(0, |manifest| manifest.height);
+        let state_digest = self.state_digest().await?.apply(height, &changes);
+
+        self.set_state_digest(&state_digest).await?;
+        self.indexer.commit(height, changes).await?;
+
+        Ok(())
+    }
+
+    /// Stream every record of a collection, without checking read permissions
+    pub fn export<'a>(
+        &'a self,
+        collection_id: &'a str,
+    ) -> Pin<Box<dyn futures::Stream<Item = Result<RecordRoot>> + 'a + Send>> {
+        self.indexer
+            .export(collection_id)
+            .map(|record| record.map_err(Error::from))
+            .boxed()
     }
 
     /// Create a snapshot iterator, that can be used to iterate over the
@@ -666,8 +1395,65 @@ impl<A: IndexerAdaptor> Db<A> {
             .boxed()
     }
 
+    /// Whether there are txns waiting to be committed
+    pub fn has_pending_txns(&self) -> bool {
+        !self.mempool.is_empty()
+    }
+
+    /// Stop blocks from being committed until the guard is dropped, e.g. while the store
+    /// is replaced by a restore
+    pub async fn lock_commits(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.commit_lock.lock().await
+    }
+
+    /// The manifest in snapshot data, without restoring it
+    pub fn snapshot_manifest(
+        &self,
+        chunks: &[Vec<SnapshotValue>],
+    ) -> Result<Option<proposal::ProposalManifest>> {
+        for chunk in chunks {
+            if let Some(record) = self.indexer.snapshot_system_key(chunk, "manifest")? {
+                return manifest_from_record(record);
+            }
+        }
+
+        Ok(None)
+    }
+
     pub async fn restore_chunk(&self, chunk: Vec<SnapshotValue>) -> Result<()> {
-        Ok(self.indexer.restore(chunk).await?)
+        let result = self.indexer.restore(chunk).await;
+        self.js_code_cache.clear();
+        Ok(result?)
+    }
+
+    /// Whether the database has no user collections, i.e. a freshly started node
+    pub async fn is_empty(&self) -> Result<bool> {
+        let collections = self
+            .list(
+                "Collection",
+                ListQuery {
+                    limit: Some(1),
+                    where_query: Default::default(),
+                    order_by: &[],
+                    cursor_before: None,
+                    cursor_after: None,
+                },
+                None,
+            )
+            .await?;
+
+        Ok(collections.is_empty())
+    }
+
+    /// Signal that the database was restored outside of the network snapshot
+    /// flow (e.g. via the admin API), so the proposal state can be reset
+    pub fn notify_restored(&self) {
+        self.restored.notify_one();
+    }
+
+    /// Wait for the database to be restored outside of the network snapshot flow
+    pub async fn restored(&self) {
+        self.restored.notified().await
     }
 
     #[tracing::instrument(skip(self))]
@@ -679,18 +1465,102 @@ impl<A: IndexerAdaptor> Db<A> {
         Ok(self.indexer.set_system_key("manifest", &record).await?)
     }
 
+    /// Digest of the state after the last committed block
+    #[tracing::instrument(skip(self))]
+    pub async fn state_digest(&self) -> Result<StateDigest> {
+        let record = self.indexer.get_system_key("state_digest").await?;
+        match record.and_then(|mut r: RecordRoot| r.remove("digest")) {
+            Some(RecordValue::Bytes(b)) => Ok(StateDigest(
+                b.try_into().map_err(|_| Error::InvalidStateDigest)?,
+            )),
+            _ => Ok(StateDigest::default()),
+        }
+    }
+
+    async fn set_state_digest(&self, digest: &StateDigest) -> Result<()> {
+        let mut record = RecordRoot::new();
+        record.insert("digest".to_string(), RecordValue::Bytes(digest.0.to_vec()));
+        Ok(self.indexer.set_system_key("state_digest", &record).await?)
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn get_manifest(&self) -> Result<Option<proposal::ProposalManifest>> {
-        let record = self.indexer.get_system_key("manifest").await?;
-        let value = match record.and_then(|mut r: RecordRoot| r.remove("manifest")) {
-            Some(RecordValue::Bytes(b)) => b,
-            _ => return Ok(None),
+        match self.indexer.get_system_key("manifest").await? {
+            Some(record) => manifest_from_record(record),
+            None => Ok(None),
+        }
+    }
+}
+
+fn manifest_from_record(mut record: RecordRoot) -> Result<Option<proposal::ProposalManifest>> {
+    let value = match record.remove("manifest") {
+        Some(RecordValue::Bytes(b)) => b,
+        _ => return Ok(None),
+    };
+    let manifest: proposal::ProposalManifest = bincode::deserialize(&value)?;
+    Ok(Some(manifest))
+}
+
+/// Digest of the database state, each block's changes are hashed together with the
+/// digest of the previous state, so nodes that have committed the same blocks have
+/// the same digest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateDigest(pub [u8; 32]);
+
+impl StateDigest {
+    /// Digest of the state after applying the changes of a block at `height`
+    fn apply(&self, height: usize, changes: &[IndexerChange]) -> Self {
+        let mut hasher = Sha3_256::new();
+        hasher.update(self.0);
+        hasher.update((height as u64).to_be_bytes());
+
+        // Length prefix each part, so moving bytes between parts changes the hash
+        let mut update = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_be_bytes());
+            hasher.update(bytes);
         };
-        let manifest: proposal::ProposalManifest = bincode::deserialize(&value)?;
-        Ok(Some(manifest))
+
+        for change in changes {
+            match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    record,
+                } => {
+                    update(b"set");
+                    update(collection_id.as_bytes());
+                    update(record_id.as_bytes());
+                    update(&record::canonical_bytes(record));
+                }
+                IndexerChange::Delete {
+                    collection_id,
+                    record_id,
+                } => {
+                    update(b"delete");
+                    update(collection_id.as_bytes());
+                    update(record_id.as_bytes());
+                }
+            }
+        }
+
+        Self(hasher.finalize().into())
+    }
+}
+
+impl fmt::Display for StateDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
     }
 }
 
+fn manifest_call_txns(manifest: &proposal::ProposalManifest) -> Result<Vec<CallTxn>> {
+    manifest
+        .txns
+        .iter()
+        .map(|txn| Ok(CallTxn::deserialize(&txn.data)?))
+        .collect()
+}
+
 fn get_key(namespace: &str, id: &str) -> [u8; 32] {
     let b = [namespace.as_bytes(), id.as_bytes()].concat();
     hash::hash_bytes(b)
@@ -741,3 +1611,591 @@ where
 
     Ok(false)
 }
+
+/// Recursively merges the fields of `patch` into `target`, non-object values in the
+/// patch replace the existing value
+fn merge_json(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge_json(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+/// Checks the serialized size of a record is within the limit
+fn check_record_size(record: &RecordRoot, max_bytes: usize) -> Result<()> {
+    let size = bincode::serialized_size(record)?;
+    if size > max_bytes as u64 {
+        return Err(UserError::RecordTooLarge {
+            size,
+            max: max_bytes,
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexer::memory::MemoryStore;
+    use serde_json::json;
+
+    const ACCOUNT_SCHEMA: &str = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+"#;
+
+    fn call_txn(collection_id: &str, args: Vec<serde_json::Value>) -> CallTxn {
+        CallTxn::new(
+            collection_id.to_string(),
+            "constructor",
+            String::new(),
+            args,
+            None,
+        )
+    }
+
+    fn txn(collection_id: &str, args: Vec<serde_json::Value>) -> solid::txn::Txn {
+        let call_txn = call_txn(collection_id, args);
+
+        solid::txn::Txn {
+            id: call_txn.hash().unwrap().to_vec(),
+            data: call_txn.serialize().unwrap(),
+        }
+    }
+
+    async fn create_db(config: DbConfig) -> Db<MemoryStore> {
+        let db = Db::new(Indexer::new(MemoryStore::new()), config)
+            .await
+            .unwrap();
+
+        db.commit(proposal::ProposalManifest {
+            height: 1,
+            txns: vec![txn(
+                "Collection",
+                vec![json!("test/Account"), json!(ACCOUNT_SCHEMA)],
+            )],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        db
+    }
+
+    #[test]
+    fn test_state_digest_ignores_record_field_order() {
+        let fields = [
+            ("id", RecordValue::String("id1".to_string())),
+            ("name", RecordValue::String("John".to_string())),
+            ("age", RecordValue::Number(30.0)),
+        ];
+        let change = |record| IndexerChange::Set {
+            collection_id: "test/Account".to_string(),
+            record_id: "id1".to_string(),
+            record,
+        };
+
+        let mut a = RecordRoot::new();
+        for (key, value) in fields.iter().cloned() {
+            a.insert(key.to_string(), value);
+        }
+        let mut b = RecordRoot::new();
+        for (key, value) in fields.iter().rev().cloned() {
+            b.insert(key.to_string(), value);
+        }
+
+        let digest = StateDigest::default();
+        assert_eq!(digest.apply(1, &[change(a)]), digest.apply(1, &[change(b)]));
+    }
+
+    #[test]
+    fn test_check_record_size() {
+        let mut record = RecordRoot::new();
+        record.insert("id".to_string(), RecordValue::String("id1".to_string()));
+        record.insert("data".to_string(), RecordValue::String("x".repeat(100)));
+
+        let size = bincode::serialized_size(&record).unwrap() as usize;
+
+        assert!(check_record_size(&record, size).is_ok());
+        assert!(matches!(
+            check_record_size(&record, size - 1),
+            Err(Error::User(UserError::RecordTooLarge { max, .. })) if max == size - 1
+        ));
+    }
+
+    #[test]
+    fn test_merge_json() {
+        let mut target = serde_json::json!({
+            "id": "id1",
+            "name": "John",
+            "address": { "city": "London", "country": "UK" },
+            "tags": ["a", "b"],
+        });
+
+        merge_json(
+            &mut target,
+            serde_json::json!({
+                "address": { "city": "Paris" },
+                "tags": ["c"],
+                "age": 30,
+            }),
+        );
+
+        assert_eq!(
+            target,
+            serde_json::json!({
+                "id": "id1",
+                "name": "John",
+                "address": { "city": "Paris", "country": "UK" },
+                "tags": ["c"],
+                "age": 30,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_commit_matches_commit() {
+        let db = create_db(DbConfig::default()).await;
+
+        let manifest = proposal::ProposalManifest {
+            height: 2,
+            txns: vec![
+                txn("test/Account", vec![json!("id1"), json!("John")]),
+                txn("test/Account", vec![json!("id2"), json!("Jane")]),
+            ],
+            ..Default::default()
+        };
+
+        let before = db.state_digest().await.unwrap();
+        let expected = db.dry_run_commit(&manifest).await.unwrap();
+        assert_ne!(expected, before);
+
+        // Dry run does not apply the changes
+        assert_eq!(db.state_digest().await.unwrap(), before);
+        assert!(db
+            .get_without_auth_check("test/Account", "id1")
+            .await
+            .unwrap()
+            .is_none());
+
+        db.commit(manifest).await.unwrap();
+        assert_eq!(db.state_digest().await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_commit_ignores_node_call_options() {
+        // No function can be called with a call limit of 0
+        let db = create_db(DbConfig {
+            call_options: CallOptions {
+                call_limit: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        assert!(matches!(
+            db.add_txn(call_txn("test/Account", vec![json!("id1"), json!("John")]))
+                .await,
+            Err(Error::Gateway(gateway::GatewayError::UserError(
+                gateway::GatewayUserError::CallLimitExceeded { limit: 0 }
+            )))
+        ));
+
+        // Blocks are applied with the protocol limits, like on every other node
+        db.commit(proposal::ProposalManifest {
+            height: 2,
+            txns: vec![txn("test/Account", vec![json!("id1"), json!("John")])],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(db
+            .get_without_auth_check("test/Account", "id1")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_commit_keeps_manifest_and_state_digest() {
+        // Writes are batched until the commit with rocksdb, unlike the memory store
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::new(
+            Indexer::new(indexer_rocksdb::RocksDBAdaptor::new(dir.path())),
+            DbConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        db.commit(proposal::ProposalManifest {
+            height: 1,
+            txns: vec![txn(
+                "Collection",
+                vec![json!("test/Account"), json!(ACCOUNT_SCHEMA)],
+            )],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let manifest = db.get_manifest().await.unwrap();
+        let digest = db.state_digest().await.unwrap();
+
+        // As in `commit_manifest`, the manifest and state digest are written before the
+        // changes, and the change to a missing collection fails the commit
+        db.set_manifest(proposal::ProposalManifest {
+            height: 2,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.set_state_digest(&StateDigest([1; 32])).await.unwrap();
+        assert!(db
+            .indexer
+            .commit(
+                2,
+                vec![IndexerChange::Set {
+                    collection_id: "test/Missing".to_string(),
+                    record_id: "id1".to_string(),
+                    record: RecordRoot::new(),
+                }],
+            )
+            .await
+            .is_err());
+
+        assert_eq!(db.get_manifest().await.unwrap(), manifest);
+        assert_eq!(db.state_digest().await.unwrap(), digest);
+
+        // They're not committed with the next commit either
+        db.indexer.commit(2, vec![]).await.unwrap();
+        assert_eq!(db.get_manifest().await.unwrap(), manifest);
+        assert_eq!(db.state_digest().await.unwrap(), digest);
+    }
+
+    #[tokio::test]
+    async fn test_update_code_invalidates_js_code() {
+        let db = create_db(DbConfig::default()).await;
+
+        db.commit(proposal::ProposalManifest {
+            height: 2,
+            txns: vec![txn("test/Account", vec![json!("id1"), json!("John")])],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let update_code = CallTxn::new(
+            "Collection".to_string(),
+            "updateCode",
+            "test/Account".to_string(),
+            vec![json!(
+                ACCOUNT_SCHEMA.replace("this.name = name;", "this.name = name + '!';")
+            )],
+            None,
+        );
+        db.commit(proposal::ProposalManifest {
+            height: 3,
+            txns: vec![solid::txn::Txn {
+                id: update_code.hash().unwrap().to_vec(),
+                data: update_code.serialize().unwrap(),
+            }],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        db.commit(proposal::ProposalManifest {
+            height: 4,
+            txns: vec![txn("test/Account", vec![json!("id2"), json!("Jane")])],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let name = |record: Option<RecordRoot>| record.and_then(|r| r.get("name").cloned());
+        assert_eq!(
+            name(
+                db.get_without_auth_check("test/Account", "id1")
+                    .await
+                    .unwrap()
+            ),
+            Some(RecordValue::String("John".to_string()))
+        );
+        assert_eq!(
+            name(
+                db.get_without_auth_check("test/Account", "id2")
+                    .await
+                    .unwrap()
+            ),
+            Some(RecordValue::String("Jane!".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_code_rejects_breaking_changes() {
+        let db = create_db(DbConfig::default()).await;
+        let code = |fields: &str, index: &str| {
+            format!(
+                "@public collection Account {{ {fields} {index} constructor (id: string) {{ this.id = id; }} }}"
+            )
+        };
+        let update_code = |code: String| {
+            CallTxn::new(
+                "Collection".to_string(),
+                "updateCode",
+                "test/Account".to_string(),
+                vec![json!(code)],
+                None,
+            )
+        };
+
+        db.commit(proposal::ProposalManifest {
+            height: 2,
+            txns: vec![{
+                let update_code = update_code(code("id: string; name?: string;", "@index(name);"));
+                solid::txn::Txn {
+                    id: update_code.hash().unwrap().to_vec(),
+                    data: update_code.serialize().unwrap(),
+                }
+            }],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // The change is checked before the new schema is validated, so the errors say
+        // why the change isn't allowed
+        assert!(matches!(
+            db.add_txn(update_code(code("id: string;", "@index(name);"))).await,
+            Err(Error::Schema(schema::Error::User(
+                schema::UserError::SchemaIndexedFieldRemovalNotAllowed { ref fields }
+            ))) if fields == "name"
+        ));
+        assert!(matches!(
+            db.add_txn(update_code(code(
+                "id?: string; name?: string;",
+                "@index(name);"
+            )))
+            .await,
+            Err(Error::Schema(schema::Error::User(
+                schema::UserError::CollectionIdFieldCannotBeOptional
+            )))
+        ));
+        assert!(db
+            .add_txn(update_code(code(
+                "id: string; name?: string; age?: number;",
+                "@index(name);"
+            )))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_commit_bounds_concurrent_calls() {
+        let db = create_db(DbConfig {
+            max_block_concurrent_calls: 2,
+            ..Default::default()
+        })
+        .await;
+
+        db.commit(proposal::ProposalManifest {
+            height: 2,
+            txns: (0..10)
+                .map(|i| txn("test/Account", vec![json!(format!("id{i}")), json!("John")]))
+                .collect(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        for i in 0..10 {
+            assert!(db
+                .get_without_auth_check("test/Account", &format!("id{i}"))
+                .await
+                .unwrap()
+                .is_some());
+        }
+        let max_running = db.max_block_calls_running.load(Ordering::SeqCst);
+        assert!(
+            (1..=2).contains(&max_running),
+            "{max_running} calls ran at once"
+        );
+        // Every permit is released once the block is applied
+        assert_eq!(db.block_calls.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_propose_txns_caps_block_txns_count() {
+        let db = create_db(DbConfig {
+            block_txns_count: 2,
+            ..Default::default()
+        })
+        .await;
+
+        for i in 0..5 {
+            db.add_txn(call_txn(
+                "test/Account",
+                vec![json!(format!("id{i}")), json!("John")],
+            ))
+            .await
+            .unwrap();
+        }
+
+        let proposed_ids = |txns: Vec<solid::txn::Txn>| {
+            txns.iter()
+                .map(|txn| CallTxn::deserialize(&txn.data).unwrap().args[0].clone())
+                .collect::<Vec<_>>()
+        };
+
+        // Each block includes at most block_txns_count txns, oldest first, and the
+        // remaining txns stay pending for later blocks
+        assert_eq!(
+            proposed_ids(db.propose_txns(2).unwrap()),
+            vec![json!("id0"), json!("id1")]
+        );
+        assert_eq!(
+            proposed_ids(db.propose_txns(3).unwrap()),
+            vec![json!("id2"), json!("id3")]
+        );
+        assert_eq!(
+            proposed_ids(db.propose_txns(4).unwrap()),
+            vec![json!("id4")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_watchdog_marks_slow_commit_unhealthy() {
+        let db = create_db(DbConfig {
+            commit_timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        })
+        .await;
+
+        // A commit that completes within the timeout leaves the node healthy
+        db.with_commit_watchdog(2, async { Ok(()) }).await.unwrap();
+        assert!(db.is_healthy());
+        assert_eq!(db.commit_timeouts(), 0);
+
+        // A slow commit is still awaited, but marks the node as unhealthy
+        db.with_commit_watchdog(2, async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert!(!db.is_healthy());
+        assert_eq!(db.commit_timeouts(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_degraded_when_lagging() {
+        let db = create_db(DbConfig {
+            max_degraded_sync_lag: 5,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(db.health().await.unwrap(), HealthStatus::Ok);
+
+        // Committed height is 1, so the node is 3 blocks behind
+        db.out_of_sync(1, 4);
+        assert_eq!(db.health().await.unwrap(), HealthStatus::Degraded);
+
+        db.out_of_sync(1, 7);
+        assert_eq!(db.health().await.unwrap(), HealthStatus::Unhealthy);
+    }
+
+    /// Log output written by a test subscriber
+    #[derive(Clone, Default)]
+    struct CapturedLogs(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_is_logged_on_commit() {
+        use crate::errors::logger::{RequestId, SlogMiddleware};
+        use actix_web::{test, web, App, HttpResponse};
+
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .with_ansi(false)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::NEW)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let db = web::Data::new(create_db(DbConfig::default()).await);
+        let app = test::init_service(App::new().wrap(SlogMiddleware).app_data(db.clone()).route(
+            "/",
+            web::post().to(
+                |db: web::Data<Db<MemoryStore>>, request_id: RequestId| async move {
+                    let txn = call_txn("test/Account", vec![json!("id1"), json!("John")])
+                        .with_request_id(request_id.0);
+                    db.call(txn).await.unwrap();
+                    HttpResponse::Ok()
+                },
+            ),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header(("X-Request-Id", "req-1"))
+            .to_request();
+        let (res, ()) = tokio::join!(test::call_service(&app, req), async {
+            // Commit the txn once the request has added it to the mempool
+            let txns = loop {
+                let txns = db.propose_txns(2).unwrap();
+                if !txns.is_empty() {
+                    break txns;
+                }
+                tokio::task::yield_now().await;
+            };
+
+            db.commit(proposal::ProposalManifest {
+                height: 2,
+                txns,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        });
+        assert_eq!(res.headers().get("x-request-id").unwrap(), "req-1");
+
+        let logs = String::from_utf8(logs.0.lock().clone()).unwrap();
+        assert!(
+            logs.lines()
+                .any(|line| line.contains("request{request_id=req-1") && line.ends_with("new")),
+            "no ingress span in logs:\n{logs}"
+        );
+        assert!(
+            logs.lines()
+                .any(|line| line.contains("Committed txn") && line.contains("request_id=req-1")),
+            "no commit log in logs:\n{logs}"
+        );
+    }
+}
@@ -15,6 +15,12 @@ pub enum AppError {
     #[error("failed to initialize indexer")]
     Indexer(#[from] indexer::Error),
 
+    #[error("failed to open the indexer store")]
+    Store(#[from] indexer_rocksdb::adaptor::Error),
+
+    #[error("failed to read the node key file")]
+    KeyFile(#[from] crate::key_file::KeyFileError),
+
     #[error("failed to join task")]
     JoinError(#[from] tokio::task::JoinError),
 
@@ -54,6 +60,30 @@ pub enum AppError {
     #[error("namespace public key is invalid, expected {0} got {1}")]
     InvalidNamespacePublicKey(String, String),
 
+    #[error("admin API is disabled, set an admin key to enable it")]
+    AdminDisabled,
+
+    #[error("invalid admin key")]
+    InvalidAdminKey,
+
+    #[error("node must be {0}")]
+    NodeNotIdle(&'static str),
+
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("invalid import: {0}")]
+    InvalidImport(String),
+
+    #[error("changes feed is disabled, enable the audit log to use it")]
+    ChangesDisabled,
+
+    #[error("batch has more than the maximum of {max} reads")]
+    BatchTooLarge { max: usize },
+
+    #[error("record has been modified since the version in If-Match")]
+    RecordModified,
+
     #[error("failed to compile Miden program")]
     MidenCompile(Box<dyn std::error::Error>),
 
@@ -1,7 +1,7 @@
 use actix_web::http::StatusCode;
 use derive_more::Display;
 
-#[derive(Debug, Display)]
+#[derive(Debug, Display, Clone, Copy, PartialEq)]
 pub enum ErrorCode {
     // #[display(fmt = "bad-request")]
     // BadRequest,
@@ -11,9 +11,12 @@ pub enum ErrorCode {
     #[display(fmt = "failed-precondition")]
     FailedPrecondition,
 
+    /// A conditional request (e.g. If-Match) didn't match the current state
+    #[display(fmt = "precondition-failed")]
+    PreconditionFailed,
+
     // #[display(fmt = "out-of-range")]
     // OutOfRange,
-    #[allow(unused)]
     #[display(fmt = "unauthenticated")]
     Unauthenticated,
 
@@ -33,21 +36,35 @@ pub enum ErrorCode {
 
     // #[display(fmt = "cancelled")]
     // Cancelled,
-
-    // #[display(fmt = "unavailable")]
-    // Unavailable,
+    #[display(fmt = "unavailable")]
+    Unavailable,
     #[display(fmt = "internal")]
     Internal,
-    // #[display(fmt = "deadline-exceeded")]
-    // DeadlineExceeded,
+
+    #[display(fmt = "deadline-exceeded")]
+    DeadlineExceeded,
 }
 
 impl ErrorCode {
+    /// Error codes that collection functions can use with `error(code, message)`,
+    /// underscores are accepted in place of dashes (e.g. not_found)
+    pub fn from_function_code(code: &str) -> Option<Self> {
+        match code.replace('_', "-").as_str() {
+            "invalid-argument" => Some(ErrorCode::InvalidArgument),
+            "failed-precondition" => Some(ErrorCode::FailedPrecondition),
+            "permission-denied" => Some(ErrorCode::PermissionDenied),
+            "not-found" => Some(ErrorCode::NotFound),
+            "already-exists" => Some(ErrorCode::AlreadyExists),
+            _ => None,
+        }
+    }
+
     pub fn status_code(&self) -> StatusCode {
         match self {
             // ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
             ErrorCode::InvalidArgument => StatusCode::BAD_REQUEST,
             ErrorCode::FailedPrecondition => StatusCode::BAD_REQUEST,
+            ErrorCode::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
             // ErrorCode::OutOfRange => StatusCode::BAD_REQUEST,
             ErrorCode::Unauthenticated => StatusCode::UNAUTHORIZED,
             ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
@@ -56,9 +73,9 @@ impl ErrorCode {
             ErrorCode::AlreadyExists => StatusCode::CONFLICT,
             // ErrorCode::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
             // ErrorCode::Cancelled => StatusCode::NOT_ACCEPTABLE,
-            // ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
             ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
-            // ErrorCode::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
         }
     }
 }
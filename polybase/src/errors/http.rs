@@ -5,10 +5,11 @@ use actix_web::{
 use serde::Serialize;
 use std::{error::Error, fmt::Display};
 
-use super::reason::ReasonCode;
+use super::{reason::ReasonCode, AppError};
 use crate::{
     auth,
     db::{self},
+    mempool::MempoolError,
 };
 
 #[derive(Debug)]
@@ -34,6 +35,21 @@ impl HTTPError {
     pub fn new(reason: ReasonCode, source: Option<Box<dyn std::error::Error>>) -> HTTPError {
         HTTPError { reason, source }
     }
+
+    /// The JSON body returned for this error
+    pub fn output(&self) -> ErrorOutput {
+        ErrorOutput {
+            error: ErrorDetail {
+                code: self.reason.code().to_string(),
+                reason: self.reason.to_string(),
+                message: self
+                    .source
+                    .as_ref()
+                    .map(|s| s.to_string())
+                    .unwrap_or_default(),
+            },
+        }
+    }
 }
 
 impl Display for HTTPError {
@@ -50,21 +66,10 @@ impl std::error::Error for HTTPError {
 
 impl actix_web::error::ResponseError for HTTPError {
     fn error_response(&self) -> HttpResponse {
-        let error = ErrorOutput {
-            error: ErrorDetail {
-                code: self.reason.code().to_string(),
-                reason: self.reason.to_string(),
-                message: self
-                    .source
-                    .as_ref()
-                    .map(|s| s.to_string())
-                    .unwrap_or_default(),
-            },
-        };
         #[allow(clippy::unwrap_used)]
         HttpResponse::build(self.status_code())
             .insert_header(ContentType::json())
-            .body(serde_json::to_string(&error).unwrap())
+            .body(serde_json::to_string(&self.output()).unwrap())
     }
 
     fn status_code(&self) -> StatusCode {
@@ -72,6 +77,16 @@ impl actix_web::error::ResponseError for HTTPError {
     }
 }
 
+impl From<AppError> for HTTPError {
+    fn from(err: AppError) -> Self {
+        match err {
+            AppError::Db(e) => e.into(),
+            AppError::Indexer(e) => e.into(),
+            _ => HTTPError::new(ReasonCode::from_app_error(&err), Some(Box::new(err))),
+        }
+    }
+}
+
 impl From<gateway::GatewayError> for HTTPError {
     fn from(err: gateway::GatewayError) -> Self {
         match err {
@@ -102,7 +117,9 @@ impl From<db::Error> for HTTPError {
             db::Error::SerdeJson(_) => internal_error(err),
             db::Error::CallTxn(_) => internal_error(err),
             db::Error::TokioSend(_) => internal_error(err),
+            db::Error::Mempool(e) => e.into(),
             db::Error::InvalidFunctionArgsResponse => internal_error(err),
+            db::Error::InvalidStateDigest => internal_error(err),
         }
     }
 }
@@ -113,6 +130,12 @@ impl From<db::UserError> for HTTPError {
     }
 }
 
+impl From<MempoolError> for HTTPError {
+    fn from(err: MempoolError) -> Self {
+        HTTPError::new(ReasonCode::from_mempool_error(&err), Some(Box::new(err)))
+    }
+}
+
 impl From<indexer::cursor::Error> for HTTPError {
     fn from(err: indexer::cursor::Error) -> Self {
         HTTPError::new(ReasonCode::Internal, Some(Box::new(err)))
@@ -189,6 +212,7 @@ impl From<indexer::Error> for HTTPError {
             indexer::Error::User(e) => e.into(),
             indexer::Error::WhereQuery(e) => e.into(),
             indexer::Error::Adaptor(e) => internal_error(e),
+            indexer::Error::Bincode(e) => internal_error(e),
         }
     }
 }
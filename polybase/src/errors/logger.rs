@@ -2,14 +2,64 @@ use super::http::HTTPError;
 use super::metrics::MetricsData;
 use super::reason::ReasonCode;
 use actix_web::{
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error, FromRequest, HttpMessage, HttpRequest,
 };
 use futures_util::future::LocalBoxFuture;
+use std::convert::Infallible;
+use std::fmt;
 use std::future::{ready, Ready};
-use tracing::{error, info};
+use tracing::{error, info, Instrument};
 use valuable::Valuable;
 
+/// Header used to pass a request id in, and to return it in the response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from a client, longer ids are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id used to correlate the logs of a request, from the RPC through to the commit of
+/// the txns it submitted. Taken from the `X-Request-Id` header, or generated if the
+/// header is missing or invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    fn generate() -> Self {
+        Self(hex::encode(rand::random::<[u8; 16]>()))
+    }
+
+    fn from_request_headers(req: &ServiceRequest) -> Self {
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+            .map(|id| Self(id.to_string()))
+            .unwrap_or_else(Self::generate)
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    /// The id set by `SlogMiddleware`, or a new id if the middleware isn't used
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(RequestId::generate)))
+    }
+}
+
 pub struct SlogMiddleware;
 
 impl<S, B> Transform<S, ServiceRequest> for SlogMiddleware
@@ -46,49 +96,67 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let fut = self.service.call(req);
-
-        Box::pin(async move {
-            match fut.await {
-                Ok(res) => {
-                    if let Some(err) = res.response().error() {
-                        if let Some(err) = err.as_error::<HTTPError>() {
-                            let mut output: String = format!("{err}");
-                            // Log out each error
-                            let mut error: &dyn std::error::Error = err;
-                            while let Some(source) = error.source() {
-                                output = format!("{output}\n  Caused by: {source}");
-                                error = source;
-                            }
-                            if err.reason == ReasonCode::Internal {
-                                let mut e = sentry::event_from_error(err);
-                                // Reverse the errors (Sentry seems to have a bug)
-                                e.exception.values.reverse();
-                                sentry::capture_event(e);
-                                error!("Error: {output}");
+        let request_id = RequestId::from_request_headers(&req);
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = req.path(),
+        );
+        req.extensions_mut().insert(request_id.clone());
+
+        let fut = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                match fut.await {
+                    Ok(mut res) => {
+                        if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                            res.headers_mut()
+                                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                        }
+
+                        if let Some(err) = res.response().error() {
+                            if let Some(err) = err.as_error::<HTTPError>() {
+                                let mut output: String = format!("{err}");
+                                // Log out each error
+                                let mut error: &dyn std::error::Error = err;
+                                while let Some(source) = error.source() {
+                                    output = format!("{output}\n  Caused by: {source}");
+                                    error = source;
+                                }
+                                if err.reason == ReasonCode::Internal {
+                                    let mut e = sentry::event_from_error(err);
+                                    // Reverse the errors (Sentry seems to have a bug)
+                                    e.exception.values.reverse();
+                                    sentry::capture_event(e);
+                                    error!("Error: {output}");
+                                } else {
+                                    error!("Error: {output}");
+                                }
                             } else {
-                                error!("Error: {output}");
+                                error!("Error: {err:?}");
                             }
-                        } else {
-                            error!("Error: {err:?}");
                         }
-                    }
 
-                    // log any metrics data that might be available
-                    {
-                        if let Some(metrics_data) = res.response().extensions().get::<MetricsData>()
+                        // log any metrics data that might be available
                         {
-                            info!(metrics_data = metrics_data.as_value(), "Metrics data");
+                            if let Some(metrics_data) =
+                                res.response().extensions().get::<MetricsData>()
+                            {
+                                info!(metrics_data = metrics_data.as_value(), "Metrics data");
+                            }
                         }
+                        Ok(res)
+                    }
+                    Err(err) => {
+                        error!("Error occurred: {}", err);
+                        Err(err)
                     }
-                    Ok(res)
-                }
-                Err(err) => {
-                    error!("Error occurred: {}", err);
-                    Err(err)
                 }
             }
-        })
+            .instrument(span),
+        )
     }
 }
 
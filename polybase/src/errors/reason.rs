@@ -1,6 +1,10 @@
 use derive_more::Display;
 
-use crate::{auth, db, errors::code::ErrorCode};
+use crate::{
+    auth, db,
+    errors::{code::ErrorCode, AppError},
+    mempool::MempoolError,
+};
 
 #[derive(Debug, Display, PartialEq)]
 pub enum ReasonCode {
@@ -25,6 +29,15 @@ pub enum ReasonCode {
     #[display(fmt = "record/invalid-field")]
     RecordInvalidField,
 
+    #[display(fmt = "record/modified")]
+    RecordModified,
+
+    #[display(fmt = "record/too-large")]
+    RecordTooLarge,
+
+    #[display(fmt = "record/too-deep")]
+    RecordTooDeep,
+
     #[allow(unused)]
     #[display(fmt = "index/missing-index")]
     IndexesMissingIndex,
@@ -46,8 +59,21 @@ pub enum ReasonCode {
     #[display(fmt = "function/javascript-exception")]
     FunctionJavaScriptException,
 
+    /// Error thrown by a collection function, with the error code passed to `error()`
     #[display(fmt = "function/collection-error")]
-    FunctionCollectionError,
+    FunctionCollectionError { code: Option<ErrorCode> },
+
+    #[display(fmt = "function/timed-out")]
+    FunctionTimedOut,
+
+    #[display(fmt = "function/call-limit-exceeded")]
+    FunctionCallLimitExceeded,
+
+    #[display(fmt = "function/memory-limit-exceeded")]
+    FunctionMemoryLimitExceeded,
+
+    #[display(fmt = "function/stack-overflow")]
+    FunctionStackOverflow,
 
     #[display(fmt = "constructor/no-id-assigned")]
     ConstructorNoId,
@@ -85,6 +111,30 @@ pub enum ReasonCode {
     #[display(fmt = "auth/invalid-signature")]
     AuthInvalidSignature,
 
+    #[display(fmt = "auth/expired-signature")]
+    AuthExpiredSignature,
+
+    #[display(fmt = "auth/replay")]
+    AuthReplay,
+
+    #[display(fmt = "admin/node-not-idle")]
+    AdminNodeNotIdle,
+
+    #[display(fmt = "admin/invalid-snapshot")]
+    AdminInvalidSnapshot,
+
+    #[display(fmt = "admin/invalid-import")]
+    AdminInvalidImport,
+
+    #[display(fmt = "changes/disabled")]
+    ChangesDisabled,
+
+    #[display(fmt = "batch/too-large")]
+    BatchTooLarge,
+
+    #[display(fmt = "mempool/full")]
+    MempoolFull,
+
     #[display(fmt = "unauthorized")]
     Unauthorized,
 
@@ -102,13 +152,22 @@ impl ReasonCode {
             ReasonCode::RecordIDModified => ErrorCode::FailedPrecondition,
             ReasonCode::RecordMissingField => ErrorCode::InvalidArgument,
             ReasonCode::RecordInvalidField => ErrorCode::InvalidArgument,
+            ReasonCode::RecordModified => ErrorCode::PreconditionFailed,
+            ReasonCode::RecordTooLarge => ErrorCode::InvalidArgument,
+            ReasonCode::RecordTooDeep => ErrorCode::InvalidArgument,
             ReasonCode::IndexesMissingIndex => ErrorCode::FailedPrecondition,
             ReasonCode::FunctionInvalidatedId => ErrorCode::FailedPrecondition,
             ReasonCode::FunctionNotFound => ErrorCode::NotFound,
             ReasonCode::FunctionInvalidArgs => ErrorCode::InvalidArgument,
             ReasonCode::FunctionInvalidCall => ErrorCode::InvalidArgument,
             ReasonCode::FunctionJavaScriptException => ErrorCode::FailedPrecondition,
-            ReasonCode::FunctionCollectionError => ErrorCode::FailedPrecondition,
+            ReasonCode::FunctionCollectionError { code } => {
+                code.unwrap_or(ErrorCode::FailedPrecondition)
+            }
+            ReasonCode::FunctionTimedOut => ErrorCode::DeadlineExceeded,
+            ReasonCode::FunctionCallLimitExceeded => ErrorCode::FailedPrecondition,
+            ReasonCode::FunctionMemoryLimitExceeded => ErrorCode::FailedPrecondition,
+            ReasonCode::FunctionStackOverflow => ErrorCode::FailedPrecondition,
             ReasonCode::ConstructorNoId => ErrorCode::InvalidArgument,
             ReasonCode::CollectionNotFound => ErrorCode::NotFound,
             ReasonCode::CollectionIdExists => ErrorCode::AlreadyExists,
@@ -121,11 +180,53 @@ impl ReasonCode {
             ReasonCode::IndexerMissingIndex => ErrorCode::FailedPrecondition,
             ReasonCode::IndexerInvalidQueryValue => ErrorCode::InvalidArgument,
             ReasonCode::AuthInvalidSignature => ErrorCode::InvalidArgument,
+            ReasonCode::AuthExpiredSignature => ErrorCode::Unauthenticated,
+            ReasonCode::AuthReplay => ErrorCode::Unauthenticated,
+            ReasonCode::AdminNodeNotIdle => ErrorCode::FailedPrecondition,
+            ReasonCode::AdminInvalidSnapshot => ErrorCode::InvalidArgument,
+            ReasonCode::AdminInvalidImport => ErrorCode::InvalidArgument,
+            ReasonCode::ChangesDisabled => ErrorCode::FailedPrecondition,
+            ReasonCode::BatchTooLarge => ErrorCode::InvalidArgument,
+            ReasonCode::MempoolFull => ErrorCode::Unavailable,
             ReasonCode::Unauthorized => ErrorCode::PermissionDenied,
             ReasonCode::Internal => ErrorCode::Internal,
         }
     }
 
+    pub fn from_app_error(err: &AppError) -> Self {
+        match err {
+            AppError::AnonNamespace => ReasonCode::Unauthorized,
+            AppError::Whitelist => ReasonCode::Unauthorized,
+            AppError::InvalidNamespace(_) => ReasonCode::Unauthorized,
+            AppError::InvalidNamespacePublicKey(_, _) => ReasonCode::Unauthorized,
+            AppError::AdminDisabled => ReasonCode::Unauthorized,
+            AppError::InvalidAdminKey => ReasonCode::Unauthorized,
+            AppError::NodeNotIdle(_) => ReasonCode::AdminNodeNotIdle,
+            AppError::InvalidSnapshot(_) => ReasonCode::AdminInvalidSnapshot,
+            AppError::InvalidImport(_) => ReasonCode::AdminInvalidImport,
+            AppError::ChangesDisabled => ReasonCode::ChangesDisabled,
+            AppError::BatchTooLarge { .. } => ReasonCode::BatchTooLarge,
+            AppError::RecordModified => ReasonCode::RecordModified,
+            AppError::Indexer(_) => ReasonCode::Internal,
+            AppError::Store(_) => ReasonCode::Internal,
+            AppError::KeyFile(_) => ReasonCode::Internal,
+            AppError::JoinError(_) => ReasonCode::Internal,
+            AppError::HttpServer(_) => ReasonCode::Internal,
+            AppError::Io(_) => ReasonCode::Internal,
+            AppError::Network(_) => ReasonCode::Internal,
+            AppError::Multiaddr(_) => ReasonCode::Internal,
+            AppError::Decoding(_) => ReasonCode::Internal,
+            AppError::FromHex(_) => ReasonCode::Internal,
+            AppError::Db(_) => ReasonCode::Internal,
+            AppError::B58(_) => ReasonCode::Internal,
+            AppError::MidenCompile(_) => ReasonCode::Internal,
+            AppError::ProveError(_) => ReasonCode::Internal,
+            AppError::ABIError(_) => ReasonCode::Internal,
+            AppError::TracingParse(_) => ReasonCode::Internal,
+            AppError::TracingSetGlobalDefault(_) => ReasonCode::Internal,
+        }
+    }
+
     pub fn from_db_error(err: &db::UserError) -> Self {
         match err {
             db::UserError::FunctionNotFound { .. } => ReasonCode::FunctionNotFound,
@@ -142,6 +243,17 @@ impl ReasonCode {
                 ReasonCode::CollectionInvalidSchema
             }
             db::UserError::UnauthorizedCall => ReasonCode::Unauthorized,
+            db::UserError::UnauthorizedWrite => ReasonCode::Unauthorized,
+            db::UserError::RecordTooLarge { .. } => ReasonCode::RecordTooLarge,
+            db::UserError::RecordIDModified => ReasonCode::RecordIDModified,
+            db::UserError::PatchNotObject => ReasonCode::RecordNotObject,
+            db::UserError::CollectionPatchNotAllowed => ReasonCode::FunctionInvalidCall,
+        }
+    }
+
+    pub fn from_mempool_error(err: &MempoolError) -> Self {
+        match err {
+            MempoolError::Full => ReasonCode::MempoolFull,
         }
     }
 
@@ -155,13 +267,25 @@ impl ReasonCode {
                 ReasonCode::FunctionJavaScriptException
             }
 
-            gateway::GatewayUserError::CollectionFunctionError { .. } => {
-                ReasonCode::FunctionCollectionError
+            gateway::GatewayUserError::CollectionFunctionError { code, .. } => {
+                ReasonCode::FunctionCollectionError {
+                    code: code.as_deref().and_then(ErrorCode::from_function_code),
+                }
             }
 
             gateway::GatewayUserError::ConstructorMustAssignId => ReasonCode::ConstructorNoId,
 
-            gateway::GatewayUserError::FunctionTimedOut => ReasonCode::FunctionJavaScriptException,
+            gateway::GatewayUserError::FunctionTimedOut => ReasonCode::FunctionTimedOut,
+
+            gateway::GatewayUserError::CallLimitExceeded { .. } => {
+                ReasonCode::FunctionCallLimitExceeded
+            }
+
+            gateway::GatewayUserError::MemoryLimitExceeded => {
+                ReasonCode::FunctionMemoryLimitExceeded
+            }
+
+            gateway::GatewayUserError::StackOverflow => ReasonCode::FunctionStackOverflow,
         }
     }
 
@@ -176,10 +300,16 @@ impl ReasonCode {
             indexer::where_query::WhereQueryUserError::CannotFilterOrSortByField(..) => {
                 ReasonCode::IndexerMissingIndex
             }
+            indexer::where_query::WhereQueryUserError::UnknownField { .. } => {
+                ReasonCode::IndexerInvalidQueryValue
+            }
             indexer::where_query::WhereQueryUserError::InvalidWhereQueryField { .. } => {
                 ReasonCode::IndexerInvalidQueryValue
             }
-            indexer::where_query::WhereQueryUserError::InvalidWhereQueryValue { .. } => {
+            indexer::where_query::WhereQueryUserError::FieldTypeMismatch { .. } => {
+                ReasonCode::IndexerInvalidQueryValue
+            }
+            indexer::where_query::WhereQueryUserError::ContainsRequiresArrayField(..) => {
                 ReasonCode::IndexerInvalidQueryValue
             }
             indexer::where_query::WhereQueryUserError::InequalitySortDirectionMismatch {
@@ -223,12 +353,21 @@ impl ReasonCode {
             schema::UserError::SchemaFieldTypeChangeNotAllowed { .. } => {
                 ReasonCode::CollectionInvalidSchema
             }
+            schema::UserError::SchemaIndexedFieldRemovalNotAllowed { .. } => {
+                ReasonCode::CollectionInvalidSchema
+            }
+            schema::UserError::SchemaReferencedFieldRemovalNotAllowed { .. } => {
+                ReasonCode::CollectionInvalidSchema
+            }
             schema::UserError::IndexFieldNotFoundInSchema { .. } => {
                 ReasonCode::CollectionInvalidSchema
             }
             schema::UserError::FieldTypeCannotBeIndexed { .. } => {
                 ReasonCode::CollectionInvalidSchema
             }
+            schema::UserError::IndexCannotHaveMultipleArrayFields { .. } => {
+                ReasonCode::CollectionInvalidSchema
+            }
             schema::UserError::CollectionDirectiveCannotHaveArguments { .. } => {
                 ReasonCode::CollectionInvalidSchema
             }
@@ -267,10 +406,32 @@ impl ReasonCode {
             schema::record::RecordUserError::ForeignRecordReferenceHasWrongCollectionId {
                 ..
             } => ReasonCode::RecordInvalidField,
+            schema::record::RecordUserError::RecordTooDeep { .. } => ReasonCode::RecordTooDeep,
         }
     }
 
-    pub fn from_auth_error(_err: &auth::AuthUserError) -> Self {
-        ReasonCode::AuthInvalidSignature
+    pub fn from_auth_error(err: &auth::AuthUserError) -> Self {
+        match err {
+            auth::AuthUserError::SignatureExpired => ReasonCode::AuthExpiredSignature,
+            auth::AuthUserError::SignatureTimestampInFuture => ReasonCode::AuthExpiredSignature,
+            auth::AuthUserError::SignatureReplayed => ReasonCode::AuthReplay,
+            auth::AuthUserError::MissingEquals
+            | auth::AuthUserError::PublicKeyMustStartWith0x
+            | auth::AuthUserError::SignatureMustStartWith0x
+            | auth::AuthUserError::SignatureMustBe65Bytes
+            | auth::AuthUserError::MissingSignature
+            | auth::AuthUserError::MissingTimestamp
+            | auth::AuthUserError::MissingVersion
+            | auth::AuthUserError::MissingHash
+            | auth::AuthUserError::UnknownKey { .. }
+            | auth::AuthUserError::SignaturePublicKeyMismatch
+            | auth::AuthUserError::FailedToDecodeHexParameter { .. }
+            | auth::AuthUserError::FailedToDecodeTimestamp(_)
+            | auth::AuthUserError::InvalidRecoveryId { .. }
+            | auth::AuthUserError::FailedToDecodePublicKey(_)
+            | auth::AuthUserError::InvalidSignature(_)
+            | auth::AuthUserError::FailedToRecoverPublicKey(_)
+            | auth::AuthUserError::FailedToParseBody(_) => ReasonCode::AuthInvalidSignature,
+        }
     }
 }
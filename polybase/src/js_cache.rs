@@ -0,0 +1,193 @@
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+/// Bounded least-recently-used cache of the JS code generated for a collection's
+/// methods, keyed by collection id. Entries are invalidated when the collection's code
+/// is committed, so the cache never needs to look at the code.
+pub struct JsCodeCache {
+    state: Mutex<JsCodeCacheState>,
+}
+
+struct JsCodeCacheState {
+    /// Cached code, or None if the cache is disabled with a capacity of 0
+    entries: Option<LruCache<String, Arc<str>>>,
+    /// Incremented on every invalidation, so code generated from a schema read before a
+    /// concurrent commit is not inserted into the cache after the commit
+    generation: u64,
+}
+
+impl JsCodeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(JsCodeCacheState {
+                entries: NonZeroUsize::new(capacity).map(LruCache::new),
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Current generation of the cache, pass this to `get_or_generate` after reading the
+    /// collection's schema
+    pub fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    /// Get the generated JS code for a collection, generating it if it's not cached. The
+    /// generated code is not cached if the cache has been invalidated since `generation`.
+    pub fn get_or_generate(
+        &self,
+        collection_id: &str,
+        generation: u64,
+        generate: impl FnOnce() -> String,
+    ) -> Arc<str> {
+        if let Some(js_code) = self
+            .state
+            .lock()
+            .entries
+            .as_mut()
+            .and_then(|entries| entries.get(collection_id))
+        {
+            return Arc::clone(js_code);
+        }
+
+        let js_code: Arc<str> = generate().into();
+
+        let mut state = self.state.lock();
+        if state.generation == generation {
+            if let Some(entries) = state.entries.as_mut() {
+                entries.put(collection_id.to_string(), Arc::clone(&js_code));
+            }
+        }
+
+        js_code
+    }
+
+    /// Remove the cached code for a collection, e.g. when the collection code is updated
+    pub fn invalidate(&self, collection_id: &str) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        if let Some(entries) = state.entries.as_mut() {
+            entries.pop(collection_id);
+        }
+    }
+
+    /// Remove all cached code, e.g. when the store is reset or restored
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        if let Some(entries) = state.entries.as_mut() {
+            entries.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_generates_once_per_collection() {
+        let cache = JsCodeCache::new(10);
+        let generated = Cell::new(0);
+        let generate = || {
+            generated.set(generated.get() + 1);
+            "code".to_string()
+        };
+
+        let generation = cache.generation();
+        assert_eq!(
+            &*cache.get_or_generate("ns/A", generation, generate),
+            "code"
+        );
+        assert_eq!(
+            &*cache.get_or_generate("ns/A", generation, generate),
+            "code"
+        );
+        assert_eq!(generated.get(), 1);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = JsCodeCache::new(10);
+        let generated = Cell::new(0);
+        let generate = || {
+            generated.set(generated.get() + 1);
+            format!("code{}", generated.get())
+        };
+
+        assert_eq!(
+            &*cache.get_or_generate("ns/A", cache.generation(), generate),
+            "code1"
+        );
+        cache.invalidate("ns/A");
+        assert_eq!(
+            &*cache.get_or_generate("ns/A", cache.generation(), generate),
+            "code2"
+        );
+        assert_eq!(
+            &*cache.get_or_generate("ns/A", cache.generation(), generate),
+            "code2"
+        );
+        assert_eq!(generated.get(), 2);
+    }
+
+    #[test]
+    fn test_ignores_code_generated_before_invalidation() {
+        let cache = JsCodeCache::new(10);
+        let generated = Cell::new(0);
+        let generate = || {
+            generated.set(generated.get() + 1);
+            "code".to_string()
+        };
+
+        // The schema was read before the collection's code was committed
+        let generation = cache.generation();
+        cache.invalidate("ns/A");
+        cache.get_or_generate("ns/A", generation, generate);
+
+        cache.get_or_generate("ns/A", cache.generation(), generate);
+        assert_eq!(generated.get(), 2);
+    }
+
+    #[test]
+    fn test_clear() {
+        let cache = JsCodeCache::new(10);
+        let generated = Cell::new(0);
+        let generate = || {
+            generated.set(generated.get() + 1);
+            "code".to_string()
+        };
+
+        cache.get_or_generate("ns/A", cache.generation(), generate);
+        cache.clear();
+        cache.get_or_generate("ns/A", cache.generation(), generate);
+        assert_eq!(generated.get(), 2);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = JsCodeCache::new(2);
+        let generated = Cell::new(0);
+        let generate = || {
+            generated.set(generated.get() + 1);
+            "code".to_string()
+        };
+
+        let generation = cache.generation();
+        cache.get_or_generate("ns/A", generation, generate);
+        cache.get_or_generate("ns/B", generation, generate);
+        // Use A, so B is the least recently used
+        cache.get_or_generate("ns/A", generation, generate);
+        cache.get_or_generate("ns/C", generation, generate);
+        assert_eq!(generated.get(), 3);
+
+        cache.get_or_generate("ns/A", generation, generate);
+        assert_eq!(generated.get(), 3);
+
+        cache.get_or_generate("ns/B", generation, generate);
+        assert_eq!(generated.get(), 4);
+    }
+}
@@ -0,0 +1,213 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Env var with the passphrase used to encrypt the auto-generated secret key at rest, and
+/// to decrypt it when the node starts. It's not a flag, so it doesn't show up in the
+/// process list. When not set, the key is stored as plaintext hex.
+pub const PASSPHRASE_ENV: &str = "KEY_PASSPHRASE";
+
+/// Maximum KDF params accepted from a key file, so a tampered file can't make the node
+/// allocate unbounded memory or spin while deriving the key on startup
+const MAX_KDF_PARAMS: KdfParams = KdfParams {
+    // 1 GiB
+    memory_cost: 1024 * 1024,
+    time_cost: 100,
+    parallelism: 64,
+};
+
+pub type Result<T> = std::result::Result<T, KeyFileError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyFileError {
+    #[error("key file is encrypted, a passphrase is required to read it")]
+    PassphraseRequired,
+
+    #[error("failed to decrypt key file, the passphrase is incorrect or the file is corrupted")]
+    Decrypt,
+
+    #[error("failed to encrypt key")]
+    Encrypt,
+
+    #[error("invalid key derivation params: {0}")]
+    InvalidKdfParams(String),
+
+    #[error("invalid key file")]
+    InvalidJson(#[from] serde_json::Error),
+
+    #[error("invalid key file")]
+    InvalidHex(#[from] hex::FromHexError),
+}
+
+/// Argon2id params used to derive the encryption key from the passphrase, stored with
+/// the encrypted key so it can still be decrypted after the configured params change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KdfParams {
+    /// Memory cost, in KiB
+    pub memory_cost: u32,
+    /// Number of iterations
+    pub time_cost: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+/// Key file contents when the key is encrypted at rest
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EncryptedKeyFile {
+    kdf: KdfParams,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Encode a secret key for the key file, encrypted with a key derived from the passphrase
+/// (using argon2id and ChaCha20-Poly1305)
+pub fn encrypt_key(secret_key: &[u8], passphrase: &str, kdf: KdfParams) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let cipher = cipher(passphrase, &salt, kdf)?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), secret_key)
+        .map_err(|_| KeyFileError::Encrypt)?;
+
+    Ok(serde_json::to_string(&EncryptedKeyFile {
+        kdf,
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })?)
+}
+
+/// Decode the secret key from the contents of a key file, which is either the hex encoded
+/// key or the key encrypted by `encrypt_key`
+pub fn decode_key(contents: &str, passphrase: Option<&str>) -> Result<Vec<u8>> {
+    let contents = contents.trim();
+
+    if !contents.starts_with('{') {
+        let key = contents.strip_prefix("0x").unwrap_or(contents);
+        return Ok(hex::decode(key)?);
+    }
+
+    let file: EncryptedKeyFile = serde_json::from_str(contents)?;
+    let Some(passphrase) = passphrase else {
+        return Err(KeyFileError::PassphraseRequired);
+    };
+
+    let nonce = hex::decode(file.nonce)?;
+    if nonce.len() != NONCE_LEN {
+        return Err(KeyFileError::Decrypt);
+    }
+
+    cipher(passphrase, &hex::decode(file.salt)?, file.kdf)?
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            hex::decode(file.ciphertext)?.as_slice(),
+        )
+        .map_err(|_| KeyFileError::Decrypt)
+}
+
+/// The passphrase from `PASSPHRASE_ENV`, if set
+pub fn passphrase_from_env() -> Option<String> {
+    std::env::var(PASSPHRASE_ENV).ok()
+}
+
+fn cipher(passphrase: &str, salt: &[u8], kdf: KdfParams) -> Result<ChaCha20Poly1305> {
+    if kdf.memory_cost > MAX_KDF_PARAMS.memory_cost
+        || kdf.time_cost > MAX_KDF_PARAMS.time_cost
+        || kdf.parallelism > MAX_KDF_PARAMS.parallelism
+    {
+        return Err(KeyFileError::InvalidKdfParams(format!(
+            "{kdf:?} exceeds the maximum of {MAX_KDF_PARAMS:?}"
+        )));
+    }
+
+    let params = Params::new(kdf.memory_cost, kdf.time_cost, kdf.parallelism, Some(32))
+        .map_err(|err| KeyFileError::InvalidKdfParams(err.to_string()))?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| KeyFileError::InvalidKdfParams(err.to_string()))?;
+
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+    use libp2p::{identity, PeerId};
+
+    /// Cheap params, so the tests run quickly
+    const TEST_KDF: KdfParams = KdfParams {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+    };
+
+    fn peer_id(secret_key: Vec<u8>) -> PeerId {
+        PeerId::from(
+            identity::Keypair::ed25519_from_bytes(secret_key)
+                .unwrap()
+                .public(),
+        )
+    }
+
+    #[test]
+    fn test_encrypted_key_roundtrip() {
+        let (keypair, secret_key) = util::generate_key();
+        let contents = encrypt_key(&secret_key, "correct horse", TEST_KDF).unwrap();
+        assert!(!contents.contains(&hex::encode(secret_key)));
+
+        let decoded = decode_key(&contents, Some("correct horse")).unwrap();
+        assert_eq!(decoded, secret_key);
+        assert_eq!(peer_id(decoded), PeerId::from(keypair.public()));
+
+        assert!(matches!(
+            decode_key(&contents, Some("wrong horse")),
+            Err(KeyFileError::Decrypt)
+        ));
+        assert!(matches!(
+            decode_key(&contents, None),
+            Err(KeyFileError::PassphraseRequired)
+        ));
+    }
+
+    #[test]
+    fn test_rejects_excessive_kdf_params() {
+        let (_, secret_key) = util::generate_key();
+        let contents = encrypt_key(&secret_key, "correct horse", TEST_KDF).unwrap();
+
+        // A tampered file asks for more memory than the node allows
+        let mut file: EncryptedKeyFile = serde_json::from_str(&contents).unwrap();
+        file.kdf.memory_cost = u32::MAX;
+        let contents = serde_json::to_string(&file).unwrap();
+
+        assert!(matches!(
+            decode_key(&contents, Some("correct horse")),
+            Err(KeyFileError::InvalidKdfParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_plaintext_key() {
+        let (_, secret_key) = util::generate_key();
+        let contents = format!("0x{}\n", hex::encode(secret_key));
+
+        assert_eq!(decode_key(&contents, None).unwrap(), secret_key);
+        // The passphrase is ignored for plaintext keys
+        assert_eq!(decode_key(&contents, Some("unused")).unwrap(), secret_key);
+    }
+}
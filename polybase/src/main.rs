@@ -5,10 +5,14 @@ mod config;
 mod db;
 mod errors;
 mod hash;
+mod js_cache;
+mod key_file;
 mod mempool;
 mod migrate;
 mod network;
+mod replay;
 mod rpc;
+mod snapshot;
 mod txn;
 mod util;
 
@@ -17,9 +21,11 @@ use crate::db::{Db, DbConfig};
 use crate::errors::AppError;
 use crate::migrate::check_for_migration;
 use crate::rpc::create_rpc_server;
+use crate::snapshot::{Chunk, SnapshotSendConfig};
 use clap::Parser;
 use ed25519_dalek::{self as ed25519};
 use futures::StreamExt;
+use gateway::CallOptions;
 use indexer::Indexer;
 use libp2p::PeerId;
 use libp2p::{identity, Multiaddr};
@@ -30,7 +36,7 @@ use solid::proposal::ProposalManifest;
 use std::time::{Duration, Instant};
 use std::{
     fs::{create_dir_all, File, OpenOptions},
-    io::{Read, Write},
+    io::{IsTerminal, Read, Write},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -95,10 +101,19 @@ async fn setup_tracing(log_level: &LogLevel, log_format: &LogFormat) -> Result<(
 
 pub type ArcDbIndexer = Arc<Db<indexer_rocksdb::adaptor::RocksDBAdaptor>>;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let config = Config::parse();
 
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(worker_threads) = config.worker_threads {
+        runtime.worker_threads(worker_threads.max(1));
+    }
+
+    runtime.build()?.block_on(run(config))
+}
+
+async fn run(config: Config) -> Result<()> {
     if let Some(Command::GenerateKey) = config.command {
         let (keypair, bytes) = util::generate_key();
         #[allow(clippy::unwrap_used)]
@@ -137,7 +152,12 @@ async fn main() -> Result<()> {
     // Create the underlying store
     #[allow(clippy::unwrap_used)]
     let indexer_dir = util::get_indexer_dir(&config.root_dir).unwrap();
-    let rocksdb_adaptor = indexer_rocksdb::adaptor::RocksDBAdaptor::new(indexer_dir);
+    let rocksdb_adaptor =
+        indexer_rocksdb::adaptor::RocksDBAdaptor::open(indexer_dir, config.repair_on_corruption)?
+            .with_audit_log(config.audit_log)
+            .with_history_retention(
+                (config.record_history_blocks > 0).then_some(config.record_history_blocks),
+            );
 
     // Check for migration
     #[allow(clippy::expect_used)]
@@ -146,7 +166,9 @@ async fn main() -> Result<()> {
         .expect("migration check");
 
     // let memory_store = memory::MemoryStore::new();
-    let indexer = Indexer::new(rocksdb_adaptor);
+    let indexer = Indexer::with_record_cache_size(rocksdb_adaptor, config.record_cache_size)
+        .with_max_list_limit(config.max_list_limit)
+        .with_usage_metering(config.usage_metering);
 
     // Database combines various components into a single interface
     // that is thread safe
@@ -155,7 +177,20 @@ async fn main() -> Result<()> {
         Db::new(
             indexer,
             DbConfig {
+                block_txns_count: config.block_txns_count,
                 migration_batch_size: config.migration_batch_size,
+                gateway_pool_size: config.gateway_pool_size,
+                call_options: CallOptions {
+                    call_limit: config.function_call_limit,
+                    timeout: (config.function_timeout > 0)
+                        .then_some(Duration::from_millis(config.function_timeout)),
+                    memory_limit: config.function_memory_limit,
+                },
+                max_block_concurrent_calls: config.max_block_concurrent_calls,
+                max_mempool_txns: config.max_mempool_txns,
+                commit_timeout: (config.commit_timeout > 0)
+                    .then_some(Duration::from_millis(config.commit_timeout)),
+                max_degraded_sync_lag: config.max_degraded_sync_lag,
                 ..Default::default()
             },
         )
@@ -180,13 +215,19 @@ async fn main() -> Result<()> {
                 util::get_key_path(&config.root_dir).expect("failed to get key path");
             if key_path.exists() {
                 let mut file = File::open(key_path)?;
-                let mut key = String::new();
-                file.read_to_string(&mut key)?;
-                let key = match key.trim().strip_prefix("0x") {
-                    Some(key) => key,
-                    None => &key,
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)?;
+                let passphrase = key_file::passphrase_from_env();
+                let key_bytes = match key_file::decode_key(&contents, passphrase.as_deref()) {
+                    // Ask for the passphrase when started from a terminal
+                    Err(key_file::KeyFileError::PassphraseRequired)
+                        if std::io::stdin().is_terminal() =>
+                    {
+                        let passphrase = rpassword::prompt_password("Key file passphrase: ")?;
+                        key_file::decode_key(&contents, Some(&passphrase))?
+                    }
+                    result => result?,
                 };
-                let key_bytes = hex::decode(key)?;
                 identity::Keypair::ed25519_from_bytes(key_bytes)?
             } else {
                 warn!(
@@ -200,11 +241,23 @@ async fn main() -> Result<()> {
                     }
                 }
                 let (keypair, bytes) = util::generate_key();
+                let contents = match &key_file::passphrase_from_env() {
+                    Some(passphrase) => key_file::encrypt_key(
+                        &bytes,
+                        passphrase,
+                        key_file::KdfParams {
+                            memory_cost: config.key_kdf_memory_cost,
+                            time_cost: config.key_kdf_time_cost,
+                            parallelism: config.key_kdf_parallelism,
+                        },
+                    )?,
+                    None => hex::encode(bytes),
+                };
                 let mut file = OpenOptions::new()
                     .create(true)
                     .write(true)
                     .open(&key_path)?;
-                file.write_all(hex::encode(bytes).as_bytes())?;
+                file.write_all(contents.as_bytes())?;
                 keypair
             }
         }
@@ -242,6 +295,7 @@ async fn main() -> Result<()> {
         &keypair,
         network_laddr.into_iter(),
         peers_addr.into_iter(),
+        config.network_event_queue_capacity,
     )?);
 
     let local_peer_solid = solid::peer::PeerId(local_peer_id.to_bytes());
@@ -255,6 +309,7 @@ async fn main() -> Result<()> {
             manifest,
             SolidConfig {
                 max_proposal_history: config.block_cache_count,
+                quorum: config.accept_quorum,
                 ..SolidConfig::default()
             },
         ),
@@ -263,6 +318,7 @@ async fn main() -> Result<()> {
             solid_peers.clone(),
             SolidConfig {
                 max_proposal_history: config.block_cache_count,
+                quorum: config.accept_quorum,
                 ..SolidConfig::default()
             },
         ),
@@ -272,8 +328,19 @@ async fn main() -> Result<()> {
     let server = create_rpc_server(
         config.rpc_laddr,
         Arc::clone(&db),
+        Arc::clone(&network),
         Arc::new(config.whitelist.clone()),
         Arc::new(config.restrict_namespaces),
+        Arc::new(config.admin_key.clone()),
+        config.snapshot_chunk_size,
+        config.audit_log,
+        config.max_batch_reads,
+        config.max_import_bytes,
+        config.max_restore_bytes,
+        auth::SignatureConfig {
+            max_age: Duration::from_secs(config.signature_max_age),
+            ..Default::default()
+        },
     )?;
 
     let solid_handle = solid.run();
@@ -373,13 +440,15 @@ async fn main() -> Result<()> {
 
                             info!(peer_id = from_peer_id.prefix(), id = id, "Peer offered snapshot, resetting db");
 
+                            // Reset the database
+                            if let Err(err) = db.reset().await {
+                                error!(peer_id = from_peer_id.prefix(), id = id, err = ?err, "Failed to reset database, ignoring offer");
+                                continue;
+                            }
+
                             // Save who the snapshot is from
                             snapshot_from = Some((network_peer_id.clone(), id));
 
-                            // Reset the database
-                            #[allow(clippy::expect_used)]
-                            db.reset().await.expect("Failed to reset database");
-
                             info!("Db reset ready for snapshot, sending accept");
 
                             network.send(
@@ -394,48 +463,55 @@ async fn main() -> Result<()> {
                         // chunks of the snapshot
                         NetworkEvent::SnapshotAccept{ id  } => {
                             let db = Arc::clone(&db);
+                            let send_config = SnapshotSendConfig {
+                                max_inflight_chunks: config.snapshot_max_inflight_chunks,
+                                chunk_delay: Duration::from_millis(config.snapshot_chunk_delay),
+                                ack_timeout: (config.snapshot_ack_timeout > 0)
+                                    .then_some(Duration::from_millis(config.snapshot_ack_timeout)),
+                            };
 
                             info!(peer_id = from_peer_id.prefix(), id = id, "Peer accepted snapshot offer, sending chunks");
 
                             // Spawn a task, as we don't want to block the thread while we send network events,
                             // and this snapshot may take a while to complete
                             tokio::spawn(async move {
-                                // 100MB chunks
-                                let mut snapshot_iter = db.snapshot_iter(config.snapshot_chunk_size).await;
-                                while let Some(chunk) = snapshot_iter.next().await {
+                                let snapshot_iter = db.snapshot_iter(config.snapshot_chunk_size).await;
+
+                                // Send chunks, waiting for acks so we don't buffer too many chunks for a slow peer
+                                let res = snapshot::send_chunks(snapshot_iter, send_config, |chunk| {
                                     let peer_id = from_peer_id.clone();
-                                    match chunk {
-                                        Ok(chunk) => {
-                                            debug!(r#for = peer_id.prefix(), chunk_size = chunk.len(), "Sending snapshot chunk");
-                                            if let Some(tx) = network.send(
-                                                &peer_id.into(),
-                                                NetworkEvent::SnapshotChunk { id, chunk: Some(chunk) },
-                                            ).await {
-                                                // Wait for the send to complete
-                                                tx.await.ok();
-                                            }
-                                        },
-                                        Err(err) => {
-                                            error!(r#for = peer_id.prefix(), err = ?err, "Error creating snapshot");
-                                            return;
-                                        }
+                                    let network = &network;
+                                    async move {
+                                        debug!(r#for = peer_id.prefix(), chunk_size = chunk.len(), "Sending snapshot chunk");
+                                        network.send(
+                                            &peer_id.into(),
+                                            NetworkEvent::SnapshotChunk { id, chunk: Some(Chunk::new(chunk)) },
+                                        ).await
                                     }
+                                }).await;
+
+                                if let Err(err) = res {
+                                    error!(r#for = from_peer_id.prefix(), err = ?err, "Error sending snapshot, aborting");
+                                    return;
                                 }
 
                                 info!(peer_id = from_peer_id.prefix(), id = id, "Snapshot complete");
 
                                 // Send end of snapshot event
-                                network.send(
-                                    &from_peer_id.into(),
+                                if let Err(err) = network.send_wait(
+                                    &from_peer_id.clone().into(),
                                     NetworkEvent::SnapshotChunk { id, chunk: None },
-                                ).await;
+                                    send_config.ack_timeout,
+                                ).await {
+                                    error!(r#for = from_peer_id.prefix(), err = ?err, "Error sending end of snapshot");
+                                }
                             });
                         },
 
                         // We've received a chunk of a snapshot from another peer, we should load this into
                         // our db
                         NetworkEvent::SnapshotChunk { id, chunk } => {
-                            info!(peer_id = from_peer_id.prefix(), id = id, chunk_size = chunk.as_ref().map(|c| c.len()).unwrap_or(0),  "Received snapshot chunk");
+                            info!(peer_id = from_peer_id.prefix(), id = id, chunk_size = chunk.as_ref().map(|c| c.value_count()).unwrap_or(0),  "Received snapshot chunk");
                             if let Some((peer_id, snapshot_id)) = &snapshot_from {
                                 if peer_id != &network_peer_id || snapshot_id != &id  {
                                     error!("Received invalid snapshot chunk");
@@ -448,9 +524,16 @@ async fn main() -> Result<()> {
                             }
 
                             if let Some(chunk) = chunk {
-                                // We should panic if we are unable to restore
-                                #[allow(clippy::unwrap_used)]
-                                db.restore_chunk(chunk).await.unwrap();
+                                // Discard the snapshot and request a new one, as the snapshot is incomplete
+                                let height = solid.height();
+                                let restored = snapshot::restore_chunk(&db, chunk, || {
+                                    network.send_all(NetworkEvent::SnapshotRequest { height, id: util::unix_now() })
+                                }).await;
+
+                                if !restored {
+                                    warn!(peer_id = from_peer_id.prefix(), id = id, "Aborted snapshot, requested a new snapshot");
+                                    snapshot_from = None;
+                                }
                             } else {
                                 // We are finished, reset solid with the new proposal state from the snapshot
                                 #[allow(clippy::unwrap_used)]
@@ -478,7 +561,7 @@ async fn main() -> Result<()> {
                             // TODO: handle the error better
                             db.lease(&manifest).await.ok();
 
-                            solid.receive_proposal(manifest);
+                            solid.receive_proposal(manifest, &from_peer_id);
                         }
 
                         NetworkEvent::Txn { txn } => {
@@ -493,6 +576,23 @@ async fn main() -> Result<()> {
                     }
                 },
 
+                // Db was restored via the admin API, reset solid with the new proposal state
+                _ = db.restored() => {
+                    match db.get_manifest().await {
+                        Ok(Some(manifest)) => {
+                            let height = manifest.height;
+                            solid.reset(manifest);
+                            info!(height = height, "Restore db from admin snapshot complete");
+                        }
+                        Ok(None) => {
+                            warn!("Restored db from admin snapshot has no manifest");
+                        }
+                        Err(err) => {
+                            error!(err = ?err, "Error getting manifest after admin restore");
+                        }
+                    }
+                },
+
                 Some(event) = solid.next() => {
                     match event {
                         // Node should send accept for an active proposal
@@ -546,7 +646,7 @@ async fn main() -> Result<()> {
                             info!(leader_id = manifest.leader_id.prefix(), hash = proposal_hash.to_string(), height = height, "skips" = skips, "Propose");
 
                             // Add proposal to own register, this will trigger an accept
-                            solid.receive_proposal(manifest.clone());
+                            solid.receive_proposal(manifest.clone(), &manifest.leader_id);
 
                             // // Send proposal to all other nodes
                             network.send_all(
@@ -577,7 +677,7 @@ async fn main() -> Result<()> {
                             info!(local_height = height, accepts_sent = accepts_sent, max_seen_height = max_seen_height, "Out of sync");
 
                             // Set as out of sync, so we mark the node as unhealthy immediately
-                            db.out_of_sync(height);
+                            db.out_of_sync(height, max_seen_height);
 
                             if snapshot_from.is_some() {
                                 // We are already restoring from a snapshot, so we don't need to request another
@@ -605,6 +705,25 @@ async fn main() -> Result<()> {
                         SolidEvent::DuplicateProposal { proposal_hash } => {
                             info!(hash = proposal_hash.to_string(),  "Duplicate proposal");
                         }
+
+                        SolidEvent::Equivocation {
+                            peer_id,
+                            height,
+                            hash_a,
+                            hash_b,
+                        } => {
+                            warn!(from = peer_id.prefix(), height = height, hash_a = hash_a.to_string(), hash_b = hash_b.to_string(), "Rejected conflicting proposal, leader made two proposals for the same round");
+                        }
+
+                        SolidEvent::ConflictingProposal {
+                            peer_id,
+                            leader_id,
+                            height,
+                            hash_a,
+                            hash_b,
+                        } => {
+                            warn!(from = peer_id.prefix(), leader = leader_id.prefix(), height = height, hash_a = hash_a.to_string(), hash_b = hash_b.to_string(), "Conflicting proposal for the same round, not received from the leader");
+                        }
                     }
                 }
             }
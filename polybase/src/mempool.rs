@@ -1,10 +1,17 @@
 use parking_lot::Mutex;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::hash::Hash;
 use std::sync::Arc;
 use std::vec;
 use tokio::sync::oneshot;
 
+#[derive(Debug, thiserror::Error)]
+pub enum MempoolError {
+    #[error("mempool is full")]
+    Full,
+}
+
 struct MempoolTxn<V, C> {
     txn: V,
     senders: Option<Vec<oneshot::Sender<()>>>,
@@ -13,6 +20,9 @@ struct MempoolTxn<V, C> {
 
 pub struct Mempool<K, V, L, C> {
     state: Arc<Mutex<MempoolState<K, V, L, C>>>,
+    /// Maximum number of txns held in the mempool (including leased txns), new txns
+    /// are rejected once the mempool is full
+    max_txns: usize,
 }
 
 pub struct MempoolState<K, V, L, C> {
@@ -28,42 +38,73 @@ where
     L: Eq + PartialEq + Hash + Clone + std::fmt::Debug,
     C: Eq + PartialEq + Hash + Clone,
 {
-    pub fn new() -> Self {
+    pub fn new(max_txns: usize) -> Self {
         Mempool {
             state: Arc::new(Mutex::new(MempoolState {
                 txns: HashMap::new(),
                 pool: VecDeque::new(),
                 leased: HashMap::new(),
             })),
+            max_txns,
         }
     }
 
     /// Add a transaction to the mempool, only adds key/txn if the key
     /// doesn't already exist in the mempool. This is used when other nodes
     /// send us a txn they have received from a client
-    pub fn add(&self, key: K, txn: V, changes: Vec<C>) {
-        self._add(key, txn, changes, None);
+    pub fn add(&self, key: K, txn: V, changes: Vec<C>) -> Result<(), MempoolError> {
+        self._add(key, txn, changes, None)
+    }
+
+    /// Whether there are no txns waiting to be committed (including leased txns)
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().txns.is_empty()
     }
 
     pub fn contains(&self, key: &K) -> bool {
         self.state.lock().txns.contains_key(key)
     }
 
-    /// Add a transaction to the mempool and wait for it to be committed. This will only
-    /// be called where the txn is directly submitted to this node from a client
+    /// Get a copy of a txn in the mempool
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.state.lock().txns.get(key).map(|txn| txn.txn.clone())
+    }
+
+    /// Add a transaction to the mempool, returning a future that resolves once it is
+    /// committed. This will only be called where the txn is directly submitted to this
+    /// node from a client
     // TODO: handle duplicate add_wait, so we properly await
-    pub async fn add_wait(&self, key: K, txn: V, changes: Vec<C>) {
+    pub fn add_wait(
+        &self,
+        key: K,
+        txn: V,
+        changes: Vec<C>,
+    ) -> Result<impl Future<Output = ()>, MempoolError> {
         let (tx, rx) = oneshot::channel();
-        self._add(key, txn, changes, Some(vec![tx]));
-        let _ = rx.await;
+        self._add(key, txn, changes, Some(vec![tx]))?;
+        Ok(async move {
+            let _ = rx.await;
+        })
     }
 
     /// Internal add function, used by both add and add_wait
-    fn _add(&self, key: K, txn: V, changes: Vec<C>, tx: Option<Vec<oneshot::Sender<()>>>) {
+    fn _add(
+        &self,
+        key: K,
+        txn: V,
+        changes: Vec<C>,
+        tx: Option<Vec<oneshot::Sender<()>>>,
+    ) -> Result<(), MempoolError> {
         let mut state = self.state.lock();
 
         if state.txns.contains_key(&key) {
-            return;
+            return Ok(());
+        }
+
+        // Reject new txns rather than dropping existing ones, as clients may be
+        // waiting on the existing txns
+        if state.txns.len() >= self.max_txns {
+            return Err(MempoolError::Full);
         }
 
         state.txns.entry(key.clone()).or_insert(MempoolTxn {
@@ -74,6 +115,8 @@ where
 
         // Add the key to the pool
         state.pool.push_back(key);
+
+        Ok(())
     }
 
     /// Commit a given transaction with key, removing it from the mempool
@@ -154,7 +197,11 @@ where
         let mut discard = vec![];
         let mut conflict_check = HashSet::new();
 
-        while let Some(key) = state.pool.pop_front() {
+        while txns.len() < max_count {
+            let Some(key) = state.pool.pop_front() else {
+                break;
+            };
+
             #[allow(clippy::expect_used)]
             let changes = state
                 .txns
@@ -179,15 +226,13 @@ where
 
             #[allow(clippy::unwrap_used)]
             txns.push((key.clone(), state.txns.get(&key).unwrap().txn.clone()));
-
-            // If we have reached the max count, break
-            if txns.len() >= max_count {
-                break;
-            }
         }
 
-        // Return the discarded keys to the pool
-        state.pool.extend(discard);
+        // Return the discarded keys to the front of the pool, so they are still
+        // proposed before any newer txns
+        for key in discard.into_iter().rev() {
+            state.pool.push_front(key);
+        }
 
         txns
     }
@@ -202,8 +247,8 @@ mod tests {
 
     #[test]
     fn test_add_txn() {
-        let mempool: Mempool<String, u32, usize, usize> = Mempool::new();
-        mempool.add("key1".to_string(), 42, vec![]);
+        let mempool: Mempool<String, u32, usize, usize> = Mempool::new(100);
+        mempool.add("key1".to_string(), 42, vec![]).unwrap();
 
         {
             let state = mempool.state.lock();
@@ -211,7 +256,7 @@ mod tests {
             assert_eq!(state.txns.get("key1").unwrap().txn, 42);
         }
 
-        mempool.add("key1".to_string(), 24, vec![]);
+        mempool.add("key1".to_string(), 24, vec![]).unwrap();
 
         {
             let state = mempool.state.lock();
@@ -229,12 +274,15 @@ mod tests {
 
     #[test]
     fn test_add_txn_wait() {
-        let mempool: Arc<Mempool<String, u32, usize, usize>> = Arc::new(Mempool::new());
+        let mempool: Arc<Mempool<String, u32, usize, usize>> = Arc::new(Mempool::new(100));
         let rt = Runtime::new().unwrap();
 
         let mempool2 = mempool.clone();
         rt.spawn(async move {
-            mempool2.add_wait("key1".to_string(), 42, vec![]).await;
+            mempool2
+                .add_wait("key1".to_string(), 42, vec![])
+                .unwrap()
+                .await;
         });
 
         sleep(Duration::from_millis(100));
@@ -248,9 +296,9 @@ mod tests {
 
     #[test]
     fn test_commit_txn() {
-        let mempool: Mempool<&'static str, u32, usize, usize> = Mempool::new();
-        mempool.add("key1", 42, vec![]);
-        mempool.add("key2", 24, vec![]);
+        let mempool: Mempool<&'static str, u32, usize, usize> = Mempool::new(100);
+        mempool.add("key1", 42, vec![]).unwrap();
+        mempool.add("key2", 24, vec![]).unwrap();
 
         mempool.commit(1, vec![&"key1"]);
 
@@ -263,10 +311,10 @@ mod tests {
 
     #[test]
     fn test_lease_batch() {
-        let mempool: Mempool<String, u32, usize, usize> = Mempool::new();
-        mempool.add("key1".to_string(), 42, vec![]);
-        mempool.add("key2".to_string(), 24, vec![]);
-        mempool.add("key3".to_string(), 15, vec![]);
+        let mempool: Mempool<String, u32, usize, usize> = Mempool::new(100);
+        mempool.add("key1".to_string(), 42, vec![]).unwrap();
+        mempool.add("key2".to_string(), 24, vec![]).unwrap();
+        mempool.add("key3".to_string(), 15, vec![]).unwrap();
 
         let batch = mempool.lease_batch(2, 2);
         assert_eq!(batch.len(), 2);
@@ -285,12 +333,45 @@ mod tests {
         // assert_eq!(batch[0], ("key1".to_string(), 42));
     }
 
+    #[test]
+    fn test_lease_batch_oldest_first() {
+        let mempool: Mempool<String, u32, usize, usize> = Mempool::new(100);
+        mempool.add("key1".to_string(), 1, vec![1]).unwrap();
+        mempool.add("key2".to_string(), 2, vec![1]).unwrap();
+        mempool.add("key3".to_string(), 3, vec![2]).unwrap();
+        mempool.add("key4".to_string(), 4, vec![3]).unwrap();
+
+        // key2 conflicts with key1, so it stays at the front of the pool
+        let batch = mempool.lease_batch(1, 2);
+        assert_eq!(
+            batch,
+            vec![("key1".to_string(), 1), ("key3".to_string(), 3)]
+        );
+
+        mempool.commit(1, vec![&"key1".to_string(), &"key3".to_string()]);
+
+        let batch = mempool.lease_batch(2, 10);
+        assert_eq!(
+            batch,
+            vec![("key2".to_string(), 2), ("key4".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn test_lease_batch_zero_max_count() {
+        let mempool: Mempool<String, u32, usize, usize> = Mempool::new(100);
+        mempool.add("key1".to_string(), 1, vec![]).unwrap();
+
+        assert!(mempool.lease_batch(1, 0).is_empty());
+        assert_eq!(mempool.state.lock().pool.len(), 1);
+    }
+
     #[test]
     fn test_lease_with_duplicate_changes() {
-        let mempool: Mempool<String, u32, usize, usize> = Mempool::new();
-        mempool.add("key1".to_string(), 42, vec![1, 2, 3]);
-        mempool.add("key2".to_string(), 24, vec![3, 4, 5]);
-        mempool.add("key3".to_string(), 15, vec![6, 7, 8]);
+        let mempool: Mempool<String, u32, usize, usize> = Mempool::new(100);
+        mempool.add("key1".to_string(), 42, vec![1, 2, 3]).unwrap();
+        mempool.add("key2".to_string(), 24, vec![3, 4, 5]).unwrap();
+        mempool.add("key3".to_string(), 15, vec![6, 7, 8]).unwrap();
 
         let batch = mempool.lease_batch(2, 3);
         assert_eq!(batch.len(), 2);
@@ -300,4 +381,34 @@ mod tests {
             assert_eq!(state.pool.len(), 1);
         }
     }
+
+    #[test]
+    fn test_rejects_txns_when_full() {
+        let mempool: Mempool<String, u32, usize, usize> = Mempool::new(2);
+        mempool.add("key1".to_string(), 1, vec![]).unwrap();
+        mempool.add("key2".to_string(), 2, vec![]).unwrap();
+
+        assert!(matches!(
+            mempool.add("key3".to_string(), 3, vec![]),
+            Err(MempoolError::Full)
+        ));
+        assert!(matches!(
+            mempool.add_wait("key3".to_string(), 3, vec![]),
+            Err(MempoolError::Full)
+        ));
+
+        // Existing txns are kept, and re-adding them is not an error
+        mempool.add("key1".to_string(), 1, vec![]).unwrap();
+        assert_eq!(mempool.state.lock().txns.len(), 2);
+
+        // Leased txns still count towards the limit
+        let batch = mempool.lease_batch(1, 10);
+        assert_eq!(batch.len(), 2);
+        assert!(mempool.add("key3".to_string(), 3, vec![]).is_err());
+
+        // Committing a block drains the mempool
+        mempool.commit(1, vec![&"key1".to_string()]);
+        mempool.add("key3".to_string(), 3, vec![]).unwrap();
+        assert!(mempool.add("key4".to_string(), 4, vec![]).is_err());
+    }
 }
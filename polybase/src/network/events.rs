@@ -1,8 +1,8 @@
-use indexer::adaptor::SnapshotValue;
 use serde::{Deserialize, Serialize};
 use solid::proposal::ProposalAccept;
 use solid::proposal::ProposalManifest;
 
+use crate::snapshot::Chunk;
 use crate::txn::CallTxn;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,11 +32,9 @@ pub enum NetworkEvent {
     /// reset the database.
     SnapshotAccept { id: usize },
 
-    /// A chunk of data for us to load into the database.
-    SnapshotChunk {
-        id: usize,
-        chunk: Option<Vec<SnapshotValue>>,
-    },
+    /// A chunk of data for us to load into the database, the chunk must be verified
+    /// against its checksum before it is restored. A None chunk indicates the end of the snapshot.
+    SnapshotChunk { id: usize, chunk: Option<Chunk> },
 
     /// A transaction sent to another peer, which we should add to our Mempool
     Txn { txn: CallTxn },
@@ -44,3 +42,22 @@ pub enum NetworkEvent {
     /// Used for testing.
     Ping,
 }
+
+impl NetworkEvent {
+    /// Whether the event can be dropped when the network event queues are full, all
+    /// other events are needed for consensus or sync and are never dropped. Txns are
+    /// already in the mempool of the node that received them, so a dropped txn is still
+    /// committed once that node proposes it, and a flood of txns can't hold up proposals.
+    pub fn is_droppable(&self) -> bool {
+        match self {
+            NetworkEvent::Ping | NetworkEvent::Txn { .. } => true,
+            NetworkEvent::OutOfSync { .. }
+            | NetworkEvent::Accept { .. }
+            | NetworkEvent::Proposal { .. }
+            | NetworkEvent::SnapshotRequest { .. }
+            | NetworkEvent::SnapshotOffer { .. }
+            | NetworkEvent::SnapshotAccept { .. }
+            | NetworkEvent::SnapshotChunk { .. } => false,
+        }
+    }
+}
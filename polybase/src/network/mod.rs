@@ -2,6 +2,7 @@ use behaviour::{Behaviour, BehaviourEvent};
 use events::NetworkEvent;
 use futures_util::StreamExt;
 use libp2p::{
+    core::ConnectedPoint,
     identity::Keypair,
     request_response,
     swarm::{keep_alive, SwarmBuilder, SwarmEvent},
@@ -9,19 +10,33 @@ use libp2p::{
 };
 use parking_lot::Mutex;
 use protocol::PolyProtocol;
+use queue::EventQueue;
+use redial::Redialer;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::{select, sync::mpsc, sync::oneshot, sync::Mutex as AsyncMutex};
+use std::time::{Duration, Instant};
+use tokio::{select, sync::oneshot};
 use tracing::{debug, error, info};
 use transport::create_transport;
 
 mod behaviour;
 pub mod events;
 mod protocol;
+mod queue;
+mod redial;
 mod transport;
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Delay before the first re-dial of a peer that failed to connect or disconnected
+const REDIAL_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay between re-dial attempts
+const REDIAL_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often to check for peers that need to be re-dialed
+const REDIAL_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Failed to dial peer: {0}")]
@@ -32,11 +47,16 @@ pub enum Error {
 
     #[error("Channel error")]
     Send(#[from] tokio::sync::oneshot::error::RecvError),
+
+    #[error("Timed out waiting for peer to respond")]
+    Timeout,
 }
 
+type OutboundEvent = (PeerId, NetworkEvent, oneshot::Sender<()>);
+
 pub struct Network {
-    netin_rx: AsyncMutex<mpsc::UnboundedReceiver<(NetworkPeerId, NetworkEvent)>>,
-    netout_tx: mpsc::UnboundedSender<(PeerId, NetworkEvent, oneshot::Sender<()>)>,
+    netin: Arc<EventQueue<(NetworkPeerId, NetworkEvent)>>,
+    netout: Arc<EventQueue<OutboundEvent>>,
     local_peer_id: PeerId,
     shared: Arc<NetworkShared>,
 }
@@ -46,6 +66,7 @@ impl Network {
         keypair: &Keypair,
         listenaddrs: impl Iterator<Item = Multiaddr>,
         dialaddrs: impl Iterator<Item = Multiaddr>,
+        event_queue_capacity: usize,
     ) -> Result<Network> {
         let local_peer_id = PeerId::from(keypair.public());
         let transport = create_transport(keypair);
@@ -65,15 +86,32 @@ impl Network {
         }
 
         // Connect to peers
-        for addr in dialaddrs {
+        let dialaddrs = dialaddrs.collect::<Vec<_>>();
+        for addr in dialaddrs.iter() {
             info!(addr = ?addr, "Dialing peer");
-            swarm.dial(addr)?;
+            swarm.dial(addr.clone())?;
         }
 
-        // Channel to receive NetworkEvents from the network
-        let (netin_tx, netin_rx) = mpsc::unbounded_channel::<(NetworkPeerId, NetworkEvent)>();
-        let (netout_tx, mut netout_rx) =
-            mpsc::unbounded_channel::<(PeerId, NetworkEvent, oneshot::Sender<()>)>();
+        // Re-dial peers if the connection fails or is closed
+        let mut redialer = Redialer::new(
+            dialaddrs.into_iter(),
+            REDIAL_INITIAL_DELAY,
+            REDIAL_MAX_DELAY,
+            Instant::now(),
+        );
+
+        // Bounded queues of events received from and sent to the network, pings and txns
+        // are dropped when a queue is full, all other events wait for space
+        let netin = Arc::new(EventQueue::new(
+            event_queue_capacity,
+            |(_, event): &(NetworkPeerId, NetworkEvent)| event.is_droppable(),
+        ));
+        let netout = Arc::new(EventQueue::new(
+            event_queue_capacity,
+            |(_, event, _): &OutboundEvent| event.is_droppable(),
+        ));
+        let netin_tx = Arc::clone(&netin);
+        let netout_rx = Arc::clone(&netout);
 
         // Shared state between the network and the spawned network behaviour event loop
         let shared: Arc<NetworkShared> = Arc::new(NetworkShared::new());
@@ -82,9 +120,18 @@ impl Network {
         tokio::spawn(async move {
             let shared = shared_clone;
             let mut requests = HashMap::new();
+            let mut redial_interval = tokio::time::interval(REDIAL_CHECK_INTERVAL);
             loop {
                 select! {
-                    Some((peer_id, event, tx)) = netout_rx.recv() => {
+                    _ = redial_interval.tick() => {
+                        for addr in redialer.due(Instant::now()) {
+                            info!(addr = ?addr, "Re-dialing peer");
+                            if let Err(err) = swarm.dial(addr.clone()) {
+                                error!(addr = ?addr, error = ?err, "Failed to re-dial peer");
+                            }
+                        }
+                    }
+                    (peer_id, event, tx) = netout_rx.pop() => {
                         let request_id = swarm.behaviour_mut().rr.send_request(&peer_id, protocol::Request { event });
                         requests.insert(request_id, tx);
                     }
@@ -95,13 +142,21 @@ impl Network {
                         SwarmEvent::Dialing(peer_id) => {
                             info!(peer_id = ?peer_id, "Dialing peer");
                         }
-                        SwarmEvent::ConnectionEstablished { peer_id, established_in, .. } => {
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, established_in, .. } => {
                             info!(peer_id = ?peer_id, established_in = ?established_in, "Connection established");
                             shared.add_peer(peer_id);
+                            let dialed_addr = match &endpoint {
+                                ConnectedPoint::Dialer { address, .. } => Some(address),
+                                ConnectedPoint::Listener { .. } => None,
+                            };
+                            redialer.connected(&peer_id, dialed_addr);
                         }
                         SwarmEvent::ConnectionClosed { peer_id, endpoint, num_established, cause } => {
                             info!(peer_id = ?peer_id, num_established = num_established, endpoint = ?endpoint, cause = ?cause, "Connection closed");
                             shared.remove_peer(&peer_id);
+                            if num_established == 0 {
+                                redialer.disconnected(&peer_id, Instant::now());
+                            }
                         }
                         SwarmEvent::IncomingConnection { local_addr, send_back_addr } => {
                             info!(local_addr = ?local_addr, send_back_addr = ?send_back_addr, "Incoming connection");
@@ -127,10 +182,17 @@ impl Network {
                                     }
                                 },
                                 request_response::Message::Request{ request, channel, .. } => {
-                                        match netin_tx.send((peer.into(), request.event)) {
-                                            Ok(_) => {},
-                                            Err(_) => {
-                                                error!(peer_id = ?peer, "Failed to send, dropping event");
+                                        // Keep sending outbound events while waiting for space, otherwise
+                                        // we could deadlock with a consumer waiting for space to send
+                                        let push = netin_tx.push((peer.into(), request.event));
+                                        tokio::pin!(push);
+                                        loop {
+                                            select! {
+                                                _ = &mut push => break,
+                                                (peer_id, event, tx) = netout_rx.pop() => {
+                                                    let request_id = swarm.behaviour_mut().rr.send_request(&peer_id, protocol::Request { event });
+                                                    requests.insert(request_id, tx);
+                                                }
                                             }
                                         }
                                         match swarm.behaviour_mut().rr.send_response(channel, protocol::Response) {
@@ -143,6 +205,11 @@ impl Network {
                            }
                         }
                         SwarmEvent::Behaviour(BehaviourEvent::Rr(request_response::Event::ResponseSent { .. })) => {}
+                        SwarmEvent::Behaviour(BehaviourEvent::Rr(request_response::Event::OutboundFailure { peer, request_id, error })) => {
+                            error!(peer_id = ?peer, error = ?error, "Failed to send request");
+                            // Dropping the sender notifies the sender that the request failed
+                            requests.remove(&request_id);
+                        }
                         event => {
                             debug!(event = ?event, "Swarm event");
                         }
@@ -152,8 +219,8 @@ impl Network {
         });
 
         Ok(Network {
-            netin_rx: AsyncMutex::new(netin_rx),
-            netout_tx,
+            netin,
+            netout,
             local_peer_id,
             shared,
         })
@@ -171,6 +238,20 @@ impl Network {
         self._send(&peer.0, event).await
     }
 
+    /// Send an event to a peer and wait for the peer to acknowledge it, returns
+    /// `Error::Timeout` if the peer does not respond within `timeout`
+    pub async fn send_wait(
+        &self,
+        peer: &NetworkPeerId,
+        event: NetworkEvent,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        match self._send(&peer.0, event).await {
+            Some(ack) => wait_for_ack(ack, timeout).await,
+            None => Ok(()),
+        }
+    }
+
     pub async fn send_all(&self, event: NetworkEvent) {
         let peers = self.shared.state.lock().connected_peers.clone();
         let mut futures = vec![];
@@ -195,19 +276,42 @@ impl Network {
 
         let (tx, rx) = oneshot::channel();
 
-        match self.netout_tx.send((*peer, event, tx)) {
-            Ok(_) => {}
-            Err(_) => {
-                error!(peer_id = ?peer, "Failed to send, dropping event");
-            }
-        }
+        // Waits if the outbound queue is full, unless the event can be dropped
+        self.netout.push((*peer, event, tx)).await;
 
         Some(rx)
     }
 
     pub async fn next(&self) -> Option<(NetworkPeerId, NetworkEvent)> {
-        self.netin_rx.lock().await.recv().await
+        Some(self.netin.pop().await)
+    }
+
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// Peers we currently have an open connection with
+    pub fn connected_peers(&self) -> Vec<PeerId> {
+        self.shared
+            .state
+            .lock()
+            .connected_peers
+            .iter()
+            .copied()
+            .collect()
+    }
+}
+
+/// Wait for a peer to acknowledge an event returned by `send`, returns `Error::Timeout`
+/// if the peer does not respond within `timeout`
+pub async fn wait_for_ack(ack: oneshot::Receiver<()>, timeout: Option<Duration>) -> Result<()> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, ack)
+            .await
+            .map_err(|_| Error::Timeout)??,
+        None => ack.await?,
     }
+    Ok(())
 }
 
 struct NetworkShared {
@@ -0,0 +1,173 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use tokio::sync::Notify;
+use tracing::debug;
+
+/// A bounded queue of network events. When the queue is full, the oldest droppable
+/// event is dropped to make space, otherwise the sender waits until there is space,
+/// so events that are not droppable are never lost.
+pub struct EventQueue<T> {
+    capacity: usize,
+    is_droppable: fn(&T) -> bool,
+    items: Mutex<VecDeque<T>>,
+    /// Notified when an item is pushed
+    pushed: Notify,
+    /// Notified when an item is popped
+    popped: Notify,
+}
+
+impl<T> EventQueue<T> {
+    pub fn new(capacity: usize, is_droppable: fn(&T) -> bool) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            is_droppable,
+            items: Mutex::new(VecDeque::new()),
+            pushed: Notify::new(),
+            popped: Notify::new(),
+        }
+    }
+
+    /// Add an item to the back of the queue, waiting for space if the queue is full
+    /// and none of the queued items (or the item itself) can be dropped
+    pub async fn push(&self, item: T) {
+        loop {
+            {
+                let mut items = self.items.lock();
+
+                if items.len() >= self.capacity {
+                    if let Some(pos) = items.iter().position(self.is_droppable) {
+                        debug!("Network event queue is full, dropping oldest droppable event");
+                        items.remove(pos);
+                    } else if (self.is_droppable)(&item) {
+                        debug!("Network event queue is full, dropping event");
+                        return;
+                    }
+                }
+
+                if items.len() < self.capacity {
+                    items.push_back(item);
+                    drop(items);
+                    self.pushed.notify_one();
+                    return;
+                }
+            }
+
+            self.popped.notified().await;
+        }
+    }
+
+    /// Remove the item at the front of the queue, waiting for an item if the queue is empty
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some(item) = self.items.lock().pop_front() {
+                self.popped.notify_one();
+                return item;
+            }
+
+            self.pushed.notified().await;
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.items.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::events::NetworkEvent;
+    use crate::txn::CallTxn;
+    use solid::proposal::ProposalManifest;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn proposal(height: usize) -> NetworkEvent {
+        NetworkEvent::Proposal {
+            manifest: ProposalManifest {
+                height,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn height(event: &NetworkEvent) -> Option<usize> {
+        match event {
+            NetworkEvent::Proposal { manifest } => Some(manifest.height),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flood_of_droppable_events_is_bounded() {
+        let queue = EventQueue::new(4, NetworkEvent::is_droppable);
+
+        queue.push(proposal(1)).await;
+        for _ in 0..1000 {
+            queue.push(NetworkEvent::Ping).await;
+        }
+        queue.push(proposal(2)).await;
+        for _ in 0..1000 {
+            queue.push(NetworkEvent::Ping).await;
+        }
+        assert_eq!(queue.len(), 4);
+
+        // Proposals are kept in order, the pings were dropped to make space
+        let mut events = vec![];
+        for _ in 0..4 {
+            events.push(height(&queue.pop().await));
+        }
+        assert_eq!(events, vec![Some(1), Some(2), None, None]);
+    }
+
+    #[tokio::test]
+    async fn test_flood_of_txns_does_not_block_proposals() {
+        let queue = EventQueue::new(4, NetworkEvent::is_droppable);
+
+        for _ in 0..1000 {
+            queue
+                .push(NetworkEvent::Txn {
+                    txn: CallTxn::new(
+                        "ns/Col".to_string(),
+                        "constructor",
+                        "id1".to_string(),
+                        vec![],
+                        None,
+                    ),
+                })
+                .await;
+        }
+        queue.push(proposal(1)).await;
+        assert_eq!(queue.len(), 4);
+
+        let mut events = vec![];
+        for _ in 0..4 {
+            events.push(height(&queue.pop().await));
+        }
+        assert_eq!(events, vec![None, None, None, Some(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_push_waits_when_full_of_events_that_cannot_be_dropped() {
+        let queue = Arc::new(EventQueue::new(2, NetworkEvent::is_droppable));
+        queue.push(proposal(1)).await;
+        queue.push(proposal(2)).await;
+
+        // Pings are dropped rather than waiting
+        queue.push(NetworkEvent::Ping).await;
+        assert_eq!(queue.len(), 2);
+
+        let push = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            async move { queue.push(proposal(3)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!push.is_finished());
+
+        assert_eq!(height(&queue.pop().await), Some(1));
+        push.await.unwrap();
+        assert_eq!(height(&queue.pop().await), Some(2));
+        assert_eq!(height(&queue.pop().await), Some(3));
+    }
+}
@@ -0,0 +1,216 @@
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::time::{Duration, Instant};
+
+/// Exponential backoff, doubling the delay after each attempt up to `max`
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            next: initial,
+        }
+    }
+
+    /// Returns the delay before the next attempt, and increases the delay for the
+    /// attempt after that
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+#[derive(Debug)]
+struct DialState {
+    addr: Multiaddr,
+    /// Id of the peer at the address, from the address or learned from the first
+    /// connection to it
+    peer_id: Option<PeerId>,
+    connected: bool,
+    next_dial: Option<Instant>,
+    backoff: Backoff,
+}
+
+/// Tracks the configured dial addresses, so that they can be re-dialed with backoff
+/// when the initial dial fails or the connection is closed
+#[derive(Debug)]
+pub struct Redialer {
+    peers: Vec<DialState>,
+}
+
+impl Redialer {
+    /// Create a redialer for addresses that have just been dialed for the first time
+    pub fn new(
+        addrs: impl Iterator<Item = Multiaddr>,
+        initial: Duration,
+        max: Duration,
+        now: Instant,
+    ) -> Self {
+        Self {
+            peers: addrs
+                .map(|addr| {
+                    let mut backoff = Backoff::new(initial, max);
+                    DialState {
+                        peer_id: peer_id_from_addr(&addr),
+                        addr,
+                        connected: false,
+                        next_dial: Some(now + backoff.next_delay()),
+                        backoff,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Mark a peer as connected, whether we dialed it or it connected to us. Peers are
+    /// matched by id, as the address of the connection may differ from the configured
+    /// address (e.g. a resolved DNS address). An address without a peer id is matched to
+    /// the peer the first time we dial it.
+    pub fn connected(&mut self, peer_id: &PeerId, dialed_addr: Option<&Multiaddr>) {
+        for peer in self.peers.iter_mut() {
+            if peer.peer_id.is_none() && Some(&peer.addr) == dialed_addr {
+                peer.peer_id = Some(*peer_id);
+            }
+
+            if peer.peer_id.as_ref() == Some(peer_id) {
+                peer.connected = true;
+                peer.next_dial = None;
+                peer.backoff.reset();
+            }
+        }
+    }
+
+    /// Mark a peer as disconnected once all of its connections are closed, so it is
+    /// re-dialed with backoff
+    pub fn disconnected(&mut self, peer_id: &PeerId, now: Instant) {
+        for peer in self.peers.iter_mut() {
+            if peer.connected && peer.peer_id.as_ref() == Some(peer_id) {
+                peer.connected = false;
+                peer.next_dial = Some(now + peer.backoff.next_delay());
+            }
+        }
+    }
+
+    /// Returns the addresses that should be dialed now, and schedules the next attempt
+    /// for each of them in case the dial fails
+    pub fn due(&mut self, now: Instant) -> Vec<Multiaddr> {
+        let mut addrs = vec![];
+        for peer in self.peers.iter_mut() {
+            match peer.next_dial {
+                Some(next_dial) if !peer.connected && next_dial <= now => {
+                    peer.next_dial = Some(now + peer.backoff.next_delay());
+                    addrs.push(peer.addr.clone());
+                }
+                _ => {}
+            }
+        }
+        addrs
+    }
+}
+
+fn peer_id_from_addr(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr() -> Multiaddr {
+        "/ip4/127.0.0.1/tcp/5000".parse().unwrap()
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+
+        let delays = (0..5).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 5, 5].map(Duration::from_secs).to_vec());
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_redial_after_failed_dial_with_increasing_delay() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut redialer = Redialer::new(
+            [addr()].into_iter(),
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            start,
+        );
+
+        // Initial dial fails, so re-dial after 1s, then 2s, then 4s
+        assert!(redialer.due(start).is_empty());
+        assert_eq!(redialer.due(at(1)), vec![addr()]);
+        assert!(redialer.due(at(2)).is_empty());
+        assert_eq!(redialer.due(at(3)), vec![addr()]);
+        assert!(redialer.due(at(6)).is_empty());
+        assert_eq!(redialer.due(at(7)), vec![addr()]);
+    }
+
+    #[test]
+    fn test_redial_after_connection_closed() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let peer_id = PeerId::random();
+        let mut redialer = Redialer::new(
+            [addr()].into_iter(),
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            start,
+        );
+
+        // No re-dials while connected
+        redialer.connected(&peer_id, Some(&addr()));
+        assert!(redialer.due(at(100)).is_empty());
+
+        // Backoff restarts from the initial delay once disconnected
+        redialer.disconnected(&peer_id, at(100));
+        assert!(redialer.due(at(100)).is_empty());
+        assert_eq!(redialer.due(at(101)), vec![addr()]);
+        assert!(redialer.due(at(102)).is_empty());
+        assert_eq!(redialer.due(at(103)), vec![addr()]);
+    }
+
+    #[test]
+    fn test_matches_peer_by_id() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let peer_id = PeerId::random();
+        let addr_with_id = addr().with(Protocol::P2p(peer_id.into()));
+        let mut redialer = Redialer::new(
+            [addr_with_id.clone()].into_iter(),
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            start,
+        );
+
+        // The peer connected to us, so there's no need to dial it
+        redialer.connected(&peer_id, None);
+        assert!(redialer.due(at(100)).is_empty());
+
+        // Other peers don't affect the dialed peer
+        redialer.disconnected(&PeerId::random(), at(100));
+        assert!(redialer.due(at(101)).is_empty());
+
+        redialer.disconnected(&peer_id, at(100));
+        assert_eq!(redialer.due(at(101)), vec![addr_with_id]);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::auth::{AuthUserError, SignedRequest};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// Default maximum number of used requests remembered for each public key
+pub const DEFAULT_MAX_REQUESTS_PER_KEY: usize = 1000;
+
+/// Rejects signed requests that have already been used, so that a captured mutation
+/// can't be replayed within the signature's freshness window
+pub struct ReplayGuard {
+    /// Requests signed longer ago than this are rejected as expired, so they don't
+    /// need to be remembered
+    max_age: Duration,
+    max_requests_per_key: usize,
+    state: Mutex<ReplayGuardState>,
+}
+
+struct ReplayGuardState {
+    /// Keyed by the indexable form of the public key
+    keys: HashMap<Vec<u8>, KeyRequests>,
+    last_sweep: SystemTime,
+}
+
+#[derive(Default)]
+struct KeyRequests {
+    /// Requests signed at or before this time are rejected, this is set when a request
+    /// is evicted to keep the number of remembered requests bounded
+    watermark: Option<SystemTime>,
+    /// Used requests, from least to most recently used
+    used: VecDeque<(SystemTime, [u8; 32])>,
+    /// Requests that are in progress, they can't be used again until they are released
+    reserved: Vec<(SystemTime, [u8; 32])>,
+}
+
+impl ReplayGuard {
+    pub fn new(max_age: Duration, max_requests_per_key: usize) -> Self {
+        Self {
+            max_age,
+            max_requests_per_key: max_requests_per_key.max(1),
+            state: Mutex::new(ReplayGuardState {
+                keys: HashMap::new(),
+                last_sweep: SystemTime::UNIX_EPOCH,
+            }),
+        }
+    }
+
+    /// Reserves the requests, or returns an error (without reserving any of them) if one
+    /// of them has already been used or reserved. The requests are only marked as used
+    /// once the reservation is accepted, so a request that fails can be retried.
+    pub fn reserve(
+        &self,
+        requests: &[SignedRequest],
+        now: SystemTime,
+    ) -> Result<Reservation<'_>, AuthUserError> {
+        let mut state = self.state.lock();
+        let expired_before = now
+            .checked_sub(self.max_age)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        // Forget expired requests for all keys, so keys that are no longer used are removed
+        if now.duration_since(state.last_sweep).unwrap_or_default() > self.max_age {
+            state.keys.retain(|_, requests| {
+                requests.forget_expired(expired_before);
+                !requests.used.is_empty()
+                    || !requests.reserved.is_empty()
+                    || requests.watermark >= Some(expired_before)
+            });
+            state.last_sweep = now;
+        }
+
+        for request in requests {
+            let Some(key_requests) = state.keys.get(&request.public_key.to_indexable()) else {
+                continue;
+            };
+
+            if key_requests.watermark >= Some(request.signed_at)
+                || key_requests
+                    .used
+                    .iter()
+                    .chain(key_requests.reserved.iter())
+                    .any(|(_, hash)| *hash == request.message_hash)
+            {
+                return Err(AuthUserError::SignatureReplayed);
+            }
+        }
+
+        let mut reserved = Vec::with_capacity(requests.len());
+        for request in requests {
+            let key = request.public_key.to_indexable();
+            let key_requests = state.keys.entry(key.clone()).or_default();
+
+            key_requests.forget_expired(expired_before);
+            key_requests
+                .reserved
+                .push((request.signed_at, request.message_hash));
+            reserved.push((key, request.message_hash));
+        }
+
+        Ok(Reservation {
+            guard: self,
+            requests: reserved,
+            accepted: false,
+        })
+    }
+
+    /// Moves the reserved requests to the used requests, or releases them so they can be
+    /// used again
+    fn finish(&self, requests: &[(Vec<u8>, [u8; 32])], accepted: bool) {
+        let mut state = self.state.lock();
+
+        for (key, hash) in requests {
+            let Some(key_requests) = state.keys.get_mut(key) else {
+                continue;
+            };
+            let Some(i) = key_requests.reserved.iter().position(|(_, h)| h == hash) else {
+                continue;
+            };
+            let request = key_requests.reserved.swap_remove(i);
+            if !accepted {
+                continue;
+            }
+
+            key_requests.used.push_back(request);
+            while key_requests.used.len() > self.max_requests_per_key {
+                let Some((signed_at, _)) = key_requests.used.pop_front() else {
+                    break;
+                };
+                key_requests.watermark = key_requests.watermark.max(Some(signed_at));
+            }
+        }
+    }
+}
+
+/// Requests reserved by `ReplayGuard::reserve`, they are released when the reservation
+/// is dropped without being accepted
+pub struct Reservation<'a> {
+    guard: &'a ReplayGuard,
+    /// Indexable public key and message hash of each request
+    requests: Vec<(Vec<u8>, [u8; 32])>,
+    accepted: bool,
+}
+
+impl Reservation<'_> {
+    /// Marks the requests as used, e.g. once the mutation has been accepted
+    pub fn accept(mut self) {
+        self.accepted = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.guard.finish(&self.requests, self.accepted);
+    }
+}
+
+impl KeyRequests {
+    fn forget_expired(&mut self, expired_before: SystemTime) {
+        self.used
+            .retain(|(signed_at, _)| *signed_at >= expired_before);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::publickey::PublicKey;
+
+    fn public_key() -> PublicKey {
+        let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        PublicKey::from_secp256k1_key(&public_key).unwrap()
+    }
+
+    fn request(public_key: &PublicKey, signed_at: SystemTime, hash: u8) -> SignedRequest {
+        SignedRequest {
+            public_key: public_key.clone(),
+            signed_at,
+            message_hash: [hash; 32],
+        }
+    }
+
+    fn accept(
+        guard: &ReplayGuard,
+        requests: &[SignedRequest],
+        now: SystemTime,
+    ) -> Result<(), AuthUserError> {
+        guard.reserve(requests, now).map(Reservation::accept)
+    }
+
+    #[test]
+    fn test_rejects_replayed_request() {
+        let guard = ReplayGuard::new(Duration::from_secs(300), 10);
+        let now = SystemTime::now();
+        let (a, b) = (public_key(), public_key());
+
+        assert!(accept(&guard, &[request(&a, now, 1)], now).is_ok());
+        assert!(matches!(
+            accept(&guard, &[request(&a, now, 1)], now),
+            Err(AuthUserError::SignatureReplayed)
+        ));
+
+        // Different requests, or the same message signed by another key, are accepted
+        assert!(accept(&guard, &[request(&a, now, 2)], now).is_ok());
+        assert!(accept(&guard, &[request(&b, now, 1)], now).is_ok());
+    }
+
+    #[test]
+    fn test_evicted_requests_are_still_rejected() {
+        let guard = ReplayGuard::new(Duration::from_secs(300), 2);
+        let now = SystemTime::now();
+        let at = |secs| now + Duration::from_secs(secs);
+        let key = public_key();
+
+        for (secs, hash) in [(1, 1), (2, 2), (3, 3)] {
+            assert!(accept(&guard, &[request(&key, at(secs), hash)], now).is_ok());
+        }
+
+        // Request 1 was evicted, but is older than the watermark
+        assert!(matches!(
+            accept(&guard, &[request(&key, at(1), 1)], now),
+            Err(AuthUserError::SignatureReplayed)
+        ));
+        assert!(accept(&guard, &[request(&key, at(4), 4)], now).is_ok());
+    }
+
+    #[test]
+    fn test_released_request_can_be_retried() {
+        let guard = ReplayGuard::new(Duration::from_secs(300), 10);
+        let now = SystemTime::now();
+        let key = public_key();
+
+        let reservation = guard.reserve(&[request(&key, now, 1)], now).unwrap();
+
+        // The request can't be used while it's in progress
+        assert!(matches!(
+            guard.reserve(&[request(&key, now, 1)], now),
+            Err(AuthUserError::SignatureReplayed)
+        ));
+
+        // The mutation failed, so the request is released
+        drop(reservation);
+        assert!(accept(&guard, &[request(&key, now, 1)], now).is_ok());
+        assert!(matches!(
+            guard.reserve(&[request(&key, now, 1)], now),
+            Err(AuthUserError::SignatureReplayed)
+        ));
+    }
+}
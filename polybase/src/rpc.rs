@@ -1,31 +1,58 @@
 #![warn(clippy::unwrap_used, clippy::expect_used)]
 
-use crate::db::DbWaitResult;
-use crate::errors::http::HTTPError;
-use crate::errors::logger::SlogMiddleware;
+use crate::db::{DbWaitResult, HealthStatus, PATCH_FUNCTION_NAME};
+use crate::errors::http::{ErrorOutput, HTTPError};
+use crate::errors::logger::{RequestId, SlogMiddleware};
 use crate::errors::metrics::MetricsData;
 use crate::errors::reason::ReasonCode;
 use crate::errors::AppError;
+use crate::network::Network;
+use crate::replay::{self, ReplayGuard, Reservation};
+use crate::snapshot::Chunk;
 use crate::txn::CallTxn;
 use crate::ArcDbIndexer;
 use crate::{auth, util::hash};
 use actix_cors::Cors;
 use actix_server::Server;
+use actix_web::http::header::{
+    EntityTag, IfMatch, IfModifiedSince, LastModified, AUTHORIZATION, CONTENT_DISPOSITION, ETAG,
+};
 use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use base64::Engine;
+use bytes::{Buf, BytesMut};
+use futures::StreamExt;
 // use indexer::adaptor::IndexerAdaptor;
-use indexer::{auth_user::AuthUser, cursor, list_query, where_query};
+use indexer::adaptor::{AuditOp, SnapshotValue};
+use indexer::{
+    auth_user::AuthUser, cursor, diff::RecordDiff, list_query, usage::Usage, where_query,
+    IndexerChange,
+};
 use polylang_prover::{compile_program, Inputs, ProgramExt};
 use schema::record;
 use serde::{de::IntoDeserializer, Deserialize, Serialize};
 use serde_with::serde_as;
-use std::collections::HashMap;
-use std::{cmp::min, sync::Arc, time::Duration};
+use solid::proposal::ProposalManifest;
+use std::collections::{HashMap, HashSet};
+use std::{
+    cmp::min,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use subtle::ConstantTimeEq;
+use tokio_stream::wrappers::ReceiverStream;
 
 struct RouteState {
     db: ArcDbIndexer,
+    network: Arc<Network>,
     whitelist: Arc<Option<Vec<String>>>,
     restrict_namespaces: Arc<bool>,
+    admin_key: Arc<Option<String>>,
+    snapshot_chunk_size: usize,
+    audit_log: bool,
+    max_batch_reads: usize,
+    max_import_bytes: usize,
+    max_restore_bytes: usize,
+    replay_guard: Arc<ReplayGuard>,
 }
 
 #[get("/")]
@@ -77,6 +104,7 @@ async fn get_record(
     state: web::Data<RouteState>,
     path: web::Path<(String, String)>,
     query: web::Query<GetRecordQuery>,
+    if_modified_since: Option<web::Header<IfModifiedSince>>,
     body: auth::SignedJSON<()>,
 ) -> Result<impl Responder, HTTPError> {
     let (collection, record_id) = path.into_inner();
@@ -104,13 +132,34 @@ async fn get_record(
 
     match record {
         Some(record) => {
+            let last_update = state.db.last_record_update(&collection, &record_id).await?;
+
+            // HTTP dates only have second precision, so we truncate the last update time
+            let last_modified = last_update.map(truncate_to_secs);
+
+            if let (Some(last_modified), Some(if_modified_since)) =
+                (last_modified, if_modified_since)
+            {
+                if last_modified <= SystemTime::from(if_modified_since.into_inner().0) {
+                    return Ok(HttpResponse::NotModified().finish());
+                }
+            }
+
+            let mut resp = HttpResponse::Ok();
+            if let Some(last_modified) = last_modified {
+                resp.insert_header(LastModified(last_modified.into()));
+            }
+            if let Some(last_update) = last_update {
+                resp.insert_header((ETAG, record_etag(last_update)));
+            }
+
             let data = schema::record::record_to_json(record);
             if let Some(f) = &query.format {
                 if f == "nft" {
-                    return Ok(HttpResponse::Ok().json(data));
+                    return Ok(resp.json(data));
                 }
             }
-            Ok(HttpResponse::Ok().json(GetRecordResponse {
+            Ok(resp.json(GetRecordResponse {
                 data,
                 block: Default::default(),
             }))
@@ -119,6 +168,23 @@ async fn get_record(
     }
 }
 
+/// ETag for a record version, based on the record's last update time in millis
+fn record_etag(last_update: SystemTime) -> EntityTag {
+    let millis = last_update
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    EntityTag::new_strong(millis.to_string())
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 enum Direction {
     #[serde(rename = "asc")]
@@ -136,6 +202,15 @@ impl From<Direction> for schema::index::IndexDirection {
     }
 }
 
+impl From<schema::index::IndexDirection> for Direction {
+    fn from(dir: schema::index::IndexDirection) -> Self {
+        match dir {
+            schema::index::IndexDirection::Ascending => Direction::Ascending,
+            schema::index::IndexDirection::Descending => Direction::Descending,
+        }
+    }
+}
+
 /// Deserialized from "<number>s"
 #[derive(Debug, Clone, Copy)]
 struct Seconds(u64);
@@ -218,6 +293,96 @@ struct ListResponse<'a> {
     cursor: Cursors<'a>,
 }
 
+#[get("/{collection}/schema")]
+async fn get_schema(
+    state: web::Data<RouteState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, HTTPError> {
+    let collection = path.into_inner();
+    let schema = state.db.get_schema(&collection).await?;
+    Ok(HttpResponse::Ok().json(schema.to_json_schema()))
+}
+
+#[derive(Serialize)]
+struct IndexesResponse {
+    data: Vec<IndexEntry>,
+}
+
+#[derive(Serialize)]
+struct IndexEntry {
+    fields: Vec<IndexFieldEntry>,
+}
+
+#[derive(Serialize)]
+struct IndexFieldEntry {
+    /// Dot separated field path, e.g. `info.name`
+    path: String,
+    direction: Direction,
+}
+
+/// Indexes of the collection, list queries must match one of them
+#[get("/{collection}/indexes")]
+async fn get_indexes(
+    state: web::Data<RouteState>,
+    path: web::Path<String>,
+) -> Result<web::Json<IndexesResponse>, HTTPError> {
+    let collection = path.into_inner();
+    let schema = state.db.get_schema(&collection).await?;
+
+    Ok(web::Json(IndexesResponse {
+        data: schema
+            .indexes
+            .iter()
+            .map(|index| IndexEntry {
+                fields: index
+                    .fields
+                    .iter()
+                    .map(|field| IndexFieldEntry {
+                        path: field.path.to_string(),
+                        direction: field.direction.into(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct IndexStatsResponse {
+    data: Vec<IndexStatsEntry>,
+}
+
+#[derive(Serialize)]
+struct IndexStatsEntry {
+    /// Index fields, e.g. `name ASC`
+    fields: Vec<String>,
+    hits: u64,
+}
+
+/// Index hit counters are local to the node, and reveal how collections are queried,
+/// so they are only available to admins
+#[get("/{collection}/indexes/stats")]
+async fn get_index_stats(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    path: web::Path<String>,
+) -> Result<web::Json<IndexStatsResponse>, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    let collection = path.into_inner();
+    let stats = state.db.index_stats(&collection).await?;
+
+    Ok(web::Json(IndexStatsResponse {
+        data: stats
+            .into_iter()
+            .map(|stats| IndexStatsEntry {
+                fields: stats.fields.iter().map(|field| field.to_string()).collect(),
+                hits: stats.hits,
+            })
+            .collect(),
+    }))
+}
+
 #[tracing::instrument(skip(state, body))]
 #[get("/{collection}/records")]
 async fn get_records<'a>(
@@ -252,6 +417,14 @@ async fn get_records<'a>(
         cursor_before: cursor_before.clone(),
     };
 
+    // Cursors include the values of the index fields, so records with the same sort
+    // values can be paginated
+    let index_fields = state
+        .db
+        .explain(&collection, &list_query)
+        .await?
+        .index_fields;
+
     let records = if let Some(since) = query.since {
         match state
             .db
@@ -285,7 +458,12 @@ async fn get_records<'a>(
                     .first()
                     .and_then(|r| {
                         Some(cursor::Cursor(
-                            cursor::WrappedCursor::from_record(r, &query.where_query).ok()?,
+                            cursor::WrappedCursor::from_record(
+                                r,
+                                &query.where_query,
+                                &index_fields,
+                            )
+                            .ok()?,
                         ))
                     })
                     .or(cursor_before),
@@ -293,7 +471,12 @@ async fn get_records<'a>(
                     .last()
                     .and_then(|r| {
                         Some(cursor::Cursor(
-                            cursor::WrappedCursor::from_record(r, &query.where_query).ok()?,
+                            cursor::WrappedCursor::from_record(
+                                r,
+                                &query.where_query,
+                                &index_fields,
+                            )
+                            .ok()?,
                         ))
                     })
                     .or(cursor_after),
@@ -323,6 +506,150 @@ async fn get_records<'a>(
     Ok(resp)
 }
 
+#[tracing::instrument(skip(state, body))]
+#[get("/v0/namespaces/{namespace}/collections")]
+async fn get_namespace_collections(
+    state: web::Data<RouteState>,
+    path: web::Path<String>,
+    body: auth::SignedJSON<()>,
+) -> Result<impl Responder, HTTPError> {
+    let namespace = path.into_inner();
+    let auth: Option<AuthUser> = body.auth.map(|a| a.into());
+
+    let collections = state
+        .db
+        .list_namespace_collections(&namespace, auth)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(ListResponse {
+        cursor: Cursors {
+            before: None,
+            after: None,
+        },
+        data: collections
+            .into_iter()
+            .map(|r| GetRecordResponse {
+                data: record::record_to_json(r),
+                block: Default::default(),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangesQuery {
+    #[serde(default)]
+    since: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Change {
+    height: usize,
+    collection_id: String,
+    record_id: String,
+    op: AuditOp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChangesResponse {
+    changes: Vec<Change>,
+    /// Height the changes are complete up to, used as `since` to get the next changes
+    height: usize,
+}
+
+/// Changes committed after the `since` height that the user can read, so clients can
+/// incrementally update their local copy of records. Requires the audit log.
+#[tracing::instrument(skip(state, body))]
+#[get("/v0/changes")]
+async fn get_changes(
+    state: web::Data<RouteState>,
+    query: web::Query<ChangesQuery>,
+    body: auth::SignedJSON<()>,
+) -> Result<impl Responder, HTTPError> {
+    if !state.audit_log {
+        return Err(HTTPError::from(AppError::ChangesDisabled));
+    }
+
+    let auth: Option<AuthUser> = body.auth.map(|a| a.into());
+    let limit = min(query.limit.unwrap_or(100), 1000);
+    let changes = state.db.changes(query.since, limit, auth).await?;
+
+    Ok(web::Json(ChangesResponse {
+        changes: changes
+            .changes
+            .into_iter()
+            .map(|entry| Change {
+                height: entry.height,
+                collection_id: entry.collection_id,
+                record_id: entry.record_id,
+                op: entry.op,
+            })
+            .collect(),
+        height: changes.height,
+    }))
+}
+
+#[derive(Deserialize)]
+struct BatchRead {
+    collection: String,
+    id: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchReadResult {
+    Record(GetRecordResponse),
+    Error(ErrorOutput),
+}
+
+#[derive(Serialize)]
+struct BatchResponse {
+    data: Vec<BatchReadResult>,
+}
+
+/// Get multiple records in one request, the results are returned in the same order as the
+/// reads, and a read that fails (e.g. the record doesn't exist) doesn't fail the others.
+/// Batches with more than `max_batch_reads` reads are rejected.
+#[tracing::instrument(skip(state, body))]
+#[post("/v0/batch")]
+async fn batch(
+    state: web::Data<RouteState>,
+    body: auth::SignedJSON<Vec<BatchRead>>,
+) -> Result<impl Responder, HTTPError> {
+    if body.data.len() > state.max_batch_reads {
+        return Err(HTTPError::from(AppError::BatchTooLarge {
+            max: state.max_batch_reads,
+        }));
+    }
+
+    let auth: Option<AuthUser> = body.auth.map(|a| a.into());
+    let ids = body
+        .data
+        .into_iter()
+        .map(|read| (read.collection, read.id))
+        .collect::<Vec<_>>();
+
+    let results = state.db.get_many(&ids, auth).await;
+
+    Ok(HttpResponse::Ok().json(BatchResponse {
+        data: results
+            .into_iter()
+            .map(|result| match result {
+                Ok(Some(record)) => BatchReadResult::Record(GetRecordResponse {
+                    data: record::record_to_json(record),
+                    block: Default::default(),
+                }),
+                Ok(None) => BatchReadResult::Error(
+                    HTTPError::new(ReasonCode::RecordNotFound, None).output(),
+                ),
+                Err(err) => BatchReadResult::Error(HTTPError::from(err).output()),
+            })
+            .collect(),
+    }))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct FunctionCall {
     args: Vec<serde_json::Value>,
@@ -338,10 +665,13 @@ struct FunctionResponse {
 async fn post_record(
     state: web::Data<RouteState>,
     path: web::Path<String>,
+    request_id: RequestId,
     body: auth::SignedJSON<FunctionCall>,
 ) -> Result<web::Json<FunctionResponse>, HTTPError> {
     let collection_id = path.into_inner();
 
+    let replay = reserve_replay(&state, body.auth.as_ref())?;
+
     let auth = body.auth.map(|a| a.into());
     let db: Arc<_> = Arc::clone(&state.db);
 
@@ -362,9 +692,11 @@ async fn post_record(
         "".to_string(),
         body.data.args,
         auth,
-    );
+    )
+    .with_request_id(request_id.0);
 
     let record_id = db.call(txn).await?;
+    replay.accept();
 
     let Some(record) = state.db.get_without_auth_check(&collection_id, &record_id).await? else {
         return Err(HTTPError::new(
@@ -378,15 +710,46 @@ async fn post_record(
     }))
 }
 
+#[derive(Debug, Deserialize)]
+struct CallFunctionQuery {
+    #[serde(rename = "dryRun", default)]
+    dry_run: bool,
+}
+
 #[tracing::instrument(skip(state, body))]
 #[post("/{collection}/records/{record}/call/{function}")]
 async fn call_function(
     state: web::Data<RouteState>,
     path: web::Path<(String, String, String)>,
+    query: web::Query<CallFunctionQuery>,
+    if_match: Option<web::Header<IfMatch>>,
+    request_id: RequestId,
     body: auth::SignedJSON<FunctionCall>,
 ) -> Result<web::Json<FunctionResponse>, HTTPError> {
     let (collection_id, record_id, function) = path.into_inner();
 
+    // Reject the call if the record has changed since the version the client has seen
+    if let Some(if_match) = if_match {
+        let etag = state
+            .db
+            .last_record_update(&collection_id, &record_id)
+            .await?
+            .map(record_etag);
+
+        let matches = match (if_match.into_inner(), etag) {
+            (IfMatch::Any, etag) => etag.is_some(),
+            (IfMatch::Items(tags), Some(etag)) => tags.iter().any(|tag| tag.strong_eq(&etag)),
+            (IfMatch::Items(_), None) => false,
+        };
+
+        if !matches {
+            return Err(HTTPError::from(AppError::RecordModified));
+        }
+    }
+
+    // Dry runs don't change any records, so can be repeated
+    let replay = reserve_replay(&state, body.auth.as_ref().filter(|_| !query.dry_run))?;
+
     let auth = body.auth.map(AuthUser::from);
     let db = Arc::clone(&state.db);
 
@@ -396,9 +759,64 @@ async fn call_function(
         record_id,
         body.data.args,
         auth,
-    );
+    )
+    .with_request_id(request_id.0);
+
+    // Return the output of the call, without submitting the txn
+    if query.dry_run {
+        let record = db.dry_run(txn).await?;
+
+        return Ok(web::Json(FunctionResponse {
+            data: match record {
+                Some(record) => record::record_to_json(record),
+                None => serde_json::Value::Null,
+            },
+        }));
+    }
 
     let record_id = db.call(txn).await?;
+    replay.accept();
+
+    let record = state
+        .db
+        .get_without_auth_check(&collection_id, &record_id)
+        .await?;
+
+    Ok(web::Json(FunctionResponse {
+        data: match record {
+            Some(record) => record::record_to_json(record),
+            None => serde_json::Value::Null,
+        },
+    }))
+}
+
+#[tracing::instrument(skip(state, body))]
+#[post("/{collection}/records/{record}/patch")]
+async fn patch_record(
+    state: web::Data<RouteState>,
+    path: web::Path<(String, String)>,
+    request_id: RequestId,
+    body: auth::SignedJSON<serde_json::Value>,
+) -> Result<web::Json<FunctionResponse>, HTTPError> {
+    let (collection_id, record_id) = path.into_inner();
+
+    let replay = reserve_replay(&state, body.auth.as_ref())?;
+
+    let auth = body.auth.map(AuthUser::from);
+    let db = Arc::clone(&state.db);
+
+    let txn = CallTxn::new(
+        collection_id.clone(),
+        PATCH_FUNCTION_NAME,
+        record_id,
+        vec![body.data],
+        auth,
+    )
+    .with_request_id(request_id.0);
+
+    let record_id = db.call(txn).await?;
+    replay.accept();
+
     let record = state
         .db
         .get_without_auth_check(&collection_id, &record_id)
@@ -433,29 +851,15 @@ struct ProveRequest {
 ))]
 #[post("/v0/prove")]
 async fn prove(req: web::Json<ProveRequest>) -> Result<impl Responder, HTTPError> {
-    let program = compile_program(&req.abi, &req.miden_code).map_err(|e| {
-        HTTPError::new(
-            ReasonCode::Internal,
-            Some(Box::new(AppError::MidenCompile(Box::new(e)))),
-        )
-    })?;
+    let program = compile_program(&req.abi, &req.miden_code)
+        .map_err(|e| HTTPError::from(AppError::MidenCompile(Box::new(e))))?;
 
     let this = req.this.clone().unwrap_or(
         req.abi
             .default_this_value()
-            .map_err(|err| {
-                HTTPError::new(
-                    ReasonCode::Internal,
-                    Some(Box::new(AppError::ABIError(err))),
-                )
-            })?
+            .map_err(|err| HTTPError::from(AppError::ABIError(err)))?
             .try_into()
-            .map_err(|err| {
-                HTTPError::new(
-                    ReasonCode::Internal,
-                    Some(Box::new(AppError::ABIError(Box::new(err)))),
-                )
-            })?,
+            .map_err(|err| HTTPError::from(AppError::ABIError(Box::new(err))))?,
     );
 
     let inputs = Inputs::new(
@@ -466,27 +870,14 @@ async fn prove(req: web::Json<ProveRequest>) -> Result<impl Responder, HTTPError
         req.args.clone(),
         req.other_records.clone(),
     )
-    .map_err(|err| {
-        HTTPError::new(
-            ReasonCode::Internal,
-            Some(Box::new(AppError::ProveError(Box::new(err)))),
-        )
-    })?;
-
-    let output = polylang_prover::prove(&program, &inputs).map_err(|err| {
-        HTTPError::new(
-            ReasonCode::Internal,
-            Some(Box::new(AppError::ProveError(Box::new(err)))),
-        )
-    })?;
+    .map_err(|err| HTTPError::from(AppError::ProveError(Box::new(err))))?;
+
+    let output = polylang_prover::prove(&program, &inputs)
+        .map_err(|err| HTTPError::from(AppError::ProveError(Box::new(err))))?;
 
     let program_info = program.to_program_info_bytes();
-    let new_this = TryInto::<serde_json::Value>::try_into(output.new_this).map_err(|err| {
-        HTTPError::new(
-            ReasonCode::Internal,
-            Some(Box::new(AppError::ProveError(Box::new(err)))),
-        )
-    })?;
+    let new_this = TryInto::<serde_json::Value>::try_into(output.new_this)
+        .map_err(|err| HTTPError::from(AppError::ProveError(Box::new(err))))?;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "old": {
@@ -510,13 +901,22 @@ async fn prove(req: web::Json<ProveRequest>) -> Result<impl Responder, HTTPError
     })))
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HealthResponse {
+    status: String,
+}
+
 #[get("/v0/health")]
-async fn health(state: web::Data<RouteState>) -> impl Responder {
-    if state.db.is_healthy() {
-        HttpResponse::Ok()
-    } else {
-        HttpResponse::ServiceUnavailable()
-    }
+async fn health(state: web::Data<RouteState>) -> Result<HttpResponse, HTTPError> {
+    let (mut response, status) = match state.db.health().await? {
+        HealthStatus::Ok => (HttpResponse::Ok(), "ok"),
+        HealthStatus::Degraded => (HttpResponse::Ok(), "degraded"),
+        HealthStatus::Unhealthy => (HttpResponse::ServiceUnavailable(), "unhealthy"),
+    };
+
+    Ok(response.json(HealthResponse {
+        status: status.to_string(),
+    }))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -525,6 +925,8 @@ struct StatusResponse {
     root: String,
     height: usize,
     peers: usize,
+    state_digest: String,
+    commit_timeouts: usize,
 }
 
 #[tracing::instrument(skip(state))]
@@ -536,48 +938,666 @@ async fn status(state: web::Data<RouteState>) -> Result<web::Json<StatusResponse
         .as_ref()
         .map(|m| m.hash().to_string())
         .unwrap_or("0x0".to_string());
+    let state_digest = state.db.state_digest().await?;
     Ok(web::Json(StatusResponse {
         status: "OK".to_string(),
         root: hash,
         height,
-        peers: 23,
+        peers: state.network.connected_peers().len(),
+        state_digest: state_digest.to_string(),
+        commit_timeouts: state.db.commit_timeouts(),
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PeersResponse {
+    local_peer_id: String,
+    peers: Vec<String>,
+}
+
+#[tracing::instrument(skip(state))]
+#[get("/v0/peers")]
+async fn peers(state: web::Data<RouteState>) -> web::Json<PeersResponse> {
+    let mut peers = state
+        .network
+        .connected_peers()
+        .iter()
+        .map(|peer_id| peer_id.to_string())
+        .collect::<Vec<_>>();
+    peers.sort_unstable();
+
+    web::Json(PeersResponse {
+        local_peer_id: state.network.local_peer_id().to_string(),
+        peers,
+    })
+}
+
+#[tracing::instrument(skip(req, state))]
+#[get("/v0/admin/snapshot")]
+async fn admin_snapshot(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    let db = Arc::clone(&state.db);
+    let chunk_size = state.snapshot_chunk_size;
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+
+    // Stream the snapshot from a separate task, the bounded channel means we only read
+    // the next chunk from the db once the previous chunk has been sent to the client
+    tokio::spawn(async move {
+        let mut snapshot_iter = db.snapshot_iter(chunk_size).await;
+        while let Some(chunk) = snapshot_iter.next().await {
+            let bytes = chunk.and_then(encode_snapshot_chunk);
+            let is_err = bytes.is_err();
+            if tx.send(bytes).await.is_err() || is_err {
+                return;
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header((CONTENT_DISPOSITION, "attachment; filename=\"snapshot.bin\""))
+        .streaming(ReceiverStream::new(rx)))
+}
+
+/// Export a collection's records as JSON lines, one record per line
+#[tracing::instrument(skip(req, state))]
+#[get("/v0/admin/collections/{collection}/export")]
+async fn admin_export(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    let collection_id = path.into_inner();
+
+    // Check the collection exists up front, so a missing collection is a 404
+    // rather than an error part way through the stream
+    state.db.get_schema(&collection_id).await?;
+
+    let db = Arc::clone(&state.db);
+    let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+    // Stream the records from a separate task, as with snapshots the bounded channel
+    // means records are only read as fast as they are sent to the client
+    tokio::spawn(async move {
+        let mut records = db.export(&collection_id);
+        while let Some(record) = records.next().await {
+            let line = record.and_then(|record| {
+                let mut line = serde_json::to_vec(&record::record_to_json(record))?;
+                line.push(b'\n');
+                Ok(web::Bytes::from(line))
+            });
+            let is_err = line.is_err();
+            if tx.send(line).await.is_err() || is_err {
+                return;
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(ReceiverStream::new(rx)))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    /// Abort the import if any line is invalid, rather than skipping invalid lines
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportLineError {
+    /// Line number, starting at 1
+    line: usize,
+    message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportResponse {
+    imported: usize,
+    skipped: usize,
+    errors: Vec<ImportLineError>,
+}
+
+/// Import records into a collection from JSON lines (as produced by the export endpoint),
+/// each record is validated against the collection's schema, and any records it references
+/// must exist or be part of the import. Imports bypass consensus, so they are rejected
+/// while the node is connected to peers.
+#[tracing::instrument(skip(req, state, payload))]
+#[post("/v0/admin/collections/{collection}/import")]
+async fn admin_import(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    path: web::Path<String>,
+    query: web::Query<ImportQuery>,
+    mut payload: web::Payload,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    // Peers would never see the imported records, and the node's state would diverge
+    if !state.network.connected_peers().is_empty() {
+        return Err(HTTPError::from(AppError::NodeNotIdle(
+            "disconnected from all peers to import records",
+        )));
+    }
+
+    let collection_id = path.into_inner();
+
+    // Collections must be created by calling the Collection functions, so their code
+    // is validated
+    if collection_id == "Collection" {
+        return Err(HTTPError::from(AppError::InvalidImport(
+            "records in the Collection collection cannot be imported".to_string(),
+        )));
+    }
+
+    let schema = state.db.get_schema(&collection_id).await?;
+
+    let mut buf = BytesMut::new();
+    while let Some(bytes) = payload.next().await {
+        let bytes =
+            bytes.map_err(|err| HTTPError::new(ReasonCode::Internal, Some(Box::new(err))))?;
+        if buf.len() + bytes.len() > state.max_import_bytes {
+            return Err(HTTPError::from(AppError::InvalidImport(format!(
+                "body is larger than the maximum of {} bytes",
+                state.max_import_bytes
+            ))));
+        }
+        buf.extend_from_slice(&bytes);
+    }
+
+    let body = std::str::from_utf8(&buf)
+        .map_err(|err| HTTPError::from(AppError::InvalidImport(err.to_string())))?;
+
+    let mut changes = vec![];
+    let mut errors = vec![];
+    for (i, line) in body.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let change = serde_json::from_str(line)
+            .map_err(|err| err.to_string())
+            .and_then(|value| {
+                state
+                    .db
+                    .import_change(&collection_id, &schema, value)
+                    .map_err(|err| err.to_string())
+            });
+
+        match change {
+            Ok(change) => changes.push((i + 1, change)),
+            Err(message) if query.strict => {
+                return Err(HTTPError::from(AppError::InvalidImport(format!(
+                    "line {}: {message}",
+                    i + 1
+                ))));
+            }
+            Err(message) => errors.push(ImportLineError {
+                line: i + 1,
+                message,
+            }),
+        }
+    }
+
+    // Records can reference records on any line of the import, so references are checked
+    // once every line is parsed. Skipping a line can leave a reference to it dangling, so
+    // keep checking until no more lines are skipped.
+    loop {
+        let imported = changes
+            .iter()
+            .filter_map(|(_, change)| match change {
+                IndexerChange::Set {
+                    collection_id,
+                    record_id,
+                    ..
+                } => Some((collection_id.clone(), record_id.clone())),
+                IndexerChange::Delete { .. } => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let count = changes.len();
+        let mut valid = Vec::with_capacity(count);
+        for (line, change) in changes {
+            match state
+                .db
+                .missing_import_reference(&change, &imported)
+                .await?
+            {
+                None => valid.push((line, change)),
+                Some((ref_collection_id, ref_record_id)) => {
+                    let message = format!(
+                        "referenced record {ref_collection_id}/{ref_record_id} does not exist"
+                    );
+                    if query.strict {
+                        return Err(HTTPError::from(AppError::InvalidImport(format!(
+                            "line {line}: {message}"
+                        ))));
+                    }
+                    errors.push(ImportLineError { line, message });
+                }
+            }
+        }
+
+        let skipped = valid.len() < count;
+        changes = valid;
+        if !skipped {
+            break;
+        }
+    }
+    errors.sort_by_key(|err| err.line);
+
+    let changes = changes
+        .into_iter()
+        .map(|(_, change)| change)
+        .collect::<Vec<_>>();
+
+    let imported = changes.len();
+    if !changes.is_empty() {
+        // Hold the commit lock while importing, so the import and the state digest it
+        // updates can't be interleaved with a block's commit
+        let _commit_guard = state.db.lock_commits().await;
+
+        // Pending txns were validated against the records before the import, and would
+        // be committed over the imported records
+        if state.db.has_pending_txns() {
+            return Err(HTTPError::from(AppError::NodeNotIdle(
+                "idle, with no pending txns, to import records",
+            )));
+        }
+
+        state.db.import(changes).await?;
+    }
+
+    Ok(web::Json(ImportResponse {
+        imported,
+        skipped: errors.len(),
+        errors,
+    }))
+}
+
+#[tracing::instrument(skip(req, state, payload))]
+#[post("/v0/admin/restore")]
+async fn admin_restore(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    mut payload: web::Payload,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    // Read and verify the whole snapshot before changing the store, so a truncated or
+    // corrupt upload leaves the node as it was. The snapshot is held in memory, so its
+    // size is limited.
+    let mut chunks = vec![];
+    let mut buf = BytesMut::new();
+    let mut received = 0;
+    while let Some(bytes) = payload.next().await {
+        let bytes =
+            bytes.map_err(|err| HTTPError::new(ReasonCode::Internal, Some(Box::new(err))))?;
+        received += bytes.len();
+        if received > state.max_restore_bytes {
+            return Err(HTTPError::from(AppError::InvalidSnapshot(format!(
+                "snapshot is larger than the maximum of {} bytes",
+                state.max_restore_bytes
+            ))));
+        }
+        buf.extend_from_slice(&bytes);
+
+        while let Some(chunk) = decode_snapshot_chunk(&mut buf)? {
+            chunks.push(chunk);
+        }
+    }
+
+    if !buf.is_empty() {
+        return Err(HTTPError::from(AppError::InvalidSnapshot(
+            "unexpected end of snapshot".to_string(),
+        )));
+    }
+
+    if state.db.snapshot_manifest(&chunks)?.is_none() {
+        return Err(HTTPError::from(AppError::InvalidSnapshot(
+            "snapshot has no manifest".to_string(),
+        )));
+    }
+
+    // Hold the commit lock from the idle check until the snapshot is restored, so a
+    // block can't be committed in between
+    let _commit_guard = state.db.lock_commits().await;
+
+    // Only allow a restore if the node is not serving any data
+    if state.db.is_healthy() && !state.db.is_empty().await? {
+        return Err(HTTPError::from(AppError::NodeNotIdle(
+            "empty or unhealthy to restore from a snapshot",
+        )));
+    }
+
+    state.db.reset().await?;
+    for chunk in chunks {
+        state.db.restore_chunk(chunk).await?;
+    }
+
+    // Let the main loop reset the proposal state to the restored manifest
+    state.db.notify_restored();
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(skip(req, state))]
+#[post("/v0/admin/compact")]
+async fn admin_compact(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    state.db.compact().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Rebuild every index of a collection from its records
+#[tracing::instrument(skip(req, state))]
+#[post("/v0/admin/collections/{collection}/rebuild-indexes")]
+async fn admin_rebuild_indexes(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    path: web::Path<String>,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    state.db.rebuild_indexes(&path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DryRunCommitResponse {
+    state_digest: String,
+}
+
+/// Computes the state digest that committing the proposal would produce, without
+/// applying it, so it can be compared with the digest reported by other nodes
+#[tracing::instrument(skip(req, state, manifest))]
+#[post("/v0/admin/dry-run-commit")]
+async fn admin_dry_run_commit(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    manifest: web::Json<ProposalManifest>,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    let state_digest = state.db.dry_run_commit(&manifest).await?;
+
+    Ok(web::Json(DryRunCommitResponse {
+        state_digest: state_digest.to_string(),
     }))
 }
 
-#[tracing::instrument(skip(db))]
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    collection: Option<String>,
+    #[serde(default)]
+    from_height: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntryResponse {
+    height: usize,
+    collection_id: String,
+    record_id: String,
+    op: AuditOp,
+    /// Time the change was committed, in millis since the unix epoch
+    timestamp: u64,
+    /// Paths of the fields added, removed or changed by the change
+    diff: RecordDiff,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditResponse {
+    entries: Vec<AuditEntryResponse>,
+}
+
+#[tracing::instrument(skip(req, state))]
+#[get("/v0/admin/audit")]
+async fn admin_audit(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    query: web::Query<AuditQuery>,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    let limit = min(query.limit.unwrap_or(100), 1000);
+    let entries = state
+        .db
+        .audit_log(query.collection.as_deref(), query.from_height, limit)
+        .await?;
+
+    Ok(web::Json(AuditResponse {
+        entries: entries
+            .into_iter()
+            .map(|entry| AuditEntryResponse {
+                height: entry.height,
+                collection_id: entry.collection_id,
+                record_id: entry.record_id,
+                op: entry.op,
+                timestamp: entry
+                    .timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0),
+                diff: entry.diff,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct UsageQuery {
+    namespace: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageCounts {
+    reads: u64,
+    writes: u64,
+    read_bytes: u64,
+    write_bytes: u64,
+}
+
+impl From<Usage> for UsageCounts {
+    fn from(usage: Usage) -> Self {
+        Self {
+            reads: usage.reads,
+            writes: usage.writes,
+            read_bytes: usage.read_bytes,
+            write_bytes: usage.write_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UsageResponse {
+    total: UsageCounts,
+    collections: HashMap<String, UsageCounts>,
+}
+
+#[tracing::instrument(skip(req, state))]
+#[get("/v0/admin/usage")]
+async fn admin_usage(
+    req: HttpRequest,
+    state: web::Data<RouteState>,
+    query: web::Query<UsageQuery>,
+) -> Result<impl Responder, HTTPError> {
+    verify_admin_key(&req, &state.admin_key)?;
+
+    let usage = state.db.namespace_usage(&query.namespace).await?;
+
+    let mut total = Usage::default();
+    for collection_usage in usage.values() {
+        total.add(collection_usage);
+    }
+
+    Ok(web::Json(UsageResponse {
+        total: total.into(),
+        collections: usage
+            .into_iter()
+            .map(|(collection_id, usage)| (collection_id, usage.into()))
+            .collect(),
+    }))
+}
+
+/// Snapshot chunks are written as a u64 (little endian) length prefix, followed by the
+/// bincode encoded chunk
+fn encode_snapshot_chunk(chunk: Vec<SnapshotValue>) -> crate::db::Result<web::Bytes> {
+    let data = bincode::serialize(&Chunk::new(chunk))?;
+    let mut bytes = Vec::with_capacity(8 + data.len());
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&data);
+    Ok(bytes.into())
+}
+
+/// Decodes the next chunk from the buffer, returns None if the buffer does not
+/// contain a complete chunk yet
+fn decode_snapshot_chunk(buf: &mut BytesMut) -> Result<Option<Vec<SnapshotValue>>, HTTPError> {
+    if buf.len() < 8 {
+        return Ok(None);
+    }
+
+    let mut len_bytes = [0u8; 8];
+    len_bytes.copy_from_slice(&buf[..8]);
+    let len = u64::from_le_bytes(len_bytes) as usize;
+    if buf.len() - 8 < len {
+        return Ok(None);
+    }
+
+    buf.advance(8);
+    let data = buf.split_to(len);
+
+    let chunk: Chunk = bincode::deserialize(&data)
+        .map_err(|err| HTTPError::from(AppError::InvalidSnapshot(err.to_string())))?;
+
+    chunk
+        .into_verified()
+        .map(Some)
+        .map_err(|err| HTTPError::from(AppError::InvalidSnapshot(err.to_string())))
+}
+
+fn verify_admin_key(req: &HttpRequest, admin_key: &Option<String>) -> Result<(), HTTPError> {
+    let Some(admin_key) = admin_key else {
+        return Err(HTTPError::from(AppError::AdminDisabled));
+    };
+
+    let provided_key = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    // Compare in constant time, so the key can't be guessed from response times
+    if !provided_key.is_some_and(|key| bool::from(key.as_bytes().ct_eq(admin_key.as_bytes()))) {
+        return Err(HTTPError::from(AppError::InvalidAdminKey));
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(db, network, admin_key))]
 pub fn create_rpc_server(
     rpc_laddr: String,
     db: ArcDbIndexer,
+    network: Arc<Network>,
     whitelist: Arc<Option<Vec<String>>>,
     restrict_namespaces: Arc<bool>,
+    admin_key: Arc<Option<String>>,
+    snapshot_chunk_size: usize,
+    audit_log: bool,
+    max_batch_reads: usize,
+    max_import_bytes: usize,
+    max_restore_bytes: usize,
+    signature_config: auth::SignatureConfig,
 ) -> Result<Server, std::io::Error> {
+    // Shared by all workers, so a request can't be replayed against a different worker
+    let replay_guard = Arc::new(ReplayGuard::new(
+        signature_config.max_age + signature_config.max_future_skew,
+        replay::DEFAULT_MAX_REQUESTS_PER_KEY,
+    ));
+
     Ok(HttpServer::new(move || {
         let cors = Cors::permissive();
 
         App::new()
             .app_data(web::Data::new(RouteState {
                 db: Arc::clone(&db),
+                network: Arc::clone(&network),
                 whitelist: Arc::clone(&whitelist),
                 restrict_namespaces: Arc::clone(&restrict_namespaces),
+                admin_key: Arc::clone(&admin_key),
+                snapshot_chunk_size,
+                audit_log,
+                max_batch_reads,
+                max_import_bytes,
+                max_restore_bytes,
+                replay_guard: Arc::clone(&replay_guard),
             }))
+            .app_data(signature_config)
             .wrap(SlogMiddleware)
             .wrap(cors)
             .service(root)
             .service(health)
             .service(status)
+            .service(peers)
             .service(prove)
+            .service(admin_snapshot)
+            .service(admin_export)
+            .service(admin_import)
+            .service(admin_restore)
+            .service(admin_compact)
+            .service(admin_rebuild_indexes)
+            .service(admin_audit)
+            .service(admin_usage)
+            .service(admin_dry_run_commit)
+            .service(get_namespace_collections)
+            .service(get_changes)
+            .service(batch)
             .service(
                 web::scope("/v0/collections")
                     .service(get_record)
                     .service(get_records)
+                    .service(get_schema)
+                    .service(get_indexes)
+                    .service(get_index_stats)
                     .service(post_record)
-                    .service(call_function),
+                    .service(call_function)
+                    .service(patch_record),
             )
     })
     .bind(rpc_laddr)?
     .run())
 }
 
+/// Rejects mutations whose signatures have already been used. The signatures are only
+/// marked as used once the returned reservation is accepted.
+fn reserve_replay<'a>(
+    state: &'a RouteState,
+    auth: Option<&auth::Auth>,
+) -> Result<Reservation<'a>, HTTPError> {
+    let requests = auth.map_or(&[][..], |auth| &auth.signed_requests[..]);
+
+    Ok(state.replay_guard.reserve(requests, SystemTime::now())?)
+}
+
 fn validate_new_collection(
     collection_id: &serde_json::Value,
     whitelist: &Option<Vec<String>>,
@@ -592,10 +1612,7 @@ fn validate_new_collection(
     // Check collection whitelist
     if let Some(whitelist) = whitelist {
         if pk.is_empty() || !whitelist.contains(&pk) {
-            return Err(HTTPError::new(
-                ReasonCode::Unauthorized,
-                Some(Box::new(AppError::Whitelist)),
-            ));
+            return Err(HTTPError::from(AppError::Whitelist));
         }
     }
 
@@ -606,37 +1623,25 @@ fn validate_new_collection(
                 let parts: Vec<&str> = id.split('/').collect();
 
                 if pk.is_empty() {
-                    return Err(HTTPError::new(
-                        ReasonCode::Unauthorized,
-                        Some(Box::new(AppError::AnonNamespace)),
-                    ));
+                    return Err(HTTPError::from(AppError::AnonNamespace));
                 }
 
                 if parts.len() <= 2 || parts[0] != "pk" {
-                    return Err(HTTPError::new(
-                        ReasonCode::Unauthorized,
-                        Some(Box::new(AppError::InvalidNamespace(id.clone()))),
-                    ));
+                    return Err(HTTPError::from(AppError::InvalidNamespace(id.clone())));
                 }
 
                 if parts[1] != pk {
-                    return Err(HTTPError::new(
-                        ReasonCode::Unauthorized,
-                        Some(Box::new(AppError::InvalidNamespacePublicKey(
-                            pk,
-                            parts[1].to_string(),
-                        ))),
-                    ));
+                    return Err(HTTPError::from(AppError::InvalidNamespacePublicKey(
+                        pk,
+                        parts[1].to_string(),
+                    )));
                 }
             }
             _ => {
-                return Err(HTTPError::new(
-                    ReasonCode::Unauthorized,
-                    Some(Box::new(AppError::InvalidNamespace(format!(
-                        "{:?}",
-                        collection_id
-                    )))),
-                ));
+                return Err(HTTPError::from(AppError::InvalidNamespace(format!(
+                    "{:?}",
+                    collection_id
+                ))));
             }
         }
     }
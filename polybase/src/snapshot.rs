@@ -0,0 +1,344 @@
+use crate::db::Db;
+use crate::network;
+use futures::{stream::FuturesUnordered, Future, Stream, StreamExt};
+use indexer::adaptor::{IndexerAdaptor, SnapshotValue};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::error;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ChunkError {
+    #[error("snapshot chunk checksum mismatch")]
+    ChecksumMismatch,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendChunksError<E> {
+    #[error("snapshot stream error: {0:?}")]
+    Stream(E),
+
+    #[error("snapshot chunk was not acknowledged: {0}")]
+    Ack(#[from] network::Error),
+}
+
+/// A chunk of snapshot data, with a checksum calculated by the sender so the receiver
+/// can detect corruption before restoring it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    data: Vec<SnapshotValue>,
+    checksum: [u8; 32],
+}
+
+impl Chunk {
+    pub fn new(data: Vec<SnapshotValue>) -> Self {
+        let checksum = checksum(&data);
+        Self { data, checksum }
+    }
+
+    /// Number of key/value pairs in the chunk
+    pub fn value_count(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the chunk data, if it matches the checksum
+    pub fn into_verified(self) -> Result<Vec<SnapshotValue>, ChunkError> {
+        if checksum(&self.data) != self.checksum {
+            return Err(ChunkError::ChecksumMismatch);
+        }
+        Ok(self.data)
+    }
+}
+
+fn checksum(data: &[SnapshotValue]) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    for value in data {
+        // Length prefix each part, so moving bytes between key and value changes the hash
+        hasher.update((value.key.len() as u64).to_be_bytes());
+        hasher.update(&value.key);
+        hasher.update((value.value.len() as u64).to_be_bytes());
+        hasher.update(&value.value);
+    }
+    hasher.finalize().into()
+}
+
+/// Restores a snapshot chunk received from a peer. If the chunk is corrupt or can't be
+/// restored, the partially restored snapshot is discarded and `request_snapshot` is called
+/// to request a new one. Returns false if the snapshot was aborted.
+pub async fn restore_chunk<A, F, Fut>(db: &Db<A>, chunk: Chunk, request_snapshot: F) -> bool
+where
+    A: IndexerAdaptor,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let res = match chunk.into_verified() {
+        Ok(data) => db.restore_chunk(data).await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    let Err(err) = res else {
+        return true;
+    };
+
+    error!(err = %err, "Failed to restore snapshot chunk, aborting snapshot");
+
+    // The next snapshot is restored into an empty db, so a failed reset is only logged
+    if let Err(err) = db.reset().await {
+        error!(err = ?err, "Failed to reset database after aborting snapshot");
+    }
+
+    request_snapshot().await;
+
+    false
+}
+
+/// Controls how quickly snapshot chunks are sent to a peer
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotSendConfig {
+    /// Maximum number of chunks sent to the peer that have not yet been acknowledged
+    pub max_inflight_chunks: usize,
+    /// Delay between sending each chunk
+    pub chunk_delay: Duration,
+    /// Maximum time to wait for the peer to acknowledge each chunk
+    pub ack_timeout: Option<Duration>,
+}
+
+/// Sends every chunk in the stream using `send`, waiting for acknowledgements so that
+/// no more than `max_inflight_chunks` are outstanding at any time. Returns once all
+/// sent chunks have been acknowledged, or with the first error returned by the stream
+/// or the first chunk that is not acknowledged within `ack_timeout`.
+pub async fn send_chunks<T, E, S, F, Fut>(
+    chunks: S,
+    config: SnapshotSendConfig,
+    mut send: F,
+) -> Result<(), SendChunksError<E>>
+where
+    S: Stream<Item = Result<T, E>>,
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = Option<oneshot::Receiver<()>>>,
+{
+    let max_inflight_chunks = config.max_inflight_chunks.max(1);
+    let mut inflight = FuturesUnordered::new();
+    futures::pin_mut!(chunks);
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(SendChunksError::Stream)?;
+
+        // Wait for the peer to acknowledge chunks until there is space in the window
+        while inflight.len() >= max_inflight_chunks {
+            if let Some(ack) = inflight.next().await {
+                ack?;
+            }
+        }
+
+        if let Some(ack) = send(chunk).await {
+            inflight.push(network::wait_for_ack(ack, config.ack_timeout));
+        }
+
+        if !config.chunk_delay.is_zero() {
+            tokio::time::sleep(config.chunk_delay).await;
+        }
+    }
+
+    // Wait for the remaining chunks to be acknowledged
+    while let Some(ack) = inflight.next().await {
+        ack?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_send_chunks_respects_inflight_cap() {
+        let inflight = Arc::new(AtomicUsize::new(0));
+        let max_seen = Arc::new(AtomicUsize::new(0));
+        let (ack_tx, mut ack_rx) = mpsc::unbounded_channel::<(usize, oneshot::Sender<()>)>();
+
+        // Slow receiver, acknowledges each chunk after a delay
+        let receiver_inflight = Arc::clone(&inflight);
+        let receiver = tokio::spawn(async move {
+            let mut received = vec![];
+            while let Some((chunk, ack)) = ack_rx.recv().await {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                received.push(chunk);
+                receiver_inflight.fetch_sub(1, Ordering::SeqCst);
+                ack.send(()).ok();
+            }
+            received
+        });
+
+        let chunks = futures::stream::iter((0..10).map(Ok::<_, ()>));
+        let config = SnapshotSendConfig {
+            max_inflight_chunks: 3,
+            chunk_delay: Duration::ZERO,
+            ack_timeout: None,
+        };
+
+        send_chunks(chunks, config, |chunk| {
+            let inflight = Arc::clone(&inflight);
+            let max_seen = Arc::clone(&max_seen);
+            let ack_tx = ack_tx.clone();
+            async move {
+                let count = inflight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_seen.fetch_max(count, Ordering::SeqCst);
+                let (tx, rx) = oneshot::channel();
+                ack_tx.send((chunk, tx)).ok();
+                Some(rx)
+            }
+        })
+        .await
+        .unwrap();
+
+        drop(ack_tx);
+        let received = receiver.await.unwrap();
+
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+        assert_eq!(inflight.load(Ordering::SeqCst), 0);
+        assert_eq!(max_seen.load(Ordering::SeqCst), 3);
+    }
+
+    fn snapshot_values() -> Vec<SnapshotValue> {
+        vec![
+            SnapshotValue {
+                key: b"key1".to_vec().into_boxed_slice(),
+                value: b"value1".to_vec().into_boxed_slice(),
+            },
+            SnapshotValue {
+                key: b"key2".to_vec().into_boxed_slice(),
+                value: b"value2".to_vec().into_boxed_slice(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_chunk_checksum_verifies() {
+        let chunk = Chunk::new(snapshot_values());
+        let bytes = serde_json::to_vec(&chunk).unwrap();
+        let chunk: Chunk = serde_json::from_slice(&bytes).unwrap();
+
+        let data = chunk.into_verified().unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(&*data[1].value, b"value2");
+    }
+
+    #[test]
+    fn test_chunk_with_flipped_byte_is_rejected() {
+        let mut chunk = Chunk::new(snapshot_values());
+
+        // Corrupt a single byte of the transferred data
+        chunk.data[1].value[0] ^= 0x01;
+
+        assert_eq!(
+            chunk.into_verified().unwrap_err(),
+            ChunkError::ChecksumMismatch
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_corrupt_chunk_aborts_and_requests_snapshot() {
+        let db = Db::new(
+            indexer::Indexer::new(indexer::memory::MemoryStore::new()),
+            crate::db::DbConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        // Partially restored data, which should be discarded
+        let call_txn = crate::txn::CallTxn::new(
+            "Collection".to_string(),
+            "constructor",
+            String::new(),
+            vec![
+                serde_json::json!("test/Account"),
+                serde_json::json!("@public collection Account { id: string; constructor (id: string) { this.id = id; } }"),
+            ],
+            None,
+        );
+        db.commit(solid::proposal::ProposalManifest {
+            height: 1,
+            txns: vec![solid::txn::Txn {
+                id: call_txn.hash().unwrap().to_vec(),
+                data: call_txn.serialize().unwrap(),
+            }],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(!db.is_empty().await.unwrap());
+
+        let mut chunk = Chunk::new(snapshot_values());
+        chunk.data[0].key[0] ^= 0x01;
+
+        let mut requested = false;
+        let restored = restore_chunk(&db, chunk, || {
+            requested = true;
+            async {}
+        })
+        .await;
+
+        assert!(!restored);
+        assert!(requested);
+        assert!(db.is_empty().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_send_chunks_returns_stream_error() {
+        let chunks = futures::stream::iter(vec![Ok(1), Err("failed"), Ok(2)]);
+        let config = SnapshotSendConfig {
+            max_inflight_chunks: 1,
+            chunk_delay: Duration::ZERO,
+            ack_timeout: None,
+        };
+
+        let mut sent = vec![];
+        let res = send_chunks(chunks, config, |chunk| {
+            sent.push(chunk);
+            async { None }
+        })
+        .await;
+
+        assert!(matches!(res, Err(SendChunksError::Stream("failed"))));
+        assert_eq!(sent, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_send_chunks_times_out_when_peer_never_acks() {
+        let chunks = futures::stream::iter((0..3).map(Ok::<_, ()>));
+        let config = SnapshotSendConfig {
+            max_inflight_chunks: 2,
+            chunk_delay: Duration::ZERO,
+            ack_timeout: Some(Duration::from_millis(50)),
+        };
+
+        // Keep the ack senders alive, so the acks never resolve
+        let mut acks = vec![];
+        let res = tokio::time::timeout(
+            Duration::from_secs(5),
+            send_chunks(chunks, config, |_| {
+                let (tx, rx) = oneshot::channel();
+                acks.push(tx);
+                async { Some(rx) }
+            }),
+        )
+        .await
+        // Fails rather than hangs if the timeout is not applied
+        .unwrap();
+
+        assert!(matches!(
+            res,
+            Err(SendChunksError::Ack(network::Error::Timeout))
+        ));
+        // Stopped sending once the first chunk timed out
+        assert_eq!(acks.len(), 2);
+    }
+}
@@ -19,6 +19,10 @@ pub struct CallTxn {
     pub record_id: String,
     pub args: Vec<serde_json::Value>,
     pub auth: Option<AuthUser>,
+    /// Id of the RPC request that submitted the txn, used to correlate logs. It's local to
+    /// the node that received the request, so it's not part of the txn data or hash.
+    #[serde(skip)]
+    pub request_id: Option<String>,
 }
 
 impl CallTxn {
@@ -35,9 +39,15 @@ impl CallTxn {
             record_id,
             args,
             auth,
+            request_id: None,
         }
     }
 
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
     pub fn hash(&self) -> Result<[u8; 32]> {
         let bytes = self.serialize()?;
         let mut hasher = Sha3_256::new();
@@ -0,0 +1,895 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::{Collection, Error, ErrorData, ListQuery, Server, ServerConfig, PORT_POOL};
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Account {
+    id: String,
+    name: String,
+}
+
+const ADMIN_KEY: &str = "admin-secret";
+
+#[tokio::test]
+async fn snapshot_and_restore() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let config = || {
+        Some(ServerConfig {
+            admin_key: Some(ADMIN_KEY.to_string()),
+            ..Default::default()
+        })
+    };
+
+    let server = Server::setup_and_wait(config()).await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    collection.create(json!(["1", "John"]), None).await.unwrap();
+    collection.create(json!(["2", "Jane"]), None).await.unwrap();
+
+    let snapshot = server.admin_snapshot(ADMIN_KEY).await.unwrap();
+    assert!(!snapshot.is_empty());
+
+    let fresh_server = Server::setup_and_wait(config()).await;
+    fresh_server
+        .admin_restore(ADMIN_KEY, snapshot)
+        .await
+        .unwrap();
+
+    let fresh_collection = fresh_server.collection::<Account>("test/Account");
+    let records = fresh_collection
+        .list(ListQuery::default(), None)
+        .await
+        .unwrap()
+        .into_record_data();
+
+    assert_eq!(
+        records,
+        vec![
+            Account {
+                id: "1".to_string(),
+                name: "John".to_string(),
+            },
+            Account {
+                id: "2".to_string(),
+                name: "Jane".to_string(),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn export_collection() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let config = || {
+        Some(ServerConfig {
+            admin_key: Some(ADMIN_KEY.to_string()),
+            ..Default::default()
+        })
+    };
+
+    let server = Server::setup_and_wait(config()).await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    collection.create(json!(["1", "John"]), None).await.unwrap();
+    collection.create(json!(["2", "Jane"]), None).await.unwrap();
+
+    let export = server
+        .admin_export(ADMIN_KEY, "test/Account")
+        .await
+        .unwrap();
+    let parse = |export: &str| {
+        let mut accounts = export
+            .lines()
+            .map(|line| serde_json::from_str::<Account>(line).unwrap())
+            .collect::<Vec<_>>();
+        accounts.sort_by(|a, b| a.id.cmp(&b.id));
+        accounts
+    };
+    let accounts = parse(&export);
+    assert_eq!(
+        accounts,
+        vec![
+            Account {
+                id: "1".to_string(),
+                name: "John".to_string(),
+            },
+            Account {
+                id: "2".to_string(),
+                name: "Jane".to_string(),
+            },
+        ]
+    );
+
+    // Import the export into a fresh node
+    let fresh_server = Server::setup_and_wait(config()).await;
+    fresh_server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+    let res = fresh_server
+        .admin_import(ADMIN_KEY, "test/Account", &export, true)
+        .await
+        .unwrap();
+    assert_eq!((res.imported, res.skipped), (2, 0));
+
+    let fresh_export = fresh_server
+        .admin_export(ADMIN_KEY, "test/Account")
+        .await
+        .unwrap();
+    assert_eq!(parse(&fresh_export), accounts);
+
+    assert_eq!(
+        server
+            .admin_export("wrong-key", "test/Account")
+            .await
+            .unwrap_err()
+            .error
+            .code,
+        "permission-denied"
+    );
+
+    assert_eq!(
+        server
+            .admin_export(ADMIN_KEY, "test/Missing")
+            .await
+            .unwrap_err()
+            .error
+            .reason,
+        "collection/not-found"
+    );
+}
+
+async fn list_sorted(collection: &Collection<Account>) -> Vec<Account> {
+    let mut accounts = collection
+        .list(ListQuery::default(), None)
+        .await
+        .unwrap()
+        .into_record_data();
+    accounts.sort_by(|a, b| a.id.cmp(&b.id));
+    accounts
+}
+
+#[tokio::test]
+async fn import_collection() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    let account = |id: &str, name: &str| Account {
+        id: id.to_string(),
+        name: name.to_string(),
+    };
+
+    let clean = "{\"id\":\"1\",\"name\":\"John\"}\n{\"id\":\"2\",\"name\":\"Jane\"}\n";
+    let res = server
+        .admin_import(ADMIN_KEY, "test/Account", clean, true)
+        .await
+        .unwrap();
+    assert_eq!((res.imported, res.skipped), (2, 0));
+    assert_eq!(
+        list_sorted(&collection).await,
+        vec![account("1", "John"), account("2", "Jane")]
+    );
+
+    // The second line is missing the required name field
+    let malformed = "{\"id\":\"3\",\"name\":\"Jim\"}\n{\"id\":\"4\"}\n";
+
+    let err = server
+        .admin_import(ADMIN_KEY, "test/Account", malformed, true)
+        .await
+        .unwrap_err();
+    assert_eq!(err.error.reason, "admin/invalid-import");
+    assert!(err.error.message.contains("line 2"));
+    assert_eq!(list_sorted(&collection).await.len(), 2);
+
+    let res = server
+        .admin_import(ADMIN_KEY, "test/Account", malformed, false)
+        .await
+        .unwrap();
+    assert_eq!((res.imported, res.skipped), (1, 1));
+    assert_eq!(res.errors[0].line, 2);
+    assert_eq!(
+        list_sorted(&collection).await,
+        vec![
+            account("1", "John"),
+            account("2", "Jane"),
+            account("3", "Jim")
+        ]
+    );
+
+    assert_eq!(
+        server
+            .admin_import("wrong-key", "test/Account", clean, false)
+            .await
+            .unwrap_err()
+            .error
+            .code,
+        "permission-denied"
+    );
+}
+
+#[tokio::test]
+async fn import_checks_references() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    user?: User;
+    friend?: Account;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+}
+
+@public
+collection User {
+    id: string;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+}
+    "#;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct User {
+        id: String,
+    }
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    let users = server
+        .create_collection::<User>("test/User", schema, None)
+        .await
+        .unwrap();
+    server
+        .create_collection::<User>("test/Account", schema, None)
+        .await
+        .unwrap();
+    users.create(json!(["1"]), None).await.unwrap();
+
+    // Line 1 references an existing user and a later line, line 2 references a missing
+    // user, and line 3 references line 2
+    let jsonl = [
+        json!({"id": "a", "user": {"collectionId": "test/User", "id": "1"}, "friend": {"id": "d"}}),
+        json!({"id": "b", "user": {"collectionId": "test/User", "id": "2"}}),
+        json!({"id": "c", "friend": {"id": "b"}}),
+        json!({"id": "d"}),
+    ]
+    .iter()
+    .map(|line| format!("{line}\n"))
+    .collect::<String>();
+
+    let err = server
+        .admin_import(ADMIN_KEY, "test/Account", &jsonl, true)
+        .await
+        .unwrap_err();
+    assert_eq!(err.error.reason, "admin/invalid-import");
+    assert!(err.error.message.contains("line 2"));
+
+    let res = server
+        .admin_import(ADMIN_KEY, "test/Account", &jsonl, false)
+        .await
+        .unwrap();
+    assert_eq!((res.imported, res.skipped), (2, 2));
+    assert_eq!(
+        res.errors.iter().map(|err| err.line).collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+
+    let export = server
+        .admin_export(ADMIN_KEY, "test/Account")
+        .await
+        .unwrap();
+    let mut ids = export
+        .lines()
+        .map(|line| serde_json::from_str::<User>(line).unwrap().id)
+        .collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids, vec!["a", "d"]);
+}
+
+#[tokio::test]
+async fn import_rejects_large_body() {
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        max_import_bytes: Some(16),
+        ..Default::default()
+    }))
+    .await;
+
+    server
+        .create_collection_untyped(
+            "test/Account",
+            "@public collection Account { id: string; name: string; }",
+            None,
+        )
+        .await
+        .unwrap();
+
+    let err = server
+        .admin_import(
+            ADMIN_KEY,
+            "test/Account",
+            "{\"id\":\"1\",\"name\":\"John\"}\n",
+            false,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.error.reason, "admin/invalid-import");
+    assert!(err.error.message.contains("maximum of 16 bytes"));
+}
+
+#[tokio::test]
+async fn import_rejected_with_peers() {
+    let network_port = PORT_POOL.lock().unwrap().get();
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        network_laddr: Some(format!("/ip4/127.0.0.1/tcp/{network_port}")),
+        ..Default::default()
+    }))
+    .await;
+
+    let peer = Server::setup_and_wait(Some(ServerConfig {
+        dial_addr: Some(format!("/ip4/127.0.0.1/tcp/{network_port}")),
+        ..Default::default()
+    }))
+    .await;
+
+    // Wait for the connection to be established
+    let mut retries = 0;
+    while server.peers().await.peers.is_empty() && retries < 50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        retries += 1;
+    }
+
+    let err = server
+        .admin_import(ADMIN_KEY, "test/Account", "{\"id\":\"1\"}\n", false)
+        .await
+        .unwrap_err();
+    assert_eq!(err.error.reason, "admin/node-not-idle");
+
+    drop(peer);
+    PORT_POOL.lock().unwrap().release(network_port);
+}
+
+#[tokio::test]
+async fn snapshot_invalid_admin_key() {
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    assert_eq!(
+        server.admin_snapshot("wrong-key").await.unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "permission-denied".to_string(),
+                reason: "unauthorized".to_string(),
+                message: "invalid admin key".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn restore_corrupt_snapshot() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let config = || {
+        Some(ServerConfig {
+            admin_key: Some(ADMIN_KEY.to_string()),
+            ..Default::default()
+        })
+    };
+
+    let server = Server::setup_and_wait(config()).await;
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+    collection.create(json!(["1", "John"]), None).await.unwrap();
+
+    let snapshot = server.admin_snapshot(ADMIN_KEY).await.unwrap();
+    let fresh_server = Server::setup_and_wait(config()).await;
+
+    // Flip a byte of the last chunk's checksum
+    let mut corrupt = snapshot.clone();
+    *corrupt.last_mut().unwrap() ^= 0x01;
+    assert_eq!(
+        fresh_server
+            .admin_restore(ADMIN_KEY, corrupt)
+            .await
+            .unwrap_err()
+            .error
+            .reason,
+        "admin/invalid-snapshot"
+    );
+
+    // Snapshots larger than the limit are rejected
+    let limited_server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        max_restore_bytes: Some(snapshot.len() - 1),
+        ..Default::default()
+    }))
+    .await;
+    let err = limited_server
+        .admin_restore(ADMIN_KEY, snapshot.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.error.reason, "admin/invalid-snapshot");
+    assert!(err.error.message.contains("larger than the maximum"));
+
+    // Truncated uploads are rejected without changing the store
+    let truncated = snapshot[..snapshot.len() - 1].to_vec();
+    assert_eq!(
+        fresh_server
+            .admin_restore(ADMIN_KEY, truncated)
+            .await
+            .unwrap_err()
+            .error
+            .reason,
+        "admin/invalid-snapshot"
+    );
+
+    fresh_server
+        .admin_restore(ADMIN_KEY, snapshot)
+        .await
+        .unwrap();
+    assert_eq!(
+        fresh_server
+            .collection::<Account>("test/Account")
+            .get("1", None)
+            .await
+            .unwrap(),
+        Account {
+            id: "1".to_string(),
+            name: "John".to_string(),
+        }
+    );
+}
+
+#[tokio::test]
+async fn restore_node_not_idle() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    let snapshot = server.admin_snapshot(ADMIN_KEY).await.unwrap();
+
+    assert_eq!(
+        server.admin_restore(ADMIN_KEY, snapshot).await.unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "failed-precondition".to_string(),
+                reason: "admin/node-not-idle".to_string(),
+                message: "node must be empty or unhealthy to restore from a snapshot".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn compact() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+
+    del () {
+        selfdestruct();
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    for i in 0..20 {
+        collection
+            .create(json!([i.to_string(), format!("Name {i}")]), None)
+            .await
+            .unwrap();
+    }
+
+    for i in 1..20 {
+        collection
+            .call(&i.to_string(), "del", json!([]), None)
+            .await
+            .unwrap();
+    }
+
+    server.admin_compact(ADMIN_KEY).await.unwrap();
+
+    let records = collection
+        .list(ListQuery::default(), None)
+        .await
+        .unwrap()
+        .into_record_data();
+
+    assert_eq!(
+        records,
+        vec![Account {
+            id: "0".to_string(),
+            name: "Name 0".to_string(),
+        }]
+    );
+
+    assert_eq!(
+        server.admin_compact("wrong-key").await.unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "permission-denied".to_string(),
+                reason: "unauthorized".to_string(),
+                message: "invalid admin key".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn rebuild_indexes() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    for i in 0..3 {
+        collection
+            .create(json!([i.to_string(), format!("Name {i}")]), None)
+            .await
+            .unwrap();
+    }
+
+    server
+        .admin_rebuild_indexes("test/Account", ADMIN_KEY)
+        .await
+        .unwrap();
+
+    let records = collection
+        .list(
+            ListQuery {
+                sort: Some(json!([["name", "desc"]])),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .unwrap()
+        .into_record_data();
+
+    assert_eq!(
+        records.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+        vec!["2", "1", "0"]
+    );
+
+    assert_eq!(
+        server
+            .admin_rebuild_indexes("test/Missing", ADMIN_KEY)
+            .await
+            .unwrap_err()
+            .error
+            .reason,
+        "collection/not-found"
+    );
+
+    assert_eq!(
+        server
+            .admin_rebuild_indexes("test/Account", "wrong-key")
+            .await
+            .unwrap_err()
+            .error
+            .reason,
+        "unauthorized"
+    );
+}
+
+#[tokio::test]
+async fn audit_log() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+
+    del () {
+        selfdestruct();
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        audit_log: true,
+        ..Default::default()
+    }))
+    .await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    collection.create(json!(["1", "John"]), None).await.unwrap();
+    collection.call("1", "del", json!([]), None).await.unwrap();
+
+    let audit = server
+        .admin_audit(ADMIN_KEY, Some("test/Account"), 0)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        audit
+            .entries
+            .iter()
+            .map(|e| (e.record_id.as_str(), e.op.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("1", "set"), ("1", "delete")]
+    );
+    let (created, deleted) = (&audit.entries[0], &audit.entries[1]);
+    assert!(created.height < deleted.height);
+    assert_eq!(created.diff.added, vec!["id", "name"]);
+    assert_eq!(deleted.diff.removed, vec!["id", "name"]);
+    assert!(deleted.diff.added.is_empty() && deleted.diff.changed.is_empty());
+
+    // The collection was created at an earlier height
+    let all = server.admin_audit(ADMIN_KEY, None, 0).await.unwrap();
+    assert_eq!(all.entries.len(), 3);
+    assert_eq!(all.entries[0].collection_id, "Collection");
+    assert!(all.entries[0].height < created.height);
+
+    // Only entries from the given height
+    let from_delete = server
+        .admin_audit(ADMIN_KEY, None, deleted.height)
+        .await
+        .unwrap();
+    assert_eq!(from_delete.entries.len(), 1);
+    assert_eq!(from_delete.entries[0].op, "delete");
+
+    assert_eq!(
+        server
+            .admin_audit("wrong-key", None, 0)
+            .await
+            .unwrap_err()
+            .error
+            .code,
+        "permission-denied"
+    );
+}
+
+#[tokio::test]
+async fn usage() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        usage_metering: true,
+        ..Default::default()
+    }))
+    .await;
+
+    let account = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+    let other = server
+        .create_collection::<Account>("other/Account", schema, None)
+        .await
+        .unwrap();
+
+    account.create(json!(["1", "John"]), None).await.unwrap();
+    account.create(json!(["2", "Jane"]), None).await.unwrap();
+    other.create(json!(["1", "John"]), None).await.unwrap();
+
+    let usage = server.admin_usage(ADMIN_KEY, "test").await.unwrap();
+    let account_usage = &usage.collections["test/Account"];
+    assert_eq!(usage.collections.len(), 1);
+    assert_eq!(account_usage.writes, 2);
+    assert!(account_usage.write_bytes > 0);
+
+    account.get("1", None).await.unwrap();
+    account.list(ListQuery::default(), None).await.unwrap();
+
+    let usage = server.admin_usage(ADMIN_KEY, "test").await.unwrap();
+    let account_usage = &usage.collections["test/Account"];
+    assert_eq!(account_usage.writes, 2);
+    assert!(account_usage.reads >= 3);
+    assert_eq!(usage.total, *account_usage);
+
+    assert_eq!(
+        server
+            .admin_usage("wrong-key", "test")
+            .await
+            .unwrap_err()
+            .error
+            .code,
+        "permission-denied"
+    );
+}
+
+#[tokio::test]
+async fn index_stats() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        admin_key: Some(ADMIN_KEY.to_string()),
+        ..Default::default()
+    }))
+    .await;
+
+    let account = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+    account.create(json!(["1", "John"]), None).await.unwrap();
+    account.list(ListQuery::default(), None).await.unwrap();
+
+    let stats = server
+        .admin_index_stats("test/Account", ADMIN_KEY)
+        .await
+        .unwrap();
+    assert!(stats.data.iter().map(|entry| entry.hits).sum::<u64>() >= 1);
+
+    assert_eq!(
+        server
+            .admin_index_stats("test/Account", "wrong-key")
+            .await
+            .unwrap_err()
+            .error
+            .code,
+        "permission-denied"
+    );
+}
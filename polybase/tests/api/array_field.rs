@@ -1,6 +1,6 @@
 use serde_json::json;
 
-use crate::api::{ForeignRecordReference, Server};
+use crate::api::{ForeignRecordReference, ListQuery, Server};
 
 #[tokio::test]
 async fn collection_array_field() {
@@ -269,3 +269,91 @@ collection PublicKeyArrayDemo {
         }
     );
 }
+
+#[tokio::test]
+async fn collection_array_field_contains_index() {
+    let server = Server::setup_and_wait(None).await;
+
+    let schema = r#"
+@public
+collection Post {
+    id: string;
+    tags: string[];
+
+    @index(tags);
+
+    constructor (id: string, tags: string[]) {
+        this.id = id;
+        this.tags = tags;
+    }
+
+    setTags(tags: string[]) {
+        this.tags = tags;
+    }
+}
+    "#;
+
+    #[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Post {
+        id: String,
+        tags: Vec<String>,
+    }
+
+    let collection = server
+        .create_collection::<Post>("test/Post", schema, None)
+        .await
+        .unwrap();
+
+    let post1 = collection
+        .create(json!(["post1", ["rust", "db"]]), None)
+        .await
+        .unwrap();
+    let post2 = collection
+        .create(json!(["post2", ["rust", "js"]]), None)
+        .await
+        .unwrap();
+    let post3 = collection
+        .create(json!(["post3", ["js"]]), None)
+        .await
+        .unwrap();
+
+    let list_contains = |tag: &'static str| {
+        let collection = &collection;
+        async move {
+            collection
+                .list(
+                    ListQuery {
+                        where_query: Some(json!({ "tags": { "$contains": tag } })),
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await
+                .unwrap()
+                .into_record_data()
+        }
+    };
+
+    assert_eq!(
+        list_contains("rust").await,
+        vec![post1.clone(), post2.clone()]
+    );
+    assert_eq!(
+        list_contains("js").await,
+        vec![post2.clone(), post3.clone()]
+    );
+    assert_eq!(list_contains("db").await, vec![post1.clone()]);
+    assert_eq!(list_contains("go").await, vec![]);
+
+    // Updating the array updates the index entries
+    let post1 = collection
+        .call("post1", "setTags", json!([["go"]]), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(list_contains("rust").await, vec![post2.clone()]);
+    assert_eq!(list_contains("db").await, vec![]);
+    assert_eq!(list_contains("go").await, vec![post1]);
+}
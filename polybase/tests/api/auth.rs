@@ -1,4 +1,4 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -54,6 +54,158 @@ async fn get_collection_collection_records_with_invalid_signature_different_publ
     );
 }
 
+#[tokio::test]
+async fn signature_timestamp_must_be_recent() {
+    let server = Server::setup_and_wait(None).await;
+
+    let (private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let signed_at = |offset: fn(SystemTime) -> SystemTime| {
+        Signer::from(move |body: &str| {
+            Signature::create(&private_key, offset(SystemTime::now()), body)
+        })
+    };
+
+    // Fresh signature
+    let fresh = signed_at(|now| now);
+    assert!(server
+        .list_records::<serde_json::Value>("Collection", ListQuery::default(), Some(&fresh))
+        .await
+        .is_ok());
+
+    // Signed longer ago than the default max age of 5 minutes
+    let expired = signed_at(|now| now - Duration::from_secs(10 * 60));
+    assert_eq!(
+        server
+            .list_records::<serde_json::Value>("Collection", ListQuery::default(), Some(&expired))
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "unauthenticated".to_string(),
+                message: "signature expired".to_string(),
+                reason: "auth/expired-signature".to_string(),
+            }
+        }
+    );
+
+    // Signed too far in the future to be clock skew
+    let future = signed_at(|now| now + Duration::from_secs(60 * 60));
+    assert_eq!(
+        server
+            .list_records::<serde_json::Value>("Collection", ListQuery::default(), Some(&future))
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "unauthenticated".to_string(),
+                message: "signature timestamp is in the future".to_string(),
+                reason: "auth/expired-signature".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn replayed_signed_create_is_rejected() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped(
+            "test/Counter",
+            r#"
+@public
+collection Counter {
+    id: string;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+}
+            "#,
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Signing with a fixed time gives the same signature for the same body, i.e. a
+    // captured request
+    let (private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let signed_at = SystemTime::now();
+    let signer = Signer::from(move |body: &str| Signature::create(&private_key, signed_at, body));
+
+    assert_eq!(
+        collection
+            .create(json!(["id1"]), Some(&signer))
+            .await
+            .unwrap(),
+        json!({ "id": "id1" })
+    );
+
+    assert_eq!(
+        collection
+            .create(json!(["id1"]), Some(&signer))
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "unauthenticated".to_string(),
+                message: "signature has already been used".to_string(),
+                reason: "auth/replay".to_string(),
+            }
+        }
+    );
+
+    // A new signature for the same request is accepted
+    let signer =
+        Signer::from(move |body: &str| Signature::create(&private_key, SystemTime::now(), body));
+    assert!(collection
+        .create(json!(["id2"]), Some(&signer))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn failed_signed_create_can_be_retried() {
+    let server = Server::setup_and_wait(None).await;
+
+    let (private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let signed_at = SystemTime::now();
+    let signer = Signer::from(move |body: &str| Signature::create(&private_key, signed_at, body));
+
+    // The collection doesn't exist yet, so the create fails without using the signature
+    let collection = server.collection_untyped("test/Counter");
+    assert!(collection
+        .create(json!(["id1"]), Some(&signer))
+        .await
+        .is_err());
+
+    server
+        .create_collection_untyped(
+            "test/Counter",
+            r#"
+@public
+collection Counter {
+    id: string;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+}
+            "#,
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        collection
+            .create(json!(["id1"]), Some(&signer))
+            .await
+            .unwrap(),
+        json!({ "id": "id1" })
+    );
+}
+
 #[tokio::test]
 async fn public_key_is_optional() {
     let server = Server::setup_and_wait(None).await;
@@ -1305,3 +1457,230 @@ collection Org {
         Some(org),
     );
 }
+
+#[tokio::test]
+async fn read_auth_multiple_keys() {
+    let server = Server::setup_and_wait(None).await;
+
+    let schema = r#"
+collection Account {
+    id: string;
+    @read
+    owner: PublicKey;
+
+    constructor (id: string) {
+        this.id = id;
+        this.owner = ctx.publicKey;
+    }
+}
+    "#;
+
+    let (root_private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let root_signer = Signer::from(move |body: &str| {
+        Signature::create(&root_private_key, SystemTime::now(), body)
+    });
+
+    let (session_private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let session_signer = Signer::from(move |body: &str| {
+        Signature::create(&session_private_key, SystemTime::now(), body)
+    });
+
+    let collection = server
+        .create_collection_untyped("test/Account", schema, Some(&root_signer))
+        .await
+        .unwrap();
+
+    collection
+        .create(json!(["id1"]), Some(&root_signer))
+        .await
+        .unwrap();
+
+    let url = server
+        .base_url
+        .join("/v0/collections/test%2FAccount/records/id1")
+        .unwrap();
+
+    // The session key alone cannot read the record
+    let res = server
+        .client
+        .get(url.clone())
+        .header("X-Polybase-Signature", session_signer("").to_header())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Presenting the root key alongside the session key authorises the read
+    let res = server
+        .client
+        .get(url)
+        .header("X-Polybase-Signature", session_signer("").to_header())
+        .header("X-Polybase-Signature", root_signer("").to_header())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["data"]["id"], json!("id1"));
+}
+
+#[tokio::test]
+async fn write_auth() {
+    let server = Server::setup_and_wait(None).await;
+
+    let schema = r#"
+@read
+@call
+collection Account {
+    id: string;
+    balance: number;
+    @write
+    owner: PublicKey;
+
+    constructor (id: string, balance: number) {
+        this.id = id;
+        this.balance = balance;
+
+        if (ctx.publicKey) {
+            this.owner = ctx.publicKey;
+        } else {
+            error('no public key');
+        }
+    }
+
+    setBalance (balance: number) {
+        this.balance = balance;
+    }
+
+    take (from: Account, amount: number) {
+        from.balance -= amount;
+        this.balance += amount;
+    }
+}
+    "#;
+
+    #[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Account {
+        id: String,
+        balance: f64,
+        owner: schema::publickey::PublicKey,
+    }
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    let (owner_private_key, owner_public_key) =
+        secp256k1::generate_keypair(&mut rand::thread_rng());
+    let owner_public_key =
+        schema::publickey::PublicKey::from_secp256k1_key(&owner_public_key).unwrap();
+    let owner_signer = Signer::from(move |body: &str| {
+        Signature::create(&owner_private_key, SystemTime::now(), body)
+    });
+
+    let (other_private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let other_signer = Signer::from(move |body: &str| {
+        Signature::create(&other_private_key, SystemTime::now(), body)
+    });
+
+    let account_id1_10 = collection
+        .create(json!(["id1", 10]), Some(&owner_signer))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        account_id1_10,
+        Account {
+            id: "id1".to_string(),
+            balance: 10.0,
+            owner: owner_public_key.clone(),
+        }
+    );
+
+    // Anyone can read the record
+    assert_eq!(
+        collection.get("id1", Some(&other_signer)).await.unwrap(),
+        account_id1_10
+    );
+    assert_eq!(collection.get("id1", None).await.unwrap(), account_id1_10);
+
+    // Non-owner can call, but not modify the record
+    assert_eq!(
+        collection
+            .call("id1", "setBalance", json!([20]), Some(&other_signer))
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "permission-denied".to_string(),
+                reason: "unauthorized".to_string(),
+                message: "you do not have permission to modify this record".to_string(),
+            }
+        }
+    );
+
+    // Fails with no key
+    assert_eq!(
+        collection
+            .call("id1", "setBalance", json!([20]), None)
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "permission-denied".to_string(),
+                reason: "unauthorized".to_string(),
+                message: "you do not have permission to modify this record".to_string(),
+            }
+        }
+    );
+
+    // Make sure account hasn't changed
+    assert_eq!(collection.get("id1", None).await.unwrap(), account_id1_10);
+
+    // Owner can modify the record
+    let account_id1_20 = Account {
+        balance: 20.0,
+        ..account_id1_10
+    };
+
+    assert_eq!(
+        collection
+            .call("id1", "setBalance", json!([20]), Some(&owner_signer))
+            .await
+            .unwrap()
+            .unwrap(),
+        account_id1_20
+    );
+
+    assert_eq!(collection.get("id1", None).await.unwrap(), account_id1_20);
+
+    // Non-owner can't modify the record by passing it to a function on their own record
+    collection
+        .create(json!(["id2", 0]), Some(&other_signer))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        collection
+            .call(
+                "id2",
+                "take",
+                json!([{"id": "id1"}, 5]),
+                Some(&other_signer)
+            )
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "permission-denied".to_string(),
+                reason: "unauthorized".to_string(),
+                message: "you do not have permission to modify this record".to_string(),
+            }
+        }
+    );
+
+    assert_eq!(collection.get("id1", None).await.unwrap(), account_id1_20);
+}
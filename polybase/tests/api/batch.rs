@@ -0,0 +1,97 @@
+use serde_json::json;
+
+use crate::api::{Server, ServerConfig};
+
+#[tokio::test]
+async fn batch_reads() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped(
+            "test/Account",
+            r#"
+@public
+collection Account {
+    id: string;
+    balance: number;
+
+    constructor (id: string, balance: number) {
+        this.id = id;
+        this.balance = balance;
+    }
+}
+            "#,
+            None,
+        )
+        .await
+        .unwrap();
+
+    collection.create(json!(["id1", 10]), None).await.unwrap();
+    collection.create(json!(["id2", 20]), None).await.unwrap();
+
+    let res = server
+        .client
+        .post(server.base_url.join("/v0/batch").unwrap())
+        .json(&json!([
+            {"collection": "test/Account", "id": "id1"},
+            {"collection": "test/Account", "id": "missing"},
+            {"collection": "test/Account", "id": "id2"},
+        ]))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+
+    let body = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        body["data"],
+        json!([
+            {
+                "data": {"id": "id1", "balance": 10.0},
+                "block": {"hash": format!("0x{}", "0".repeat(64))},
+            },
+            {
+                "error": {
+                    "code": "not-found",
+                    "reason": "record/not-found",
+                    "message": "",
+                },
+            },
+            {
+                "data": {"id": "id2", "balance": 20.0},
+                "block": {"hash": format!("0x{}", "0".repeat(64))},
+            },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn batch_with_too_many_reads_is_rejected() {
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        max_batch_reads: Some(2),
+        ..Default::default()
+    }))
+    .await;
+
+    let read = json!({"collection": "test/Account", "id": "id1"});
+    let res = server
+        .client
+        .post(server.base_url.join("/v0/batch").unwrap())
+        .json(&json!([read, read, read]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let body = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "error": {
+                "code": "invalid-argument",
+                "reason": "batch/too-large",
+                "message": "batch has more than the maximum of 2 reads",
+            },
+        })
+    );
+}
@@ -1,7 +1,7 @@
 use base64::Engine;
 use serde_json::json;
 
-use crate::api::{Error, ErrorData, Server};
+use crate::api::{Error, ErrorData, ListQuery, Server};
 
 #[tokio::test]
 async fn bytes_field() {
@@ -68,3 +68,84 @@ collection Account {
         },
     );
 }
+
+#[tokio::test]
+async fn bytes_field_index() {
+    let server = Server::setup_and_wait(None).await;
+
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    data: bytes;
+
+    @index(data);
+
+    constructor (id: string, data: bytes) {
+        this.id = id;
+        this.data = data;
+    }
+}
+    "#;
+
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Account {
+        id: String,
+        data: String,
+    }
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    let mut accounts = vec![];
+    for (id, data) in [
+        ("id1", vec![0x02u8]),
+        ("id2", vec![0x01, 0xff]),
+        ("id3", vec![0x01]),
+    ] {
+        let data = base64::engine::general_purpose::STANDARD.encode(data);
+        accounts.push(collection.create(json!([id, data]), None).await.unwrap());
+    }
+
+    assert_eq!(
+        collection
+            .list(
+                ListQuery {
+                    sort: Some(json!([["data", "asc"]])),
+                    ..Default::default()
+                },
+                None
+            )
+            .await
+            .unwrap()
+            .into_record_data(),
+        vec![
+            accounts[2].clone(),
+            accounts[1].clone(),
+            accounts[0].clone()
+        ]
+    );
+
+    assert_eq!(
+        collection
+            .list(
+                ListQuery {
+                    where_query: Some(json!({
+                        "data": {
+                            "$gt": base64::engine::general_purpose::STANDARD.encode([0x01]),
+                        },
+                    })),
+                    sort: Some(json!([["data", "desc"]])),
+                    ..Default::default()
+                },
+                None
+            )
+            .await
+            .unwrap()
+            .into_record_data(),
+        vec![accounts[0].clone(), accounts[1].clone()]
+    );
+}
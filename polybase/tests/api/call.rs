@@ -1,6 +1,8 @@
+use std::time::{Duration, Instant};
+
 use serde_json::json;
 
-use crate::api::{Error, ErrorData, Server};
+use crate::api::{Error, ErrorData, Server, ServerConfig};
 
 #[tokio::test]
 async fn call() {
@@ -73,6 +75,62 @@ collection Account {
     );
 }
 
+#[tokio::test]
+async fn dry_run() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    balance: number;
+
+    constructor (id: string, balance: number) {
+        this.id = id;
+        this.balance = balance;
+    }
+
+    function deposit(amount: number) {
+        this.balance += amount;
+    }
+}
+    "#;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Account {
+        id: String,
+        balance: f64,
+    }
+
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    collection.create(json!(["0", 10.0]), None).await.unwrap();
+
+    assert_eq!(
+        collection
+            .dry_run_call("0", "deposit", json!([5.0]), None)
+            .await
+            .unwrap()
+            .unwrap(),
+        Account {
+            id: "0".to_string(),
+            balance: 15.0,
+        },
+    );
+
+    assert_eq!(
+        collection.get("0", None).await.unwrap(),
+        Account {
+            id: "0".to_string(),
+            balance: 10.0,
+        },
+    );
+}
+
 #[tokio::test]
 async fn with_optional_parameters() {
     let schema = r#"
@@ -176,10 +234,60 @@ collection Account {
         result.unwrap_err(),
         Error {
             error: ErrorData {
-                code: "failed-precondition".to_string(),
-                reason: "function/javascript-exception".to_string(),
+                code: "deadline-exceeded".to_string(),
+                reason: "function/timed-out".to_string(),
                 message: "function timed out".to_string(),
             }
         }
     );
 }
+
+#[tokio::test]
+async fn long_running_call_does_not_block_health_check() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+
+    constructor (id: string) {
+        while (true) {}
+    }
+}
+    "#;
+
+    // With a single runtime worker thread, the health check would wait for the
+    // function to time out if the function ran on the runtime
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        worker_threads: Some(1),
+        ..Default::default()
+    }))
+    .await;
+
+    let collection = server
+        .create_collection::<serde_json::Value>("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    let health = async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let start = Instant::now();
+        let res = server
+            .client
+            .get(server.base_url.join("/v0/health").unwrap())
+            .send()
+            .await
+            .unwrap();
+
+        (res.status(), start.elapsed())
+    };
+
+    let (create, (status, elapsed)) = tokio::join!(collection.create(json!(["0"]), None), health);
+
+    assert!(status.is_success());
+    assert!(
+        elapsed < Duration::from_secs(1),
+        "health check took {elapsed:?}"
+    );
+    assert_eq!(create.unwrap_err().error.reason, "function/timed-out");
+}
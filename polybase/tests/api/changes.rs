@@ -0,0 +1,174 @@
+use std::time::SystemTime;
+
+use serde_json::json;
+
+use crate::api::{Server, ServerConfig, Signature, Signer};
+
+#[tokio::test]
+async fn changes_since_height() {
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        audit_log: true,
+        ..Default::default()
+    }))
+    .await;
+
+    let public_schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.name = name;
+    }
+
+    setName (name: string) {
+        this.name = name;
+    }
+}
+    "#;
+
+    let private_schema = r#"
+collection Secret {
+    id: string;
+    @read
+    owner: PublicKey;
+
+    constructor (id: string) {
+        this.id = id;
+        this.owner = ctx.publicKey;
+    }
+}
+    "#;
+
+    let (private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let owner =
+        Signer::from(move |body: &str| Signature::create(&private_key, SystemTime::now(), body));
+
+    let accounts = server
+        .create_collection_untyped("test/Account", public_schema, None)
+        .await
+        .unwrap();
+    let secrets = server
+        .create_collection_untyped("test/Secret", private_schema, Some(&owner))
+        .await
+        .unwrap();
+
+    accounts.create(json!(["1", "John"]), None).await.unwrap();
+    let since = server.changes(0, None).await.unwrap().height;
+
+    accounts.create(json!(["2", "Jane"]), None).await.unwrap();
+    secrets.create(json!(["s1"]), Some(&owner)).await.unwrap();
+    accounts
+        .call("1", "setName", json!(["Johnny"]), None)
+        .await
+        .unwrap();
+
+    let changes = server.changes(since, None).await.unwrap();
+    assert_eq!(
+        changes
+            .changes
+            .iter()
+            .map(|c| (
+                c.collection_id.as_str(),
+                c.record_id.as_str(),
+                c.op.as_str()
+            ))
+            .collect::<Vec<_>>(),
+        vec![("test/Account", "2", "set"), ("test/Account", "1", "set")]
+    );
+    assert!(changes.changes.iter().all(|c| c.height > since));
+    assert!(changes.changes[0].height < changes.changes[1].height);
+    assert_eq!(changes.height, changes.changes[1].height);
+
+    // The owner can also read the change to their private record
+    let owner_changes = server.changes(since, Some(&owner)).await.unwrap();
+    assert_eq!(
+        owner_changes
+            .changes
+            .iter()
+            .map(|c| (c.collection_id.as_str(), c.record_id.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("test/Account", "2"),
+            ("test/Secret", "s1"),
+            ("test/Account", "1")
+        ]
+    );
+
+    // Nothing has changed since the last change
+    let latest = server.changes(changes.height, None).await.unwrap();
+    assert!(latest.changes.is_empty());
+    assert_eq!(latest.height, changes.height);
+}
+
+#[tokio::test]
+async fn changes_include_private_deletes() {
+    let server = Server::setup_and_wait(Some(ServerConfig {
+        audit_log: true,
+        ..Default::default()
+    }))
+    .await;
+
+    let private_schema = r#"
+collection Secret {
+    id: string;
+    @read
+    owner: PublicKey;
+
+    constructor (id: string) {
+        this.id = id;
+        this.owner = ctx.publicKey;
+    }
+
+    del () {
+        selfdestruct();
+    }
+}
+    "#;
+
+    let (private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let owner =
+        Signer::from(move |body: &str| Signature::create(&private_key, SystemTime::now(), body));
+
+    let secrets = server
+        .create_collection_untyped("test/Secret", private_schema, Some(&owner))
+        .await
+        .unwrap();
+
+    secrets.create(json!(["s1"]), Some(&owner)).await.unwrap();
+    let since = server.changes(0, Some(&owner)).await.unwrap().height;
+
+    secrets
+        .call("s1", "del", json!([]), Some(&owner))
+        .await
+        .unwrap();
+
+    // The record is gone, but the owner could read it before it was deleted
+    let changes = server.changes(since, Some(&owner)).await.unwrap();
+    assert_eq!(
+        changes
+            .changes
+            .iter()
+            .map(|c| (
+                c.collection_id.as_str(),
+                c.record_id.as_str(),
+                c.op.as_str()
+            ))
+            .collect::<Vec<_>>(),
+        vec![("test/Secret", "s1", "delete")]
+    );
+
+    let changes = server.changes(since, None).await.unwrap();
+    assert!(changes.changes.is_empty());
+}
+
+#[tokio::test]
+async fn changes_requires_audit_log() {
+    let server = Server::setup_and_wait(None).await;
+
+    let err = server.changes(0, None).await.unwrap_err();
+    assert_eq!(err.error.code, "failed-precondition");
+    assert_eq!(err.error.reason, "changes/disabled");
+}
@@ -1,4 +1,8 @@
-use crate::api::{ListQuery, Server};
+use std::time::SystemTime;
+
+use serde_json::json;
+
+use crate::api::{Error, ErrorData, ListQuery, Server, Signature, Signer};
 
 #[tokio::test]
 async fn collection_collection_records() {
@@ -27,3 +31,228 @@ collection Account {
     assert_eq!(records.data[0].data.get("id").unwrap(), "test/Account");
     assert_eq!(records.data[0].data.get("code").unwrap(), schema);
 }
+
+#[tokio::test]
+async fn delete_collection() {
+    let server = Server::setup_and_wait(None).await;
+
+    let (private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let owner =
+        Signer::from(move |body: &str| Signature::create(&private_key, SystemTime::now(), body));
+
+    let collection = server
+        .create_collection_untyped(
+            "test/Account",
+            r#"
+@public
+collection Account {
+    id: string;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+}
+            "#,
+            Some(&owner),
+        )
+        .await
+        .unwrap();
+
+    collection.create(json!(["id1"]), None).await.unwrap();
+    collection.create(json!(["id2"]), None).await.unwrap();
+
+    let collection_collection = server.collection_untyped("Collection");
+
+    // Only the owner can delete the collection
+    let (another_private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let another = Signer::from(move |body: &str| {
+        Signature::create(&another_private_key, SystemTime::now(), body)
+    });
+    assert_eq!(
+        collection_collection
+            .call(
+                "test/Account",
+                "deleteCollection",
+                json!([]),
+                Some(&another)
+            )
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "failed-precondition".to_string(),
+                reason: "function/collection-error".to_string(),
+                message: "collection function error: invalid owner".to_string(),
+            }
+        }
+    );
+    assert!(collection.get("id1", None).await.is_ok());
+
+    assert_eq!(
+        collection_collection
+            .call("test/Account", "deleteCollection", json!([]), Some(&owner))
+            .await
+            .unwrap(),
+        None
+    );
+
+    let collection_not_found = Error {
+        error: ErrorData {
+            code: "not-found".to_string(),
+            reason: "collection/not-found".to_string(),
+            message: "collection not found".to_string(),
+        },
+    };
+    assert_eq!(
+        collection.get("id1", None).await.unwrap_err(),
+        collection_not_found
+    );
+    assert_eq!(
+        collection
+            .list(ListQuery::default(), None)
+            .await
+            .unwrap_err(),
+        collection_not_found
+    );
+
+    let collections = collection_collection
+        .list(ListQuery::default(), None)
+        .await
+        .unwrap();
+    assert!(collections.data.is_empty());
+}
+
+#[tokio::test]
+async fn delete_unowned_collection() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped(
+            "test/Account",
+            r#"
+@public
+collection Account {
+    id: string;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+}
+            "#,
+            None,
+        )
+        .await
+        .unwrap();
+
+    collection.create(json!(["id1"]), None).await.unwrap();
+
+    // A collection without an owner can't be deleted, even by an unauthenticated caller
+    assert_eq!(
+        server
+            .collection_untyped("Collection")
+            .call("test/Account", "deleteCollection", json!([]), None)
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "failed-precondition".to_string(),
+                reason: "function/collection-error".to_string(),
+                message: "collection function error: invalid owner".to_string(),
+            }
+        }
+    );
+    assert!(collection.get("id1", None).await.is_ok());
+}
+
+#[tokio::test]
+async fn transfer_ownership() {
+    let server = Server::setup_and_wait(None).await;
+
+    let (owner_private_key, _) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let owner = Signer::from(move |body: &str| {
+        Signature::create(&owner_private_key, SystemTime::now(), body)
+    });
+
+    let (new_owner_private_key, new_owner_public_key) =
+        secp256k1::generate_keypair(&mut rand::thread_rng());
+    let new_owner = Signer::from(move |body: &str| {
+        Signature::create(&new_owner_private_key, SystemTime::now(), body)
+    });
+    let new_owner_public_key =
+        schema::publickey::PublicKey::from_secp256k1_key(&new_owner_public_key).unwrap();
+
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+}
+    "#;
+    let updated_schema = r#"
+@public
+collection Account {
+    id: string;
+    name?: string;
+}
+    "#;
+
+    server
+        .create_collection_untyped("test/Account", schema, Some(&owner))
+        .await
+        .unwrap();
+
+    let collection_collection = server.collection_untyped("Collection");
+    let invalid_owner = Error {
+        error: ErrorData {
+            code: "failed-precondition".to_string(),
+            reason: "function/collection-error".to_string(),
+            message: "collection function error: invalid owner".to_string(),
+        },
+    };
+
+    // Only the owner can transfer ownership
+    assert_eq!(
+        collection_collection
+            .call(
+                "test/Account",
+                "transferOwnership",
+                json!([new_owner_public_key]),
+                Some(&new_owner)
+            )
+            .await
+            .unwrap_err(),
+        invalid_owner
+    );
+
+    collection_collection
+        .call(
+            "test/Account",
+            "transferOwnership",
+            json!([new_owner_public_key]),
+            Some(&owner),
+        )
+        .await
+        .unwrap();
+
+    let record = collection_collection
+        .get("test/Account", None)
+        .await
+        .unwrap();
+    assert_eq!(
+        record.get("publicKey").unwrap(),
+        &serde_json::to_value(&new_owner_public_key).unwrap()
+    );
+
+    // The old owner can no longer update the collection
+    assert_eq!(
+        server
+            .update_collection_untyped("test/Account", updated_schema, Some(&owner))
+            .await
+            .unwrap_err(),
+        invalid_owner
+    );
+
+    server
+        .update_collection_untyped("test/Account", updated_schema, Some(&new_owner))
+        .await
+        .unwrap();
+}
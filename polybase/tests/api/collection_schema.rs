@@ -0,0 +1,161 @@
+use serde_json::json;
+
+use crate::api::{Error, ErrorData, Server};
+
+#[tokio::test]
+async fn get_collection_json_schema() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    balance?: number;
+    info: {
+        name: string;
+        age?: number;
+    };
+
+    constructor (id: string, name: string) {
+        this.id = id;
+        this.info = { name: name };
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(None).await;
+
+    server
+        .create_collection_untyped("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    let res = server
+        .client
+        .get(
+            server
+                .base_url
+                .join("/v0/collections/test%2FAccount/schema")
+                .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let json_schema = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        json_schema,
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "Account",
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "balance": { "type": "number" },
+                "info": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "age": { "type": "number" },
+                    },
+                    "required": ["name"],
+                },
+            },
+            "required": ["id", "info"],
+        })
+    );
+}
+
+#[tokio::test]
+async fn get_collection_json_schema_not_found() {
+    let server = Server::setup_and_wait(None).await;
+
+    let res = server
+        .client
+        .get(
+            server
+                .base_url
+                .join("/v0/collections/test%2FMissing/schema")
+                .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let err = res.json::<Error>().await.unwrap();
+    assert_eq!(
+        err,
+        Error {
+            error: ErrorData {
+                code: "not-found".to_string(),
+                reason: "collection/not-found".to_string(),
+                message: "collection not found".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn get_collection_indexes() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    name: string;
+    age: number;
+    info: {
+        country: string;
+    };
+
+    @index(name, [age, desc]);
+    @index([info.country, desc]);
+
+    constructor (id: string, name: string, age: number) {
+        this.id = id;
+        this.name = name;
+        this.age = age;
+        this.info = { country: 'UK' };
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(None).await;
+
+    server
+        .create_collection_untyped("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    let res = server
+        .client
+        .get(
+            server
+                .base_url
+                .join("/v0/collections/test%2FAccount/indexes")
+                .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let indexes = res.json::<serde_json::Value>().await.unwrap()["data"]
+        .as_array()
+        .unwrap()
+        .clone();
+
+    // Automatic field indexes are also listed, the id is appended to every index
+    assert!(indexes.contains(&json!({
+        "fields": [
+            { "path": "name", "direction": "asc" },
+            { "path": "age", "direction": "desc" },
+            { "path": "id", "direction": "asc" },
+        ],
+    })));
+    assert!(indexes.contains(&json!({
+        "fields": [
+            { "path": "info.country", "direction": "desc" },
+            { "path": "id", "direction": "asc" },
+        ],
+    })));
+}
@@ -0,0 +1,79 @@
+use serde_json::json;
+
+use crate::api::Server;
+
+#[tokio::test]
+async fn if_modified_since() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    balance: number;
+
+    constructor (id: string, balance: number) {
+        this.id = id;
+        this.balance = balance;
+    }
+
+    setBalance (balance: number) {
+        this.balance = balance;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    collection.create(json!(["id1", 10.0]), None).await.unwrap();
+
+    let url = server
+        .base_url
+        .join("/v0/collections/test%2FAccount/records/id1")
+        .unwrap();
+
+    let res = server.client.get(url.clone()).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let last_modified = res
+        .headers()
+        .get(reqwest::header::LAST_MODIFIED)
+        .expect("missing Last-Modified header")
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    // Record has not changed, so we get a 304
+    let res = server
+        .client
+        .get(url.clone())
+        .header(reqwest::header::IF_MODIFIED_SINCE, &last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+    // Last-Modified has second precision, so wait before updating the record
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    collection
+        .call("id1", "setBalance", json!([20.0]), None)
+        .await
+        .unwrap();
+
+    // Record has changed, so we get the updated record
+    let res = server
+        .client
+        .get(url)
+        .header(reqwest::header::IF_MODIFIED_SINCE, &last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["data"], json!({ "id": "id1", "balance": 20.0 }));
+}
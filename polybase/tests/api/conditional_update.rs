@@ -0,0 +1,92 @@
+use serde_json::json;
+
+use crate::api::{Error, ErrorData, Server};
+
+#[tokio::test]
+async fn if_match() {
+    let schema = r#"
+@public
+collection Account {
+    id: string;
+    balance: number;
+
+    constructor (id: string, balance: number) {
+        this.id = id;
+        this.balance = balance;
+    }
+
+    setBalance (balance: number) {
+        this.balance = balance;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped("test/Account", schema, None)
+        .await
+        .unwrap();
+
+    collection.create(json!(["id1", 10.0]), None).await.unwrap();
+
+    let res = server
+        .client
+        .get(
+            server
+                .base_url
+                .join("/v0/collections/test%2FAccount/records/id1")
+                .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let etag = res
+        .headers()
+        .get(reqwest::header::ETAG)
+        .expect("missing ETag header")
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    // Another client updates the record
+    collection
+        .call("id1", "setBalance", json!([20.0]), None)
+        .await
+        .unwrap();
+
+    // Update using the stale version is rejected
+    let res = server
+        .client
+        .post(
+            server
+                .base_url
+                .join("/v0/collections/test%2FAccount/records/id1/call/setBalance")
+                .unwrap(),
+        )
+        .header(reqwest::header::IF_MATCH, &etag)
+        .json(&json!({ "args": [30.0] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::PRECONDITION_FAILED);
+
+    assert_eq!(
+        res.json::<Error>().await.unwrap(),
+        Error {
+            error: ErrorData {
+                code: "precondition-failed".to_string(),
+                reason: "record/modified".to_string(),
+                message: "record has been modified since the version in If-Match".to_string(),
+            },
+        }
+    );
+
+    // The record keeps the other client's update
+    assert_eq!(
+        collection.get("id1", None).await.unwrap(),
+        json!({ "id": "id1", "balance": 20.0 })
+    );
+}
@@ -145,7 +145,7 @@ create_collection_test!(
         error: ErrorData {
             code: "invalid-argument".to_string(),
             reason: "collection/invalid-schema".to_string(),
-            message: "cannot index field \"arr\" of type string[]".to_string(),
+            message: "cannot index field \"arr\" of type boolean[]".to_string(),
         }
     },
     collection_with_index_on_array_field,
@@ -154,7 +154,7 @@ create_collection_test!(
 @public
 collection test {
     id: string;
-    arr: string[];
+    arr: boolean[];
 
     @index(arr);
 
@@ -170,7 +170,7 @@ create_collection_test!(
         error: ErrorData {
             code: "invalid-argument".to_string(),
             reason: "collection/invalid-schema".to_string(),
-            message: "cannot index field \"more.arr\" of type string[]".to_string(),
+            message: "cannot index field \"more.arr\" of type boolean[]".to_string(),
         }
     },
     collection_with_index_on_nested_array_field,
@@ -180,7 +180,7 @@ create_collection_test!(
 collection test {
     id: string;
     more: {
-        arr: string[];
+        arr: boolean[];
     };
 
     @index(more.arr);
@@ -197,18 +197,20 @@ create_collection_test!(
         error: ErrorData {
             code: "invalid-argument".to_string(),
             reason: "collection/invalid-schema".to_string(),
-            message: "cannot index field \"m\" of type map<string, string>".to_string(),
+            message: "index cannot include more than one array field, got \"tags, scores\""
+                .to_string(),
         }
     },
-    collection_with_index_on_map_field,
+    collection_with_index_on_multiple_array_fields,
     "ns/test",
     "
 @public
 collection test {
     id: string;
-    m: map<string, string>;
+    tags: string[];
+    scores: number[];
 
-    @index(m);
+    @index(tags, scores);
 
     constructor (id: string) {
         this.id = id;
@@ -222,20 +224,18 @@ create_collection_test!(
         error: ErrorData {
             code: "invalid-argument".to_string(),
             reason: "collection/invalid-schema".to_string(),
-            message: "cannot index field \"info\" of type { info.name: string; }".to_string(),
+            message: "cannot index field \"m\" of type map<string, string>".to_string(),
         }
     },
-    collection_with_index_on_object_field,
+    collection_with_index_on_map_field,
     "ns/test",
     "
 @public
 collection test {
     id: string;
-    info: {
-        name: string;
-    };
+    m: map<string, string>;
 
-    @index(info);
+    @index(m);
 
     constructor (id: string) {
         this.id = id;
@@ -249,18 +249,20 @@ create_collection_test!(
         error: ErrorData {
             code: "invalid-argument".to_string(),
             reason: "collection/invalid-schema".to_string(),
-            message: "cannot index field \"data\" of type bytes".to_string(),
+            message: "cannot index field \"info\" of type { info.name: string; }".to_string(),
         }
     },
-    collection_with_index_on_bytes_field,
+    collection_with_index_on_object_field,
     "ns/test",
     "
 @public
 collection test {
     id: string;
-    data: bytes;
+    info: {
+        name: string;
+    };
 
-    @index(data);
+    @index(info);
 
     constructor (id: string) {
         this.id = id;
@@ -841,3 +843,194 @@ async fn change_schema_type() {
         }
     );
 }
+
+#[tokio::test]
+async fn record_not_found() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped(
+            "ns/test",
+            "
+@public
+collection test {
+    id: string;
+}
+    ",
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        collection.get("none", None).await.unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "not-found".to_string(),
+                reason: "record/not-found".to_string(),
+                message: "".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn record_collection_not_found() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server.collection_untyped("ns/missing");
+
+    assert_eq!(
+        collection.get("none", None).await.unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "not-found".to_string(),
+                reason: "collection/not-found".to_string(),
+                message: "collection not found".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn record_too_large() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped(
+            "ns/test",
+            "
+@public
+collection test {
+    id: string;
+    data: string;
+
+    constructor (id: string, data: string) {
+        this.id = id;
+        this.data = data;
+    }
+}
+    ",
+            None,
+        )
+        .await
+        .unwrap();
+
+    collection
+        .create(json!(["id1", "x".repeat(1_000_000)]), None)
+        .await
+        .unwrap();
+
+    let err = collection
+        .create(json!(["id2", "x".repeat(1_100_000)]), None)
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.error.code, "invalid-argument");
+    assert_eq!(err.error.reason, "record/too-large");
+    assert!(
+        err.error
+            .message
+            .ends_with("exceeds the maximum of 1048576 bytes"),
+        "unexpected message: {}",
+        err.error.message
+    );
+}
+
+#[tokio::test]
+async fn collection_function_error_code() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped(
+            "ns/test",
+            "
+@public
+collection test {
+    id: string;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+
+    find () {
+        error('not_found', 'no such thing');
+    }
+
+    custom () {
+        error('custom-code', 'custom error');
+    }
+}
+    ",
+            None,
+        )
+        .await
+        .unwrap();
+
+    collection.create(json!(["id1"]), None).await.unwrap();
+
+    assert_eq!(
+        collection
+            .call("id1", "find", json!([]), None)
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "not-found".to_string(),
+                reason: "function/collection-error".to_string(),
+                message: "collection function error: no such thing".to_string(),
+            }
+        }
+    );
+
+    // Unknown codes use the default error code
+    assert_eq!(
+        collection
+            .call("id1", "custom", json!([]), None)
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "failed-precondition".to_string(),
+                reason: "function/collection-error".to_string(),
+                message: "collection function error: custom error".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn unauthorized_read() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped(
+            "ns/test",
+            "
+@call
+collection test {
+    id: string;
+
+    constructor (id: string) {
+        this.id = id;
+    }
+}
+    ",
+            None,
+        )
+        .await
+        .unwrap();
+
+    collection.create(json!(["id1"]), None).await.unwrap();
+
+    assert_eq!(
+        collection.get("id1", None).await.unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "permission-denied".to_string(),
+                reason: "unauthorized".to_string(),
+                message: "unauthorized read".to_string(),
+            }
+        }
+    );
+}
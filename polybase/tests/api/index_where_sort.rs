@@ -124,3 +124,75 @@ collection PeopleIndexWhereSort {
         assert_eq!(res.data[1].data, person_cal3_50_uk);
     }
 }
+
+#[tokio::test]
+async fn sort_pagination_with_equal_values() {
+    let schema = r#"
+@public
+collection PeopleEqualSort {
+    id: string;
+    age: number;
+
+    constructor (id: string, age: number) {
+        this.id = id;
+        this.age = age;
+    }
+}
+    "#;
+
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection_untyped("test/PeopleEqualSort", schema, None)
+        .await
+        .unwrap();
+
+    for (id, age) in [
+        ("a", 30),
+        ("b", 30),
+        ("c", 20),
+        ("d", 30),
+        ("e", 20),
+        ("f", 30),
+        ("g", 10),
+    ] {
+        collection.create(json!([id, age]), None).await.unwrap();
+    }
+
+    // Records with the same age are ordered by id, in the direction of the sort
+    for (direction, expected) in [
+        ("desc", ["f", "d", "b", "a", "e", "c", "g"]),
+        ("asc", ["g", "c", "e", "a", "b", "d", "f"]),
+    ] {
+        let mut ids = vec![];
+        let mut after = None;
+
+        loop {
+            let list = collection
+                .list(
+                    ListQuery {
+                        sort: Some(json!([["age", direction]])),
+                        limit: Some(2),
+                        after,
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await
+                .unwrap();
+
+            if list.data.is_empty() {
+                break;
+            }
+
+            after = list.cursor.after.clone();
+            ids.extend(
+                list.into_record_data()
+                    .into_iter()
+                    .map(|r| r["id"].as_str().unwrap().to_string()),
+            );
+        }
+
+        assert_eq!(ids, expected, "sort {direction}");
+    }
+}
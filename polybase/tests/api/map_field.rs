@@ -1,6 +1,6 @@
 use serde_json::json;
 
-use crate::api::Server;
+use crate::api::{ListQuery, Server};
 
 #[tokio::test]
 async fn collection_map_field() {
@@ -156,3 +156,82 @@ collection Asset {
         }
     );
 }
+
+#[tokio::test]
+async fn collection_map_field_key_index() {
+    let server = Server::setup_and_wait(None).await;
+
+    let schema = r#"
+@public
+collection Node {
+    id: string;
+    metadata: map<string, string>;
+
+    @index(metadata.region);
+
+    constructor (id: string, metadata: map<string, string>) {
+        this.id = id;
+        this.metadata = metadata;
+    }
+
+    setRegion(region: string) {
+        this.metadata['region'] = region;
+    }
+}
+    "#;
+
+    #[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+    struct Node {
+        id: String,
+        metadata: std::collections::HashMap<String, String>,
+    }
+
+    let collection = server
+        .create_collection::<Node>("test/Node", schema, None)
+        .await
+        .unwrap();
+
+    let node1 = collection
+        .create(json!(["node1", { "region": "us", "env": "prod" }]), None)
+        .await
+        .unwrap();
+    let node2 = collection
+        .create(json!(["node2", { "region": "eu" }]), None)
+        .await
+        .unwrap();
+    let node3 = collection
+        .create(json!(["node3", { "region": "us" }]), None)
+        .await
+        .unwrap();
+    collection.create(json!(["node4", {}]), None).await.unwrap();
+
+    let list_region = |region: &'static str| {
+        let collection = &collection;
+        async move {
+            collection
+                .list(
+                    ListQuery {
+                        where_query: Some(json!({ "metadata.region": region })),
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await
+                .unwrap()
+                .into_record_data()
+        }
+    };
+
+    assert_eq!(list_region("us").await, vec![node1.clone(), node3]);
+    assert_eq!(list_region("eu").await, vec![node2]);
+    assert_eq!(list_region("asia").await, vec![]);
+
+    // Updating the map key updates the index entry
+    let node1 = collection
+        .call("node1", "setRegion", json!(["asia"]), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(list_region("asia").await, vec![node1]);
+}
@@ -1,16 +1,25 @@
+mod admin;
 mod array_field;
 mod auth;
+mod batch;
 mod boolean_field;
 mod bytes_field;
 mod call;
+mod changes;
 mod collection_collection;
+mod collection_schema;
+mod conditional_read;
+mod conditional_update;
 mod errors;
 mod general_collection;
 mod index_record_refs;
 mod index_where_sort;
 mod map_field;
+mod namespace_collections;
 mod nested_field;
 mod other_collection_fns;
+mod patch_record;
+mod peers;
 mod restrict_namespaces;
 mod schema_index_update;
 mod start_stop;
@@ -18,7 +27,7 @@ mod store_other_collection_records;
 mod whitelist;
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     path::PathBuf,
     process::Command,
     sync::{Arc, Mutex},
@@ -68,6 +77,90 @@ struct ListQuery {
     after: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PeersResponse {
+    local_peer_id: String,
+    peers: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntry {
+    height: usize,
+    collection_id: String,
+    record_id: String,
+    op: String,
+    timestamp: u64,
+    diff: AuditDiff,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditResponse {
+    entries: Vec<AuditEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Change {
+    height: usize,
+    collection_id: String,
+    record_id: String,
+    op: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChangesResponse {
+    changes: Vec<Change>,
+    height: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageCounts {
+    reads: u64,
+    writes: u64,
+    read_bytes: u64,
+    write_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UsageResponse {
+    total: UsageCounts,
+    collections: HashMap<String, UsageCounts>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexStatsEntry {
+    fields: Vec<String>,
+    hits: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexStatsResponse {
+    data: Vec<IndexStatsEntry>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ImportLineError {
+    line: usize,
+    message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ImportResponse {
+    imported: usize,
+    skipped: usize,
+    errors: Vec<ImportLineError>,
+}
+
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 struct Error {
     error: ErrorData,
@@ -107,6 +200,15 @@ struct ServerConfig {
     whitelist: Option<Vec<String>>,
     keep_port_after_drop: bool,
     restrict_namespaces: bool,
+    admin_key: Option<String>,
+    max_batch_reads: Option<usize>,
+    max_import_bytes: Option<usize>,
+    max_restore_bytes: Option<usize>,
+    network_laddr: Option<String>,
+    dial_addr: Option<String>,
+    audit_log: bool,
+    usage_metering: bool,
+    worker_threads: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -145,6 +247,50 @@ impl Server {
             if config.restrict_namespaces {
                 command.arg("--restrict-namespaces");
             }
+
+            if let Some(ref admin_key) = config.admin_key {
+                command.arg("--admin-key").arg(admin_key);
+            }
+
+            if let Some(max_batch_reads) = config.max_batch_reads {
+                command
+                    .arg("--max-batch-reads")
+                    .arg(max_batch_reads.to_string());
+            }
+
+            if let Some(max_import_bytes) = config.max_import_bytes {
+                command
+                    .arg("--max-import-bytes")
+                    .arg(max_import_bytes.to_string());
+            }
+
+            if let Some(max_restore_bytes) = config.max_restore_bytes {
+                command
+                    .arg("--max-restore-bytes")
+                    .arg(max_restore_bytes.to_string());
+            }
+
+            if let Some(ref network_laddr) = config.network_laddr {
+                command.arg("--network-laddr").arg(network_laddr);
+            }
+
+            if let Some(ref dial_addr) = config.dial_addr {
+                command.arg("--dial-addr").arg(dial_addr);
+            }
+
+            if config.audit_log {
+                command.arg("--audit-log");
+            }
+
+            if config.usage_metering {
+                command.arg("--usage-metering");
+            }
+
+            if let Some(worker_threads) = config.worker_threads {
+                command
+                    .arg("--worker-threads")
+                    .arg(worker_threads.to_string());
+            }
         }
 
         command.arg("--root-dir").arg(root_dir.path());
@@ -256,6 +402,7 @@ impl Server {
         function: &str,
         args: serde_json::Value,
         signer: Option<&Signer>,
+        dry_run: bool,
     ) -> Result<RecordResponse<T>, Error> {
         let body = json!({
             "args": args,
@@ -267,10 +414,51 @@ impl Server {
             .post(
                 self.base_url
                     .join(&format!(
-                        "/v0/collections/{}/records/{}/call/{}",
+                        "/v0/collections/{}/records/{}/call/{}{}",
+                        urlencoding::encode(collection),
+                        urlencoding::encode(record),
+                        urlencoding::encode(function),
+                        if dry_run { "?dryRun=true" } else { "" },
+                    ))
+                    .unwrap(),
+            )
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+
+        let req = if let Some(signer) = signer {
+            req.header("X-Polybase-Signature", signer(&body).to_header())
+        } else {
+            req
+        };
+
+        let req = req.build().unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.json().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn patch_record<T: DeserializeOwned>(
+        &self,
+        collection: &str,
+        record: &str,
+        patch: serde_json::Value,
+        signer: Option<&Signer>,
+    ) -> Result<RecordResponse<T>, Error> {
+        let body = serde_json::to_string_pretty(&patch).unwrap();
+
+        let req = self
+            .client
+            .post(
+                self.base_url
+                    .join(&format!(
+                        "/v0/collections/{}/records/{}/patch",
                         urlencoding::encode(collection),
                         urlencoding::encode(record),
-                        urlencoding::encode(function)
                     ))
                     .unwrap(),
             )
@@ -442,6 +630,275 @@ impl Server {
         }
     }
 
+    async fn list_namespace_collections(
+        &self,
+        namespace: &str,
+    ) -> Result<ListResponse<serde_json::Value>, Error> {
+        let req = self
+            .client
+            .get(
+                self.base_url
+                    .join(&format!(
+                        "/v0/namespaces/{}/collections",
+                        urlencoding::encode(namespace)
+                    ))
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.json().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn peers(&self) -> PeersResponse {
+        let req = self
+            .client
+            .get(self.base_url.join("/v0/peers").unwrap())
+            .build()
+            .unwrap();
+
+        self.client
+            .execute(req)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    async fn admin_snapshot(&self, admin_key: &str) -> Result<Vec<u8>, Error> {
+        let req = self
+            .client
+            .get(self.base_url.join("/v0/admin/snapshot").unwrap())
+            .bearer_auth(admin_key)
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.bytes().await.unwrap().to_vec())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_export(&self, admin_key: &str, collection: &str) -> Result<String, Error> {
+        let req = self
+            .client
+            .get(
+                self.base_url
+                    .join(&format!(
+                        "/v0/admin/collections/{}/export",
+                        urlencoding::encode(collection)
+                    ))
+                    .unwrap(),
+            )
+            .bearer_auth(admin_key)
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.text().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_import(
+        &self,
+        admin_key: &str,
+        collection: &str,
+        jsonl: &str,
+        strict: bool,
+    ) -> Result<ImportResponse, Error> {
+        let mut url = self
+            .base_url
+            .join(&format!(
+                "/v0/admin/collections/{}/import",
+                urlencoding::encode(collection)
+            ))
+            .unwrap();
+        url.query_pairs_mut()
+            .append_pair("strict", &strict.to_string());
+
+        let req = self
+            .client
+            .post(url)
+            .bearer_auth(admin_key)
+            .body(jsonl.to_string())
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.json().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_restore(&self, admin_key: &str, snapshot: Vec<u8>) -> Result<(), Error> {
+        let req = self
+            .client
+            .post(self.base_url.join("/v0/admin/restore").unwrap())
+            .bearer_auth(admin_key)
+            .header("Content-Type", "application/octet-stream")
+            .body(snapshot)
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_rebuild_indexes(&self, collection: &str, admin_key: &str) -> Result<(), Error> {
+        let req = self
+            .client
+            .post(
+                self.base_url
+                    .join(&format!(
+                        "/v0/admin/collections/{}/rebuild-indexes",
+                        urlencoding::encode(collection)
+                    ))
+                    .unwrap(),
+            )
+            .bearer_auth(admin_key)
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_index_stats(
+        &self,
+        collection: &str,
+        admin_key: &str,
+    ) -> Result<IndexStatsResponse, Error> {
+        let req = self
+            .client
+            .get(
+                self.base_url
+                    .join(&format!(
+                        "/v0/collections/{}/indexes/stats",
+                        urlencoding::encode(collection)
+                    ))
+                    .unwrap(),
+            )
+            .bearer_auth(admin_key)
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.json().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_compact(&self, admin_key: &str) -> Result<(), Error> {
+        let req = self
+            .client
+            .post(self.base_url.join("/v0/admin/compact").unwrap())
+            .bearer_auth(admin_key)
+            .build()
+            .unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_audit(
+        &self,
+        admin_key: &str,
+        collection: Option<&str>,
+        from_height: usize,
+    ) -> Result<AuditResponse, Error> {
+        let mut url = self.base_url.join("/v0/admin/audit").unwrap();
+        url.query_pairs_mut()
+            .append_pair("from_height", &from_height.to_string());
+        if let Some(collection) = collection {
+            url.query_pairs_mut().append_pair("collection", collection);
+        }
+
+        let req = self.client.get(url).bearer_auth(admin_key).build().unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.json().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn changes(
+        &self,
+        since: usize,
+        signer: Option<&Signer>,
+    ) -> Result<ChangesResponse, Error> {
+        let mut url = self.base_url.join("/v0/changes").unwrap();
+        url.query_pairs_mut()
+            .append_pair("since", &since.to_string());
+
+        let req = self.client.get(url);
+        let req = if let Some(signer) = signer {
+            req.header("X-Polybase-Signature", signer("").to_header())
+        } else {
+            req
+        };
+
+        let res = self.client.execute(req.build().unwrap()).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.json().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
+    async fn admin_usage(&self, admin_key: &str, namespace: &str) -> Result<UsageResponse, Error> {
+        let mut url = self.base_url.join("/v0/admin/usage").unwrap();
+        url.query_pairs_mut().append_pair("namespace", namespace);
+
+        let req = self.client.get(url).bearer_auth(admin_key).build().unwrap();
+
+        let res = self.client.execute(req).await.unwrap();
+
+        if res.status().is_success() {
+            Ok(res.json().await.unwrap())
+        } else {
+            Err(res.json().await.unwrap())
+        }
+    }
+
     async fn create_collection<T: DeserializeOwned>(
         self: &Arc<Self>,
         collection: &str,
@@ -510,7 +967,36 @@ impl<T: DeserializeOwned> Collection<T> {
     ) -> Result<Option<T>, Error> {
         let res = self
             .server
-            .call(&self.id, record, function, args, signer)
+            .call(&self.id, record, function, args, signer, false)
+            .await?;
+
+        Ok(res.data)
+    }
+
+    async fn dry_run_call(
+        &self,
+        record: &str,
+        function: &str,
+        args: serde_json::Value,
+        signer: Option<&Signer>,
+    ) -> Result<Option<T>, Error> {
+        let res = self
+            .server
+            .call(&self.id, record, function, args, signer, true)
+            .await?;
+
+        Ok(res.data)
+    }
+
+    async fn patch(
+        &self,
+        record: &str,
+        patch: serde_json::Value,
+        signer: Option<&Signer>,
+    ) -> Result<T, Error> {
+        let res = self
+            .server
+            .patch_record(&self.id, record, patch, signer)
             .await?;
 
         Ok(res.data)
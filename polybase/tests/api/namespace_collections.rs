@@ -0,0 +1,44 @@
+use crate::api::Server;
+
+#[tokio::test]
+async fn list_namespace_collections() {
+    let server = Server::setup_and_wait(None).await;
+
+    let schema = |name: &str| {
+        format!(
+            r#"
+@public
+collection {name} {{
+    id: string;
+}}
+    "#
+        )
+    };
+
+    for id in ["test/A", "test/B", "test/sub/C", "test2/D"] {
+        let name = id.rsplit_once('/').unwrap().1;
+        server
+            .create_collection_untyped(id, &schema(name), None)
+            .await
+            .unwrap();
+    }
+
+    let collection_ids = |namespace: &'static str| {
+        let server = &server;
+        async move {
+            server
+                .list_namespace_collections(namespace)
+                .await
+                .unwrap()
+                .into_record_data()
+                .into_iter()
+                .map(|c| c["id"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(collection_ids("test").await, vec!["test/A", "test/B"]);
+    assert_eq!(collection_ids("test/sub").await, vec!["test/sub/C"]);
+    assert_eq!(collection_ids("test2").await, vec!["test2/D"]);
+    assert_eq!(collection_ids("missing").await, Vec::<String>::new());
+}
@@ -0,0 +1,157 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::api::{Error, ErrorData, Server, Signature, Signer};
+
+const SCHEMA: &str = r#"
+@read
+@call
+collection Account {
+    id: string;
+    name: string;
+    info: {
+        city: string;
+        country: string;
+    };
+    @write
+    owner: PublicKey;
+
+    constructor (id: string, name: string, city: string, country: string) {
+        this.id = id;
+        this.name = name;
+        this.info = { city: city, country: country };
+        this.owner = ctx.publicKey;
+    }
+}
+"#;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Info {
+    city: String,
+    country: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Account {
+    id: String,
+    name: String,
+    info: Info,
+    owner: schema::publickey::PublicKey,
+}
+
+fn signer() -> (Signer, schema::publickey::PublicKey) {
+    let (private_key, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let public_key = schema::publickey::PublicKey::from_secp256k1_key(&public_key).unwrap();
+    let signer =
+        Signer::from(move |body: &str| Signature::create(&private_key, SystemTime::now(), body));
+    (signer, public_key)
+}
+
+#[tokio::test]
+async fn patch_nested_field() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", SCHEMA, None)
+        .await
+        .unwrap();
+
+    let (owner_signer, owner_public_key) = signer();
+
+    collection
+        .create(json!(["id1", "John", "London", "UK"]), Some(&owner_signer))
+        .await
+        .unwrap();
+
+    let expected = Account {
+        id: "id1".to_string(),
+        name: "John".to_string(),
+        info: Info {
+            city: "Paris".to_string(),
+            country: "UK".to_string(),
+        },
+        owner: owner_public_key,
+    };
+
+    assert_eq!(
+        collection
+            .patch(
+                "id1",
+                json!({ "info": { "city": "Paris" } }),
+                Some(&owner_signer)
+            )
+            .await
+            .unwrap(),
+        expected
+    );
+
+    assert_eq!(
+        collection.get("id1", Some(&owner_signer)).await.unwrap(),
+        expected
+    );
+}
+
+#[tokio::test]
+async fn patch_rejects_id_change() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", SCHEMA, None)
+        .await
+        .unwrap();
+
+    let (owner_signer, _) = signer();
+
+    collection
+        .create(json!(["id1", "John", "London", "UK"]), Some(&owner_signer))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        collection
+            .patch("id1", json!({ "id": "id2" }), Some(&owner_signer))
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "failed-precondition".to_string(),
+                reason: "record/id-modified".to_string(),
+                message: "record ID was modified".to_string(),
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn patch_requires_write_permission() {
+    let server = Server::setup_and_wait(None).await;
+
+    let collection = server
+        .create_collection::<Account>("test/Account", SCHEMA, None)
+        .await
+        .unwrap();
+
+    let (owner_signer, _) = signer();
+    let (other_signer, _) = signer();
+
+    collection
+        .create(json!(["id1", "John", "London", "UK"]), Some(&owner_signer))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        collection
+            .patch("id1", json!({ "name": "Jane" }), Some(&other_signer))
+            .await
+            .unwrap_err(),
+        Error {
+            error: ErrorData {
+                code: "permission-denied".to_string(),
+                reason: "unauthorized".to_string(),
+                message: "you do not have permission to modify this record".to_string(),
+            }
+        }
+    );
+}
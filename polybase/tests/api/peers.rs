@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use crate::api::{Server, ServerConfig, PORT_POOL};
+
+#[tokio::test]
+async fn peers_lists_connected_peers() {
+    let network_port = PORT_POOL.lock().unwrap().get();
+
+    let server1 = Server::setup_and_wait(Some(ServerConfig {
+        network_laddr: Some(format!("/ip4/127.0.0.1/tcp/{network_port}")),
+        ..Default::default()
+    }))
+    .await;
+
+    let server2 = Server::setup_and_wait(Some(ServerConfig {
+        dial_addr: Some(format!("/ip4/127.0.0.1/tcp/{network_port}")),
+        ..Default::default()
+    }))
+    .await;
+
+    // Wait for the connection to be established
+    let mut retries = 0;
+    let (peers1, peers2) = loop {
+        let peers1 = server1.peers().await;
+        let peers2 = server2.peers().await;
+        if (!peers1.peers.is_empty() && !peers2.peers.is_empty()) || retries == 50 {
+            break (peers1, peers2);
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        retries += 1;
+    };
+
+    assert_ne!(peers1.local_peer_id, peers2.local_peer_id);
+    assert_eq!(peers1.peers, vec![peers2.local_peer_id]);
+    assert_eq!(peers2.peers, vec![peers1.local_peer_id]);
+
+    PORT_POOL.lock().unwrap().release(network_port);
+}
@@ -46,6 +46,20 @@ pub static COLLECTION_CODE: Lazy<&'static str> = Lazy::new(|| {
             this.code = code;
             this.ast = parse(code, this.id);
         }
+
+        transferOwnership (newKey: PublicKey) {
+            if (!ctx.publicKey || this.publicKey != ctx.publicKey) {
+                throw error('invalid owner');
+            }
+            this.publicKey = newKey;
+        }
+
+        deleteCollection () {
+            if (!ctx.publicKey || this.publicKey != ctx.publicKey) {
+                throw error('invalid owner');
+            }
+            selfdestruct();
+        }
     }
     "#
 });
@@ -27,6 +27,7 @@ impl Directive {
                 "delegate" => DirectiveKind::Delegate,
                 "read" => DirectiveKind::Read,
                 "call" => DirectiveKind::Call,
+                "write" => DirectiveKind::Write,
                 "public" => DirectiveKind::Public,
                 _ => DirectiveKind::Unknown,
             },
@@ -40,6 +41,7 @@ pub enum DirectiveKind {
     Delegate,
     Read,
     Call,
+    Write,
     Public,
     Unknown,
 }
@@ -48,7 +50,10 @@ impl DirectiveKind {
     pub fn allow_root(&self) -> bool {
         matches!(
             self,
-            DirectiveKind::Read | DirectiveKind::Call | DirectiveKind::Public
+            DirectiveKind::Read
+                | DirectiveKind::Call
+                | DirectiveKind::Write
+                | DirectiveKind::Public
         )
     }
 }
@@ -59,6 +64,7 @@ impl Display for DirectiveKind {
             DirectiveKind::Delegate => write!(f, "delegate"),
             DirectiveKind::Read => write!(f, "read"),
             DirectiveKind::Call => write!(f, "call"),
+            DirectiveKind::Write => write!(f, "write"),
             DirectiveKind::Public => write!(f, "public"),
             DirectiveKind::Unknown => write!(f, "unknown"),
         }
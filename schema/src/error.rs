@@ -52,9 +52,18 @@ pub enum UserError {
     #[error("cannot index field \"{field}\" of type {field_type}")]
     FieldTypeCannotBeIndexed { field: String, field_type: String },
 
+    #[error("index cannot include more than one array field, got \"{fields}\"")]
+    IndexCannotHaveMultipleArrayFields { fields: String },
+
     #[error("cannot change type of fields: \"{fields}\", delete the fields and re-create them")]
     SchemaFieldTypeChangeNotAllowed { fields: String },
 
+    #[error("cannot remove fields \"{fields}\" as they are used by an index")]
+    SchemaIndexedFieldRemovalNotAllowed { fields: String },
+
+    #[error("cannot remove fields \"{fields}\" as they are referenced by a directive")]
+    SchemaReferencedFieldRemovalNotAllowed { fields: String },
+
     #[error("collection directive {directive:?} cannot have arguments")]
     CollectionDirectiveCannotHaveArguments { directive: String },
 
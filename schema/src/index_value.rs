@@ -1,7 +1,8 @@
 use super::publickey::PublicKey;
 use super::record::{self, ForeignRecordReference, RecordError, RecordValue};
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
+use std::{borrow::Cow, cmp::Ordering};
 
 #[derive(Debug, thiserror::Error)]
 pub enum IndexValueError {
@@ -18,6 +19,7 @@ pub enum IndexValue<'a> {
     String(Cow<'a, str>),
     PublicKey(Cow<'a, PublicKey>),
     ForeignRecordReference(Cow<'a, ForeignRecordReference>),
+    Bytes(Cow<'a, [u8]>),
 }
 
 impl From<u64> for IndexValue<'_> {
@@ -55,12 +57,50 @@ impl IndexValue<'_> {
             IndexValue::Number(n) => IndexValue::Number(n),
             IndexValue::Boolean(b) => IndexValue::Boolean(b),
             IndexValue::Null => IndexValue::Null,
+            IndexValue::Bytes(b) => IndexValue::Bytes(Cow::Owned(b.into_owned())),
         }
     }
 
     pub fn is_string(&self) -> bool {
         matches!(self, IndexValue::String(_))
     }
+
+    /// Position of the value's type when comparing values of different types,
+    /// matching the type prefixes used for index keys.
+    fn type_order(&self) -> u8 {
+        match self {
+            IndexValue::Null => 0,
+            IndexValue::String(_) => 1,
+            IndexValue::Number(_) => 2,
+            IndexValue::Boolean(_) => 3,
+            IndexValue::Bytes(_) => 4,
+            IndexValue::PublicKey(_) => 5,
+            IndexValue::ForeignRecordReference(_) => 6,
+        }
+    }
+}
+
+impl PartialOrd for IndexValue<'_> {
+    /// Values of the same type are compared by value. Bytes are ordered
+    /// lexicographically, public keys and foreign record references by their
+    /// canonical indexable encoding. Values of different types are ordered
+    /// by type.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (IndexValue::Null, IndexValue::Null) => Some(Ordering::Equal),
+            (IndexValue::Number(a), IndexValue::Number(b)) => a.partial_cmp(b),
+            (IndexValue::Boolean(a), IndexValue::Boolean(b)) => Some(a.cmp(b)),
+            (IndexValue::String(a), IndexValue::String(b)) => Some(a.cmp(b)),
+            (IndexValue::Bytes(a), IndexValue::Bytes(b)) => Some(a.cmp(b)),
+            (IndexValue::PublicKey(a), IndexValue::PublicKey(b)) => {
+                Some(a.to_indexable().cmp(&b.to_indexable()))
+            }
+            (IndexValue::ForeignRecordReference(a), IndexValue::ForeignRecordReference(b)) => {
+                Some(a.to_indexable().cmp(&b.to_indexable()))
+            }
+            (a, b) => Some(a.type_order().cmp(&b.type_order())),
+        }
+    }
 }
 
 impl From<IndexValue<'_>> for RecordValue {
@@ -74,6 +114,7 @@ impl From<IndexValue<'_>> for RecordValue {
             IndexValue::ForeignRecordReference(fr) => {
                 RecordValue::ForeignRecordReference(fr.into_owned())
             }
+            IndexValue::Bytes(b) => RecordValue::Bytes(b.into_owned()),
         }
     }
 }
@@ -91,7 +132,7 @@ impl TryFrom<RecordValue> for IndexValue<'_> {
             RecordValue::ForeignRecordReference(fr) => {
                 Ok(IndexValue::ForeignRecordReference(Cow::Owned(fr)))
             }
-            RecordValue::Bytes(_) => Err(IndexValueError::TryFromRecordValue),
+            RecordValue::Bytes(b) => Ok(IndexValue::Bytes(Cow::Owned(b))),
             RecordValue::RecordReference(_) => Err(IndexValueError::TryFromRecordValue),
             RecordValue::Map(_) => Err(IndexValueError::TryFromRecordValue),
             RecordValue::Array(_) => Err(IndexValueError::TryFromRecordValue),
@@ -113,6 +154,100 @@ impl TryFrom<IndexValue<'_>> for serde_json::Value {
             IndexValue::PublicKey(p) => serde_json::Value::from(p.into_owned()),
             IndexValue::ForeignRecordReference(r) => serde_json::Value::from(r.into_owned()),
             IndexValue::Null => serde_json::Value::Null,
+            IndexValue::Bytes(b) => {
+                serde_json::Value::String(base64::engine::general_purpose::STANDARD.encode(b))
+            }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(b: &[u8]) -> IndexValue<'static> {
+        IndexValue::Bytes(Cow::Owned(b.to_vec()))
+    }
+
+    fn sorted(mut values: Vec<IndexValue<'static>>) -> Vec<IndexValue<'static>> {
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        values
+    }
+
+    #[test]
+    fn test_bytes_ordering() {
+        let expected = vec![
+            bytes(&[]),
+            bytes(&[0x01]),
+            bytes(&[0x01, 0xff]),
+            bytes(&[0x02]),
+        ];
+
+        assert_eq!(sorted(expected.iter().rev().cloned().collect()), expected);
+        assert_eq!(
+            sorted(vec![
+                bytes(&[0x02]),
+                bytes(&[0x01]),
+                bytes(&[]),
+                bytes(&[0x01, 0xff])
+            ]),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_public_key_ordering() {
+        let keys = (0..16)
+            .map(|_| IndexValue::PublicKey(Cow::Owned(PublicKey::random())))
+            .collect::<Vec<_>>();
+
+        let forwards = sorted(keys.clone());
+        let backwards = sorted(keys.into_iter().rev().collect());
+
+        assert_eq!(forwards, backwards);
+        for pair in forwards.windows(2) {
+            let (IndexValue::PublicKey(a), IndexValue::PublicKey(b)) = (&pair[0], &pair[1]) else {
+                panic!("expected public keys");
+            };
+            assert!(a.to_indexable() <= b.to_indexable());
+        }
+    }
+
+    #[test]
+    fn test_equal_values_compare_equal_after_round_trip() {
+        let public_key = PublicKey::random();
+
+        let values = vec![
+            bytes(&[0x01, 0x02, 0x03]),
+            IndexValue::PublicKey(Cow::Owned(public_key.clone())),
+        ];
+
+        for value in values {
+            let round_tripped = IndexValue::try_from(RecordValue::from(value.clone())).unwrap();
+            assert_eq!(value.partial_cmp(&round_tripped), Some(Ordering::Equal));
+
+            let round_tripped: IndexValue =
+                serde_json::from_str(&serde_json::to_string(&value).unwrap()).unwrap();
+            assert_eq!(value.partial_cmp(&round_tripped), Some(Ordering::Equal));
+        }
+
+        let round_tripped = PublicKey::from_indexable(&public_key.to_indexable()).unwrap();
+        assert_eq!(
+            IndexValue::PublicKey(Cow::Owned(public_key))
+                .partial_cmp(&IndexValue::PublicKey(Cow::Owned(round_tripped))),
+            Some(Ordering::Equal)
+        );
+    }
+
+    #[test]
+    fn test_different_types_ordered_by_type() {
+        assert_eq!(
+            IndexValue::Null.partial_cmp(&bytes(&[])),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            bytes(&[0xff]).partial_cmp(&IndexValue::PublicKey(Cow::Owned(PublicKey::random()))),
+            Some(Ordering::Less)
+        );
+    }
+}
@@ -33,6 +33,20 @@ impl PropertyList {
         self.iter_all().find(|p| p.path == *path)
     }
 
+    /// Get the type of the value at a path, unlike `get_path` this also resolves paths
+    /// to a key of a map field, e.g. `metadata.region` for `metadata: map<string, string>`
+    pub fn get_path_type(&self, path: &FieldPath) -> Option<&Type> {
+        if let Some(prop) = self.get_path(path) {
+            return Some(&prop.type_);
+        }
+
+        // Find the map field the path starts with, the rest of the path are the map keys
+        (1..path.len()).rev().find_map(|i| {
+            let prop = self.get_path(&FieldPath::new(path.as_slice()[..i].to_vec()))?;
+            map_value_type(&prop.type_, &path.as_slice()[i..])
+        })
+    }
+
     /// Iterate through the top-level fields of a PropertyList
     pub fn iter(&self) -> impl Iterator<Item = &Property> {
         self.properties.iter()
@@ -43,6 +57,15 @@ impl PropertyList {
         PropertyListIterator::new(self)
     }
 }
+
+fn map_value_type<'a>(type_: &'a Type, keys: &[String]) -> Option<&'a Type> {
+    match (type_, keys.split_first()) {
+        (_, None) => Some(type_),
+        (Type::Map(map), Some((_, keys))) => map_value_type(&map.value, keys),
+        _ => None,
+    }
+}
+
 pub struct PropertyListIterator<'a> {
     stack: Vec<std::slice::Iter<'a, Property>>,
 }
@@ -161,7 +184,7 @@ pub fn properties_from_ast<'a>(
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::types::PrimitiveType;
+    use crate::types::{Map, PrimitiveType};
 
     #[test]
     fn test_get_path_single_depth() {
@@ -194,4 +217,32 @@ mod test {
             Some(&properties.properties[0])
         );
     }
+
+    #[test]
+    fn test_get_path_type_map_key() {
+        let properties = PropertyList::new(vec![Property {
+            path: FieldPath::new(vec!["metadata".to_string()]),
+            type_: Type::Map(Map {
+                key: Box::new(Type::Primitive(PrimitiveType::String)),
+                value: Box::new(Type::Primitive(PrimitiveType::Number)),
+            }),
+            required: false,
+            index: false,
+            directives: vec![],
+        }]);
+
+        assert_eq!(
+            properties.get_path_type(&vec!["metadata", "region"].into()),
+            Some(&Type::Primitive(PrimitiveType::Number))
+        );
+        assert_eq!(
+            properties.get_path_type(&"metadata".into()),
+            Some(&properties.properties[0].type_)
+        );
+        assert_eq!(
+            properties.get_path_type(&vec!["metadata", "region", "x"].into()),
+            None
+        );
+        assert_eq!(properties.get_path_type(&"other".into()), None);
+    }
 }
@@ -17,9 +17,12 @@ pub enum PublicKeyError {
     #[error("invalid hex for public key")]
     InvalidHexPublicKey,
 
-    #[error("invalid length for public key, must be 64 bytes")]
+    #[error("invalid length for public key, must be 33, 64 or 65 bytes")]
     InvalidHexPublicKeyLength,
 
+    #[error("invalid length for indexable public key coordinates")]
+    InvalidIndexableCoordinatesLength,
+
     #[error("invalid value {value:?} for field {field:?}")]
     InvalidValue { field: &'static str, value: String },
 
@@ -149,7 +152,9 @@ impl PublicKey {
     }
 
     pub fn from_indexable(v: &[u8]) -> Result<Self> {
-        let mut parts = v.split(|b| *b == b'|');
+        // x and y are raw bytes that may contain the separator, so only split
+        // off the string fields and read the coordinates by their fixed length
+        let mut parts = v.splitn(5, |b| *b == b'|');
 
         let kty = parts
             .next()
@@ -163,12 +168,13 @@ impl PublicKey {
         let use_ = parts
             .next()
             .ok_or(PublicKeyError::MissingField { name: "use" })?;
-        let x = parts
+        let coordinates = parts
             .next()
             .ok_or(PublicKeyError::MissingField { name: "x" })?;
-        let y = parts
-            .next()
-            .ok_or(PublicKeyError::MissingField { name: "y" })?;
+        if coordinates.len() != 65 || coordinates[32] != b'|' {
+            return Err(PublicKeyError::InvalidIndexableCoordinatesLength);
+        }
+        let (x, y) = (&coordinates[..32], &coordinates[33..]);
 
         Ok(Self {
             kty: String::from_utf8(kty.to_vec())?,
@@ -180,12 +186,28 @@ impl PublicKey {
         })
     }
 
+    /// Parse a hex encoded public key, with or without the 0x prefix. Accepts the
+    /// 64 byte x and y coordinates (as returned by `to_hex`), or a 65 byte uncompressed
+    /// or 33 byte compressed secp256k1 key.
     pub fn from_hex(s: &str) -> Result<Self> {
-        let bytes =
-            hex::decode(s[2..].as_bytes()).map_err(|_| PublicKeyError::InvalidHexPublicKey)?; // Remove the 0x prefix
-        let bytes = <[u8; 64]>::try_from(bytes.as_slice())
-            .map_err(|_| PublicKeyError::InvalidHexPublicKeyLength)?;
-        Self::try_from(bytes)
+        let s = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        let bytes = hex::decode(s.as_bytes()).map_err(|_| PublicKeyError::InvalidHexPublicKey)?;
+
+        match bytes.len() {
+            64 => {
+                #[allow(clippy::unwrap_used)] // unwrap is safe because we know the length is 64
+                let bytes = <[u8; 64]>::try_from(bytes.as_slice()).unwrap();
+                Self::try_from(bytes)
+            }
+            33 | 65 => {
+                let key = secp256k1::PublicKey::from_slice(&bytes)?;
+                Ok(Self::from_secp256k1_key(&key)?)
+            }
+            _ => Err(PublicKeyError::InvalidHexPublicKeyLength),
+        }
     }
 
     pub fn to_hex(&self) -> Result<String> {
@@ -366,4 +388,73 @@ mod tests {
     fn test_public_key() {
         super::PublicKey::random();
     }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let key = super::PublicKey::random();
+        let hex = key.to_hex().unwrap();
+
+        assert_eq!(super::PublicKey::from_hex(&hex).unwrap(), key);
+        assert_eq!(super::PublicKey::from_hex(&hex[2..]).unwrap(), key);
+        assert_eq!(
+            super::PublicKey::from_hex(&hex.replacen("0x", "0X", 1)).unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn test_from_hex_compressed_and_uncompressed() {
+        let key = super::PublicKey::random();
+        let secp_key = key.to_secp256k1_key().unwrap();
+
+        let compressed = hex::encode(secp_key.serialize());
+        assert_eq!(super::PublicKey::from_hex(&compressed).unwrap(), key);
+
+        let uncompressed = format!("0x{}", hex::encode(secp_key.serialize_uncompressed()));
+        assert_eq!(super::PublicKey::from_hex(&uncompressed).unwrap(), key);
+    }
+
+    #[test]
+    fn test_from_hex_rejects_malformed_input() {
+        use super::PublicKeyError;
+
+        assert!(matches!(
+            super::PublicKey::from_hex("0xzz"),
+            Err(PublicKeyError::InvalidHexPublicKey)
+        ));
+        assert!(matches!(
+            super::PublicKey::from_hex("0x123"),
+            Err(PublicKeyError::InvalidHexPublicKey)
+        ));
+        assert!(matches!(
+            super::PublicKey::from_hex(""),
+            Err(PublicKeyError::InvalidHexPublicKeyLength)
+        ));
+        assert!(matches!(
+            super::PublicKey::from_hex("0"),
+            Err(PublicKeyError::InvalidHexPublicKey)
+        ));
+        assert!(matches!(
+            super::PublicKey::from_hex("0x1234"),
+            Err(PublicKeyError::InvalidHexPublicKeyLength)
+        ));
+        // Not a point on the curve
+        assert!(matches!(
+            super::PublicKey::from_hex(&format!("0x{}", "00".repeat(64))),
+            Err(PublicKeyError::Secp256k1Error(_))
+        ));
+    }
+
+    #[test]
+    fn test_indexable_round_trip_with_separator_in_coordinates() {
+        let key = (0..)
+            .map(|_| super::PublicKey::random())
+            .find(|key| key.x.contains(&b'|') || key.y.contains(&b'|'))
+            .unwrap();
+
+        assert_eq!(
+            super::PublicKey::from_indexable(&key.to_indexable()).unwrap(),
+            key
+        );
+    }
 }
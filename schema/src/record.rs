@@ -94,6 +94,9 @@ pub enum RecordUserError {
 
     #[error("unexpected fields: {}", .fields.join(", "))]
     UnexpectedFields { fields: Vec<String> },
+
+    #[error("record is nested too deeply, the maximum depth is {max}")]
+    RecordTooDeep { max: usize },
 }
 
 pub type RecordRootRaw = HashMap<String, RecordValue>;
@@ -286,15 +289,61 @@ impl IntoIterator for RecordRoot {
     }
 }
 
-/// Converts JSON to RootRecord, also validates that the structure is correct
+/// Maximum depth of nested objects and arrays in a record, see
+/// `json_to_record_with_max_depth`. Records deeper than this are invalid, so every
+/// node must use the same limit.
+pub const DEFAULT_MAX_RECORD_DEPTH: usize = 64;
+
+/// Converts JSON to RootRecord, also validates that the structure is correct. Forced
+/// conversions (e.g. migrating stored records to a new schema) don't limit the depth,
+/// so records stored before the limit was added can still be migrated.
 pub fn json_to_record(
     schema: &Schema,
     value: serde_json::Value,
     force: bool,
 ) -> Result<RecordRoot> {
+    if force {
+        return RecordRoot::try_from_json(schema, value, force);
+    }
+
+    json_to_record_with_max_depth(schema, value, force, DEFAULT_MAX_RECORD_DEPTH)
+}
+
+/// Convert a JSON value to a record, rejecting values with more than `max_depth` levels
+/// of nested objects and arrays (the record itself is the first level). The depth is
+/// checked without recursion, before the value is converted.
+pub fn json_to_record_with_max_depth(
+    schema: &Schema,
+    value: serde_json::Value,
+    force: bool,
+    max_depth: usize,
+) -> Result<RecordRoot> {
+    if json_depth_exceeds(&value, max_depth) {
+        return Err(RecordUserError::RecordTooDeep { max: max_depth })?;
+    }
+
     RecordRoot::try_from_json(schema, value, force)
 }
 
+fn json_depth_exceeds(value: &serde_json::Value, max_depth: usize) -> bool {
+    let mut stack = vec![(value, 1)];
+    while let Some((value, depth)) = stack.pop() {
+        let children: Box<dyn Iterator<Item = &serde_json::Value>> = match value {
+            serde_json::Value::Object(map) => Box::new(map.values()),
+            serde_json::Value::Array(array) => Box::new(array.iter()),
+            _ => continue,
+        };
+
+        if depth > max_depth {
+            return true;
+        }
+
+        stack.extend(children.map(|child| (child, depth + 1)));
+    }
+
+    false
+}
+
 pub fn record_to_json(value: RecordRoot) -> serde_json::Value {
     let mut map = serde_json::Map::new();
 
@@ -317,6 +366,84 @@ pub fn foreign_record_to_json(value: RecordRoot, collection_id: &str) -> serde_j
     serde_json::Value::Object(map)
 }
 
+/// Deterministic serialization of a record, for use wherever records are hashed.
+/// Map keys are sorted, numbers are encoded as their IEEE 754 bits, and every value
+/// is tagged with its type and length prefixed, so logically equal records always
+/// produce the same bytes.
+pub fn canonical_bytes(record: &RecordRoot) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_canonical_map(&mut buf, &record.0);
+    buf
+}
+
+fn write_canonical_map(buf: &mut Vec<u8>, map: &HashMap<String, RecordValue>) {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    buf.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+    for (key, value) in entries {
+        write_canonical_bytes(buf, key.as_bytes());
+        write_canonical_value(buf, value);
+    }
+}
+
+fn write_canonical_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn write_canonical_value(buf: &mut Vec<u8>, value: &RecordValue) {
+    match value {
+        RecordValue::Number(n) => {
+            buf.push(0);
+            // -0.0 == 0.0 and all NaNs are equivalent, so they have a single encoding
+            let n = match n {
+                n if n.is_nan() => f64::NAN,
+                n if *n == 0.0 => 0.0,
+                n => *n,
+            };
+            buf.extend_from_slice(&n.to_bits().to_be_bytes());
+        }
+        RecordValue::Boolean(b) => {
+            buf.push(1);
+            buf.push(u8::from(*b));
+        }
+        RecordValue::Null => buf.push(2),
+        RecordValue::String(s) => {
+            buf.push(3);
+            write_canonical_bytes(buf, s.as_bytes());
+        }
+        RecordValue::PublicKey(pk) => {
+            buf.push(4);
+            write_canonical_bytes(buf, &pk.to_indexable());
+        }
+        RecordValue::Bytes(bytes) => {
+            buf.push(5);
+            write_canonical_bytes(buf, bytes);
+        }
+        RecordValue::Map(map) => {
+            buf.push(6);
+            write_canonical_map(buf, map);
+        }
+        RecordValue::Array(values) => {
+            buf.push(7);
+            buf.extend_from_slice(&(values.len() as u64).to_be_bytes());
+            for value in values {
+                write_canonical_value(buf, value);
+            }
+        }
+        RecordValue::RecordReference(reference) => {
+            buf.push(8);
+            write_canonical_bytes(buf, reference.id.as_bytes());
+        }
+        RecordValue::ForeignRecordReference(reference) => {
+            buf.push(9);
+            write_canonical_bytes(buf, reference.collection_id.as_bytes());
+            write_canonical_bytes(buf, reference.id.as_bytes());
+        }
+    }
+}
+
 // TODO: should we not have a Object type? Or allow Map key to be
 // more than just String
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -780,7 +907,9 @@ impl RecordValue {
             RecordValue::PublicKey(p) => {
                 f(current_path, IndexValue::PublicKey(Cow::Borrowed(p)))?;
             }
-            RecordValue::Bytes(_) => {}
+            RecordValue::Bytes(b) => {
+                f(current_path, IndexValue::Bytes(Cow::Borrowed(b)))?;
+            }
             RecordValue::Map(m) => {
                 for (k, v) in m.iter() {
                     current_path.push(Cow::Borrowed(k));
@@ -1003,3 +1132,102 @@ fn get_reference_field_value(
     };
     Ok(val)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_to_record_max_depth() {
+        let mut program = None;
+        let (_, ast) = polylang::parse(
+            "collection Nested { id: string; a?: { b?: { c?: string; }; }; }",
+            "ns",
+            &mut program,
+        )
+        .unwrap();
+        let schema =
+            Schema::from_json_str("Nested", &serde_json::to_string(&ast).unwrap()).unwrap();
+
+        // The record, a and b are 3 levels
+        let record = || serde_json::json!({ "id": "id1", "a": { "b": { "c": "x" } } });
+        assert!(json_to_record_with_max_depth(&schema, record(), false, 3).is_ok());
+        assert!(matches!(
+            json_to_record_with_max_depth(&schema, record(), false, 2),
+            Err(RecordError::UserError(RecordUserError::RecordTooDeep {
+                max: 2
+            }))
+        ));
+
+        let mut value = serde_json::Value::Null;
+        for _ in 0..1000 {
+            value = serde_json::Value::Array(vec![value]);
+        }
+        assert!(matches!(
+            json_to_record(
+                &schema,
+                serde_json::json!({ "id": "id1", "a": value.clone() }),
+                false
+            ),
+            Err(RecordError::UserError(RecordUserError::RecordTooDeep {
+                max: DEFAULT_MAX_RECORD_DEPTH
+            }))
+        ));
+
+        // Forced conversions, e.g. migrations of stored records, are not limited
+        assert!(json_to_record(
+            &schema,
+            serde_json::json!({ "id": "id1", "a": value }),
+            true
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_canonical_bytes_ignores_insertion_order() {
+        let fields = [
+            ("id", RecordValue::String("id1".to_string())),
+            ("age", RecordValue::Number(30.0)),
+            ("tags", RecordValue::Array(vec![RecordValue::Null])),
+            (
+                "address",
+                RecordValue::Map(HashMap::from([
+                    (
+                        "city".to_string(),
+                        RecordValue::String("London".to_string()),
+                    ),
+                    ("country".to_string(), RecordValue::String("UK".to_string())),
+                ])),
+            ),
+        ];
+
+        let mut a = RecordRoot::new();
+        for (key, value) in fields.iter().cloned() {
+            a.insert(key.to_string(), value);
+        }
+        let mut b = RecordRoot::new();
+        for (key, value) in fields.iter().rev().cloned() {
+            b.insert(key.to_string(), value);
+        }
+
+        assert_eq!(canonical_bytes(&a), canonical_bytes(&b));
+    }
+
+    #[test]
+    fn test_canonical_bytes_distinguishes_types() {
+        let record = |value| {
+            let mut record = RecordRoot::new();
+            record.insert("value".to_string(), value);
+            record
+        };
+
+        assert_ne!(
+            canonical_bytes(&record(RecordValue::String("AQI=".to_string()))),
+            canonical_bytes(&record(RecordValue::Bytes(vec![1, 2]))),
+        );
+        assert_eq!(
+            canonical_bytes(&record(RecordValue::Number(0.0))),
+            canonical_bytes(&record(RecordValue::Number(-0.0))),
+        );
+    }
+}
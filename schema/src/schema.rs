@@ -8,8 +8,9 @@ use crate::{
     property::{Property, PropertyList},
     publickey::{self, PublicKey},
     record::{RecordRoot, RecordValue, Reference},
-    types::{PrimitiveType, Type},
+    types::{properties_json_schema, PrimitiveType, Type},
 };
+use once_cell::sync::OnceCell;
 use polylang::stableast;
 use std::{
     collections::{HashMap, HashSet},
@@ -34,6 +35,22 @@ pub struct Schema {
     pub read_all: bool,
     /// Anyone can call the collection functions
     pub call_all: bool,
+    /// Anyone who can call the collection functions can also modify records, true unless
+    /// a field is marked with @write
+    pub write_all: bool,
+    /// Cached `record_version`
+    record_version: RecordVersion,
+}
+
+/// Lazily computed `Schema::record_version`. It's derived from the properties, so it's
+/// ignored when schemas are compared.
+#[derive(Debug, Default, Clone)]
+struct RecordVersion(OnceCell<String>);
+
+impl PartialEq for RecordVersion {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
 }
 
 impl Schema {
@@ -42,9 +59,10 @@ impl Schema {
 
         // Get a vec of all indexes
         let mut indexes = custom_indexes_from_ast(collection_ast);
+        // Bytes can be large, so they are only indexed when explicitly requested
         properties
             .iter()
-            .filter(|p| p.type_.is_indexable())
+            .filter(|p| p.type_.is_indexable() && p.type_ != Type::Primitive(PrimitiveType::Bytes))
             .for_each(|p| {
                 let new_index_asc = Index::new(vec![IndexField::new_asc(p.path.clone())]);
                 let new_index_desc = Index::new(vec![IndexField::new_desc(p.path.clone())]);
@@ -80,6 +98,9 @@ impl Schema {
         let is_public = collection_ast.attributes.iter().any(|attr| matches!(attr, stableast::CollectionAttribute::Directive(d) if d.name == "public"));
         let read_all = is_public || collection_ast.attributes.iter().any(|attr| matches!(attr, stableast::CollectionAttribute::Directive(d) if d.name == "read" && d.arguments.is_empty()));
         let call_all = is_public || collection_ast.attributes.iter().any(|attr| matches!(attr, stableast::CollectionAttribute::Directive(d) if d.name == "call" && d.arguments.is_empty()));
+        let write_all = collection_ast.attributes.iter().any(|attr| matches!(attr, stableast::CollectionAttribute::Directive(d) if d.name == "write" && d.arguments.is_empty()))
+            || (!root_directives.iter().any(|d| d.kind == DirectiveKind::Write)
+                && !properties.iter_all().any(|p| p.directives.iter().any(|d| d.kind == DirectiveKind::Write)));
 
         Self {
             name: collection_ast.name.to_string(),
@@ -88,10 +109,36 @@ impl Schema {
             indexes,
             read_all,
             call_all,
+            write_all,
             properties: PropertyList::from_ast_collection(collection_ast),
+            record_version: RecordVersion::default(),
         }
     }
 
+    /// Version of the shape of the collection's records, a hash of the path, type and
+    /// required flag of every field. Changes that don't affect the shape of records (e.g.
+    /// methods, indexes or directives) keep the same version. The version is computed
+    /// once per schema, as it's checked for every record read.
+    pub fn record_version(&self) -> &str {
+        self.record_version.0.get_or_init(|| {
+            let mut fields = self
+                .properties
+                .iter_all()
+                .map(|p| format!("{}:{}:{}", p.path, p.type_, p.required))
+                .collect::<Vec<_>>();
+            fields.sort();
+
+            // FNV-1a, as the version is persisted it must not depend on the std hasher
+            let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+            for byte in fields.join(";").bytes() {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+
+            format!("{hash:016x}")
+        })
+    }
+
     pub fn from_record(record: &RecordRoot) -> Result<Self> {
         let id = match record.get("id") {
             Some(RecordValue::String(id)) => id,
@@ -225,6 +272,20 @@ impl Schema {
         self.methods.get(method)
     }
 
+    /// Export the collection's properties as a JSON Schema (draft-07) document, describing the
+    /// shape of the records returned by the API
+    pub fn to_json_schema(&self) -> serde_json::Value {
+        let mut json_schema = properties_json_schema(&self.properties);
+        if let serde_json::Value::Object(o) = &mut json_schema {
+            o.insert(
+                "$schema".to_string(),
+                "http://json-schema.org/draft-07/schema#".into(),
+            );
+            o.insert("title".to_string(), self.name.clone().into());
+        }
+        json_schema
+    }
+
     pub fn generate_js(&self) -> String {
         let fns = self
             .methods
@@ -244,8 +305,71 @@ impl Schema {
         )
     }
 
-    // Check if the user is attempting to change a schema field type
-    pub fn validate_schema_change(&self, new_schema: Schema) -> Result<()> {
+    // Check if the user is attempting to make a breaking change to the schema, such as
+    // changing a field type, removing the id field or removing a field that is still in use.
+    // Must be called before `validate` on the new schema, which would reject the same
+    // changes with less specific errors.
+    pub fn validate_schema_change(&self, new_schema: &Schema) -> Result<()> {
+        // Ensure the id field is not removed or made optional
+        if self.name != "Collection" {
+            let Some(id_property) = new_schema.properties.get_path(&FieldPath::id()) else {
+                return Err(UserError::CollectionMissingIdField.into());
+            };
+
+            if !id_property.required {
+                return Err(UserError::CollectionIdFieldCannotBeOptional.into());
+            }
+        }
+
+        // Fields that exist in the old schema, but not in the new schema
+        let removed_fields = self
+            .properties
+            .iter_all()
+            .filter(|p| new_schema.properties.get_path(&p.path).is_none())
+            .map(|p| &p.path)
+            .collect::<HashSet<_>>();
+
+        let removed_index_fields = unique_filter(
+            new_schema
+                .indexes
+                .iter()
+                .flat_map(|i| i.fields.iter())
+                .map(|f| &f.path)
+                .filter(|path| removed_fields.contains(path)),
+            |path| *path,
+        )
+        .map(|path| path.to_string())
+        .collect::<Vec<_>>();
+
+        if !removed_index_fields.is_empty() {
+            return Err(UserError::SchemaIndexedFieldRemovalNotAllowed {
+                fields: removed_index_fields.join(", "),
+            })?;
+        }
+
+        let removed_referenced_fields = unique_filter(
+            new_schema
+                .root_directives
+                .iter()
+                .chain(
+                    new_schema
+                        .methods
+                        .values()
+                        .flat_map(|m| m.directives.iter()),
+                )
+                .flat_map(|d| d.arguments.iter())
+                .filter(|path| removed_fields.contains(path)),
+            |path| *path,
+        )
+        .map(|path| path.to_string())
+        .collect::<Vec<_>>();
+
+        if !removed_referenced_fields.is_empty() {
+            return Err(UserError::SchemaReferencedFieldRemovalNotAllowed {
+                fields: removed_referenced_fields.join(", "),
+            })?;
+        }
+
         let existing_types = self
             .properties
             .iter_all()
@@ -298,22 +422,35 @@ impl Schema {
 
         // Validate indexes
         for index in self.indexes.iter() {
+            let mut array_fields = vec![];
+
             for index_field in &index.fields {
-                let Some(prop) = self.properties.get_path(&index_field.path) else {
+                // Keys of a map field can be indexed, but not the map itself
+                let Some(type_) = self.properties.get_path_type(&index_field.path) else {
                     return Err(UserError::IndexFieldNotFoundInSchema {
                         field: index_field.path.to_string(),
                     }
                     .into());
                 };
 
-                if !prop.type_.is_indexable() {
+                if type_.is_array_indexable() {
+                    array_fields.push(index_field.path.to_string());
+                } else if !type_.is_indexable() {
                     return Err(UserError::FieldTypeCannotBeIndexed {
                         field: index_field.path.to_string(),
-                        field_type: prop.type_.to_string(),
+                        field_type: type_.to_string(),
                     }
                     .into());
                 }
             }
+
+            // Each array element gets its own index entry, so we only allow one array per index
+            if array_fields.len() > 1 {
+                return Err(UserError::IndexCannotHaveMultipleArrayFields {
+                    fields: array_fields.join(", "),
+                }
+                .into());
+            }
         }
 
         // Validate collection directives
@@ -477,6 +614,98 @@ mod test {
         assert!(schema.call_all, "call_all should be true");
     }
 
+    #[test]
+    fn test_write_all() {
+        let code = r#"
+            @public
+            collection Test {
+                id: string;
+            }
+        "#;
+        let schema = create_schema("Test", code);
+        assert!(schema.write_all, "write_all should be true");
+
+        let code = r#"
+            @public
+            collection Test {
+                id: string;
+                @write
+                owner: PublicKey;
+            }
+        "#;
+        let schema = create_schema("Test", code);
+        assert!(!schema.write_all, "write_all should be false");
+        assert_eq!(
+            schema
+                .fields_auth(&[DirectiveKind::Write])
+                .map(|p| p.name())
+                .collect::<Vec<_>>(),
+            vec!["owner"]
+        );
+
+        let code = r#"
+            @write
+            collection Test {
+                id: string;
+                @write
+                owner: PublicKey;
+            }
+        "#;
+        let schema = create_schema("Test", code);
+        assert!(schema.write_all, "write_all should be true");
+
+        let code = r#"
+            @write(owner)
+            collection Test {
+                id: string;
+                owner: PublicKey;
+            }
+        "#;
+        let schema = create_schema("Test", code);
+        assert!(!schema.write_all, "write_all should be false");
+    }
+
+    #[test]
+    fn test_to_json_schema() {
+        let code = r#"
+            collection Test {
+                id: string;
+                age?: number;
+                tags: string[];
+                owner: PublicKey;
+                info: {
+                    name: string;
+                    avatar?: bytes;
+                };
+            }
+        "#;
+        let schema = create_schema("Test", code);
+
+        assert_eq!(
+            schema.to_json_schema(),
+            serde_json::json!({
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "title": "Test",
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "age": { "type": "number" },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "owner": Type::PublicKey.to_json_schema(),
+                    "info": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "avatar": { "type": "string", "contentEncoding": "base64" },
+                        },
+                        "required": ["name"],
+                    },
+                },
+                "required": ["id", "tags", "owner", "info"],
+            })
+        );
+    }
+
     #[test]
     fn test_fields_auth() {
         let code = r#"
@@ -681,4 +910,165 @@ mod test {
             vec!["call_prop"]
         );
     }
+
+    #[test]
+    fn test_validate_schema_change_remove_indexed_field() {
+        let old_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id: string;
+                name: string;
+                age: number;
+
+                @index(name, age);
+            }
+        "#,
+        );
+
+        let new_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id: string;
+                age: number;
+
+                @index(name, age);
+            }
+        "#,
+        );
+
+        let err = old_schema.validate_schema_change(&new_schema).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::User(UserError::SchemaIndexedFieldRemovalNotAllowed { ref fields }) if fields == "name"
+            ),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_schema_change_remove_referenced_field() {
+        let old_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id: string;
+                owner: PublicKey;
+
+                @call(owner)
+                function test() {}
+            }
+        "#,
+        );
+
+        let new_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id: string;
+
+                @call(owner)
+                function test() {}
+            }
+        "#,
+        );
+
+        let err = old_schema.validate_schema_change(&new_schema).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::User(UserError::SchemaReferencedFieldRemovalNotAllowed { ref fields }) if fields == "owner"
+            ),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_schema_change_optional_id() {
+        let old_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id: string;
+            }
+        "#,
+        );
+
+        let new_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id?: string;
+            }
+        "#,
+        );
+
+        let err = old_schema.validate_schema_change(&new_schema).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::User(UserError::CollectionIdFieldCannotBeOptional)
+            ),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_schema_change_add_optional_field() {
+        let old_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id: string;
+                name: string;
+            }
+        "#,
+        );
+
+        let new_schema = create_schema(
+            "Test",
+            r#"
+            collection Test {
+                id: string;
+                name: string;
+                age?: number;
+            }
+        "#,
+        );
+
+        assert!(old_schema.validate_schema_change(&new_schema).is_ok());
+    }
+
+    #[test]
+    fn test_validate_index_on_map_key() {
+        let schema = create_schema(
+            "Test",
+            r#"
+            @index(metadata.region)
+            collection Test {
+                id: string;
+                metadata: map<string, string>;
+            }
+        "#,
+        );
+        assert!(schema.validate().is_ok());
+
+        let schema = create_schema(
+            "Test",
+            r#"
+            @index(metadata)
+            collection Test {
+                id: string;
+                metadata: map<string, string>;
+            }
+        "#,
+        );
+        let err = schema.validate().unwrap_err();
+        assert!(
+            matches!(err, Error::User(UserError::FieldTypeCannotBeIndexed { .. })),
+            "unexpected error: {err:?}"
+        );
+    }
 }
@@ -1,5 +1,6 @@
 use crate::{field_path::FieldPath, property::PropertyList};
 use polylang::stableast;
+use serde_json::json;
 use std::{boxed::Box, fmt::Display};
 
 pub use stableast::PrimitiveType;
@@ -45,12 +46,25 @@ impl Type {
             Type::Primitive(PrimitiveType::Boolean)
                 | Type::Primitive(PrimitiveType::String)
                 | Type::Primitive(PrimitiveType::Number)
+                | Type::Primitive(PrimitiveType::Bytes)
                 | Type::Record
                 | Type::ForeignRecord(_)
                 | Type::PublicKey
         )
     }
 
+    /// Arrays of strings or numbers can be indexed, each element of the array is
+    /// indexed separately, so records can be queried by the elements they contain
+    pub fn is_array_indexable(&self) -> bool {
+        match self {
+            Type::Array(a) => matches!(
+                *a.value,
+                Type::Primitive(PrimitiveType::String) | Type::Primitive(PrimitiveType::Number)
+            ),
+            _ => false,
+        }
+    }
+
     pub fn is_public_key(&self) -> bool {
         match self {
             Type::PublicKey => true,
@@ -77,6 +91,75 @@ impl Type {
             _ => false,
         }
     }
+
+    /// JSON Schema (draft-07) describing how values of this type are represented in JSON
+    pub fn to_json_schema(&self) -> serde_json::Value {
+        match self {
+            Type::Primitive(PrimitiveType::String) => json!({ "type": "string" }),
+            Type::Primitive(PrimitiveType::Number) => json!({ "type": "number" }),
+            Type::Primitive(PrimitiveType::Boolean) => json!({ "type": "boolean" }),
+            Type::Primitive(PrimitiveType::Bytes) => json!({
+                "type": "string",
+                "contentEncoding": "base64",
+            }),
+            Type::PublicKey => json!({
+                "type": "object",
+                "properties": {
+                    "kty": { "type": "string" },
+                    "crv": { "type": "string" },
+                    "alg": { "type": "string" },
+                    "use": { "type": "string" },
+                    "x": { "type": "string" },
+                    "y": { "type": "string" },
+                },
+                "required": ["kty", "crv", "alg", "use", "x", "y"],
+            }),
+            Type::Array(a) => json!({
+                "type": "array",
+                "items": a.value.to_json_schema(),
+            }),
+            Type::Map(m) => json!({
+                "type": "object",
+                "additionalProperties": m.value.to_json_schema(),
+            }),
+            Type::Object(o) => properties_json_schema(&o.fields),
+            Type::Record => json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                },
+                "required": ["id"],
+            }),
+            Type::ForeignRecord(_) => json!({
+                "type": "object",
+                "properties": {
+                    "collectionId": { "type": "string" },
+                    "id": { "type": "string" },
+                },
+                "required": ["collectionId", "id"],
+            }),
+            Type::Unknown => json!({}),
+        }
+    }
+}
+
+/// JSON Schema for an object with the given (top-level) properties
+pub(crate) fn properties_json_schema(properties: &PropertyList) -> serde_json::Value {
+    let mut props = serde_json::Map::new();
+    let mut required = vec![];
+
+    for property in properties.iter() {
+        props.insert(property.name().to_string(), property.type_.to_json_schema());
+        if property.required {
+            required.push(serde_json::Value::from(property.name()));
+        }
+    }
+
+    json!({
+        "type": "object",
+        "properties": props,
+        "required": required,
+    })
 }
 
 impl Display for Type {
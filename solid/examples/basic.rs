@@ -72,7 +72,7 @@ async fn main() {
                         info!(hash = proposal_hash.to_string(), height = height, skips = skips, "Propose");
 
                         // Add proposal to own register, this will trigger an accept
-                        solid.receive_proposal(manifest.clone());
+                        solid.receive_proposal(manifest.clone(), &manifest.leader_id);
                     }
 
                     // Commit a confirmed proposal changes
@@ -100,6 +100,14 @@ async fn main() {
                     SolidEvent::DuplicateProposal { proposal_hash } => {
                         info!(hash = proposal_hash.to_string(), "Duplicate proposal");
                     }
+
+                    SolidEvent::Equivocation { peer_id, height, hash_a, hash_b } => {
+                        info!(from = peer_id.prefix(), height = height, hash_a = hash_a.to_string(), hash_b = hash_b.to_string(), "Conflicting proposal");
+                    }
+
+                    SolidEvent::ConflictingProposal { peer_id, leader_id, height, hash_a, hash_b } => {
+                        info!(from = peer_id.prefix(), leader = leader_id.prefix(), height = height, hash_a = hash_a.to_string(), hash_b = hash_b.to_string(), "Conflicting relayed proposal");
+                    }
                 }
             }
         }
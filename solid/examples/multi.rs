@@ -5,7 +5,7 @@ use futures::StreamExt;
 use parking_lot::deadlock;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use solid::config::SolidConfig;
+use solid::config::{QuorumRule, SolidConfig};
 use solid::event::SolidEvent;
 use solid::peer::PeerId;
 use solid::proposal::ProposalAccept;
@@ -289,6 +289,7 @@ async fn main() {
                 max_proposal_history: 20,
                 skip_timeout: Duration::from_secs(5),
                 out_of_sync_timeout: Duration::from_secs(60),
+                quorum: QuorumRule::Majority,
             },
         );
 
@@ -336,7 +337,7 @@ async fn main() {
 
                             NetworkEvent::Proposal { manifest } => {
                                 info!(height = &manifest.height, skips = &manifest.skips, from = &manifest.leader_id.prefix(), hash = &manifest.hash().to_string(), "Received proposal");
-                                solid.receive_proposal(manifest);
+                                solid.receive_proposal(manifest, &peer_id);
                             }
                         }
                     },
@@ -382,7 +383,7 @@ async fn main() {
                                 info!(hash = proposal_hash.to_string(), height = height, skips = skips, "Propose");
 
                                 // Add proposal to own register, this will trigger an accept
-                                solid.receive_proposal(manifest.clone());
+                                solid.receive_proposal(manifest.clone(), &manifest.leader_id);
 
                                 // // Send proposal to all other nodes
                                 network.send_all(
@@ -419,6 +420,14 @@ async fn main() {
                             SolidEvent::DuplicateProposal { proposal_hash } => {
                                 info!(hash = proposal_hash.to_string(), "Duplicate proposal");
                             }
+
+                            SolidEvent::Equivocation { peer_id, height, hash_a, hash_b } => {
+                                info!(from = peer_id.prefix(), height = height, hash_a = hash_a.to_string(), hash_b = hash_b.to_string(), "Conflicting proposal");
+                            }
+
+                            SolidEvent::ConflictingProposal { peer_id, leader_id, height, hash_a, hash_b } => {
+                                info!(from = peer_id.prefix(), leader = leader_id.prefix(), height = height, hash_a = hash_a.to_string(), hash_b = hash_b.to_string(), "Conflicting relayed proposal");
+                            }
                         }
                     }
                 }
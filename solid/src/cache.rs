@@ -1,4 +1,4 @@
-use crate::proposal::{Proposal, ProposalHash};
+use crate::proposal::{Proposal, ProposalHash, ProposalManifest};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 
@@ -52,7 +52,7 @@ impl ProposalCache {
         self.max_height
     }
 
-    #[cfg(test)]
+    /// Number of proposals in the cache, including pending proposals
     pub fn len(&self) -> usize {
         self.proposals.len()
     }
@@ -71,6 +71,17 @@ impl ProposalCache {
         self.proposals.insert(proposal.hash().clone(), proposal);
     }
 
+    /// Find a different proposal from the same leader, for the same height and skips
+    pub fn conflicting(&self, manifest: &ProposalManifest) -> Option<&Proposal> {
+        let hash = manifest.hash();
+        self.proposals.values().find(|p| {
+            p.height() == manifest.height
+                && p.skips() == manifest.skips
+                && p.manifest.leader_id == manifest.leader_id
+                && p.hash() != &hash
+        })
+    }
+
     /// Get a proposal by hash (mutable)
     pub fn get_mut(&mut self, proposal_hash: &ProposalHash) -> Option<&mut Proposal> {
         self.proposals.get_mut(proposal_hash)
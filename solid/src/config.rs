@@ -1,3 +1,4 @@
+use std::str::FromStr;
 use std::time::Duration;
 
 #[derive(Debug, Clone)]
@@ -13,6 +14,9 @@ pub struct SolidConfig {
 
     /// Amount of time to wait before we send another out of sync message
     pub out_of_sync_timeout: Duration,
+
+    /// Number of accepts required before the next proposal can be made
+    pub quorum: QuorumRule,
 }
 
 impl Default for SolidConfig {
@@ -22,6 +26,72 @@ impl Default for SolidConfig {
             max_proposal_history: 1024,
             skip_timeout: Duration::from_secs(5),
             out_of_sync_timeout: Duration::from_secs(60),
+            quorum: QuorumRule::Majority,
+        }
+    }
+}
+
+/// Rule for the number of accepts a proposal needs from its peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuorumRule {
+    /// More than half of the peers (f+1 of 2f+1 peers)
+    #[default]
+    Majority,
+
+    /// More than two thirds of the peers (2f+1 of 3f+1 peers)
+    TwoThirds,
+
+    /// A fixed number of peers, capped at the number of peers
+    Fixed(usize),
+}
+
+impl QuorumRule {
+    /// Number of accepts needed for a proposal with the given number of peers
+    pub fn threshold(&self, peers: usize) -> usize {
+        match self {
+            QuorumRule::Majority => peers / 2 + 1,
+            QuorumRule::TwoThirds => peers * 2 / 3 + 1,
+            QuorumRule::Fixed(n) => (*n).clamp(1, peers.max(1)),
+        }
+    }
+}
+
+impl FromStr for QuorumRule {
+    type Err = String;
+
+    /// Parses `majority`, `two-thirds` or a fixed number of peers
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "majority" => Ok(QuorumRule::Majority),
+            "two-thirds" => Ok(QuorumRule::TwoThirds),
+            n => n
+                .parse()
+                .map(QuorumRule::Fixed)
+                .map_err(|_| format!("invalid quorum rule: {n}")),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quorum_threshold() {
+        assert_eq!(QuorumRule::Majority.threshold(3), 2);
+        assert_eq!(QuorumRule::Majority.threshold(4), 3);
+        assert_eq!(QuorumRule::TwoThirds.threshold(4), 3);
+        assert_eq!(QuorumRule::TwoThirds.threshold(7), 5);
+        assert_eq!(QuorumRule::Fixed(2).threshold(4), 2);
+        assert_eq!(QuorumRule::Fixed(10).threshold(4), 4);
+        assert_eq!(QuorumRule::Fixed(0).threshold(4), 1);
+    }
+
+    #[test]
+    fn test_parse_quorum_rule() {
+        assert_eq!("majority".parse(), Ok(QuorumRule::Majority));
+        assert_eq!("two-thirds".parse(), Ok(QuorumRule::TwoThirds));
+        assert_eq!("3".parse(), Ok(QuorumRule::Fixed(3)));
+        assert!("most".parse::<QuorumRule>().is_err());
+    }
+}
@@ -39,4 +39,28 @@ pub enum SolidEvent {
 
     /// Duplicate proposal received
     DuplicateProposal { proposal_hash: ProposalHash },
+
+    /// Leader made two different proposals for the same height and skips, both received
+    /// directly from the leader, the second proposal is rejected
+    Equivocation {
+        peer_id: PeerId,
+        height: usize,
+        /// Hash of the proposal that was received first
+        hash_a: ProposalHash,
+        /// Hash of the rejected proposal
+        hash_b: ProposalHash,
+    },
+
+    /// Two different proposals for the same leader, height and skips, where at least
+    /// one was relayed by another peer, so the leader can't be blamed and neither is rejected
+    ConflictingProposal {
+        /// Peer that sent the second proposal
+        peer_id: PeerId,
+        leader_id: PeerId,
+        height: usize,
+        /// Hash of the proposal that was received first
+        hash_a: ProposalHash,
+        /// Hash of the proposal that was received second
+        hash_b: ProposalHash,
+    },
 }
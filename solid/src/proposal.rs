@@ -1,3 +1,4 @@
+use crate::config::QuorumRule;
 use crate::key::Key;
 use crate::peer::PeerId;
 use crate::txn::Txn;
@@ -20,6 +21,10 @@ pub struct Proposal {
 
     /// Peers (in order based on manifest.last_hash)
     peers: Vec<PeerId>,
+
+    /// Whether the proposal was received directly from its leader, relayed proposals
+    /// are not authenticated and can't be used to blame the leader
+    pub from_leader: bool,
 }
 
 /// ProposalAccept is sent by all peers to the next leader to indicate
@@ -85,6 +90,7 @@ impl Proposal {
             hash,
             manifest,
             peers,
+            from_leader: false,
         }
     }
 
@@ -112,25 +118,31 @@ impl Proposal {
         self.manifest.skips
     }
 
-    pub fn add_accept(&mut self, skips: &usize, peer_id: PeerId) -> bool {
+    pub fn add_accept(&mut self, skips: &usize, peer_id: PeerId, quorum: &QuorumRule) -> bool {
         let added = self
             .incoming_accepts
             .entry(*skips)
             .or_insert(HashSet::new())
             .insert(peer_id);
-        added && self.majority_accept_breached(skips)
+        added && self.quorum_breached(skips, quorum)
     }
 
     /// Checks that we have just enough accepts for meeting the majority
     /// threshold, allowing us to confirm the proposal when majority threshold met,
     /// but only when the threshold is first breached
     pub fn majority_accept_breached(&self, skips: &usize) -> bool {
+        self.quorum_breached(skips, &QuorumRule::Majority)
+    }
+
+    /// Checks that we have just enough accepts for meeting the quorum threshold,
+    /// but only when the threshold is first breached
+    pub fn quorum_breached(&self, skips: &usize, quorum: &QuorumRule) -> bool {
         let len = self
             .incoming_accepts
             .get(skips)
             .map(|p| p.len())
             .unwrap_or(0);
-        len > (&self.peers.len() / 2) && len - 1 <= (&self.peers.len() / 2)
+        len == quorum.threshold(self.peers.len())
     }
 
     pub fn get_next_leader(&self, skip: usize) -> PeerId {
@@ -263,22 +275,50 @@ mod test {
             "Should not be breached"
         );
 
-        proposal.add_accept(&0, p1);
+        proposal.add_accept(&0, p1, &QuorumRule::Majority);
 
         assert!(
             !proposal.majority_accept_breached(&0),
             "Should not be breached"
         );
 
-        proposal.add_accept(&0, p2);
+        proposal.add_accept(&0, p2, &QuorumRule::Majority);
 
         assert!(proposal.majority_accept_breached(&0), "Should be breached");
 
-        proposal.add_accept(&0, p3);
+        proposal.add_accept(&0, p3, &QuorumRule::Majority);
 
         assert!(
             !proposal.majority_accept_breached(&0),
             "Should not be breached"
         );
     }
+
+    #[test]
+    fn test_quorum_breached_with_four_peers() {
+        let peers = (1..=4).map(|i| PeerId::new(vec![i])).collect::<Vec<_>>();
+
+        for (quorum, threshold) in [
+            (QuorumRule::Majority, 3),
+            (QuorumRule::TwoThirds, 3),
+            (QuorumRule::Fixed(2), 2),
+        ] {
+            let mut proposal = Proposal::new(ProposalManifest {
+                last_proposal_hash: ProposalHash::new(vec![0u8]),
+                skips: 0,
+                height: 0,
+                leader_id: peers[0].clone(),
+                txns: vec![],
+                peers: peers.clone(),
+            });
+
+            // Only the accept that first meets the threshold is reported
+            let breached = peers
+                .iter()
+                .map(|p| proposal.add_accept(&0, p.clone(), &quorum))
+                .collect::<Vec<_>>();
+            let expected = (1..=4).map(|n| n == threshold).collect::<Vec<_>>();
+            assert_eq!(breached, expected, "{quorum:?}");
+        }
+    }
 }
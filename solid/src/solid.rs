@@ -84,6 +84,7 @@ impl Solid {
                 local_peer_id,
                 manifest,
                 config.max_proposal_history,
+                config.quorum,
             )),
             skip_timeout: AtomicTimestamp::new(None),
             out_of_sync_timeout: Mutex::new(None),
@@ -108,6 +109,7 @@ impl Solid {
             self.shared.local_peer_id.clone(),
             manifest,
             self.shared.config.max_proposal_history,
+            self.shared.config.quorum,
         );
         let mut events = self.shared.events.lock();
         events.clear();
@@ -150,9 +152,16 @@ impl Solid {
         self.shared.store.lock().min_proposal_height()
     }
 
+    /// Number of proposals held in memory, this is bounded by `max_proposal_history`
+    /// confirmed proposals below the confirmed height, plus the confirmed and pending proposals
+    pub fn proposal_count(&self) -> usize {
+        self.shared.store.lock().proposal_count()
+    }
+
     /// Receive a new proposal from an external source, we do some basic validation
-    /// to make sure this is a valid proposal that could be confirmed.
-    pub fn receive_proposal(&mut self, manifest: ProposalManifest) {
+    /// to make sure this is a valid proposal that could be confirmed. `from` is the
+    /// peer that sent the proposal, which is only the leader if it was not relayed.
+    pub fn receive_proposal(&mut self, manifest: ProposalManifest, from: &PeerId) {
         let hash: ProposalHash = (&manifest).into();
 
         // Proposal already exists, don't recreate
@@ -178,9 +187,38 @@ impl Solid {
             return;
         }
 
+        // A leader only gets one proposal for each height and skips, a different proposal
+        // for the same round means the leader is misbehaving. The leader_id in a relayed
+        // proposal is not authenticated, so we only reject (and blame the leader) when both
+        // proposals were received directly from the leader.
+        let from_leader = from == &manifest.leader_id;
+        let conflicting = self.shared.store.lock().conflicting_proposal(&manifest);
+        if let Some((existing_hash, existing_from_leader)) = conflicting {
+            if from_leader && existing_from_leader {
+                self.shared.send_event(SolidEvent::Equivocation {
+                    peer_id: manifest.leader_id,
+                    height: manifest_height,
+                    hash_a: existing_hash,
+                    hash_b: hash,
+                });
+                return;
+            }
+
+            self.shared.send_event(SolidEvent::ConflictingProposal {
+                peer_id: from.clone(),
+                leader_id: manifest.leader_id.clone(),
+                height: manifest_height,
+                hash_a: existing_hash,
+                hash_b: hash,
+            });
+        }
+
         // Add proposal to the store
         {
-            self.shared.store.lock().add_pending_proposal(manifest);
+            self.shared
+                .store
+                .lock()
+                .add_pending_proposal(manifest, from_leader);
         }
 
         // Process next rounds with the newly added proposal state, and keep
@@ -388,8 +426,8 @@ mod test {
 
         // Send proposal twice
         // let mut state = register.state.lock().unwrap();
-        register.receive_proposal(manifest.clone());
-        register.receive_proposal(manifest.clone());
+        register.receive_proposal(manifest.clone(), &manifest.leader_id);
+        register.receive_proposal(manifest.clone(), &manifest.leader_id);
 
         register.next().await.unwrap();
         let next = register.next().await.unwrap();
@@ -402,6 +440,87 @@ mod test {
         )
     }
 
+    #[tokio::test]
+    async fn rejects_conflicting_proposal_from_same_leader() {
+        let [p1, p2, _] = create_peers();
+        let config = SolidConfig::default();
+        let mut register = Solid::genesis(p1, create_peers().to_vec(), config);
+        let manifest_a = ProposalManifest {
+            last_proposal_hash: ProposalHash::default(),
+            height: 1,
+            skips: 0,
+            leader_id: p2.clone(),
+            txns: vec![],
+            peers: vec![],
+        };
+        let manifest_b = ProposalManifest {
+            last_proposal_hash: ProposalHash::new(vec![1u8]),
+            ..manifest_a.clone()
+        };
+
+        register.receive_proposal(manifest_a.clone(), &manifest_a.leader_id);
+        register.next().await.unwrap();
+
+        // Same height, skips and leader, but a different proposal
+        register.receive_proposal(manifest_b.clone(), &manifest_b.leader_id);
+        assert_eq!(
+            register.next().await.unwrap(),
+            SolidEvent::Equivocation {
+                peer_id: p2,
+                height: 1,
+                hash_a: manifest_a.hash(),
+                hash_b: manifest_b.hash(),
+            }
+        );
+        assert!(!register.exists(&manifest_b.hash()));
+
+        // Re-sending the same proposal is only a duplicate
+        register.receive_proposal(manifest_a.clone(), &manifest_a.leader_id);
+        assert_eq!(
+            register.next().await.unwrap(),
+            SolidEvent::DuplicateProposal {
+                proposal_hash: manifest_a.hash()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_leader_proposal_after_relayed_conflicting_proposal() {
+        let [p1, p2, p3] = create_peers();
+        let config = SolidConfig::default();
+        let mut register = Solid::genesis(p1, create_peers().to_vec(), config);
+        let forged = ProposalManifest {
+            last_proposal_hash: ProposalHash::new(vec![1u8]),
+            height: 1,
+            skips: 0,
+            leader_id: p2.clone(),
+            txns: vec![],
+            peers: vec![],
+        };
+        let manifest = ProposalManifest {
+            last_proposal_hash: ProposalHash::default(),
+            ..forged.clone()
+        };
+
+        // Relayed by another peer, so the leader_id is not authenticated
+        register.receive_proposal(forged.clone(), &p3);
+        register.next().await.unwrap();
+
+        // The leader's own proposal is not rejected, and the leader is not blamed
+        register.receive_proposal(manifest.clone(), &p2);
+        assert_eq!(
+            register.next().await.unwrap(),
+            SolidEvent::ConflictingProposal {
+                peer_id: p2.clone(),
+                leader_id: p2,
+                height: 1,
+                hash_a: forged.hash(),
+                hash_b: manifest.hash(),
+            }
+        );
+        assert!(register.exists(&manifest.hash()));
+    }
+
     #[tokio::test]
     async fn first_proposal_single_peer() {
         let [p1, _, _] = create_peers();
@@ -418,7 +537,7 @@ mod test {
         };
         let hash: ProposalHash = (&manifest).into();
 
-        register.receive_proposal(manifest.clone());
+        register.receive_proposal(manifest.clone(), &manifest.leader_id);
 
         let next = register.next().await.unwrap();
 
@@ -448,7 +567,7 @@ mod test {
         };
         let hash: ProposalHash = (&manifest).into();
 
-        register.receive_proposal(manifest.clone());
+        register.receive_proposal(manifest.clone(), &manifest.leader_id);
 
         let next = register.next().await.unwrap();
 
@@ -1,3 +1,4 @@
+use super::config::QuorumRule;
 use super::event::SolidEvent;
 use super::proposal::{Proposal, ProposalAccept, ProposalHash, ProposalManifest};
 use crate::cache::ProposalCache;
@@ -24,7 +25,11 @@ pub struct ProposalStore {
 
     /// Orphaned accepts are when we receive an accept for a proposal before we
     /// receive the proposal itself. We can then add these as soon as the proposal arrives.
-    orphan_accepts: HashMap<ProposalHash, Vec<(usize, PeerId)>>,
+    /// Keyed by proposal hash, with the height of the proposal and the accepts.
+    orphan_accepts: HashMap<ProposalHash, (usize, Vec<(usize, PeerId)>)>,
+
+    /// Number of accepts required before we propose the next proposal
+    quorum: QuorumRule,
 }
 
 #[derive(Debug)]
@@ -38,6 +43,7 @@ impl ProposalStore {
         local_peer_id: PeerId,
         last_confirmed_proposal: ProposalManifest,
         cache_size: usize,
+        quorum: QuorumRule,
     ) -> Self {
         let max_height = last_confirmed_proposal.height;
 
@@ -48,12 +54,18 @@ impl ProposalStore {
             accepts_sent: 0,
             accepts_sent_height: max_height,
             orphan_accepts: HashMap::new(),
+            quorum,
         }
     }
 
     #[cfg(test)]
     pub fn genesis(local_peer_id: PeerId, peers: Vec<PeerId>, cache_size: usize) -> Self {
-        Self::with_last_confirmed(local_peer_id, ProposalManifest::genesis(peers), cache_size)
+        Self::with_last_confirmed(
+            local_peer_id,
+            ProposalManifest::genesis(peers),
+            cache_size,
+            QuorumRule::default(),
+        )
     }
 
     /// Height of the proposal that was last confirmed
@@ -87,15 +99,32 @@ impl ProposalStore {
         self.proposals.min_proposal_height()
     }
 
+    /// Number of proposals held in memory, including pending proposals
+    pub fn proposal_count(&self) -> usize {
+        self.proposals.len()
+    }
+
+    /// Hash of a different proposal from the same leader, for the same height and skips,
+    /// and whether that proposal was received directly from the leader
+    pub fn conflicting_proposal(
+        &self,
+        manifest: &ProposalManifest,
+    ) -> Option<(ProposalHash, bool)> {
+        self.proposals
+            .conflicting(manifest)
+            .map(|p| (p.hash().clone(), p.from_leader))
+    }
+
     /// Add a pending proposal to the store
-    pub fn add_pending_proposal(&mut self, manifest: ProposalManifest) {
+    pub fn add_pending_proposal(&mut self, manifest: ProposalManifest, from_leader: bool) {
         let hash: ProposalHash = (&manifest).into();
         let mut proposal = Proposal::new(manifest);
+        proposal.from_leader = from_leader;
 
         // Check if we have orphaned accepts
-        if let Some(accepts) = self.orphan_accepts.remove(&hash) {
+        if let Some((_, accepts)) = self.orphan_accepts.remove(&hash) {
             for (skips, peer_id) in accepts {
-                proposal.add_accept(&skips, peer_id);
+                proposal.add_accept(&skips, peer_id, &self.quorum);
             }
         }
 
@@ -141,6 +170,11 @@ impl ProposalStore {
             // Add proposal to confirmed list
             self.proposals.confirm(proposal_hash);
 
+            // Proposals at or below the confirmed height can no longer be accepted
+            let height = self.height();
+            self.orphan_accepts
+                .retain(|_, (accept_height, _)| *accept_height > height);
+
             // Reset accepts sent, as we have a new commit
             self.accepts_sent = 0;
 
@@ -248,7 +282,7 @@ impl ProposalStore {
         return match self.proposals.get_mut(last_proposal_hash) {
             Some(p) => {
                 // Skip if skips is not valid
-                if p.add_accept(skips, from.clone()) {
+                if p.add_accept(skips, from.clone(), &self.quorum) {
                     return Some(SolidEvent::Propose {
                         last_proposal_hash: last_proposal_hash.clone(),
                         height: p.height() + 1,
@@ -259,12 +293,12 @@ impl ProposalStore {
             }
             None => {
                 // Get exisiting orphaned proposal list (or create it if it doesn't exist yet)
-                if let Some(p) = self.orphan_accepts.get_mut(last_proposal_hash) {
+                if let Some((_, p)) = self.orphan_accepts.get_mut(last_proposal_hash) {
                     p.push((*skips, leader_id.clone()));
                 } else {
                     self.orphan_accepts.insert(
                         last_proposal_hash.clone(),
-                        vec![(*skips, leader_id.clone())],
+                        (*accept_height, vec![(*skips, leader_id.clone())]),
                     );
                 }
 
@@ -366,7 +400,7 @@ mod test {
         assert_eq!(store.process_next(), None);
 
         let (m1, m1_hash) = create_manifest(1, 0, 1, genesis_hash);
-        store.add_pending_proposal(m1.clone());
+        store.add_pending_proposal(m1.clone(), true);
 
         assert_eq!(
             store.process_next(),
@@ -383,7 +417,7 @@ mod test {
         assert_eq!(store.proposals.len(), 2);
 
         let (m2, m2_hash) = create_manifest(2, 0, 1, m1_hash);
-        store.add_pending_proposal(m2.clone());
+        store.add_pending_proposal(m2.clone(), true);
 
         assert_eq!(
             store.process_next(),
@@ -407,7 +441,7 @@ mod test {
         let (m3, m3_hash) = create_manifest(3, 0, 2, m2_hash);
         let (m4, m4_hash) = create_manifest(4, 0, 1, m3_hash);
 
-        store.add_pending_proposal(m4);
+        store.add_pending_proposal(m4, true);
 
         assert_eq!(
             store.process_next(),
@@ -417,7 +451,7 @@ mod test {
                 accepts_sent: 1
             })
         );
-        store.add_pending_proposal(m3.clone());
+        store.add_pending_proposal(m3.clone(), true);
 
         assert_eq!(
             store.process_next(),
@@ -450,7 +484,7 @@ mod test {
         let genesis_hash = ProposalManifest::genesis(create_peers().to_vec()).hash();
 
         let (m10, m10_hash) = create_manifest(10, 0, 1, genesis_hash);
-        let mut store = ProposalStore::with_last_confirmed(p1, m10, 100);
+        let mut store = ProposalStore::with_last_confirmed(p1, m10, 100, QuorumRule::default());
 
         assert_eq!(
             store.process_next(),
@@ -465,7 +499,7 @@ mod test {
         );
 
         let (m11, m11_hash) = create_manifest(11, 0, 1, m10_hash);
-        store.add_pending_proposal(m11.clone());
+        store.add_pending_proposal(m11.clone(), true);
 
         assert_eq!(
             store.process_next(),
@@ -482,7 +516,7 @@ mod test {
         assert_eq!(store.process_next(), None);
 
         let (m12, m12_hash) = create_manifest(12, 0, 1, m11_hash);
-        store.add_pending_proposal(m12);
+        store.add_pending_proposal(m12, true);
 
         assert_eq!(
             store.process_next(),
@@ -524,7 +558,7 @@ mod test {
 
         // First pending proposal
         let (m1, m1_hash) = create_manifest(1, 0, 1, genesis_hash);
-        store.add_pending_proposal(m1.clone());
+        store.add_pending_proposal(m1.clone(), true);
 
         // Send accept for m1
         assert_eq!(
@@ -556,13 +590,13 @@ mod test {
 
         // Proposal arrives late (but is now invalid)
         let (m2a, _) = create_manifest(2, 0, 2, m1_hash.clone());
-        store.add_pending_proposal(m2a);
+        store.add_pending_proposal(m2a, true);
 
         assert_eq!(store.process_next(), None);
 
         // Proposal (+1 skip) now arrives
         let (m2b, m2b_hash) = create_manifest(2, 1, 2, m1_hash);
-        store.add_pending_proposal(m2b);
+        store.add_pending_proposal(m2b, true);
 
         assert_eq!(
             store.process_next(),
@@ -588,7 +622,7 @@ mod test {
     fn test_out_of_sync() {
         let [p1, _, _] = create_peers();
         let (m3, m3_hash) = create_manifest(3, 0, 1, ProposalHash::default());
-        let mut store = ProposalStore::with_last_confirmed(p1, m3, 100);
+        let mut store = ProposalStore::with_last_confirmed(p1, m3, 100, QuorumRule::default());
 
         assert_eq!(
             store.process_next(),
@@ -603,7 +637,7 @@ mod test {
         );
 
         let (m5, _) = create_manifest(5, 0, 1, ProposalHash::default());
-        store.add_pending_proposal(m5);
+        store.add_pending_proposal(m5, true);
 
         assert_eq!(
             store.process_next(),
@@ -644,7 +678,7 @@ mod test {
         );
 
         let (m1, m1_hash) = create_manifest(1, 0, 2, genesis_hash);
-        store.add_pending_proposal(m1.clone());
+        store.add_pending_proposal(m1.clone(), true);
 
         // Send accept for m1
         assert_eq!(
@@ -678,14 +712,14 @@ mod test {
         // Proposal m2 received (but with skips=0), we can no longer
         // accept this proposal as we sent a skip message
         let (m2, m2_hash) = create_manifest(2, 0, 3, m1_hash);
-        store.add_pending_proposal(m2.clone());
+        store.add_pending_proposal(m2.clone(), true);
 
         assert_eq!(store.process_next(), None);
 
         // Proposal m3 receivied which references m2, so we know network
         // approved m2
         let (m3, m3_hash) = create_manifest(3, 0, 2, m2_hash);
-        store.add_pending_proposal(m3);
+        store.add_pending_proposal(m3, true);
 
         // We now need to catch up to network by applying m2
         assert_eq!(
@@ -731,7 +765,7 @@ mod test {
         );
 
         let (m1, m1_hash) = create_manifest(1, 0, 1, genesis_hash);
-        store.add_pending_proposal(m1);
+        store.add_pending_proposal(m1, true);
 
         assert_eq!(
             store.process_next(),
@@ -873,6 +907,41 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_accept_quorum_with_four_peers() {
+        let peers = (1..=4).map(peer).collect::<Vec<_>>();
+        let genesis = ProposalManifest::genesis(peers.clone());
+        let genesis_hash = genesis.hash();
+
+        for (quorum, threshold) in [
+            (QuorumRule::Majority, 3),
+            (QuorumRule::TwoThirds, 3),
+            (QuorumRule::Fixed(2), 2),
+        ] {
+            let mut store =
+                ProposalStore::with_last_confirmed(peer(1), genesis.clone(), 100, quorum);
+
+            // Propose only once the configured number of accepts is received
+            for (i, from) in peers.iter().enumerate() {
+                let event = store.add_accept(
+                    &ProposalAccept {
+                        leader_id: peer(1),
+                        proposal_hash: genesis_hash.clone(),
+                        height: 0,
+                        skips: 0,
+                    },
+                    from,
+                );
+                let expected = (i + 1 == threshold).then(|| SolidEvent::Propose {
+                    last_proposal_hash: genesis_hash.clone(),
+                    height: 1,
+                    skips: 0,
+                });
+                assert_eq!(event, expected, "{quorum:?} accept {}", i + 1);
+            }
+        }
+    }
+
     #[test]
     fn test_higher_skip_accept_received() {
         let [p1, _, _] = create_peers();
@@ -1039,6 +1108,62 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_proposal_history_is_bounded() {
+        let [p1, _, _] = create_peers();
+        let max_history = 10;
+        let mut store = ProposalStore::genesis(p1, create_peers().to_vec(), max_history);
+        let mut last_hash = ProposalManifest::genesis(create_peers().to_vec()).hash();
+
+        for height in 1..=50 {
+            let (m, m_hash) = create_manifest(height, 0, 1, last_hash);
+            last_hash = m_hash;
+            store.add_pending_proposal(m, true);
+            while store.process_next().is_some() {}
+
+            // History below the confirmed height, plus the confirmed and pending proposals
+            assert!(store.proposal_count() <= max_history + 2);
+        }
+
+        assert_eq!(store.height(), 49);
+
+        // Proposals within the history window are still available
+        let mut heights = store
+            .proposals_from(49 - max_history)
+            .iter()
+            .map(|m| m.height)
+            .collect::<Vec<_>>();
+        heights.sort();
+        assert_eq!(heights, (39..=50).collect::<Vec<_>>());
+        assert_eq!(store.confirmed_proposals_from(45).len(), 6);
+    }
+
+    #[test]
+    fn test_orphan_accepts_pruned_on_commit() {
+        let [p1, p2, _] = create_peers();
+        let mut store = ProposalStore::genesis(p1, create_peers().to_vec(), 100);
+        let genesis_hash = ProposalManifest::genesis(create_peers().to_vec()).hash();
+
+        // Accept for a proposal that is never received
+        let accept = ProposalAccept {
+            proposal_hash: ProposalHash::new(vec![9u8]),
+            leader_id: p2.clone(),
+            height: 1,
+            skips: 0,
+        };
+        store.add_accept(&accept, &p2);
+        assert_eq!(store.orphan_accepts.len(), 1);
+
+        let (m1, m1_hash) = create_manifest(1, 0, 1, genesis_hash);
+        let (m2, _) = create_manifest(2, 0, 1, m1_hash);
+        store.add_pending_proposal(m1, true);
+        store.add_pending_proposal(m2, true);
+        while store.process_next().is_some() {}
+
+        assert_eq!(store.height(), 1);
+        assert!(store.orphan_accepts.is_empty());
+    }
+
     #[test]
     fn test_next_pending_propsal() {
         let [p1, _, _] = create_peers();
@@ -1056,7 +1181,7 @@ mod test {
         let (m3, _) = create_manifest(3, 0, 1, ProposalHash::default());
 
         // Add m1 to pending proposals
-        store.add_pending_proposal(m1);
+        store.add_pending_proposal(m1, true);
 
         assert_eq!(
             store
@@ -1069,7 +1194,7 @@ mod test {
         );
 
         // Add next proposal height: 2
-        store.add_pending_proposal(m2a);
+        store.add_pending_proposal(m2a, true);
 
         assert_eq!(
             store
@@ -1082,7 +1207,7 @@ mod test {
         );
 
         // Add additional proposal for height: 2
-        store.add_pending_proposal(m2b);
+        store.add_pending_proposal(m2b, true);
 
         assert_eq!(
             store
@@ -1095,7 +1220,7 @@ mod test {
         );
 
         // Add proposal with gap
-        store.add_pending_proposal(m3);
+        store.add_pending_proposal(m3, true);
 
         assert!(store.proposals.next_pending_proposal(0).is_none());
     }
@@ -1110,11 +1235,11 @@ mod test {
         let (m1, _) = create_manifest(1, 0, 1, genesis_hash.clone());
         let (m2, _) = create_manifest(2, 0, 2, genesis_hash);
 
-        store.add_pending_proposal(m1);
+        store.add_pending_proposal(m1, true);
 
         assert!(!store.has_next_commit());
 
-        store.add_pending_proposal(m2);
+        store.add_pending_proposal(m2, true);
 
         assert!(store.has_next_commit());
     }